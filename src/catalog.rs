@@ -0,0 +1,2220 @@
+//! Catalog of the tools the server exposes
+//!
+//! Each entry carries the tool's input schema; annotations come from
+//! [`default_tool_annotations`](crate::default_tool_annotations).
+
+use serde_json::{Value, json};
+
+use crate::{JjTool, advertised_tool, server_config, tool_group};
+
+/// Tools as JSON, in the shape of an MCP `tools/list` result, with each tool's group
+pub fn tool_catalog(tools: &[JjTool]) -> Value {
+    let tools: Vec<Value> = tools
+        .iter()
+        .map(|tool| {
+            json!({
+                "name": tool.name,
+                "description": tool.description,
+                "inputSchema": tool.input_schema,
+                "annotations": tool.annotations,
+                "group": tool_group(server_config().canonical_name(&tool.name)),
+            })
+        })
+        .collect();
+    json!({ "tools": tools })
+}
+
+/// Every tool as clients would see it, for snapshotting the advertised surface
+///
+/// Unlike `--list-tools`, this ignores the server configuration: every tool is
+/// included under its own name.
+pub fn export_tool_catalog() -> Value {
+    let tools: Vec<JjTool> = all_tools().into_iter().map(advertised_tool).collect();
+    tool_catalog(&tools)
+}
+
+/// Every tool the server exposes, in registration order
+pub fn all_tools() -> Vec<JjTool> {
+    vec![
+        // Status tool
+        JjTool::new(
+            "status",
+            "Show the status of the working directory",
+            json!({
+                "type": "object",
+                "properties": {
+                    "allWorkspaces": {
+                        "type": "boolean",
+                        "description": "Return a JSON summary of every workspace's working-copy commit and changed files instead"
+                    },
+                    "repoPath": {
+                        "type": "string",
+                        "description": "Optional path to repo root"
+                    },
+                    "cwd": {
+                        "type": "string",
+                        "description": "Optional working directory"
+                    }
+                }
+            }),
+        ),
+        // Rebase tool
+        JjTool::new(
+            "rebase",
+            "Rebase a revision onto another",
+            json!({
+                "type": "object",
+                "properties": {
+                    "source": {
+                        "type": "string",
+                        "description": "Source revision to rebase"
+                    },
+                    "destination": {
+                        "type": "string",
+                        "description": "Destination revision to rebase onto"
+                    },
+                    "repoPath": {
+                        "type": "string",
+                        "description": "Optional path to repo root"
+                    },
+                    "cwd": {
+                        "type": "string",
+                        "description": "Optional working directory"
+                    }
+                }
+            }),
+        ),
+        // Commit tool
+        JjTool::new(
+            "commit",
+            "Create a new commit",
+            json!({
+                "type": "object",
+                "properties": {
+                    "message": {
+                        "type": "string",
+                        "description": "Commit message; may span several lines, and messages over 64 KiB are passed on stdin"
+                    },
+                    "messageFile": {
+                        "type": "string",
+                        "description": "File to read the commit message from, relative to cwd (instead of message)"
+                    },
+                    "maxFileSize": {
+                        "type": "number",
+                        "description": "Refuse to commit if a changed file is larger than this many bytes"
+                    },
+                    "sign": {
+                        "type": "boolean",
+                        "description": "Sign the commit (true) or leave it unsigned (false); defaults to jj's signing configuration"
+                    },
+                    "signingBackend": {
+                        "type": "string",
+                        "enum": ["gpg", "gpgsm", "ssh", "none"],
+                        "description": "Signing backend to use instead of the configured one"
+                    },
+                    "signingKey": {
+                        "type": "string",
+                        "description": "Signing key to use instead of the configured one"
+                    },
+                    "authorName": {
+                        "type": "string",
+                        "description": "Author name for the commit (default: user.name)"
+                    },
+                    "authorEmail": {
+                        "type": "string",
+                        "description": "Author email for the commit (default: user.email)"
+                    },
+                    "repoPath": {
+                        "type": "string",
+                        "description": "Optional path to repo root"
+                    },
+                    "cwd": {
+                        "type": "string",
+                        "description": "Optional working directory"
+                    }
+                }
+            }),
+        ),
+        // New tool
+        JjTool::new(
+            "new",
+            "Create a new empty commit",
+            json!({
+                "type": "object",
+                "properties": {
+                    "parents": {
+                        "type": "string",
+                        "description": "Parent revisions for the new commit"
+                    },
+                    "authorName": {
+                        "type": "string",
+                        "description": "Author name for the new commit (default: user.name)"
+                    },
+                    "authorEmail": {
+                        "type": "string",
+                        "description": "Author email for the new commit (default: user.email)"
+                    },
+                    "repoPath": {
+                        "type": "string",
+                        "description": "Optional path to repo root"
+                    },
+                    "cwd": {
+                        "type": "string",
+                        "description": "Optional working directory"
+                    }
+                }
+            }),
+        ),
+        // Log tool
+        JjTool::new(
+            "log",
+            "Show commit history",
+            json!({
+                "type": "object",
+                "properties": {
+                    "limit": {
+                        "type": "number",
+                        "description": "Maximum number of commits to show"
+                    },
+                    "template": {
+                        "type": "string",
+                        "description": "Template for formatting output"
+                    },
+                    "revisions": {
+                        "type": "string",
+                        "description": "Revisions to show"
+                    },
+                    "format": {
+                        "type": "string",
+                        "enum": ["text", "mermaid", "dot"],
+                        "description": "text (default) for jj's own output, or mermaid or dot for the commit graph of the revisions"
+                    },
+                    "author": {
+                        "type": "string",
+                        "description": "Only show commits whose author name or email contains this"
+                    },
+                    "since": {
+                        "type": "string",
+                        "description": "Only show commits committed after this date, e.g. 2024-01-31 or \"2 weeks ago\""
+                    },
+                    "until": {
+                        "type": "string",
+                        "description": "Only show commits committed before this date"
+                    },
+                    "paths": {
+                        "type": "array",
+                        "items": {"type": "string"},
+                        "description": "Only show commits that change these files or directories (filesets)"
+                    },
+                    "maxBytes": {
+                        "type": "number",
+                        "description": "Largest response wanted in bytes; larger logs fall back to one line per commit, then are cut"
+                    },
+                    "maxTokens": {
+                        "type": "number",
+                        "description": "Like maxBytes, in tokens of about 4 bytes"
+                    },
+                    "repoPath": {
+                        "type": "string",
+                        "description": "Optional path to repo root"
+                    },
+                    "cwd": {
+                        "type": "string",
+                        "description": "Optional working directory"
+                    }
+                }
+            }),
+        ),
+        // Diff tool
+        JjTool::new(
+            "diff",
+            "Show differences between revisions",
+            json!({
+                "type": "object",
+                "properties": {
+                    "from": {
+                        "type": "string",
+                        "description": "Source revision"
+                    },
+                    "to": {
+                        "type": "string",
+                        "description": "Target revision"
+                    },
+                    "base": {
+                        "type": "string",
+                        "description": "Diff against the merge base of the target and this revision instead of from; \"auto\" uses trunk(), showing a stacked change's whole delta"
+                    },
+                    "paths": {
+                        "type": "array",
+                        "items": {"type": "string"},
+                        "description": "Specific paths to diff"
+                    },
+                    "context": {
+                        "type": "number",
+                        "description": "Number of context lines"
+                    },
+                    "summary": {
+                        "type": "boolean",
+                        "description": "Show summary only"
+                    },
+                    "stat": {
+                        "type": "boolean",
+                        "description": "Show file statistics"
+                    },
+                    "embedFiles": {
+                        "type": "boolean",
+                        "description": "Also return each changed file's content at the target revision as an embedded resource"
+                    },
+                    "html": {
+                        "type": "boolean",
+                        "description": "Also return a self-contained side-by-side HTML rendering of the diff as a resource"
+                    },
+                    "wordDiff": {
+                        "type": "boolean",
+                        "description": "Mark changed words within lines as [-removed-]{+added+} in the full diff instead of showing whole lines; summary and stat output is left as it is"
+                    },
+                    "allowBinary": {
+                        "type": "boolean",
+                        "description": "With embedFiles, include binary files as base64 instead of only their size and type"
+                    },
+                    "maxBytes": {
+                        "type": "number",
+                        "description": "Largest response wanted in bytes; larger diffs fall back to --stat, then --summary, then are cut"
+                    },
+                    "maxTokens": {
+                        "type": "number",
+                        "description": "Like maxBytes, in tokens of about 4 bytes"
+                    },
+                    "repoPath": {
+                        "type": "string",
+                        "description": "Optional path to repo root"
+                    },
+                    "cwd": {
+                        "type": "string",
+                        "description": "Optional working directory"
+                    }
+                }
+            }),
+        ),
+        // Git clone tool
+        JjTool::new(
+            "git-clone",
+            "Clone a Git repository using jj",
+            json!({
+                "type": "object",
+                "properties": {
+                    "source": {
+                        "type": "string",
+                        "description": "Git repository URL to clone"
+                    },
+                    "destination": {
+                        "type": "string",
+                        "description": "Destination directory"
+                    },
+                    "colocate": {
+                        "type": "boolean",
+                        "description": "Create a colocated jj/git repository"
+                    },
+                    "remote": {
+                        "type": "string",
+                        "description": "Name for the remote"
+                    },
+                    "depth": {
+                        "type": "number",
+                        "description": "Depth for shallow clone"
+                    }
+                }
+            }),
+        ),
+        // Conflict content tool
+        JjTool::new(
+            "conflict-content",
+            "Extract the conflicted regions of a file as structured JSON",
+            json!({
+                "type": "object",
+                "properties": {
+                    "path": {
+                        "type": "string",
+                        "description": "Path of the conflicted file, relative to the repo root"
+                    },
+                    "revision": {
+                        "type": "string",
+                        "description": "Revision containing the conflict (defaults to @)"
+                    },
+                    "repoPath": {
+                        "type": "string",
+                        "description": "Optional path to repo root"
+                    },
+                    "cwd": {
+                        "type": "string",
+                        "description": "Optional working directory"
+                    }
+                }
+            }),
+        ),
+        // Conflict resolve tool
+        JjTool::new(
+            "conflict-resolve",
+            "Write resolved content for a conflicted file and verify the conflict is gone",
+            json!({
+                "type": "object",
+                "properties": {
+                    "path": {
+                        "type": "string",
+                        "description": "Path of the conflicted file, relative to the repo root"
+                    },
+                    "content": {
+                        "type": "string",
+                        "description": "Fully resolved file content"
+                    },
+                    "revision": {
+                        "type": "string",
+                        "description": "Conflicted revision to resolve (defaults to @); other revisions are resolved in a temporary workspace"
+                    },
+                    "repoPath": {
+                        "type": "string",
+                        "description": "Optional path to repo root"
+                    },
+                    "cwd": {
+                        "type": "string",
+                        "description": "Optional working directory"
+                    }
+                }
+            }),
+        ),
+        // Divergent changes tool
+        JjTool::new(
+            "divergent-changes",
+            "List divergent changes with their commits and hints about which to abandon",
+            json!({
+                "type": "object",
+                "properties": {
+                    "revisions": {
+                        "type": "string",
+                        "description": "Revset to search for divergent changes (defaults to all())"
+                    },
+                    "repoPath": {
+                        "type": "string",
+                        "description": "Optional path to repo root"
+                    },
+                    "cwd": {
+                        "type": "string",
+                        "description": "Optional working directory"
+                    }
+                }
+            }),
+        ),
+        // Hidden commits tool
+        JjTool::new(
+            "hidden-commits",
+            "List commits that were visible at an earlier operation but are now hidden",
+            json!({
+                "type": "object",
+                "properties": {
+                    "operations": {
+                        "type": "number",
+                        "description": "How many operations back to compare against (defaults to 10)"
+                    },
+                    "revisions": {
+                        "type": "string",
+                        "description": "Revset to compare (defaults to all())"
+                    },
+                    "includeRewritten": {
+                        "type": "boolean",
+                        "description": "Also list hidden predecessors of changes that are still visible"
+                    },
+                    "repoPath": {
+                        "type": "string",
+                        "description": "Optional path to repo root"
+                    },
+                    "cwd": {
+                        "type": "string",
+                        "description": "Optional working directory"
+                    }
+                }
+            }),
+        ),
+        // Unabandon tool
+        JjTool::new(
+            "unabandon",
+            "Recover a hidden commit by duplicating it into a new visible commit",
+            json!({
+                "type": "object",
+                "properties": {
+                    "commit": {
+                        "type": "string",
+                        "description": "Commit ID of the hidden commit to recover"
+                    },
+                    "destination": {
+                        "type": "string",
+                        "description": "Optional revision to place the recovered commit onto"
+                    },
+                    "repoPath": {
+                        "type": "string",
+                        "description": "Optional path to repo root"
+                    },
+                    "cwd": {
+                        "type": "string",
+                        "description": "Optional working directory"
+                    }
+                }
+            }),
+        ),
+        // Cleanup empty tool
+        JjTool::new(
+            "cleanup-empty",
+            "Abandon empty commits without a description, with an optional dry run",
+            json!({
+                "type": "object",
+                "properties": {
+                    "revisions": {
+                        "type": "string",
+                        "description": "Revset to clean up (defaults to mutable())"
+                    },
+                    "dryRun": {
+                        "type": "boolean",
+                        "description": "Only list the commits that would be abandoned"
+                    },
+                    "repoPath": {
+                        "type": "string",
+                        "description": "Optional path to repo root"
+                    },
+                    "cwd": {
+                        "type": "string",
+                        "description": "Optional working directory"
+                    }
+                }
+            }),
+        ),
+        // Cherry-pick tool
+        JjTool::new(
+            "cherry-pick",
+            "Copy revisions onto a destination (jj duplicate followed by jj rebase)",
+            json!({
+                "type": "object",
+                "properties": {
+                    "revisions": {
+                        "type": "array",
+                        "items": {"type": "string"},
+                        "description": "Revisions to cherry-pick"
+                    },
+                    "destination": {
+                        "type": "string",
+                        "description": "Revision to place the copies onto"
+                    },
+                    "repoPath": {
+                        "type": "string",
+                        "description": "Optional path to repo root"
+                    },
+                    "cwd": {
+                        "type": "string",
+                        "description": "Optional working directory"
+                    }
+                }
+            }),
+        ),
+        // Revert range tool
+        JjTool::new(
+            "revert-range",
+            "Back out a range of revisions, newest first, stacking the reverts onto a destination",
+            json!({
+                "type": "object",
+                "properties": {
+                    "revisions": {
+                        "type": "string",
+                        "description": "Revset of the revisions to back out (e.g. \"abc::def\")"
+                    },
+                    "destination": {
+                        "type": "string",
+                        "description": "Revision to stack the revert commits onto (defaults to @)"
+                    },
+                    "repoPath": {
+                        "type": "string",
+                        "description": "Optional path to repo root"
+                    },
+                    "cwd": {
+                        "type": "string",
+                        "description": "Optional working directory"
+                    }
+                }
+            }),
+        ),
+        // Bisect start tool
+        JjTool::new(
+            "bisect-start",
+            "Start a bisection between good revisions and a bad revision, checking out the first candidate",
+            json!({
+                "type": "object",
+                "properties": {
+                    "good": {
+                        "type": "array",
+                        "items": {"type": "string"},
+                        "description": "Revisions known to be good"
+                    },
+                    "bad": {
+                        "type": "string",
+                        "description": "Revision known to be bad (defaults to @)"
+                    },
+                    "repoPath": {
+                        "type": "string",
+                        "description": "Optional path to repo root"
+                    },
+                    "cwd": {
+                        "type": "string",
+                        "description": "Optional working directory"
+                    }
+                }
+            }),
+        ),
+        // Bisect mark tool
+        JjTool::new(
+            "bisect-mark",
+            "Mark a revision as good, bad or skip and check out the next candidate",
+            json!({
+                "type": "object",
+                "properties": {
+                    "result": {
+                        "type": "string",
+                        "enum": ["good", "bad", "skip"],
+                        "description": "Outcome of testing the revision"
+                    },
+                    "revision": {
+                        "type": "string",
+                        "description": "Revision being marked (defaults to the current candidate)"
+                    },
+                    "repoPath": {
+                        "type": "string",
+                        "description": "Optional path to repo root"
+                    },
+                    "cwd": {
+                        "type": "string",
+                        "description": "Optional working directory"
+                    }
+                }
+            }),
+        ),
+        // Bisect status tool
+        JjTool::new(
+            "bisect-status",
+            "Show the bisection state of a repository",
+            json!({
+                "type": "object",
+                "properties": {
+                    "repoPath": {
+                        "type": "string",
+                        "description": "Optional path to repo root"
+                    },
+                    "cwd": {
+                        "type": "string",
+                        "description": "Optional working directory"
+                    }
+                }
+            }),
+        ),
+        // Changelog tool
+        JjTool::new(
+            "changelog",
+            "Render a markdown changelog grouped into features, fixes and other changes",
+            json!({
+                "type": "object",
+                "properties": {
+                    "from": {
+                        "type": "string",
+                        "description": "Revision or tag the changelog starts after"
+                    },
+                    "to": {
+                        "type": "string",
+                        "description": "Revision or tag the changelog ends at (defaults to @)"
+                    },
+                    "repoPath": {
+                        "type": "string",
+                        "description": "Optional path to repo root"
+                    },
+                    "cwd": {
+                        "type": "string",
+                        "description": "Optional working directory"
+                    }
+                }
+            }),
+        ),
+        // Contributors tool
+        JjTool::new(
+            "contributors",
+            "Aggregate per-author commit counts and date ranges over a revset",
+            json!({
+                "type": "object",
+                "properties": {
+                    "revisions": {
+                        "type": "string",
+                        "description": "Revset to aggregate (defaults to ::@)"
+                    },
+                    "repoPath": {
+                        "type": "string",
+                        "description": "Optional path to repo root"
+                    },
+                    "cwd": {
+                        "type": "string",
+                        "description": "Optional working directory"
+                    }
+                }
+            }),
+        ),
+        // Repo stats tool
+        JjTool::new(
+            "repo-stats",
+            "Report repository statistics: heads, commits, bookmarks, workspaces, colocation and working-copy size",
+            json!({
+                "type": "object",
+                "properties": {
+                    "revisions": {
+                        "type": "string",
+                        "description": "Revset whose commits are counted (defaults to all())"
+                    },
+                    "repoPath": {
+                        "type": "string",
+                        "description": "Optional path to repo root"
+                    },
+                    "cwd": {
+                        "type": "string",
+                        "description": "Optional working directory"
+                    }
+                }
+            }),
+        ),
+        // Changes since tool
+        JjTool::new(
+            "changes-since",
+            "List commits added since a timestamp or an earlier operation",
+            json!({
+                "type": "object",
+                "properties": {
+                    "since": {
+                        "type": "string",
+                        "description": "Date or timestamp, e.g. \"2024-05-01\" or \"2 days ago\""
+                    },
+                    "operation": {
+                        "type": "string",
+                        "description": "Operation ID to compare the current view against"
+                    },
+                    "revisions": {
+                        "type": "string",
+                        "description": "Revset to search (defaults to all())"
+                    },
+                    "repoPath": {
+                        "type": "string",
+                        "description": "Optional path to repo root"
+                    },
+                    "cwd": {
+                        "type": "string",
+                        "description": "Optional working directory"
+                    }
+                }
+            }),
+        ),
+        // Anonymous heads tool
+        JjTool::new(
+            "anonymous-heads",
+            "List visible heads without bookmarks, with descriptions and ages",
+            json!({
+                "type": "object",
+                "properties": {
+                    "repoPath": {
+                        "type": "string",
+                        "description": "Optional path to repo root"
+                    },
+                    "cwd": {
+                        "type": "string",
+                        "description": "Optional working directory"
+                    }
+                }
+            }),
+        ),
+        // Compare tool
+        JjTool::new(
+            "compare",
+            "Report how many commits a revision is ahead of and behind a base revision",
+            json!({
+                "type": "object",
+                "properties": {
+                    "revision": {
+                        "type": "string",
+                        "description": "Revision or bookmark to compare (defaults to @)"
+                    },
+                    "base": {
+                        "type": "string",
+                        "description": "Revision or bookmark to compare against, e.g. main@origin"
+                    },
+                    "limit": {
+                        "type": "number",
+                        "description": "Maximum number of commit summaries per side (defaults to 20)"
+                    },
+                    "repoPath": {
+                        "type": "string",
+                        "description": "Optional path to repo root"
+                    },
+                    "cwd": {
+                        "type": "string",
+                        "description": "Optional working directory"
+                    }
+                }
+            }),
+        ),
+        // Megamerge tool
+        JjTool::new(
+            "megamerge",
+            "Create a new working-copy commit merging several revisions and report conflicts per source",
+            json!({
+                "type": "object",
+                "properties": {
+                    "revisions": {
+                        "type": "array",
+                        "items": {"type": "string"},
+                        "description": "Bookmarks or revisions to merge"
+                    },
+                    "message": {
+                        "type": "string",
+                        "description": "Optional description for the merge commit"
+                    },
+                    "repoPath": {
+                        "type": "string",
+                        "description": "Optional path to repo root"
+                    },
+                    "cwd": {
+                        "type": "string",
+                        "description": "Optional working directory"
+                    }
+                }
+            }),
+        ),
+        // Split by directory tool
+        JjTool::new(
+            "split-by-directory",
+            "Split a revision into one commit per top-level directory",
+            json!({
+                "type": "object",
+                "properties": {
+                    "revision": {
+                        "type": "string",
+                        "description": "Revision to split (defaults to @)"
+                    },
+                    "repoPath": {
+                        "type": "string",
+                        "description": "Optional path to repo root"
+                    },
+                    "cwd": {
+                        "type": "string",
+                        "description": "Optional working directory"
+                    }
+                }
+            }),
+        ),
+        // Has conflicts tool
+        JjTool::new(
+            "has-conflicts",
+            "Check whether @ or a revset contains conflicts, returning a boolean and the conflicted revisions",
+            json!({
+                "type": "object",
+                "properties": {
+                    "revisions": {
+                        "type": "string",
+                        "description": "Revset to check (defaults to @)"
+                    },
+                    "repoPath": {
+                        "type": "string",
+                        "description": "Optional path to repo root"
+                    },
+                    "cwd": {
+                        "type": "string",
+                        "description": "Optional working directory"
+                    }
+                }
+            }),
+        ),
+        // Large files tool
+        JjTool::new(
+            "large-files",
+            "Report files changed in a revision that exceed a size threshold",
+            json!({
+                "type": "object",
+                "properties": {
+                    "revision": {
+                        "type": "string",
+                        "description": "Revision to scan (defaults to @)"
+                    },
+                    "thresholdBytes": {
+                        "type": "number",
+                        "description": "Size threshold in bytes (defaults to 1048576)"
+                    },
+                    "repoPath": {
+                        "type": "string",
+                        "description": "Optional path to repo root"
+                    },
+                    "cwd": {
+                        "type": "string",
+                        "description": "Optional working directory"
+                    }
+                }
+            }),
+        ),
+        // Check immutable tool
+        JjTool::new(
+            "check-immutable",
+            "Report whether revisions are immutable (ancestors of immutable_heads()) before rewriting them",
+            json!({
+                "type": "object",
+                "properties": {
+                    "revisions": {
+                        "type": "array",
+                        "items": {"type": "string"},
+                        "description": "Revisions to check (defaults to [\"@\"])"
+                    },
+                    "repoPath": {
+                        "type": "string",
+                        "description": "Optional path to repo root"
+                    },
+                    "cwd": {
+                        "type": "string",
+                        "description": "Optional working directory"
+                    }
+                }
+            }),
+        ),
+        // Search descriptions tool
+        JjTool::new(
+            "search-descriptions",
+            "Search commit descriptions for a substring or regex over a revset",
+            json!({
+                "type": "object",
+                "properties": {
+                    "pattern": {
+                        "type": "string",
+                        "description": "Text or regular expression to search for"
+                    },
+                    "regex": {
+                        "type": "boolean",
+                        "description": "Treat the pattern as a regular expression"
+                    },
+                    "ignoreCase": {
+                        "type": "boolean",
+                        "description": "Match case-insensitively"
+                    },
+                    "revisions": {
+                        "type": "string",
+                        "description": "Revset to search (defaults to all())"
+                    },
+                    "limit": {
+                        "type": "number",
+                        "description": "Maximum number of matches, most recent first"
+                    },
+                    "repoPath": {
+                        "type": "string",
+                        "description": "Optional path to repo root"
+                    },
+                    "cwd": {
+                        "type": "string",
+                        "description": "Optional working directory"
+                    }
+                }
+            }),
+        ),
+        // Common ancestor tool
+        JjTool::new(
+            "common-ancestor",
+            "Find the merge base of two or more revisions",
+            json!({
+                "type": "object",
+                "properties": {
+                    "revisions": {
+                        "type": "array",
+                        "items": {"type": "string"},
+                        "description": "Revisions whose common ancestor to find (at least two)"
+                    },
+                    "repoPath": {
+                        "type": "string",
+                        "description": "Optional path to repo root"
+                    },
+                    "cwd": {
+                        "type": "string",
+                        "description": "Optional working directory"
+                    }
+                }
+            }),
+        ),
+        // Is-ancestor tool
+        JjTool::new(
+            "is-ancestor",
+            "Check whether one revision is an ancestor of another, with the number of commits between them",
+            json!({
+                "type": "object",
+                "properties": {
+                    "ancestor": {
+                        "type": "string",
+                        "description": "Candidate ancestor revision"
+                    },
+                    "descendant": {
+                        "type": "string",
+                        "description": "Candidate descendant revision (defaults to @)"
+                    },
+                    "repoPath": {
+                        "type": "string",
+                        "description": "Optional path to repo root"
+                    },
+                    "cwd": {
+                        "type": "string",
+                        "description": "Optional working directory"
+                    }
+                }
+            }),
+        ),
+        // Op log tool
+        JjTool::new(
+            "op-log",
+            "Show the operation log with paging and filtering by time range or description",
+            json!({
+                "type": "object",
+                "properties": {
+                    "limit": {
+                        "type": "number",
+                        "description": "Maximum number of operations to return (defaults to 50)"
+                    },
+                    "offset": {
+                        "type": "number",
+                        "description": "Number of matching operations to skip, newest first"
+                    },
+                    "since": {
+                        "type": "string",
+                        "description": "Only operations started at or after this UTC timestamp (e.g. 2024-03-01T00:00:00Z)"
+                    },
+                    "until": {
+                        "type": "string",
+                        "description": "Only operations started before this UTC timestamp"
+                    },
+                    "descriptionContains": {
+                        "type": "string",
+                        "description": "Only operations whose description contains this text"
+                    },
+                    "opDiff": {
+                        "type": "boolean",
+                        "description": "Include the commits and bookmarks each operation added, removed or moved"
+                    },
+                    "patch": {
+                        "type": "boolean",
+                        "description": "Also include the content diff of each changed commit (implies opDiff)"
+                    },
+                    "repoPath": {
+                        "type": "string",
+                        "description": "Optional path to repo root"
+                    },
+                    "cwd": {
+                        "type": "string",
+                        "description": "Optional working directory"
+                    }
+                }
+            }),
+        ),
+        // Undo tool
+        JjTool::new(
+            "undo",
+            "Undo the last operation, or revert a specific earlier operation by ID",
+            json!({
+                "type": "object",
+                "properties": {
+                    "operation": {
+                        "type": "string",
+                        "description": "Operation ID to undo (defaults to the most recent operation)"
+                    },
+                    "repoPath": {
+                        "type": "string",
+                        "description": "Optional path to repo root"
+                    },
+                    "cwd": {
+                        "type": "string",
+                        "description": "Optional working directory"
+                    }
+                }
+            }),
+        ),
+        // Snapshot tool
+        JjTool::new(
+            "snapshot",
+            "Snapshot the working copy so files written outside jj are recorded, and report the new working-copy state",
+            json!({
+                "type": "object",
+                "properties": {
+                    "repoPath": {
+                        "type": "string",
+                        "description": "Optional path to repo root"
+                    },
+                    "cwd": {
+                        "type": "string",
+                        "description": "Optional working directory"
+                    }
+                }
+            }),
+        ),
+        // Write file tool
+        JjTool::new(
+            "write-file",
+            "Write content to a file in the working copy and snapshot it, returning the working-copy diff stat",
+            json!({
+                "type": "object",
+                "properties": {
+                    "path": {
+                        "type": "string",
+                        "description": "File path relative to the repo root"
+                    },
+                    "content": {
+                        "type": "string",
+                        "description": "Content to write"
+                    },
+                    "append": {
+                        "type": "boolean",
+                        "description": "Append to the file instead of replacing it"
+                    },
+                    "repoPath": {
+                        "type": "string",
+                        "description": "Optional path to repo root"
+                    },
+                    "cwd": {
+                        "type": "string",
+                        "description": "Optional working directory"
+                    }
+                }
+            }),
+        ),
+        // Grep tool
+        JjTool::new(
+            "grep",
+            "Search file contents at a revision for a literal string or regex, with include/exclude globs",
+            json!({
+                "type": "object",
+                "properties": {
+                    "pattern": {
+                        "type": "string",
+                        "description": "Text or regular expression to search for"
+                    },
+                    "regex": {
+                        "type": "boolean",
+                        "description": "Treat the pattern as a regular expression"
+                    },
+                    "ignoreCase": {
+                        "type": "boolean",
+                        "description": "Match case-insensitively"
+                    },
+                    "revision": {
+                        "type": "string",
+                        "description": "Revision to search (defaults to @)"
+                    },
+                    "include": {
+                        "type": "array",
+                        "items": {"type": "string"},
+                        "description": "Only search paths matching these globs"
+                    },
+                    "exclude": {
+                        "type": "array",
+                        "items": {"type": "string"},
+                        "description": "Skip paths matching these globs"
+                    },
+                    "maxMatches": {
+                        "type": "number",
+                        "description": "Maximum number of matching lines to return (defaults to 200)"
+                    },
+                    "repoPath": {
+                        "type": "string",
+                        "description": "Optional path to repo root"
+                    },
+                    "cwd": {
+                        "type": "string",
+                        "description": "Optional working directory"
+                    }
+                }
+            }),
+        ),
+        // Apply patch tool
+        JjTool::new(
+            "apply-patch",
+            "Apply a unified diff to the working copy and snapshot the result",
+            json!({
+                "type": "object",
+                "properties": {
+                    "patch": {
+                        "type": "string",
+                        "description": "Unified diff to apply"
+                    },
+                    "method": {
+                        "type": "string",
+                        "enum": ["auto", "git", "native"],
+                        "description": "Use git apply (colocated repos only) or the built-in applier; auto picks git when colocated"
+                    },
+                    "repoPath": {
+                        "type": "string",
+                        "description": "Optional path to repo root"
+                    },
+                    "cwd": {
+                        "type": "string",
+                        "description": "Optional working directory"
+                    }
+                }
+            }),
+        ),
+        // Export patch tool
+        JjTool::new(
+            "export-patch",
+            "Export revisions as git-format patches (description plus git diff), oldest first",
+            json!({
+                "type": "object",
+                "properties": {
+                    "revisions": {
+                        "type": "string",
+                        "description": "Revset to export (defaults to @)"
+                    },
+                    "emailHeaders": {
+                        "type": "boolean",
+                        "description": "Include From/Date/Subject email headers (defaults to true)"
+                    },
+                    "repoPath": {
+                        "type": "string",
+                        "description": "Optional path to repo root"
+                    },
+                    "cwd": {
+                        "type": "string",
+                        "description": "Optional working directory"
+                    }
+                }
+            }),
+        ),
+        // Archive tool
+        JjTool::new(
+            "archive",
+            "Export the tree of a revision to a directory or .tar.gz without touching the working copy",
+            json!({
+                "type": "object",
+                "properties": {
+                    "revision": {
+                        "type": "string",
+                        "description": "Revision to export (defaults to @)"
+                    },
+                    "destination": {
+                        "type": "string",
+                        "description": "Target directory or .tar.gz path, outside the workspace"
+                    },
+                    "format": {
+                        "type": "string",
+                        "enum": ["directory", "tar.gz"],
+                        "description": "Output format (inferred from the destination when omitted)"
+                    },
+                    "repoPath": {
+                        "type": "string",
+                        "description": "Optional path to repo root"
+                    },
+                    "cwd": {
+                        "type": "string",
+                        "description": "Optional working directory"
+                    }
+                }
+            }),
+        ),
+        // Multi-repo tool
+        JjTool::new(
+            "multi-repo",
+            "Run status, log or diff stat across several repositories concurrently and return per-repo results",
+            json!({
+                "type": "object",
+                "properties": {
+                    "repos": {
+                        "type": "array",
+                        "items": {"type": "string"},
+                        "description": "Repository paths (defaults to the JJ_MCP_REPOS environment variable)"
+                    },
+                    "operation": {
+                        "type": "string",
+                        "enum": ["status", "log", "diff-stat"],
+                        "description": "Query to run in each repository (defaults to status)"
+                    },
+                    "limit": {
+                        "type": "number",
+                        "description": "Number of log entries per repository (defaults to 5)"
+                    }
+                }
+            }),
+        ),
+        // Doctor tool
+        JjTool::new(
+            "doctor",
+            "Check the jj installation, git colocation, filesystem monitor configuration and whether watchman is reachable",
+            json!({
+                "type": "object",
+                "properties": {
+                    "repoPath": {
+                        "type": "string",
+                        "description": "Optional path to repo root"
+                    },
+                    "cwd": {
+                        "type": "string",
+                        "description": "Optional working directory"
+                    }
+                }
+            }),
+        ),
+        // Git passthrough tool
+        JjTool::new(
+            "git-passthrough",
+            "Run an allowlisted read-only git subcommand (e.g. ls-remote, rev-parse, config --get) in a colocated repository",
+            json!({
+                "type": "object",
+                "properties": {
+                    "args": {
+                        "type": "array",
+                        "items": {"type": "string"},
+                        "description": "git arguments, starting with the subcommand"
+                    },
+                    "repoPath": {
+                        "type": "string",
+                        "description": "Optional path to repo root"
+                    },
+                    "cwd": {
+                        "type": "string",
+                        "description": "Optional working directory"
+                    }
+                }
+            }),
+        ),
+        // Sandbox promote tool
+        JjTool::new(
+            "sandbox-promote",
+            "Replay the mutating calls made with `sandbox: true` on the real repository and remove the sandbox",
+            json!({
+                "type": "object",
+                "properties": {
+                    "dryRun": {
+                        "type": "boolean",
+                        "description": "List the pending calls without replaying them"
+                    },
+                    "repoPath": {
+                        "type": "string",
+                        "description": "Optional path to repo root"
+                    },
+                    "cwd": {
+                        "type": "string",
+                        "description": "Optional working directory"
+                    }
+                }
+            }),
+        ),
+        // Sandbox discard tool
+        JjTool::new(
+            "sandbox-discard",
+            "Throw away the sandbox copy of a repository and the calls made in it",
+            json!({
+                "type": "object",
+                "properties": {
+                    "repoPath": {
+                        "type": "string",
+                        "description": "Optional path to repo root"
+                    },
+                    "cwd": {
+                        "type": "string",
+                        "description": "Optional working directory"
+                    }
+                }
+            }),
+        ),
+        // Abandon tool
+        JjTool::new(
+            "abandon",
+            "Abandon revisions, rebasing their descendants onto their parents, and report the rewritten descendants",
+            json!({
+                "type": "object",
+                "properties": {
+                    "revisions": {
+                        "type": "array",
+                        "items": {"type": "string"},
+                        "description": "Revisions to abandon (default: @)"
+                    },
+                    "retainBookmarks": {
+                        "type": "boolean",
+                        "description": "Move bookmarks on abandoned commits to their parents instead of deleting them"
+                    },
+                    "restoreDescendants": {
+                        "type": "boolean",
+                        "description": "Keep the file contents of descendants unchanged instead of dropping the abandoned changes from them"
+                    },
+                    "repoPath": {
+                        "type": "string",
+                        "description": "Optional path to repo root"
+                    },
+                    "cwd": {
+                        "type": "string",
+                        "description": "Optional working directory"
+                    }
+                }
+            }),
+        ),
+        // Restore tool
+        JjTool::new(
+            "restore",
+            "Restore files from another revision: discard working-copy edits, or copy file contents from one revision into another",
+            json!({
+                "type": "object",
+                "properties": {
+                    "from": {
+                        "type": "string",
+                        "description": "Revision to restore from (default: the parents of the destination)"
+                    },
+                    "into": {
+                        "type": "string",
+                        "description": "Revision to restore into (default: @); `to` is accepted as an alias"
+                    },
+                    "paths": {
+                        "type": "array",
+                        "items": {"type": "string"},
+                        "description": "Paths to restore (default: all paths)"
+                    },
+                    "restoreDescendants": {
+                        "type": "boolean",
+                        "description": "Keep the file contents of descendants unchanged instead of letting them inherit the restored content"
+                    },
+                    "repoPath": {
+                        "type": "string",
+                        "description": "Optional path to repo root"
+                    },
+                    "cwd": {
+                        "type": "string",
+                        "description": "Optional working directory"
+                    }
+                }
+            }),
+        ),
+        // Squash tool
+        JjTool::new(
+            "squash",
+            "Move changes from one revision into another (default: @ into its parent), with control over the resulting description",
+            json!({
+                "type": "object",
+                "properties": {
+                    "from": {
+                        "type": "string",
+                        "description": "Revision to move changes out of (default: @)"
+                    },
+                    "into": {
+                        "type": "string",
+                        "description": "Revision to move changes into (default: the parent of from); `to` is accepted as an alias"
+                    },
+                    "message": {
+                        "type": "string",
+                        "description": "Description for the destination"
+                    },
+                    "useDestinationMessage": {
+                        "type": "boolean",
+                        "description": "Keep the destination's description and drop the source's"
+                    },
+                    "keepEmptied": {
+                        "type": "boolean",
+                        "description": "Keep the source revision even if it becomes empty"
+                    },
+                    "paths": {
+                        "type": "array",
+                        "items": {"type": "string"},
+                        "description": "Only move changes to these paths"
+                    },
+                    "repoPath": {
+                        "type": "string",
+                        "description": "Optional path to repo root"
+                    },
+                    "cwd": {
+                        "type": "string",
+                        "description": "Optional working directory"
+                    }
+                }
+            }),
+        ),
+        // Annotate tool
+        JjTool::new(
+            "annotate",
+            "Show the change that last modified each line of a file, optionally as of a specific revision",
+            json!({
+                "type": "object",
+                "properties": {
+                    "path": {
+                        "type": "string",
+                        "description": "File to annotate"
+                    },
+                    "revision": {
+                        "type": "string",
+                        "description": "Revision to annotate the file at (default: @)"
+                    },
+                    "template": {
+                        "type": "string",
+                        "description": "Template for each line, e.g. 'commit.change_id().short() ++ \" \" ++ content'"
+                    },
+                    "startLine": {
+                        "type": "number",
+                        "description": "First line to return, counting from 1 (default: 1)"
+                    },
+                    "endLine": {
+                        "type": "number",
+                        "description": "Last line to return, inclusive (default: the end of the file)"
+                    },
+                    "repoPath": {
+                        "type": "string",
+                        "description": "Optional path to repo root"
+                    },
+                    "cwd": {
+                        "type": "string",
+                        "description": "Optional working directory"
+                    }
+                }
+            }),
+        ),
+        // Bookmark list tool
+        JjTool::new(
+            "bookmark-list",
+            "List bookmarks as structured JSON, filtered by name pattern, target revisions, tracking state or conflicts",
+            json!({
+                "type": "object",
+                "properties": {
+                    "names": {
+                        "type": "array",
+                        "items": {"type": "string"},
+                        "description": "Bookmark name patterns, e.g. \"main\" or \"glob:feature-*\""
+                    },
+                    "revisions": {
+                        "type": "string",
+                        "description": "Only bookmarks pointing at these revisions"
+                    },
+                    "tracked": {
+                        "type": "boolean",
+                        "description": "Only tracked remote bookmarks (and their local counterparts)"
+                    },
+                    "conflicted": {
+                        "type": "boolean",
+                        "description": "Only conflicted bookmarks"
+                    },
+                    "allRemotes": {
+                        "type": "boolean",
+                        "description": "Include remote bookmarks, tracked or not"
+                    },
+                    "remote": {
+                        "type": "string",
+                        "description": "Include remote bookmarks of this remote"
+                    },
+                    "repoPath": {
+                        "type": "string",
+                        "description": "Optional path to repo root"
+                    },
+                    "cwd": {
+                        "type": "string",
+                        "description": "Optional working directory"
+                    }
+                }
+            }),
+        ),
+        // Git push tool
+        JjTool::new(
+            "git-push",
+            "Push bookmarks to a git remote; with dryRun, return the bookmark updates that would be pushed as structured JSON",
+            json!({
+                "type": "object",
+                "properties": {
+                    "remote": {
+                        "type": "string",
+                        "description": "Remote to push to (default: git.push setting or origin)"
+                    },
+                    "bookmarks": {
+                        "type": "array",
+                        "items": {"type": "string"},
+                        "description": "Bookmarks to push (may be patterns such as \"glob:feature-*\")"
+                    },
+                    "changes": {
+                        "type": "array",
+                        "items": {"type": "string"},
+                        "description": "Revisions to push under generated bookmark names (push-<change id>)"
+                    },
+                    "all": {
+                        "type": "boolean",
+                        "description": "Push all bookmarks, including new ones"
+                    },
+                    "tracked": {
+                        "type": "boolean",
+                        "description": "Push all tracked bookmarks"
+                    },
+                    "deleted": {
+                        "type": "boolean",
+                        "description": "Push all deleted bookmarks"
+                    },
+                    "allowNew": {
+                        "type": "boolean",
+                        "description": "Allow pushing bookmarks that do not exist on the remote yet"
+                    },
+                    "dryRun": {
+                        "type": "boolean",
+                        "description": "Only report what would be pushed"
+                    },
+                    "repoPath": {
+                        "type": "string",
+                        "description": "Optional path to repo root"
+                    },
+                    "cwd": {
+                        "type": "string",
+                        "description": "Optional working directory"
+                    }
+                }
+            }),
+        ),
+        // Git fetch tool
+        JjTool::new(
+            "git-fetch",
+            "Fetch from git remotes, reporting the result of each remote separately",
+            json!({
+                "type": "object",
+                "properties": {
+                    "remotes": {
+                        "type": "array",
+                        "items": {"type": "string"},
+                        "description": "Remotes to fetch from (default: git.fetch setting or origin)"
+                    },
+                    "allRemotes": {
+                        "type": "boolean",
+                        "description": "Fetch from every configured remote"
+                    },
+                    "bookmarks": {
+                        "type": "array",
+                        "items": {"type": "string"},
+                        "description": "Only fetch these bookmarks (may be patterns such as \"glob:release-*\")"
+                    },
+                    "repoPath": {
+                        "type": "string",
+                        "description": "Optional path to repo root"
+                    },
+                    "cwd": {
+                        "type": "string",
+                        "description": "Optional working directory"
+                    }
+                }
+            }),
+        ),
+        // Config list tool
+        JjTool::new(
+            "config-list",
+            "List configuration values with where each comes from (default, user or repo file, environment, command line)",
+            json!({
+                "type": "object",
+                "properties": {
+                    "name": {
+                        "type": "string",
+                        "description": "Only values under this name, e.g. \"ui\" or \"user.email\""
+                    },
+                    "includeDefaults": {
+                        "type": "boolean",
+                        "description": "Include jj's built-in defaults"
+                    },
+                    "includeOverridden": {
+                        "type": "boolean",
+                        "description": "Include values shadowed by a higher-precedence source"
+                    },
+                    "repoPath": {
+                        "type": "string",
+                        "description": "Optional path to repo root"
+                    },
+                    "cwd": {
+                        "type": "string",
+                        "description": "Optional working directory"
+                    }
+                }
+            }),
+        ),
+        // Config path tool
+        JjTool::new(
+            "config-path",
+            "Report the path of the user or repository configuration file",
+            json!({
+                "type": "object",
+                "properties": {
+                    "level": {
+                        "type": "string",
+                        "enum": ["user", "repo"],
+                        "description": "Which configuration file (default: user)"
+                    },
+                    "repoPath": {
+                        "type": "string",
+                        "description": "Optional path to repo root"
+                    },
+                    "cwd": {
+                        "type": "string",
+                        "description": "Optional working directory"
+                    }
+                }
+            }),
+        ),
+        // Workspace info tool
+        JjTool::new(
+            "workspace-info",
+            "Report the workspace name, workspace root, repository location and whether the working copy is stale",
+            json!({
+                "type": "object",
+                "properties": {
+                    "repoPath": {
+                        "type": "string",
+                        "description": "Optional path to repo root"
+                    },
+                    "cwd": {
+                        "type": "string",
+                        "description": "Optional working directory"
+                    }
+                }
+            }),
+        ),
+        // Evolog tool
+        JjTool::new(
+            "evolog",
+            "Show the previous versions of a change, optionally with what each rewrite altered",
+            json!({
+                "type": "object",
+                "properties": {
+                    "revision": {
+                        "type": "string",
+                        "description": "Change to show the evolution of (default: @)"
+                    },
+                    "limit": {
+                        "type": "number",
+                        "description": "Maximum number of versions to show"
+                    },
+                    "patch": {
+                        "type": "boolean",
+                        "description": "Show the diff each rewrite made compared to the previous version"
+                    },
+                    "template": {
+                        "type": "string",
+                        "description": "Template for each version"
+                    },
+                    "repoPath": {
+                        "type": "string",
+                        "description": "Optional path to repo root"
+                    },
+                    "cwd": {
+                        "type": "string",
+                        "description": "Optional working directory"
+                    }
+                }
+            }),
+        ),
+        // Describe auto tool
+        JjTool::new(
+            "describe-auto",
+            "Ask the client's model (via MCP sampling) to write a commit message from a revision's diff and apply it with jj describe",
+            json!({
+                "type": "object",
+                "properties": {
+                    "revision": {
+                        "type": "string",
+                        "description": "Revision to describe (default: @)"
+                    },
+                    "instructions": {
+                        "type": "string",
+                        "description": "Extra guidance for the message, e.g. a ticket number or style rules"
+                    },
+                    "dryRun": {
+                        "type": "boolean",
+                        "description": "Only return the proposed message"
+                    },
+                    "repoPath": {
+                        "type": "string",
+                        "description": "Optional path to repo root"
+                    },
+                    "cwd": {
+                        "type": "string",
+                        "description": "Optional working directory"
+                    }
+                }
+            }),
+        ),
+        // Complete tool
+        JjTool::new(
+            "complete",
+            "Suggest values for a revision, bookmark or remote argument, for clients without MCP completion support",
+            json!({
+                "type": "object",
+                "properties": {
+                    "argument": {
+                        "type": "string",
+                        "description": "Argument name, e.g. \"revision\", \"bookmark\" or \"remote\""
+                    },
+                    "value": {
+                        "type": "string",
+                        "description": "Prefix typed so far"
+                    },
+                    "repoPath": {
+                        "type": "string",
+                        "description": "Optional path to repo root"
+                    },
+                    "cwd": {
+                        "type": "string",
+                        "description": "Optional working directory"
+                    }
+                }
+            }),
+        ),
+        // Read output tool
+        JjTool::new(
+            "read-output",
+            "Read part of a large tool output that was returned as a resource link",
+            json!({
+                "type": "object",
+                "properties": {
+                    "uri": {
+                        "type": "string",
+                        "description": "URI of the stored output, as returned in the resource link"
+                    },
+                    "offset": {
+                        "type": "number",
+                        "description": "Byte offset to start reading at (default: 0)"
+                    },
+                    "length": {
+                        "type": "number",
+                        "description": "Number of bytes to read (default: the output size limit)"
+                    }
+                }
+            }),
+        ),
+        // Pipeline tool
+        JjTool::new(
+            "pipeline",
+            "Run tool calls in order; step arguments can reference earlier results as {{stepId.field}} (output, json, changeId, commitId or a named capture)",
+            json!({
+                "type": "object",
+                "properties": {
+                    "steps": {
+                        "type": "array",
+                        "items": {
+                            "type": "object",
+                            "properties": {
+                                "id": {
+                                    "type": "string",
+                                    "description": "Name later steps use to reference this step (defaults to its index)"
+                                },
+                                "tool": {
+                                    "type": "string",
+                                    "description": "Tool to call"
+                                },
+                                "arguments": {
+                                    "type": "object",
+                                    "description": "Tool arguments; strings may contain {{stepId.field}} references"
+                                },
+                                "capture": {
+                                    "type": "object",
+                                    "additionalProperties": {"type": "string"},
+                                    "description": "Named regular expressions matched against the step's output (group 1 or the whole match)"
+                                }
+                            },
+                            "required": ["tool"]
+                        },
+                        "description": "Steps to run in order"
+                    },
+                    "continueOnError": {
+                        "type": "boolean",
+                        "description": "Keep running later steps after one fails (default: stop)"
+                    },
+                    "repoPath": {
+                        "type": "string",
+                        "description": "Default repoPath for steps that don't set one"
+                    },
+                    "cwd": {
+                        "type": "string",
+                        "description": "Default cwd for steps that don't set one"
+                    }
+                },
+                "required": ["steps"]
+            }),
+        ),
+        // Describe tool
+        JjTool::new(
+            "describe",
+            "Set the description of a revision, optionally signing the rewritten commit",
+            json!({
+                "type": "object",
+                "properties": {
+                    "revision": {
+                        "type": "string",
+                        "description": "Revision to describe (default: @)"
+                    },
+                    "message": {
+                        "type": "string",
+                        "description": "New description"
+                    },
+                    "messageFile": {
+                        "type": "string",
+                        "description": "File to read the description from, relative to cwd (instead of message)"
+                    },
+                    "sign": {
+                        "type": "boolean",
+                        "description": "Sign the rewritten commit (true) or leave it unsigned (false); defaults to jj's signing configuration"
+                    },
+                    "signingBackend": {
+                        "type": "string",
+                        "enum": ["gpg", "gpgsm", "ssh", "none"],
+                        "description": "Signing backend to use instead of the configured one"
+                    },
+                    "signingKey": {
+                        "type": "string",
+                        "description": "Signing key to use instead of the configured one"
+                    },
+                    "authorName": {
+                        "type": "string",
+                        "description": "Set the revision's author name (default: user.name when only the email is given)"
+                    },
+                    "authorEmail": {
+                        "type": "string",
+                        "description": "Set the revision's author email (default: user.email when only the name is given)"
+                    },
+                    "repoPath": {
+                        "type": "string",
+                        "description": "Optional path to repo root"
+                    },
+                    "cwd": {
+                        "type": "string",
+                        "description": "Optional working directory"
+                    }
+                }
+            }),
+        ),
+        // Sign tool
+        JjTool::new(
+            "sign",
+            "Cryptographically sign commits with the configured or given key",
+            json!({
+                "type": "object",
+                "properties": {
+                    "revisions": {
+                        "type": "array",
+                        "items": {"type": "string"},
+                        "description": "Revisions to sign (default: @)"
+                    },
+                    "signingBackend": {
+                        "type": "string",
+                        "enum": ["gpg", "gpgsm", "ssh", "none"],
+                        "description": "Signing backend to use instead of the configured one"
+                    },
+                    "signingKey": {
+                        "type": "string",
+                        "description": "Signing key to use instead of the configured one"
+                    },
+                    "repoPath": {
+                        "type": "string",
+                        "description": "Optional path to repo root"
+                    },
+                    "cwd": {
+                        "type": "string",
+                        "description": "Optional working directory"
+                    }
+                }
+            }),
+        ),
+        // Discover repos tool
+        JjTool::new(
+            "discover-repos",
+            "Scan a directory tree for jj workspaces and report their roots, git colocation and current bookmarks",
+            json!({
+                "type": "object",
+                "properties": {
+                    "path": {
+                        "type": "string",
+                        "description": "Directory to scan (default: the current directory)"
+                    },
+                    "maxDepth": {
+                        "type": "number",
+                        "description": "How many directory levels to descend (default: 3)"
+                    }
+                }
+            }),
+        ),
+        // Remote list tool
+        JjTool::new(
+            "remote-list",
+            "List git remotes with their URLs, optionally checking that each answers and reporting its default branch",
+            json!({
+                "type": "object",
+                "properties": {
+                    "check": {
+                        "type": "boolean",
+                        "description": "Run git ls-remote against each remote to check connectivity and find its default branch"
+                    },
+                    "repoPath": {
+                        "type": "string",
+                        "description": "Optional path to repo root"
+                    },
+                    "cwd": {
+                        "type": "string",
+                        "description": "Optional working directory"
+                    }
+                }
+            }),
+        ),
+        // Stash tool
+        JjTool::new(
+            "stash",
+            "Move the working-copy changes onto a new bookmarked child of trunk and leave a clean working copy, like git stash",
+            json!({
+                "type": "object",
+                "properties": {
+                    "name": {
+                        "type": "string",
+                        "description": "Stash name; the bookmark is stash/<name> (default: the stash commit's change ID)"
+                    },
+                    "base": {
+                        "type": "string",
+                        "description": "Revision to put the stash on (default: trunk())"
+                    },
+                    "message": {
+                        "type": "string",
+                        "description": "Description for the stash commit (prefixed with \"stash: \")"
+                    },
+                    "repoPath": {
+                        "type": "string",
+                        "description": "Optional path to repo root"
+                    },
+                    "cwd": {
+                        "type": "string",
+                        "description": "Optional working directory"
+                    }
+                }
+            }),
+        ),
+        // Unstash tool
+        JjTool::new(
+            "unstash",
+            "Apply stashed changes to the working copy and drop the stash, like git stash pop",
+            json!({
+                "type": "object",
+                "properties": {
+                    "name": {
+                        "type": "string",
+                        "description": "Stash to apply (default: the most recent one)"
+                    },
+                    "repoPath": {
+                        "type": "string",
+                        "description": "Optional path to repo root"
+                    },
+                    "cwd": {
+                        "type": "string",
+                        "description": "Optional working directory"
+                    }
+                }
+            }),
+        ),
+        // Graph export tool
+        JjTool::new(
+            "graph-export",
+            "Export a revset's commit graph as Graphviz DOT or a mermaid flowchart, with change IDs, bookmarks and descriptions on the nodes and an edge to each parent",
+            json!({
+                "type": "object",
+                "properties": {
+                    "revisions": {
+                        "type": "string",
+                        "description": "Revset to export (default: the revisions jj log shows by default)"
+                    },
+                    "limit": {
+                        "type": "number",
+                        "description": "Maximum number of commits (default: 100)"
+                    },
+                    "format": {
+                        "type": "string",
+                        "enum": ["dot", "mermaid"],
+                        "description": "Output format (default: dot)"
+                    },
+                    "repoPath": {
+                        "type": "string",
+                        "description": "Optional path to repo root"
+                    },
+                    "cwd": {
+                        "type": "string",
+                        "description": "Optional working directory"
+                    }
+                }
+            }),
+        ),
+        // Author stats tool
+        JjTool::new(
+            "author-stats",
+            "Report insertions, deletions and files changed per author across a revset, for sprint and release reporting",
+            json!({
+                "type": "object",
+                "properties": {
+                    "revisions": {
+                        "type": "string",
+                        "description": "Revset to report on (default: ::@)"
+                    },
+                    "repoPath": {
+                        "type": "string",
+                        "description": "Optional path to repo root"
+                    },
+                    "cwd": {
+                        "type": "string",
+                        "description": "Optional working directory"
+                    }
+                }
+            }),
+        ),
+        // Reconcile tool
+        JjTool::new(
+            "reconcile",
+            "Bring the workspace up to date after concurrent operations: update a stale working copy and merge divergent operation heads. Call this when a tool reports a concurrent operation, then re-read status and log",
+            json!({
+                "type": "object",
+                "properties": {
+                    "repoPath": {
+                        "type": "string",
+                        "description": "Optional path to repo root"
+                    },
+                    "cwd": {
+                        "type": "string",
+                        "description": "Optional working directory"
+                    }
+                }
+            }),
+        ),
+        // Init tool
+        JjTool::new(
+            "init",
+            "Create a new jj repository, backed by git (optionally colocated or on an existing git repo) or by jj's native backend",
+            json!({
+                "type": "object",
+                "properties": {
+                    "destination": {
+                        "type": "string",
+                        "description": "Directory to create the repository in, relative to cwd (default: cwd)"
+                    },
+                    "backend": {
+                        "type": "string",
+                        "enum": ["git", "native"],
+                        "description": "Storage backend (default: git)"
+                    },
+                    "colocate": {
+                        "type": "boolean",
+                        "description": "Keep a .git directory alongside .jj so git tools work too (git backend only)"
+                    },
+                    "gitRepo": {
+                        "type": "string",
+                        "description": "Existing git repository to back the new repository (git backend only)"
+                    },
+                    "cwd": {
+                        "type": "string",
+                        "description": "Directory destination is relative to"
+                    }
+                }
+            }),
+        ),
+        // Deepen tool
+        JjTool::new(
+            "deepen",
+            "Fetch more history into a shallow clone made with git-clone's depth, or all of it",
+            json!({
+                "type": "object",
+                "properties": {
+                    "remote": {
+                        "type": "string",
+                        "description": "Remote to fetch from (default: origin)"
+                    },
+                    "depth": {
+                        "type": "number",
+                        "description": "Commits of extra history to fetch (default: the full history)"
+                    },
+                    "repoPath": {
+                        "type": "string",
+                        "description": "Optional path to repo root"
+                    },
+                    "cwd": {
+                        "type": "string",
+                        "description": "Optional working directory"
+                    }
+                }
+            }),
+        ),
+        // Submodules tool
+        JjTool::new(
+            "submodules",
+            "List the git submodules of a revision with their URLs and pinned commits; jj does not manage these directories",
+            json!({
+                "type": "object",
+                "properties": {
+                    "revision": {
+                        "type": "string",
+                        "description": "Revision to read (default: @)"
+                    },
+                    "repoPath": {
+                        "type": "string",
+                        "description": "Optional path to repo root"
+                    },
+                    "cwd": {
+                        "type": "string",
+                        "description": "Optional working directory"
+                    }
+                }
+            }),
+        ),
+        // Append commit tool
+        JjTool::new(
+            "append-commit",
+            "Add a commit on top of a bookmark, optionally with file contents, and move the bookmark to it without touching the working copy",
+            json!({
+                "type": "object",
+                "properties": {
+                    "bookmark": {
+                        "type": "string",
+                        "description": "Bookmark to build on and advance"
+                    },
+                    "message": {
+                        "type": "string",
+                        "description": "Description of the new commit"
+                    },
+                    "files": {
+                        "type": "object",
+                        "additionalProperties": {"type": "string"},
+                        "description": "File contents to commit, keyed by repo-relative path"
+                    },
+                    "moveBookmark": {
+                        "type": "boolean",
+                        "description": "Move the bookmark to the new commit (default: true)"
+                    },
+                    "repoPath": {
+                        "type": "string",
+                        "description": "Optional path to repo root"
+                    },
+                    "cwd": {
+                        "type": "string",
+                        "description": "Optional working directory"
+                    }
+                }
+            }),
+        ),
+        // File show tool
+        JjTool::new(
+            "file-show",
+            "Show a file's content at a revision; binary files are described by size and type, with their bytes as base64 on request",
+            json!({
+                "type": "object",
+                "properties": {
+                    "path": {
+                        "type": "string",
+                        "description": "File to show, relative to cwd"
+                    },
+                    "revision": {
+                        "type": "string",
+                        "description": "Revision to read the file at (default: @)"
+                    },
+                    "allowBinary": {
+                        "type": "boolean",
+                        "description": "Include the content of binary files as base64 (images are also returned as image content)"
+                    },
+                    "repoPath": {
+                        "type": "string",
+                        "description": "Optional path to repo root"
+                    },
+                    "cwd": {
+                        "type": "string",
+                        "description": "Optional working directory"
+                    }
+                }
+            }),
+        ),
+    ]
+}
@@ -0,0 +1,141 @@
+//! Read operations served by `jj-lib` directly instead of spawning the jj CLI.
+//!
+//! Only compiled with the `jj-lib` feature. The backend reads the repository as of
+//! its last operation and never snapshots the working copy, so callers fall back to
+//! the CLI for anything involving `@` or whenever the backend reports an error. It
+//! also doesn't read the user's jj config, so revsets using `mine()` are refused
+//! rather than silently matching nothing.
+//!
+//! Only revision lookups and file reads go through the backend; `log`, `show` and
+//! the other tools still render their output with the CLI.
+
+use std::io::Read as _;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::Result;
+use jj_lib::backend::TreeValue;
+use jj_lib::config::StackedConfig;
+use jj_lib::object_id::ObjectId;
+use jj_lib::repo::{ReadonlyRepo, Repo, StoreFactories};
+use jj_lib::repo_path::{RepoPathBuf, RepoPathUiConverter};
+use jj_lib::revset::{
+    DefaultSymbolResolver, RevsetAliasesMap, RevsetDiagnostics, RevsetExtensions,
+    RevsetParseContext, RevsetWorkspaceContext, SymbolResolverExtension,
+};
+use jj_lib::settings::UserSettings;
+use jj_lib::workspace::{Workspace, default_working_copy_factories};
+
+/// Summary of a commit returned by [`evaluate_revset`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct CommitSummary {
+    pub commit_id: String,
+    pub change_id: String,
+    pub author_name: String,
+    pub author_email: String,
+    pub description: String,
+}
+
+/// Walk up from `start` to the nearest directory containing a `.jj` directory
+pub fn find_workspace_root(start: &Path) -> Option<PathBuf> {
+    start
+        .ancestors()
+        .find(|dir| dir.join(".jj").is_dir())
+        .map(Path::to_path_buf)
+}
+
+/// Whether the backend can answer a revset without a working-copy snapshot or user config
+pub fn supports_revset(revset: &str) -> bool {
+    static MINE: std::sync::LazyLock<regex::Regex> =
+        std::sync::LazyLock::new(|| regex::Regex::new(r"\bmine\s*\(").unwrap());
+    !revset.contains('@') && !MINE.is_match(revset)
+}
+
+fn load(root: &Path) -> Result<(Workspace, Arc<ReadonlyRepo>)> {
+    let settings = UserSettings::from_config(StackedConfig::with_defaults())?;
+    let workspace = Workspace::load(
+        &settings,
+        root,
+        &StoreFactories::default(),
+        &default_working_copy_factories(),
+    )?;
+    let repo = workspace.repo_loader().load_at_head()?;
+    Ok((workspace, repo))
+}
+
+/// Evaluate a revset, newest commits first
+pub fn evaluate_revset(root: &Path, revset: &str) -> Result<Vec<CommitSummary>> {
+    if !supports_revset(revset) {
+        return Err(anyhow::anyhow!(
+            "Error: the jj-lib backend can't evaluate {} (it mentions @ or mine())",
+            revset
+        ));
+    }
+    let (workspace, repo) = load(root)?;
+
+    let path_converter = RepoPathUiConverter::Fs {
+        cwd: root.to_path_buf(),
+        base: root.to_path_buf(),
+    };
+    let aliases_map = RevsetAliasesMap::new();
+    let extensions = RevsetExtensions::new();
+    let context = RevsetParseContext {
+        aliases_map: &aliases_map,
+        local_variables: Default::default(),
+        user_email: "",
+        date_pattern_context: chrono::Local::now().into(),
+        extensions: &extensions,
+        workspace: Some(RevsetWorkspaceContext {
+            path_converter: &path_converter,
+            workspace_name: workspace.workspace_name(),
+        }),
+    };
+
+    let expression = jj_lib::revset::parse(&mut RevsetDiagnostics::new(), revset, &context)
+        .map_err(|e| anyhow::anyhow!("Error: {}", e))?;
+    let no_extensions: &[Box<dyn SymbolResolverExtension>] = &[];
+    let symbol_resolver = DefaultSymbolResolver::new(repo.as_ref(), no_extensions);
+    let resolved = expression
+        .resolve_user_expression(repo.as_ref(), &symbol_resolver)
+        .map_err(|e| anyhow::anyhow!("Error: {}", e))?;
+    let evaluated = resolved.evaluate(repo.as_ref())?;
+
+    let mut commits = Vec::new();
+    for commit_id in evaluated.iter() {
+        let commit = repo.store().get_commit(&commit_id?)?;
+        commits.push(CommitSummary {
+            commit_id: commit.id().hex(),
+            change_id: commit.change_id().reverse_hex(),
+            author_name: commit.author().name.clone(),
+            author_email: commit.author().email.clone(),
+            description: commit.description().to_string(),
+        });
+    }
+    Ok(commits)
+}
+
+/// Read a file's content at a commit; `path` is relative to the workspace root
+pub fn read_file(root: &Path, commit_id: &str, path: &str) -> Result<Vec<u8>> {
+    let (_, repo) = load(root)?;
+    let id = jj_lib::backend::CommitId::try_from_hex(commit_id)
+        .map_err(|_| anyhow::anyhow!("Error: not a full commit ID: {}", commit_id))?;
+    let commit = repo.store().get_commit(&id)?;
+    let repo_path = RepoPathBuf::from_relative_path(path.replace('\\', "/"))?;
+
+    let value = commit.tree()?.path_value(&repo_path)?;
+    match value.as_resolved() {
+        Some(Some(TreeValue::File { id, .. })) => {
+            let mut content = Vec::new();
+            repo.store()
+                .read_file(&repo_path, id)?
+                .read_to_end(&mut content)?;
+            Ok(content)
+        }
+        Some(None) => Err(anyhow::anyhow!("Error: no such path: {}", path)),
+        _ => Err(anyhow::anyhow!(
+            "Error: {} is not a regular file at {}",
+            path,
+            commit_id
+        )),
+    }
+}
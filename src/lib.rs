@@ -5,18 +5,37 @@
 
 use anyhow::Result;
 use mcp_sdk::tools::Tool;
-pub use mcp_sdk::types::{CallToolResponse, ServerCapabilities, ToolResponseContent};
+pub use mcp_sdk::types::{
+    CallToolResponse, ResourceContents, ServerCapabilities, ToolResponseContent,
+};
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 
+pub mod catalog;
+#[cfg(feature = "jj-lib")]
+pub mod jj_lib_backend;
+#[cfg(feature = "testing")]
+pub mod testing;
+
+pub use catalog::{all_tools, export_tool_catalog, tool_catalog};
+
+/// The jj binary run unless `--jj-bin` names another
 const JJ_COMMAND: &str = "jj";
 
+/// The jj binary to run
+fn jj_command() -> &'static str {
+    server_config().jj_bin.as_deref().unwrap_or(JJ_COMMAND)
+}
+
 /// Parameters for the status tool
 #[derive(Debug, Serialize, Deserialize, Default)]
 pub struct StatusParams {
     #[serde(rename = "repoPath")]
     pub repo_path: Option<String>,
     pub cwd: Option<String>,
+    /// Summarize the working copy of every workspace in the repository
+    #[serde(rename = "allWorkspaces")]
+    pub all_workspaces: Option<bool>,
 }
 
 /// Parameters for the rebase tool
@@ -33,6 +52,19 @@ pub struct RebaseParams {
 #[derive(Debug, Serialize, Deserialize, Default)]
 pub struct CommitParams {
     pub message: Option<String>,
+    #[serde(rename = "messageFile")]
+    pub message_file: Option<String>,
+    #[serde(rename = "maxFileSize")]
+    pub max_file_size: Option<u64>,
+    pub sign: Option<bool>,
+    #[serde(rename = "signingBackend")]
+    pub signing_backend: Option<String>,
+    #[serde(rename = "signingKey")]
+    pub signing_key: Option<String>,
+    #[serde(rename = "authorName")]
+    pub author_name: Option<String>,
+    #[serde(rename = "authorEmail")]
+    pub author_email: Option<String>,
     #[serde(rename = "repoPath")]
     pub repo_path: Option<String>,
     pub cwd: Option<String>,
@@ -42,6 +74,10 @@ pub struct CommitParams {
 #[derive(Debug, Serialize, Deserialize, Default)]
 pub struct NewParams {
     pub parents: Option<String>,
+    #[serde(rename = "authorName")]
+    pub author_name: Option<String>,
+    #[serde(rename = "authorEmail")]
+    pub author_email: Option<String>,
     #[serde(rename = "repoPath")]
     pub repo_path: Option<String>,
     pub cwd: Option<String>,
@@ -56,6 +92,22 @@ pub struct LogParams {
     pub limit: Option<u32>,
     pub template: Option<String>,
     pub revisions: Option<String>,
+    /// `text` (default), or `mermaid` or `dot` for the commit graph
+    pub format: Option<String>,
+    /// Only commits whose author name or email contains this
+    pub author: Option<String>,
+    /// Only commits committed after this date, e.g. `2024-01-31` or `2 weeks ago`
+    pub since: Option<String>,
+    /// Only commits committed before this date
+    pub until: Option<String>,
+    /// Only commits touching these files or directories, as filesets
+    pub paths: Option<Vec<String>>,
+    /// Largest response wanted, in bytes; the log falls back to one line per commit above it
+    #[serde(rename = "maxBytes")]
+    pub max_bytes: Option<usize>,
+    /// Largest response wanted, in tokens of about [`BYTES_PER_TOKEN`] bytes
+    #[serde(rename = "maxTokens")]
+    pub max_tokens: Option<usize>,
 }
 
 /// Parameters for the diff tool
@@ -66,10 +118,28 @@ pub struct DiffParams {
     pub cwd: Option<String>,
     pub from: Option<String>,
     pub to: Option<String>,
+    /// Diff `to` against its merge base with this revision instead of `from`; `auto` means `trunk()`
+    pub base: Option<String>,
     pub paths: Option<Vec<String>>,
     pub summary: Option<bool>,
     pub stat: Option<bool>,
     pub context: Option<u32>,
+    #[serde(rename = "embedFiles")]
+    pub embed_files: Option<bool>,
+    /// Also return a side-by-side HTML rendering as a resource
+    pub html: Option<bool>,
+    /// Mark changed words within lines instead of showing whole removed and added lines
+    #[serde(rename = "wordDiff")]
+    pub word_diff: Option<bool>,
+    /// Embed binary files as base64 instead of only describing them
+    #[serde(rename = "allowBinary")]
+    pub allow_binary: Option<bool>,
+    /// Largest response wanted, in bytes; the diff falls back to `--stat`, then `--summary` above it
+    #[serde(rename = "maxBytes")]
+    pub max_bytes: Option<usize>,
+    /// Largest response wanted, in tokens of about [`BYTES_PER_TOKEN`] bytes
+    #[serde(rename = "maxTokens")]
+    pub max_tokens: Option<usize>,
 }
 
 /// Parameters for the git-clone tool
@@ -82,443 +152,13603 @@ pub struct GitCloneParams {
     pub depth: Option<u32>,
 }
 
-/// A jj tool that implements the MCP Tool trait
-pub struct JjTool {
-    pub name: String,
-    pub description: String,
-    pub input_schema: Value,
+/// Parameters for the conflict-content tool
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct ConflictContentParams {
+    pub path: Option<String>,
+    pub revision: Option<String>,
+    #[serde(rename = "repoPath")]
+    pub repo_path: Option<String>,
+    pub cwd: Option<String>,
 }
 
-impl Tool for JjTool {
-    fn name(&self) -> String {
-        self.name.clone()
-    }
+/// A single conflicted region of a file, split into its bases and sides
+#[derive(Debug, Serialize, Deserialize, Default, PartialEq)]
+pub struct ConflictHunk {
+    #[serde(rename = "startLine")]
+    pub start_line: usize,
+    #[serde(rename = "endLine")]
+    pub end_line: usize,
+    pub bases: Vec<String>,
+    pub sides: Vec<String>,
+}
 
-    fn description(&self) -> String {
-        self.description.clone()
-    }
+/// Parameters for the conflict-resolve tool
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct ConflictResolveParams {
+    pub path: Option<String>,
+    pub content: Option<String>,
+    pub revision: Option<String>,
+    #[serde(rename = "repoPath")]
+    pub repo_path: Option<String>,
+    pub cwd: Option<String>,
+}
 
-    fn input_schema(&self) -> Value {
-        self.input_schema.clone()
-    }
+/// Parameters for the divergent-changes tool
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct DivergentChangesParams {
+    pub revisions: Option<String>,
+    #[serde(rename = "repoPath")]
+    pub repo_path: Option<String>,
+    pub cwd: Option<String>,
+}
 
-    fn call(&self, arguments: Option<Value>) -> Result<CallToolResponse> {
-        let args = arguments.unwrap_or_default();
+/// Parameters for the hidden-commits tool
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct HiddenCommitsParams {
+    pub operations: Option<u32>,
+    pub revisions: Option<String>,
+    #[serde(rename = "includeRewritten")]
+    pub include_rewritten: Option<bool>,
+    #[serde(rename = "repoPath")]
+    pub repo_path: Option<String>,
+    pub cwd: Option<String>,
+}
 
-        match self.name.as_str() {
-            "status" => {
-                let params: StatusParams = serde_json::from_value(args).unwrap_or_default();
-                Ok(run_jj_status(params))
-            }
-            "rebase" => {
-                let params: RebaseParams = serde_json::from_value(args).unwrap_or_default();
-                Ok(run_jj_rebase(params))
-            }
-            "commit" => {
-                let params: CommitParams = serde_json::from_value(args).unwrap_or_default();
-                Ok(run_jj_commit(params))
-            }
-            "new" => {
-                let params: NewParams = serde_json::from_value(args).unwrap_or_default();
-                Ok(run_jj_new(params))
-            }
-            "log" => {
-                let params: LogParams = serde_json::from_value(args).unwrap_or_default();
-                Ok(run_jj_log(params))
-            }
-            "diff" => {
-                let params: DiffParams = serde_json::from_value(args).unwrap_or_default();
-                Ok(run_jj_diff(params))
-            }
-            "git-clone" => {
-                let params: GitCloneParams = serde_json::from_value(args).unwrap_or_default();
-                Ok(run_jj_git_clone(params))
-            }
-            _ => Ok(CallToolResponse {
-                content: vec![ToolResponseContent::Text {
-                    text: format!("Unknown tool: {}", self.name),
-                }],
-                is_error: Some(true),
-                meta: None,
-            }),
-        }
-    }
+/// Parameters for the unabandon tool
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct UnabandonParams {
+    pub commit: Option<String>,
+    pub destination: Option<String>,
+    #[serde(rename = "repoPath")]
+    pub repo_path: Option<String>,
+    pub cwd: Option<String>,
 }
 
-/// Add repository arguments to a command
-pub fn add_repo_args(args: &mut Vec<String>, repo_path: Option<String>) {
-    if let Some(path) = repo_path {
-        args.push("-R".to_string());
-        args.push(path);
-    }
+/// Parameters for the cleanup-empty tool
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct CleanupEmptyParams {
+    pub revisions: Option<String>,
+    #[serde(rename = "dryRun")]
+    pub dry_run: Option<bool>,
+    #[serde(rename = "repoPath")]
+    pub repo_path: Option<String>,
+    pub cwd: Option<String>,
 }
 
-/// Run a jj command synchronously
-pub fn run_jj_command_sync(args: Vec<String>, cwd: Option<String>) -> Result<String> {
-    let mut cmd = std::process::Command::new(JJ_COMMAND);
-    cmd.args(&args);
-    cmd.stdout(std::process::Stdio::piped());
-    cmd.stderr(std::process::Stdio::piped());
+/// Parameters for the cherry-pick tool
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct CherryPickParams {
+    pub revisions: Option<Vec<String>>,
+    pub destination: Option<String>,
+    #[serde(rename = "repoPath")]
+    pub repo_path: Option<String>,
+    pub cwd: Option<String>,
+}
 
-    if let Some(cwd_path) = cwd {
-        cmd.current_dir(cwd_path);
-    }
+/// A commit created by `jj duplicate`
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct DuplicatedCommit {
+    pub source: String,
+    #[serde(rename = "changeId")]
+    pub change_id: String,
+    #[serde(rename = "commitId")]
+    pub commit_id: String,
+}
 
-    match cmd.output() {
-        Ok(output) => {
-            if output.status.success() {
-                Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
-            } else {
-                let stderr = String::from_utf8_lossy(&output.stderr);
-                let stderr_trimmed = stderr.trim();
-                Err(anyhow::anyhow!("Error: {}", stderr_trimmed))
-            }
-        }
-        Err(e) => Err(anyhow::anyhow!("Error: {}", e)),
-    }
+/// Parameters for the revert-range tool
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct RevertRangeParams {
+    pub revisions: Option<String>,
+    pub destination: Option<String>,
+    #[serde(rename = "repoPath")]
+    pub repo_path: Option<String>,
+    pub cwd: Option<String>,
 }
 
-/// Execute jj status command
-pub fn run_jj_status(params: StatusParams) -> CallToolResponse {
-    let mut args = vec!["status".to_string()];
-    add_repo_args(&mut args, params.repo_path);
+/// Parameters for the bisect-start tool
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct BisectStartParams {
+    pub good: Option<Vec<String>>,
+    pub bad: Option<String>,
+    #[serde(rename = "repoPath")]
+    pub repo_path: Option<String>,
+    pub cwd: Option<String>,
+}
 
-    match run_jj_command_sync(args, params.cwd) {
-        Ok(output) => CallToolResponse {
-            content: vec![ToolResponseContent::Text { text: output }],
-            is_error: Some(false),
-            meta: None,
-        },
-        Err(e) => CallToolResponse {
-            content: vec![ToolResponseContent::Text {
-                text: e.to_string(),
-            }],
-            is_error: Some(true),
-            meta: None,
-        },
-    }
+/// Parameters for the bisect-mark tool
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct BisectMarkParams {
+    pub result: Option<String>,
+    pub revision: Option<String>,
+    #[serde(rename = "repoPath")]
+    pub repo_path: Option<String>,
+    pub cwd: Option<String>,
 }
 
-/// Execute jj rebase command
-pub fn run_jj_rebase(params: RebaseParams) -> CallToolResponse {
-    let mut args = vec!["rebase".to_string()];
+/// Parameters for the bisect-status tool
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct BisectStatusParams {
+    #[serde(rename = "repoPath")]
+    pub repo_path: Option<String>,
+    pub cwd: Option<String>,
+}
 
-    if let Some(source) = params.source {
-        args.push("-s".to_string());
-        args.push(source);
-    }
+/// Bisection state for one repository, kept in server memory between calls
+#[derive(Debug, Serialize, Deserialize, Default, Clone)]
+pub struct BisectSession {
+    pub good: Vec<String>,
+    pub bad: String,
+    pub skipped: Vec<String>,
+    pub current: Option<String>,
+    #[serde(rename = "firstBad")]
+    pub first_bad: Option<String>,
+}
 
-    if let Some(destination) = params.destination {
-        args.push("-d".to_string());
-        args.push(destination);
-    }
+/// Parameters for the changelog tool
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct ChangelogParams {
+    pub from: Option<String>,
+    pub to: Option<String>,
+    #[serde(rename = "repoPath")]
+    pub repo_path: Option<String>,
+    pub cwd: Option<String>,
+}
 
-    add_repo_args(&mut args, params.repo_path);
+/// Parameters for the contributors tool
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct ContributorsParams {
+    pub revisions: Option<String>,
+    #[serde(rename = "repoPath")]
+    pub repo_path: Option<String>,
+    pub cwd: Option<String>,
+}
 
-    match run_jj_command_sync(args, params.cwd) {
-        Ok(output) => CallToolResponse {
-            content: vec![ToolResponseContent::Text { text: output }],
-            is_error: Some(false),
-            meta: None,
-        },
-        Err(e) => CallToolResponse {
-            content: vec![ToolResponseContent::Text {
-                text: e.to_string(),
-            }],
-            is_error: Some(true),
-            meta: None,
-        },
-    }
+/// Commit statistics for a single author
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct ContributorStats {
+    pub name: String,
+    pub email: String,
+    pub commits: usize,
+    #[serde(rename = "firstCommit")]
+    pub first_commit: String,
+    #[serde(rename = "lastCommit")]
+    pub last_commit: String,
 }
 
-/// Execute jj commit command
-pub fn run_jj_commit(params: CommitParams) -> CallToolResponse {
-    let mut args = vec!["commit".to_string()];
+/// Parameters for the repo-stats tool
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct RepoStatsParams {
+    pub revisions: Option<String>,
+    #[serde(rename = "repoPath")]
+    pub repo_path: Option<String>,
+    pub cwd: Option<String>,
+}
 
-    if let Some(message) = params.message {
-        args.push("-m".to_string());
-        args.push(message);
-    }
+/// How a workspace is backed by git
+#[derive(Debug, Serialize, Deserialize, Default, Clone, PartialEq)]
+pub struct GitBacking {
+    pub colocated: bool,
+    #[serde(rename = "gitDir")]
+    pub git_dir: Option<std::path::PathBuf>,
+}
 
-    add_repo_args(&mut args, params.repo_path);
+/// Parameters for the changes-since tool
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct ChangesSinceParams {
+    pub since: Option<String>,
+    pub operation: Option<String>,
+    pub revisions: Option<String>,
+    #[serde(rename = "repoPath")]
+    pub repo_path: Option<String>,
+    pub cwd: Option<String>,
+}
 
-    match run_jj_command_sync(args, params.cwd) {
-        Ok(output) => CallToolResponse {
-            content: vec![ToolResponseContent::Text { text: output }],
-            is_error: Some(false),
-            meta: None,
-        },
-        Err(e) => CallToolResponse {
-            content: vec![ToolResponseContent::Text {
-                text: e.to_string(),
-            }],
-            is_error: Some(true),
-            meta: None,
-        },
-    }
+/// Parameters for the anonymous-heads tool
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct AnonymousHeadsParams {
+    #[serde(rename = "repoPath")]
+    pub repo_path: Option<String>,
+    pub cwd: Option<String>,
 }
 
-/// Execute jj new command
-pub fn run_jj_new(params: NewParams) -> CallToolResponse {
-    let mut args = vec!["new".to_string()];
+/// Parameters for the compare tool
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct CompareParams {
+    pub revision: Option<String>,
+    pub base: Option<String>,
+    pub limit: Option<u32>,
+    #[serde(rename = "repoPath")]
+    pub repo_path: Option<String>,
+    pub cwd: Option<String>,
+}
 
-    if let Some(parents) = params.parents {
-        args.push(parents);
-    }
+/// Parameters for the megamerge tool
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct MegamergeParams {
+    pub revisions: Option<Vec<String>>,
+    pub message: Option<String>,
+    #[serde(rename = "repoPath")]
+    pub repo_path: Option<String>,
+    pub cwd: Option<String>,
+}
 
-    add_repo_args(&mut args, params.repo_path);
+/// Parameters for the split-by-directory tool
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct SplitByDirectoryParams {
+    pub revision: Option<String>,
+    #[serde(rename = "repoPath")]
+    pub repo_path: Option<String>,
+    pub cwd: Option<String>,
+}
 
-    match run_jj_command_sync(args, params.cwd) {
-        Ok(output) => CallToolResponse {
+/// Parameters for the has-conflicts tool
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct HasConflictsParams {
+    pub revisions: Option<String>,
+    #[serde(rename = "repoPath")]
+    pub repo_path: Option<String>,
+    pub cwd: Option<String>,
+}
+
+/// Parameters for the large-files tool
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct LargeFilesParams {
+    pub revision: Option<String>,
+    #[serde(rename = "thresholdBytes")]
+    pub threshold_bytes: Option<u64>,
+    #[serde(rename = "repoPath")]
+    pub repo_path: Option<String>,
+    pub cwd: Option<String>,
+}
+
+/// Parameters for the check-immutable tool
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct CheckImmutableParams {
+    pub revisions: Option<Vec<String>>,
+    #[serde(rename = "repoPath")]
+    pub repo_path: Option<String>,
+    pub cwd: Option<String>,
+}
+
+/// Parameters for the search-descriptions tool
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct SearchDescriptionsParams {
+    pub pattern: Option<String>,
+    pub regex: Option<bool>,
+    #[serde(rename = "ignoreCase")]
+    pub ignore_case: Option<bool>,
+    pub revisions: Option<String>,
+    pub limit: Option<u32>,
+    #[serde(rename = "repoPath")]
+    pub repo_path: Option<String>,
+    pub cwd: Option<String>,
+}
+
+/// Parameters for the common-ancestor tool
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct CommonAncestorParams {
+    pub revisions: Option<Vec<String>>,
+    #[serde(rename = "repoPath")]
+    pub repo_path: Option<String>,
+    pub cwd: Option<String>,
+}
+
+/// Parameters for the is-ancestor tool
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct IsAncestorParams {
+    pub ancestor: Option<String>,
+    pub descendant: Option<String>,
+    #[serde(rename = "repoPath")]
+    pub repo_path: Option<String>,
+    pub cwd: Option<String>,
+}
+
+/// Parameters for the op-log tool
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct OpLogParams {
+    pub limit: Option<u32>,
+    pub offset: Option<u32>,
+    pub since: Option<String>,
+    pub until: Option<String>,
+    #[serde(rename = "descriptionContains")]
+    pub description_contains: Option<String>,
+    /// Include the changes each operation made to commits and bookmarks
+    #[serde(rename = "opDiff")]
+    pub op_diff: Option<bool>,
+    /// Include the content diff of each changed commit; implies `opDiff`
+    pub patch: Option<bool>,
+    #[serde(rename = "repoPath")]
+    pub repo_path: Option<String>,
+    pub cwd: Option<String>,
+}
+
+/// Parameters for the undo tool
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct UndoParams {
+    pub operation: Option<String>,
+    #[serde(rename = "repoPath")]
+    pub repo_path: Option<String>,
+    pub cwd: Option<String>,
+}
+
+/// Parameters for the snapshot tool
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct SnapshotParams {
+    #[serde(rename = "repoPath")]
+    pub repo_path: Option<String>,
+    pub cwd: Option<String>,
+}
+
+/// Parameters for the write-file tool
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct WriteFileParams {
+    pub path: Option<String>,
+    pub content: Option<String>,
+    pub append: Option<bool>,
+    #[serde(rename = "repoPath")]
+    pub repo_path: Option<String>,
+    pub cwd: Option<String>,
+}
+
+/// Parameters for the grep tool
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct GrepParams {
+    pub pattern: Option<String>,
+    pub regex: Option<bool>,
+    #[serde(rename = "ignoreCase")]
+    pub ignore_case: Option<bool>,
+    pub revision: Option<String>,
+    pub include: Option<Vec<String>>,
+    pub exclude: Option<Vec<String>>,
+    #[serde(rename = "maxMatches")]
+    pub max_matches: Option<usize>,
+    #[serde(rename = "repoPath")]
+    pub repo_path: Option<String>,
+    pub cwd: Option<String>,
+}
+
+/// Parameters for the apply-patch tool
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct ApplyPatchParams {
+    pub patch: Option<String>,
+    pub method: Option<String>,
+    #[serde(rename = "repoPath")]
+    pub repo_path: Option<String>,
+    pub cwd: Option<String>,
+}
+
+/// Parameters for the export-patch tool
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct ExportPatchParams {
+    pub revisions: Option<String>,
+    #[serde(rename = "emailHeaders")]
+    pub email_headers: Option<bool>,
+    #[serde(rename = "repoPath")]
+    pub repo_path: Option<String>,
+    pub cwd: Option<String>,
+}
+
+/// Parameters for the archive tool
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct ArchiveParams {
+    pub revision: Option<String>,
+    pub destination: Option<String>,
+    pub format: Option<String>,
+    #[serde(rename = "repoPath")]
+    pub repo_path: Option<String>,
+    pub cwd: Option<String>,
+}
+
+/// Parameters for the multi-repo tool
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct MultiRepoParams {
+    pub repos: Option<Vec<String>>,
+    pub operation: Option<String>,
+    pub limit: Option<u32>,
+}
+
+/// Parameters for the doctor tool
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct DoctorParams {
+    #[serde(rename = "repoPath")]
+    pub repo_path: Option<String>,
+    pub cwd: Option<String>,
+}
+
+/// Parameters for the git-passthrough tool
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct GitPassthroughParams {
+    pub args: Option<Vec<String>>,
+    #[serde(rename = "repoPath")]
+    pub repo_path: Option<String>,
+    pub cwd: Option<String>,
+}
+
+/// MCP tool annotations, hints clients can use for their confirmation policies
+///
+/// The defaults are the MCP specification's: a tool may modify state destructively
+/// and interact with the outside world.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub struct ToolAnnotations {
+    #[serde(rename = "readOnlyHint")]
+    pub read_only_hint: bool,
+    #[serde(rename = "destructiveHint")]
+    pub destructive_hint: bool,
+    #[serde(rename = "idempotentHint")]
+    pub idempotent_hint: bool,
+    #[serde(rename = "openWorldHint")]
+    pub open_world_hint: bool,
+}
+
+impl Default for ToolAnnotations {
+    fn default() -> Self {
+        ToolAnnotations {
+            read_only_hint: false,
+            destructive_hint: true,
+            idempotent_hint: false,
+            open_world_hint: true,
+        }
+    }
+}
+
+impl ToolAnnotations {
+    /// Only reads the repository
+    pub const fn read_only() -> Self {
+        ToolAnnotations {
+            read_only_hint: true,
+            destructive_hint: false,
+            idempotent_hint: true,
+            open_world_hint: false,
+        }
+    }
+
+    /// Creates new state without rewriting or discarding existing state
+    pub const fn additive() -> Self {
+        ToolAnnotations {
+            read_only_hint: false,
+            destructive_hint: false,
+            idempotent_hint: false,
+            open_world_hint: false,
+        }
+    }
+
+    /// Rewrites, abandons or overwrites existing state
+    pub const fn destructive() -> Self {
+        ToolAnnotations {
+            read_only_hint: false,
+            destructive_hint: true,
+            idempotent_hint: false,
+            open_world_hint: false,
+        }
+    }
+
+    /// Mark the tool as talking to remotes or other systems outside the repository
+    pub const fn open_world(self) -> Self {
+        ToolAnnotations {
+            open_world_hint: true,
+            ..self
+        }
+    }
+}
+
+/// Annotations for a tool by name; unknown tools get the conservative MCP defaults
+pub fn default_tool_annotations(name: &str) -> ToolAnnotations {
+    match name {
+        "status"
+        | "log"
+        | "diff"
+        | "conflict-content"
+        | "divergent-changes"
+        | "hidden-commits"
+        | "bisect-status"
+        | "changelog"
+        | "contributors"
+        | "author-stats"
+        | "repo-stats"
+        | "changes-since"
+        | "anonymous-heads"
+        | "compare"
+        | "has-conflicts"
+        | "large-files"
+        | "check-immutable"
+        | "search-descriptions"
+        | "common-ancestor"
+        | "is-ancestor"
+        | "op-log"
+        | "grep"
+        | "export-patch"
+        | "multi-repo"
+        | "doctor"
+        | "annotate"
+        | "bookmark-list"
+        | "config-list"
+        | "config-path"
+        | "workspace-info"
+        | "evolog"
+        | "complete"
+        | "discover-repos"
+        | "read-output"
+        | "graph-export"
+        | "submodules"
+        | "file-show" => ToolAnnotations::read_only(),
+        "git-passthrough" | "remote-list" => ToolAnnotations::read_only().open_world(),
+        "new" | "commit" | "unabandon" | "cherry-pick" | "revert-range" | "bisect-start"
+        | "bisect-mark" | "megamerge" | "archive" | "init" | "append-commit" => {
+            ToolAnnotations::additive()
+        }
+        "snapshot" | "reconcile" => ToolAnnotations {
+            idempotent_hint: true,
+            ..ToolAnnotations::additive()
+        },
+        "git-clone" | "git-fetch" | "deepen" => ToolAnnotations::additive().open_world(),
+        // Pushing the same bookmark state twice is a no-op on the remote
+        "git-push" => ToolAnnotations {
+            idempotent_hint: true,
+            ..ToolAnnotations::destructive().open_world()
+        },
+        "rebase" | "abandon" | "restore" | "squash" | "describe-auto" | "sign" | "pipeline"
+        | "stash" | "unstash" | "conflict-resolve" | "cleanup-empty" | "split-by-directory"
+        | "undo" | "apply-patch" | "sandbox-promote" | "sandbox-discard" => {
+            ToolAnnotations::destructive()
+        }
+        "write-file" | "describe" => ToolAnnotations {
+            idempotent_hint: true,
+            ..ToolAnnotations::destructive()
+        },
+        _ => ToolAnnotations::default(),
+    }
+}
+
+/// Parameters for the sandbox-promote tool
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct SandboxPromoteParams {
+    #[serde(rename = "dryRun")]
+    pub dry_run: Option<bool>,
+    #[serde(rename = "repoPath")]
+    pub repo_path: Option<String>,
+    pub cwd: Option<String>,
+}
+
+/// Parameters for the sandbox-discard tool
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct SandboxDiscardParams {
+    #[serde(rename = "repoPath")]
+    pub repo_path: Option<String>,
+    pub cwd: Option<String>,
+}
+
+/// A mutating tool call made in a sandbox, replayed by `sandbox-promote`
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct SandboxCall {
+    pub tool: String,
+    pub arguments: Value,
+}
+
+/// A disposable copy of a repository and the calls made against it
+///
+/// The copy lives in a private temporary directory that is removed when the
+/// sandbox is dropped.
+#[derive(Debug)]
+pub struct Sandbox {
+    pub dir: tempfile::TempDir,
+    pub calls: Vec<SandboxCall>,
+}
+
+impl Sandbox {
+    /// Where the copy of the repository is
+    pub fn path(&self) -> &std::path::Path {
+        self.dir.path()
+    }
+}
+
+/// Parameters for the abandon tool
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct AbandonParams {
+    pub revisions: Option<Vec<String>>,
+    #[serde(rename = "retainBookmarks")]
+    pub retain_bookmarks: Option<bool>,
+    #[serde(rename = "restoreDescendants")]
+    pub restore_descendants: Option<bool>,
+    #[serde(rename = "repoPath")]
+    pub repo_path: Option<String>,
+    pub cwd: Option<String>,
+}
+
+/// Parameters for the restore tool
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct RestoreParams {
+    pub from: Option<String>,
+    #[serde(alias = "to")]
+    pub into: Option<String>,
+    pub paths: Option<Vec<String>>,
+    #[serde(rename = "restoreDescendants")]
+    pub restore_descendants: Option<bool>,
+    #[serde(rename = "repoPath")]
+    pub repo_path: Option<String>,
+    pub cwd: Option<String>,
+}
+
+/// Parameters for the squash tool
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct SquashParams {
+    pub from: Option<String>,
+    #[serde(alias = "to")]
+    pub into: Option<String>,
+    pub message: Option<String>,
+    #[serde(rename = "useDestinationMessage")]
+    pub use_destination_message: Option<bool>,
+    #[serde(rename = "keepEmptied")]
+    pub keep_emptied: Option<bool>,
+    pub paths: Option<Vec<String>>,
+    #[serde(rename = "repoPath")]
+    pub repo_path: Option<String>,
+    pub cwd: Option<String>,
+}
+
+/// Parameters for the annotate tool
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct AnnotateParams {
+    pub path: Option<String>,
+    pub revision: Option<String>,
+    pub template: Option<String>,
+    /// First line to return, counting from 1
+    #[serde(rename = "startLine")]
+    pub start_line: Option<usize>,
+    /// Last line to return, inclusive
+    #[serde(rename = "endLine")]
+    pub end_line: Option<usize>,
+    #[serde(rename = "repoPath")]
+    pub repo_path: Option<String>,
+    pub cwd: Option<String>,
+}
+
+/// Parameters for the bookmark-list tool
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct BookmarkListParams {
+    pub names: Option<Vec<String>>,
+    pub revisions: Option<String>,
+    pub tracked: Option<bool>,
+    pub conflicted: Option<bool>,
+    #[serde(rename = "allRemotes")]
+    pub all_remotes: Option<bool>,
+    pub remote: Option<String>,
+    #[serde(rename = "repoPath")]
+    pub repo_path: Option<String>,
+    pub cwd: Option<String>,
+}
+
+/// Parameters for the git-push tool
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct GitPushParams {
+    pub remote: Option<String>,
+    pub bookmarks: Option<Vec<String>>,
+    pub changes: Option<Vec<String>>,
+    pub all: Option<bool>,
+    pub tracked: Option<bool>,
+    pub deleted: Option<bool>,
+    #[serde(rename = "allowNew")]
+    pub allow_new: Option<bool>,
+    #[serde(rename = "dryRun")]
+    pub dry_run: Option<bool>,
+    #[serde(rename = "repoPath")]
+    pub repo_path: Option<String>,
+    pub cwd: Option<String>,
+}
+
+/// A bookmark update reported by `jj git push`
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct BookmarkUpdate {
+    pub remote: Option<String>,
+    pub bookmark: String,
+    /// `add`, `delete`, `move-forward`, `move-backward` or `move-sideways`
+    pub action: String,
+    #[serde(rename = "oldCommit")]
+    pub old_commit: Option<String>,
+    #[serde(rename = "newCommit")]
+    pub new_commit: Option<String>,
+}
+
+/// Parameters for the git-fetch tool
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct GitFetchParams {
+    pub remotes: Option<Vec<String>>,
+    #[serde(rename = "allRemotes")]
+    pub all_remotes: Option<bool>,
+    pub bookmarks: Option<Vec<String>>,
+    #[serde(rename = "repoPath")]
+    pub repo_path: Option<String>,
+    pub cwd: Option<String>,
+}
+
+/// Parameters for the config-list tool
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct ConfigListParams {
+    pub name: Option<String>,
+    #[serde(rename = "includeDefaults")]
+    pub include_defaults: Option<bool>,
+    #[serde(rename = "includeOverridden")]
+    pub include_overridden: Option<bool>,
+    #[serde(rename = "repoPath")]
+    pub repo_path: Option<String>,
+    pub cwd: Option<String>,
+}
+
+/// Parameters for the config-path tool
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct ConfigPathParams {
+    /// `user` or `repo`
+    pub level: Option<String>,
+    #[serde(rename = "repoPath")]
+    pub repo_path: Option<String>,
+    pub cwd: Option<String>,
+}
+
+/// A configuration value and where it was set
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct ConfigEntry {
+    pub name: String,
+    /// TOML rendering of the value
+    pub value: String,
+    /// `default`, `env-base`, `user`, `repo`, `env-overrides` or `command-arg`
+    pub source: String,
+    pub path: Option<String>,
+    pub overridden: bool,
+}
+
+/// Parameters for the workspace-info tool
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct WorkspaceInfoParams {
+    #[serde(rename = "repoPath")]
+    pub repo_path: Option<String>,
+    pub cwd: Option<String>,
+}
+
+/// Parameters for the evolog tool
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct EvologParams {
+    pub revision: Option<String>,
+    pub limit: Option<u32>,
+    pub patch: Option<bool>,
+    pub template: Option<String>,
+    #[serde(rename = "repoPath")]
+    pub repo_path: Option<String>,
+    pub cwd: Option<String>,
+}
+
+/// Parameters for the describe-auto tool
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct DescribeAutoParams {
+    pub revision: Option<String>,
+    /// Extra guidance for the message, e.g. a ticket number or style rules
+    pub instructions: Option<String>,
+    #[serde(rename = "dryRun")]
+    pub dry_run: Option<bool>,
+    #[serde(rename = "repoPath")]
+    pub repo_path: Option<String>,
+    pub cwd: Option<String>,
+}
+
+/// Parameters for the complete tool
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct CompleteParams {
+    /// Name of the argument being completed, e.g. `revision` or `bookmark`
+    pub argument: Option<String>,
+    /// What has been typed so far
+    pub value: Option<String>,
+    #[serde(rename = "repoPath")]
+    pub repo_path: Option<String>,
+    pub cwd: Option<String>,
+}
+
+/// Parameters for the read-output tool
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct ReadOutputParams {
+    pub uri: Option<String>,
+    pub offset: Option<usize>,
+    pub length: Option<usize>,
+}
+
+/// One tool call in a pipeline
+#[derive(Debug, Serialize, Deserialize, Default, Clone)]
+pub struct PipelineStep {
+    /// Name later steps use in references; defaults to the step's index
+    pub id: Option<String>,
+    pub tool: Option<String>,
+    pub arguments: Option<Value>,
+    /// Named regular expressions matched against the step's output; group 1 (or the
+    /// whole match) becomes a reference
+    pub capture: Option<std::collections::HashMap<String, String>>,
+}
+
+/// Parameters for the pipeline tool
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct PipelineParams {
+    pub steps: Option<Vec<PipelineStep>>,
+    #[serde(rename = "continueOnError")]
+    pub continue_on_error: Option<bool>,
+    #[serde(rename = "repoPath")]
+    pub repo_path: Option<String>,
+    pub cwd: Option<String>,
+}
+
+/// Parameters for the describe tool
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct DescribeParams {
+    pub revision: Option<String>,
+    pub message: Option<String>,
+    #[serde(rename = "messageFile")]
+    pub message_file: Option<String>,
+    pub sign: Option<bool>,
+    #[serde(rename = "signingBackend")]
+    pub signing_backend: Option<String>,
+    #[serde(rename = "signingKey")]
+    pub signing_key: Option<String>,
+    #[serde(rename = "authorName")]
+    pub author_name: Option<String>,
+    #[serde(rename = "authorEmail")]
+    pub author_email: Option<String>,
+    #[serde(rename = "repoPath")]
+    pub repo_path: Option<String>,
+    pub cwd: Option<String>,
+}
+
+/// Parameters for the sign tool
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct SignParams {
+    pub revisions: Option<Vec<String>>,
+    #[serde(rename = "signingBackend")]
+    pub signing_backend: Option<String>,
+    #[serde(rename = "signingKey")]
+    pub signing_key: Option<String>,
+    #[serde(rename = "repoPath")]
+    pub repo_path: Option<String>,
+    pub cwd: Option<String>,
+}
+
+/// Parameters for the discover-repos tool
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct DiscoverReposParams {
+    pub path: Option<String>,
+    #[serde(rename = "maxDepth")]
+    pub max_depth: Option<usize>,
+}
+
+/// Parameters for the remote-list tool
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct RemoteListParams {
+    pub check: Option<bool>,
+    #[serde(rename = "repoPath")]
+    pub repo_path: Option<String>,
+    pub cwd: Option<String>,
+}
+
+/// A git remote configured for a repository
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct GitRemote {
+    pub name: String,
+    pub url: String,
+}
+
+/// Parameters for the stash tool
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct StashParams {
+    pub name: Option<String>,
+    pub base: Option<String>,
+    pub message: Option<String>,
+    #[serde(rename = "repoPath")]
+    pub repo_path: Option<String>,
+    pub cwd: Option<String>,
+}
+
+/// Parameters for the unstash tool
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct UnstashParams {
+    pub name: Option<String>,
+    #[serde(rename = "repoPath")]
+    pub repo_path: Option<String>,
+    pub cwd: Option<String>,
+}
+
+/// Parameters for the graph-export tool
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct GraphExportParams {
+    pub revisions: Option<String>,
+    pub limit: Option<u32>,
+    /// `dot` (default) or `mermaid`
+    pub format: Option<String>,
+    #[serde(rename = "repoPath")]
+    pub repo_path: Option<String>,
+    pub cwd: Option<String>,
+}
+
+/// A commit in an exported graph
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct GraphNode {
+    #[serde(rename = "commitId")]
+    pub commit_id: String,
+    #[serde(rename = "changeId")]
+    pub change_id: String,
+    /// Commit IDs of the parents, including ones outside the exported revset
+    pub parents: Vec<String>,
+    pub bookmarks: Vec<String>,
+    #[serde(rename = "workingCopy")]
+    pub working_copy: bool,
+    pub immutable: bool,
+    pub description: String,
+}
+
+/// Parameters for the author-stats tool
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct AuthorStatsParams {
+    pub revisions: Option<String>,
+    #[serde(rename = "repoPath")]
+    pub repo_path: Option<String>,
+    pub cwd: Option<String>,
+}
+
+/// Lines changed by a single author across a revset
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct AuthorDiffStats {
+    pub name: String,
+    pub email: String,
+    pub commits: usize,
+    #[serde(rename = "filesChanged")]
+    pub files_changed: usize,
+    pub insertions: usize,
+    pub deletions: usize,
+}
+
+/// Parameters for the reconcile tool
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct ReconcileParams {
+    #[serde(rename = "repoPath")]
+    pub repo_path: Option<String>,
+    pub cwd: Option<String>,
+}
+
+/// Parameters for the init tool
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct InitParams {
+    pub destination: Option<String>,
+    /// `git` (default) or `native`
+    pub backend: Option<String>,
+    pub colocate: Option<bool>,
+    #[serde(rename = "gitRepo")]
+    pub git_repo: Option<String>,
+    pub cwd: Option<String>,
+}
+
+/// Parameters for the deepen tool
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct DeepenParams {
+    pub remote: Option<String>,
+    /// Commits of extra history to fetch; all of it when unset
+    pub depth: Option<u32>,
+    #[serde(rename = "repoPath")]
+    pub repo_path: Option<String>,
+    pub cwd: Option<String>,
+}
+
+/// Parameters for the submodules tool
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct SubmodulesParams {
+    pub revision: Option<String>,
+    #[serde(rename = "repoPath")]
+    pub repo_path: Option<String>,
+    pub cwd: Option<String>,
+}
+
+/// A git submodule declared in `.gitmodules`
+#[derive(Debug, Serialize, Deserialize, Default, Clone, PartialEq)]
+pub struct Submodule {
+    pub name: String,
+    pub path: String,
+    pub url: Option<String>,
+    pub branch: Option<String>,
+    /// Commit the revision's tree pins the submodule to
+    pub commit: Option<String>,
+    /// Whether the submodule is checked out in the workspace
+    pub initialized: bool,
+}
+
+/// Parameters for the append-commit tool
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct AppendCommitParams {
+    pub bookmark: Option<String>,
+    pub message: Option<String>,
+    /// File contents to commit, keyed by repo-relative path
+    pub files: Option<std::collections::BTreeMap<String, String>>,
+    #[serde(rename = "moveBookmark")]
+    pub move_bookmark: Option<bool>,
+    #[serde(rename = "repoPath")]
+    pub repo_path: Option<String>,
+    pub cwd: Option<String>,
+}
+
+/// Parameters for the file-show tool
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct FileShowParams {
+    pub path: Option<String>,
+    pub revision: Option<String>,
+    /// Return binary content as base64 instead of only describing it
+    #[serde(rename = "allowBinary")]
+    pub allow_binary: Option<bool>,
+    #[serde(rename = "repoPath")]
+    pub repo_path: Option<String>,
+    pub cwd: Option<String>,
+}
+
+/// A jj tool that implements the MCP Tool trait
+pub struct JjTool {
+    pub name: String,
+    pub description: String,
+    pub input_schema: Value,
+    pub annotations: ToolAnnotations,
+}
+
+impl Tool for JjTool {
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn description(&self) -> String {
+        self.description.clone()
+    }
+
+    fn input_schema(&self) -> Value {
+        self.input_schema.clone()
+    }
+
+    fn call(&self, arguments: Option<Value>) -> Result<CallToolResponse> {
+        let started = std::time::Instant::now();
+        tracing::info!(tool = %self.name, "tool call");
+        let result = self.call_logged(arguments);
+        let duration_ms = started.elapsed().as_millis() as u64;
+        match &result {
+            Ok(response) if response.is_error == Some(true) => {
+                let error = response.content.first().and_then(|item| match item {
+                    ToolResponseContent::Text { text } => Some(text.as_str()),
+                    _ => None,
+                });
+                tracing::warn!(tool = %self.name, duration_ms, error, "tool call failed")
+            }
+            Ok(_) => tracing::info!(tool = %self.name, duration_ms, "tool call finished"),
+            Err(e) => {
+                tracing::error!(tool = %self.name, duration_ms, error = %e, "tool call failed")
+            }
+        }
+        result
+    }
+}
+
+impl JjTool {
+    /// The body of [`Tool::call`], which logs the call around it
+    fn call_logged(&self, arguments: Option<Value>) -> Result<CallToolResponse> {
+        let mut args = arguments.unwrap_or_default();
+        if let Err(e) = validate_arguments(&self.input_schema, &args) {
+            return Ok(json_response(Err(e)));
+        }
+        let name = server_config().canonical_name(&self.name);
+        if let Err(e) = restrict_to_roots(name, &mut args, &allowed_roots()) {
+            return Ok(json_response(Err(e)));
+        }
+        if let Err(e) = check_call_env(&args).and_then(|_| check_call_timestamp(&args)) {
+            return Ok(json_response(Err(e)));
+        }
+        let options = CallOptions::from_args(&args);
+        if options.explain {
+            return Ok(self.explain(args, options));
+        }
+        let name = server_config().canonical_name(&self.name);
+        if options.sandbox && !name.starts_with("sandbox-") {
+            return self
+                .call_in_sandbox(args, options)
+                .map(offload_large_output);
+        }
+        // git-push asks for itself, and only when it would delete remote bookmarks
+        if name != "git-push" && server_config().requires_confirmation(name) {
+            let mut parameters = args.clone();
+            if let Some(object) = parameters.as_object_mut() {
+                object.retain(|key, _| !GLOBAL_PARAMETERS.contains(&key.as_str()));
+            }
+            let confirmed = with_call_options(options.clone(), || {
+                confirm_operation(&format!("{} {}", name, parameters))
+            });
+            if let Err(e) = confirmed {
+                return Ok(json_response(Err(e)));
+            }
+        }
+        with_call_options(options, || {
+            self.dispatch(args)
+                .map(attach_raw_outputs)
+                .map(attach_concurrent_operations)
+        })
+        .map(offload_large_output)
+    }
+}
+
+/// Tools that write files or run git themselves rather than through jj, or that
+/// replay other calls, so explaining them can't intercept their effects
+const DIRECT_WRITE_TOOLS: &[&str] = &[
+    "conflict-resolve",
+    "write-file",
+    "apply-patch",
+    "archive",
+    "sandbox-promote",
+    "sandbox-discard",
+    "deepen",
+];
+
+impl JjTool {
+    /// Create a tool with the annotations registered for its name
+    pub fn new(name: &str, description: &str, input_schema: Value) -> Self {
+        JjTool {
+            name: name.to_string(),
+            description: description.to_string(),
+            input_schema,
+            annotations: default_tool_annotations(name),
+        }
+    }
+
+    /// Describe what a call would do: the jj commands it would run and how risky it is
+    fn explain(&self, args: Value, options: CallOptions) -> CallToolResponse {
+        let name = server_config().canonical_name(&self.name).to_string();
+        let mut parameters = args.clone();
+        if let Some(object) = parameters.as_object_mut() {
+            object.retain(|key, _| !GLOBAL_PARAMETERS.contains(&key.as_str()));
+        }
+
+        let (commands, outcome) = if DIRECT_WRITE_TOOLS.contains(&name.as_str()) {
+            (Vec::new(), None)
+        } else {
+            EXPLAINED_COMMANDS.with(|commands| commands.borrow_mut().clear());
+            let outcome = with_call_options(options, || self.dispatch(args));
+            let commands = EXPLAINED_COMMANDS.with(|commands| commands.take());
+            (commands, Some(outcome))
+        };
+        let error = match &outcome {
+            Some(Ok(response)) if response.is_error == Some(true) => {
+                response.content.iter().find_map(|content| match content {
+                    ToolResponseContent::Text { text } => Some(text.clone()),
+                    _ => None,
+                })
+            }
+            Some(Err(e)) => Some(e.to_string()),
+            _ => None,
+        };
+
+        let describe = |executed: bool| -> Vec<Value> {
+            commands
+                .iter()
+                .filter(|command| command.executed == executed)
+                .map(|command| serde_json::json!(command.argv))
+                .collect()
+        };
+        json_response(Ok(serde_json::json!({
+            "tool": name,
+            "parameters": parameters,
+            "readOnly": self.annotations.read_only_hint,
+            "destructive": self.annotations.destructive_hint,
+            "commands": describe(false),
+            "lookups": describe(true),
+            "writesFilesDirectly": DIRECT_WRITE_TOOLS.contains(&name.as_str()),
+            "error": error,
+        })))
+    }
+
+    /// Run a call against the sandbox copy of its repository, recording it for promotion
+    fn call_in_sandbox(&self, args: Value, options: CallOptions) -> Result<CallToolResponse> {
+        let repo_path = args.get("repoPath").and_then(Value::as_str);
+        let cwd = args.get("cwd").and_then(Value::as_str);
+        let sandbox = resolve_workspace_root(repo_path.map(String::from), cwd.map(String::from))
+            .and_then(|root| sandbox_path(&root).map(|sandbox| (root, sandbox)));
+        let (root, sandbox) = match sandbox {
+            Ok(paths) => paths,
+            Err(e) => return Ok(json_response(Err(e))),
+        };
+
+        let sandboxed = sandbox_arguments(&args, &root, &sandbox);
+        let options = CallOptions {
+            sandbox: false,
+            ..options
+        };
+        let mut response = with_call_options(options, || self.dispatch(sandboxed))?;
+        if response.is_error != Some(true) && !self.annotations.read_only_hint {
+            let mut arguments = args;
+            if let Some(object) = arguments.as_object_mut() {
+                object.remove("sandbox");
+            }
+            let pending = record_sandbox_call(
+                &root,
+                SandboxCall {
+                    tool: server_config().canonical_name(&self.name).to_string(),
+                    arguments,
+                },
+            );
+            response.content.push(ToolResponseContent::Text {
+                text: format!(
+                    "Ran in sandbox {}; {} call(s) pending sandbox-promote",
+                    sandbox.display(),
+                    pending
+                ),
+            });
+        }
+        Ok(response)
+    }
+
+    fn dispatch(&self, args: Value) -> Result<CallToolResponse> {
+        match server_config().canonical_name(&self.name) {
+            "status" => {
+                let params: StatusParams = serde_json::from_value(args).unwrap_or_default();
+                Ok(run_jj_status(params))
+            }
+            "rebase" => {
+                let params: RebaseParams = serde_json::from_value(args).unwrap_or_default();
+                Ok(run_jj_rebase(params))
+            }
+            "commit" => {
+                let params: CommitParams = serde_json::from_value(args).unwrap_or_default();
+                Ok(run_jj_commit(params))
+            }
+            "new" => {
+                let params: NewParams = serde_json::from_value(args).unwrap_or_default();
+                Ok(run_jj_new(params))
+            }
+            "log" => {
+                let params: LogParams = serde_json::from_value(args).unwrap_or_default();
+                Ok(run_jj_log(params))
+            }
+            "diff" => {
+                let params: DiffParams = serde_json::from_value(args).unwrap_or_default();
+                Ok(run_jj_diff(params))
+            }
+            "git-clone" => {
+                let params: GitCloneParams = serde_json::from_value(args).unwrap_or_default();
+                Ok(run_jj_git_clone(params))
+            }
+            "conflict-content" => {
+                let params: ConflictContentParams =
+                    serde_json::from_value(args).unwrap_or_default();
+                Ok(run_jj_conflict_content(params))
+            }
+            "conflict-resolve" => {
+                let params: ConflictResolveParams =
+                    serde_json::from_value(args).unwrap_or_default();
+                Ok(run_jj_conflict_resolve(params))
+            }
+            "divergent-changes" => {
+                let params: DivergentChangesParams =
+                    serde_json::from_value(args).unwrap_or_default();
+                Ok(run_jj_divergent_changes(params))
+            }
+            "hidden-commits" => {
+                let params: HiddenCommitsParams = serde_json::from_value(args).unwrap_or_default();
+                Ok(run_jj_hidden_commits(params))
+            }
+            "unabandon" => {
+                let params: UnabandonParams = serde_json::from_value(args).unwrap_or_default();
+                Ok(run_jj_unabandon(params))
+            }
+            "cleanup-empty" => {
+                let params: CleanupEmptyParams = serde_json::from_value(args).unwrap_or_default();
+                Ok(run_jj_cleanup_empty(params))
+            }
+            "cherry-pick" => {
+                let params: CherryPickParams = serde_json::from_value(args).unwrap_or_default();
+                Ok(run_jj_cherry_pick(params))
+            }
+            "revert-range" => {
+                let params: RevertRangeParams = serde_json::from_value(args).unwrap_or_default();
+                Ok(run_jj_revert_range(params))
+            }
+            "bisect-start" => {
+                let params: BisectStartParams = serde_json::from_value(args).unwrap_or_default();
+                Ok(run_jj_bisect_start(params))
+            }
+            "bisect-mark" => {
+                let params: BisectMarkParams = serde_json::from_value(args).unwrap_or_default();
+                Ok(run_jj_bisect_mark(params))
+            }
+            "bisect-status" => {
+                let params: BisectStatusParams = serde_json::from_value(args).unwrap_or_default();
+                Ok(run_jj_bisect_status(params))
+            }
+            "changelog" => {
+                let params: ChangelogParams = serde_json::from_value(args).unwrap_or_default();
+                Ok(run_jj_changelog(params))
+            }
+            "contributors" => {
+                let params: ContributorsParams = serde_json::from_value(args).unwrap_or_default();
+                Ok(run_jj_contributors(params))
+            }
+            "repo-stats" => {
+                let params: RepoStatsParams = serde_json::from_value(args).unwrap_or_default();
+                Ok(run_jj_repo_stats(params))
+            }
+            "changes-since" => {
+                let params: ChangesSinceParams = serde_json::from_value(args).unwrap_or_default();
+                Ok(run_jj_changes_since(params))
+            }
+            "anonymous-heads" => {
+                let params: AnonymousHeadsParams = serde_json::from_value(args).unwrap_or_default();
+                Ok(run_jj_anonymous_heads(params))
+            }
+            "compare" => {
+                let params: CompareParams = serde_json::from_value(args).unwrap_or_default();
+                Ok(run_jj_compare(params))
+            }
+            "megamerge" => {
+                let params: MegamergeParams = serde_json::from_value(args).unwrap_or_default();
+                Ok(run_jj_megamerge(params))
+            }
+            "split-by-directory" => {
+                let params: SplitByDirectoryParams =
+                    serde_json::from_value(args).unwrap_or_default();
+                Ok(run_jj_split_by_directory(params))
+            }
+            "has-conflicts" => {
+                let params: HasConflictsParams = serde_json::from_value(args).unwrap_or_default();
+                Ok(run_jj_has_conflicts(params))
+            }
+            "large-files" => {
+                let params: LargeFilesParams = serde_json::from_value(args).unwrap_or_default();
+                Ok(run_jj_large_files(params))
+            }
+            "check-immutable" => {
+                let params: CheckImmutableParams = serde_json::from_value(args).unwrap_or_default();
+                Ok(run_jj_check_immutable(params))
+            }
+            "search-descriptions" => {
+                let params: SearchDescriptionsParams =
+                    serde_json::from_value(args).unwrap_or_default();
+                Ok(run_jj_search_descriptions(params))
+            }
+            "common-ancestor" => {
+                let params: CommonAncestorParams = serde_json::from_value(args).unwrap_or_default();
+                Ok(run_jj_common_ancestor(params))
+            }
+            "is-ancestor" => {
+                let params: IsAncestorParams = serde_json::from_value(args).unwrap_or_default();
+                Ok(run_jj_is_ancestor(params))
+            }
+            "op-log" => {
+                let params: OpLogParams = serde_json::from_value(args).unwrap_or_default();
+                Ok(run_jj_op_log(params))
+            }
+            "undo" => {
+                let params: UndoParams = serde_json::from_value(args).unwrap_or_default();
+                Ok(run_jj_undo(params))
+            }
+            "snapshot" => {
+                let params: SnapshotParams = serde_json::from_value(args).unwrap_or_default();
+                Ok(run_jj_snapshot(params))
+            }
+            "write-file" => {
+                let params: WriteFileParams = serde_json::from_value(args).unwrap_or_default();
+                Ok(run_jj_write_file(params))
+            }
+            "grep" => {
+                let params: GrepParams = serde_json::from_value(args).unwrap_or_default();
+                Ok(run_jj_grep(params))
+            }
+            "apply-patch" => {
+                let params: ApplyPatchParams = serde_json::from_value(args).unwrap_or_default();
+                Ok(run_jj_apply_patch(params))
+            }
+            "export-patch" => {
+                let params: ExportPatchParams = serde_json::from_value(args).unwrap_or_default();
+                Ok(run_jj_export_patch(params))
+            }
+            "archive" => {
+                let params: ArchiveParams = serde_json::from_value(args).unwrap_or_default();
+                Ok(run_jj_archive(params))
+            }
+            "multi-repo" => {
+                let params: MultiRepoParams = serde_json::from_value(args).unwrap_or_default();
+                Ok(run_jj_multi_repo(params))
+            }
+            "doctor" => {
+                let params: DoctorParams = serde_json::from_value(args).unwrap_or_default();
+                Ok(run_jj_doctor(params))
+            }
+            "git-passthrough" => {
+                let params: GitPassthroughParams = serde_json::from_value(args).unwrap_or_default();
+                Ok(run_git_passthrough(params))
+            }
+            "sandbox-promote" => {
+                let params: SandboxPromoteParams = serde_json::from_value(args).unwrap_or_default();
+                Ok(run_jj_sandbox_promote(params))
+            }
+            "sandbox-discard" => {
+                let params: SandboxDiscardParams = serde_json::from_value(args).unwrap_or_default();
+                Ok(run_jj_sandbox_discard(params))
+            }
+            "abandon" => {
+                let params: AbandonParams = serde_json::from_value(args).unwrap_or_default();
+                Ok(run_jj_abandon(params))
+            }
+            "restore" => {
+                let params: RestoreParams = serde_json::from_value(args).unwrap_or_default();
+                Ok(run_jj_restore(params))
+            }
+            "squash" => {
+                let params: SquashParams = serde_json::from_value(args).unwrap_or_default();
+                Ok(run_jj_squash(params))
+            }
+            "annotate" => {
+                let params: AnnotateParams = serde_json::from_value(args).unwrap_or_default();
+                Ok(run_jj_annotate(params))
+            }
+            "bookmark-list" => {
+                let params: BookmarkListParams = serde_json::from_value(args).unwrap_or_default();
+                Ok(run_jj_bookmark_list(params))
+            }
+            "git-push" => {
+                let params: GitPushParams = serde_json::from_value(args).unwrap_or_default();
+                Ok(run_jj_git_push(params))
+            }
+            "git-fetch" => {
+                let params: GitFetchParams = serde_json::from_value(args).unwrap_or_default();
+                Ok(run_jj_git_fetch(params))
+            }
+            "config-list" => {
+                let params: ConfigListParams = serde_json::from_value(args).unwrap_or_default();
+                Ok(run_jj_config_list(params))
+            }
+            "config-path" => {
+                let params: ConfigPathParams = serde_json::from_value(args).unwrap_or_default();
+                Ok(run_jj_config_path(params))
+            }
+            "workspace-info" => {
+                let params: WorkspaceInfoParams = serde_json::from_value(args).unwrap_or_default();
+                Ok(run_jj_workspace_info(params))
+            }
+            "evolog" => {
+                let params: EvologParams = serde_json::from_value(args).unwrap_or_default();
+                Ok(run_jj_evolog(params))
+            }
+            "describe-auto" => {
+                let params: DescribeAutoParams = serde_json::from_value(args).unwrap_or_default();
+                Ok(run_jj_describe_auto(params))
+            }
+            "complete" => {
+                let params: CompleteParams = serde_json::from_value(args).unwrap_or_default();
+                Ok(run_complete(params))
+            }
+            "read-output" => {
+                let params: ReadOutputParams = serde_json::from_value(args).unwrap_or_default();
+                Ok(run_read_output(params))
+            }
+            "pipeline" => {
+                let params: PipelineParams = serde_json::from_value(args).unwrap_or_default();
+                Ok(run_pipeline(params))
+            }
+            "describe" => {
+                let params: DescribeParams = serde_json::from_value(args).unwrap_or_default();
+                Ok(run_jj_describe(params))
+            }
+            "sign" => {
+                let params: SignParams = serde_json::from_value(args).unwrap_or_default();
+                Ok(run_jj_sign(params))
+            }
+            "discover-repos" => {
+                let params: DiscoverReposParams = serde_json::from_value(args).unwrap_or_default();
+                Ok(run_discover_repos(params))
+            }
+            "remote-list" => {
+                let params: RemoteListParams = serde_json::from_value(args).unwrap_or_default();
+                Ok(run_jj_remote_list(params))
+            }
+            "stash" => {
+                let params: StashParams = serde_json::from_value(args).unwrap_or_default();
+                Ok(run_jj_stash(params))
+            }
+            "unstash" => {
+                let params: UnstashParams = serde_json::from_value(args).unwrap_or_default();
+                Ok(run_jj_unstash(params))
+            }
+            "graph-export" => {
+                let params: GraphExportParams = serde_json::from_value(args).unwrap_or_default();
+                Ok(run_jj_graph_export(params))
+            }
+            "author-stats" => {
+                let params: AuthorStatsParams = serde_json::from_value(args).unwrap_or_default();
+                Ok(run_jj_author_stats(params))
+            }
+            "reconcile" => {
+                let params: ReconcileParams = serde_json::from_value(args).unwrap_or_default();
+                Ok(run_jj_reconcile(params))
+            }
+            "init" => {
+                let params: InitParams = serde_json::from_value(args).unwrap_or_default();
+                Ok(run_jj_init(params))
+            }
+            "deepen" => {
+                let params: DeepenParams = serde_json::from_value(args).unwrap_or_default();
+                Ok(run_jj_deepen(params))
+            }
+            "submodules" => {
+                let params: SubmodulesParams = serde_json::from_value(args).unwrap_or_default();
+                Ok(run_submodules(params))
+            }
+            "append-commit" => {
+                let params: AppendCommitParams = serde_json::from_value(args).unwrap_or_default();
+                Ok(run_jj_append_commit(params))
+            }
+            "file-show" => {
+                let params: FileShowParams = serde_json::from_value(args).unwrap_or_default();
+                Ok(run_jj_file_show(params))
+            }
+            _ => Ok(CallToolResponse {
+                content: vec![ToolResponseContent::Text {
+                    text: format!("Unknown tool: {}", self.name),
+                }],
+                is_error: Some(true),
+                meta: None,
+            }),
+        }
+    }
+}
+
+/// Add repository arguments to a command
+pub fn add_repo_args(args: &mut Vec<String>, repo_path: Option<String>) {
+    if let Some(path) = repo_path {
+        args.push("-R".to_string());
+        args.push(path);
+    }
+}
+
+/// Environment variable enabling watchman as jj's filesystem monitor for every call
+pub const FSMONITOR_ENV_VAR: &str = "JJ_MCP_FSMONITOR";
+
+/// Options taken from a tool call's arguments that apply to every jj command it runs
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct CallOptions {
+    /// Use watchman for working-copy snapshots; overrides [`FSMONITOR_ENV_VAR`] when set
+    pub fsmonitor: Option<bool>,
+    /// Record mutating commands instead of running them
+    pub explain: bool,
+    /// Run against a disposable copy of the repository
+    pub sandbox: bool,
+    /// Proceed with an operation that requires confirmation, without asking
+    pub confirm: bool,
+    /// Also return jj output that isn't valid UTF-8 as base64
+    pub base64: bool,
+    /// Environment variables set for every jj process, limited to [`CALL_ENV_ALLOWLIST`]
+    pub env: std::collections::BTreeMap<String, String>,
+    /// RFC 3339 time recorded on the commits and operations the call creates
+    pub timestamp: Option<String>,
+    /// Seconds each jj process may run, overriding the server's `--command-timeout`; 0 for no limit
+    pub timeout: Option<f64>,
+}
+
+/// Argument names every tool accepts in addition to its own parameters
+pub const GLOBAL_PARAMETERS: &[&str] = &[
+    "fsmonitor",
+    "explain",
+    "sandbox",
+    "confirm",
+    "base64",
+    "env",
+    "timestamp",
+    "timeout",
+];
+
+/// Check call arguments against a tool's input schema
+///
+/// A schema that lists properties is closed: parameters it doesn't declare are
+/// rejected, naming the closest declared one, rather than silently dropped. The
+/// global parameters are accepted by every tool.
+pub fn validate_arguments(schema: &Value, args: &Value) -> Result<()> {
+    if args.is_null() {
+        return Ok(());
+    }
+    let mut args = args.clone();
+    if let Some(properties) = schema.get("properties").and_then(Value::as_object)
+        && let Some(object) = args.as_object_mut()
+    {
+        object.retain(|key, _| {
+            properties.contains_key(key) || !GLOBAL_PARAMETERS.contains(&key.as_str())
+        });
+        let unknown: Vec<String> = object
+            .keys()
+            .filter(|key| !properties.contains_key(*key))
+            .map(
+                |key| match closest_name(key, properties.keys().map(String::as_str)) {
+                    Some(known) => format!("{} (did you mean {}?)", key, known),
+                    None => key.clone(),
+                },
+            )
+            .collect();
+        if !unknown.is_empty() {
+            return Err(anyhow::anyhow!(
+                "Error: unknown parameter {}",
+                unknown.join(", ")
+            ));
+        }
+    }
+
+    let validator = jsonschema::validator_for(schema)
+        .map_err(|e| anyhow::anyhow!("Error: invalid input schema: {}", e))?;
+    let errors: Vec<String> = validator
+        .iter_errors(&args)
+        .map(|error| {
+            let path = error.instance_path.to_string();
+            match path.trim_start_matches('/') {
+                "" => error.to_string(),
+                path => format!("{}: {}", path, error),
+            }
+        })
+        .collect();
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(anyhow::anyhow!(
+            "Error: invalid arguments: {}",
+            errors.join("; ")
+        ))
+    }
+}
+
+/// The candidate closest to `name` by edit distance, if any is close enough to be a typo
+fn closest_name<'a>(name: &str, candidates: impl Iterator<Item = &'a str>) -> Option<&'a str> {
+    let distance = |a: &str, b: &str| {
+        let b: Vec<char> = b.chars().collect();
+        let mut row: Vec<usize> = (0..=b.len()).collect();
+        for (i, a) in a.chars().enumerate() {
+            let mut previous = row[0];
+            row[0] = i + 1;
+            for (j, b) in b.iter().enumerate() {
+                let substitution = previous + usize::from(a != *b);
+                previous = row[j + 1];
+                row[j + 1] = substitution.min(row[j] + 1).min(previous + 1);
+            }
+        }
+        row[b.len()]
+    };
+    candidates
+        .map(|candidate| {
+            (
+                distance(&name.to_lowercase(), &candidate.to_lowercase()),
+                candidate,
+            )
+        })
+        .filter(|(distance, _)| *distance <= (name.chars().count() / 3).max(1))
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, candidate)| candidate)
+}
+
+/// Environment variables a call may set for jj through its `env` parameter
+pub const CALL_ENV_ALLOWLIST: &[&str] = &[
+    "JJ_CONFIG",
+    "JJ_USER",
+    "JJ_EMAIL",
+    "JJ_TIMESTAMP",
+    "JJ_OP_TIMESTAMP",
+    "JJ_OP_HOSTNAME",
+    "JJ_OP_USERNAME",
+    "JJ_RANDOMNESS_SEED",
+];
+
+/// Check a call's `timestamp` parameter is an RFC 3339 date and time
+pub fn check_call_timestamp(args: &Value) -> Result<()> {
+    static RFC_3339: std::sync::LazyLock<regex::Regex> = std::sync::LazyLock::new(|| {
+        regex::Regex::new(
+            r"^\d{4}-\d{2}-\d{2}[Tt ]\d{2}:\d{2}:\d{2}(\.\d+)?([Zz]|[+-]\d{2}:\d{2})$",
+        )
+        .unwrap()
+    });
+
+    match args.get("timestamp") {
+        None => Ok(()),
+        Some(Value::String(timestamp)) if RFC_3339.is_match(timestamp) => Ok(()),
+        Some(other) => Err(anyhow::anyhow!(
+            "Error: timestamp must be an RFC 3339 date and time such as 2024-01-01T00:00:00Z, not {}",
+            other
+        )),
+    }
+}
+
+/// Check a call's `env` parameter: an object of strings with allowlisted keys
+pub fn check_call_env(args: &Value) -> Result<()> {
+    let Some(env) = args.get("env") else {
+        return Ok(());
+    };
+    let env = env
+        .as_object()
+        .ok_or_else(|| anyhow::anyhow!("Error: env must be an object of strings"))?;
+    for (key, value) in env {
+        if !CALL_ENV_ALLOWLIST.contains(&key.as_str()) {
+            return Err(anyhow::anyhow!(
+                "Error: env variable {} is not allowed (allowed: {})",
+                key,
+                CALL_ENV_ALLOWLIST.join(", ")
+            ));
+        }
+        if !value.is_string() {
+            return Err(anyhow::anyhow!(
+                "Error: env variable {} must be a string",
+                key
+            ));
+        }
+    }
+    Ok(())
+}
+
+impl CallOptions {
+    /// Read the call-wide options from raw tool arguments
+    pub fn from_args(args: &Value) -> Self {
+        CallOptions {
+            fsmonitor: args.get("fsmonitor").and_then(Value::as_bool),
+            explain: args
+                .get("explain")
+                .and_then(Value::as_bool)
+                .unwrap_or(false),
+            sandbox: args
+                .get("sandbox")
+                .and_then(Value::as_bool)
+                .unwrap_or(false),
+            confirm: args
+                .get("confirm")
+                .and_then(Value::as_bool)
+                .unwrap_or(false),
+            base64: args.get("base64").and_then(Value::as_bool).unwrap_or(false),
+            env: args
+                .get("env")
+                .and_then(Value::as_object)
+                .map(|env| {
+                    env.iter()
+                        .filter(|(key, _)| CALL_ENV_ALLOWLIST.contains(&key.as_str()))
+                        .filter_map(|(key, value)| Some((key.clone(), value.as_str()?.to_string())))
+                        .collect()
+                })
+                .unwrap_or_default(),
+            timestamp: args
+                .get("timestamp")
+                .and_then(Value::as_str)
+                .map(str::to_string),
+            timeout: args.get("timeout").and_then(Value::as_f64),
+        }
+    }
+}
+
+/// Add the schemas of [`GLOBAL_PARAMETERS`] to a tool's input schema
+pub fn add_global_parameters(input_schema: &mut Value) {
+    if let Some(properties) = input_schema["properties"].as_object_mut() {
+        properties.insert(
+            "fsmonitor".to_string(),
+            serde_json::json!({
+                "type": "boolean",
+                "description": "Use watchman to speed up working-copy snapshots (overrides JJ_MCP_FSMONITOR)"
+            }),
+        );
+        properties.insert(
+            "timeout".to_string(),
+            serde_json::json!({
+                "type": "number",
+                "description": "Seconds each jj command may run before it is stopped and its output so far returned (0 for no limit)"
+            }),
+        );
+        properties.insert(
+            "explain".to_string(),
+            serde_json::json!({
+                "type": "boolean",
+                "description": "Return the jj commands this call would run, without running them"
+            }),
+        );
+        properties.insert(
+            "sandbox".to_string(),
+            serde_json::json!({
+                "type": "boolean",
+                "description": "Run against a disposable copy of the repository; apply the changes later with sandbox-promote"
+            }),
+        );
+        properties.insert(
+            "confirm".to_string(),
+            serde_json::json!({
+                "type": "boolean",
+                "description": "Confirm an operation the server requires confirmation for"
+            }),
+        );
+        properties.insert(
+            "base64".to_string(),
+            serde_json::json!({
+                "type": "boolean",
+                "description": "Also return jj output that isn't valid UTF-8 as base64-encoded bytes"
+            }),
+        );
+        properties.insert(
+            "env".to_string(),
+            serde_json::json!({
+                "type": "object",
+                "additionalProperties": {"type": "string"},
+                "description": format!(
+                    "Environment variables for the jj processes of this call ({})",
+                    CALL_ENV_ALLOWLIST.join(", ")
+                )
+            }),
+        );
+    }
+}
+
+/// Add the `timestamp` parameter, which only matters to tools that create commits
+pub fn add_timestamp_parameter(input_schema: &mut Value) {
+    if let Some(properties) = input_schema["properties"].as_object_mut() {
+        properties.insert(
+            "timestamp".to_string(),
+            serde_json::json!({
+                "type": "string",
+                "description": "RFC 3339 time to record on the commits and operations this call creates, for reproducible commits"
+            }),
+        );
+    }
+}
+
+/// A tool as clients see it: its schema extended with the options every call accepts
+/// (and `timestamp` for tools that can create commits)
+pub fn advertised_tool(mut tool: JjTool) -> JjTool {
+    add_global_parameters(&mut tool.input_schema);
+    if !tool.annotations.read_only_hint {
+        add_timestamp_parameter(&mut tool.input_schema);
+    }
+    tool
+}
+
+/// A jj command seen while explaining a tool call
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExplainedCommand {
+    pub argv: Vec<String>,
+    /// Read-only lookups still run so later commands can be assembled
+    pub executed: bool,
+}
+
+thread_local! {
+    static CALL_OPTIONS: std::cell::RefCell<CallOptions> = Default::default();
+    static EXPLAINED_COMMANDS: std::cell::RefCell<Vec<ExplainedCommand>> = Default::default();
+}
+
+/// Global options of a jj command line that take a value
+const JJ_GLOBAL_OPTIONS_WITH_VALUE: &[&str] = &[
+    "-R",
+    "--repository",
+    "--at-op",
+    "--at-operation",
+    "--config",
+    "--config-toml",
+    "--config-file",
+    "--color",
+];
+
+/// Whether a jj command line only reads the repository
+pub fn is_read_only_jj_command(args: &[String]) -> bool {
+    let mut words = Vec::new();
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        if JJ_GLOBAL_OPTIONS_WITH_VALUE.contains(&arg.as_str()) {
+            iter.next();
+        } else if !arg.starts_with('-') || matches!(arg.as_str(), "--version" | "--help") {
+            words.push(arg.as_str());
+            if words.len() == 2 {
+                break;
+            }
+        } else if !words.is_empty() {
+            // Only the word right after the subcommand names a nested subcommand
+            break;
+        }
+    }
+
+    let flag = |names: &[&str]| args.iter().any(|arg| names.contains(&arg.as_str()));
+    match words.as_slice() {
+        [] => false,
+        [
+            "log" | "diff" | "show" | "root" | "status" | "st" | "evolog" | "interdiff",
+            ..,
+        ]
+        | ["version" | "help" | "--version" | "--help", ..] => true,
+        ["file", "list" | "show" | "annotate"]
+        | ["op" | "operation", "log" | "show" | "diff"]
+        | ["config", "get" | "list" | "path"]
+        | ["bookmark" | "b" | "tag", "list" | "l"]
+        | ["workspace", "list" | "root"] => true,
+        ["git", "remote"] => flag(&["list"]),
+        ["resolve", ..] => flag(&["--list", "-l"]),
+        _ => false,
+    }
+}
+
+/// Run `f` with `options` applied to the jj commands it spawns on this thread
+pub fn with_call_options<T>(options: CallOptions, f: impl FnOnce() -> T) -> T {
+    let previous = CALL_OPTIONS.with(|current| current.replace(options));
+    let result = f();
+    CALL_OPTIONS.with(|current| current.replace(previous));
+    result
+}
+
+/// Options of the tool call being handled on this thread
+pub fn current_call_options() -> CallOptions {
+    CALL_OPTIONS.with(|current| current.borrow().clone())
+}
+
+/// Whether watchman should be requested, from the call override or the environment
+pub fn fsmonitor_setting(call: Option<bool>, env_value: Option<&str>) -> Option<bool> {
+    call.or_else(|| {
+        env_value.map(|value| {
+            matches!(
+                value.trim().to_ascii_lowercase().as_str(),
+                "1" | "true" | "yes" | "watchman"
+            )
+        })
+    })
+}
+
+/// Global arguments selecting jj's filesystem monitor, empty when left to the user's config
+pub fn fsmonitor_args(setting: Option<bool>) -> Vec<String> {
+    match setting {
+        Some(true) => vec![
+            "--config".to_string(),
+            "core.fsmonitor=watchman".to_string(),
+        ],
+        Some(false) => vec!["--config".to_string(), "core.fsmonitor=none".to_string()],
+        None => Vec::new(),
+    }
+}
+
+/// Output of a successful jj command
+///
+/// jj writes results to stdout but reports what a mutation did (created,
+/// rebased or abandoned commits) on stderr, so both are kept.
+#[derive(Debug, Default, Clone)]
+pub struct JjOutput {
+    pub stdout: String,
+    pub stderr: String,
+}
+
+/// Run a jj command synchronously
+pub fn run_jj_command_sync(args: Vec<String>, cwd: Option<String>) -> Result<String> {
+    run_jj_command_output(args, cwd).map(|output| output.stdout)
+}
+
+/// Run a jj command synchronously, capturing both stdout and stderr
+pub fn run_jj_command_output(args: Vec<String>, cwd: Option<String>) -> Result<JjOutput> {
+    let output = execute_jj(args.clone(), cwd)?;
+    Ok(JjOutput {
+        stdout: decode_output(&args, &output.stdout).trim().to_string(),
+        stderr: String::from_utf8_lossy(&output.stderr).trim().to_string(),
+    })
+}
+
+/// jj output that wasn't valid UTF-8, kept for calls made with the `base64` option
+#[derive(Debug, Clone, PartialEq)]
+pub struct RawOutput {
+    pub argv: Vec<String>,
+    pub bytes: Vec<u8>,
+}
+
+thread_local! {
+    static RAW_OUTPUTS: std::cell::RefCell<Vec<RawOutput>> = const { std::cell::RefCell::new(Vec::new()) };
+}
+
+/// Decode jj's stdout, keeping the raw bytes when they aren't UTF-8 and the call asked for base64
+fn decode_output(args: &[String], bytes: &[u8]) -> String {
+    if std::str::from_utf8(bytes).is_err() && current_call_options().base64 {
+        RAW_OUTPUTS.with(|outputs| {
+            outputs.borrow_mut().push(RawOutput {
+                argv: std::iter::once(jj_command().to_string())
+                    .chain(args.iter().cloned())
+                    .collect(),
+                bytes: bytes.to_vec(),
+            })
+        });
+    }
+    String::from_utf8_lossy(bytes).into_owned()
+}
+
+/// What jj reports on stderr when another process changed the repository at the same time
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConcurrentOperation {
+    /// jj found divergent operation heads and merged them
+    Merged,
+    /// The working copy was last updated at an operation the repository has moved past
+    StaleWorkingCopy,
+}
+
+/// Recognize jj's messages about concurrent operations
+pub fn concurrent_operation(stderr: &str) -> Option<ConcurrentOperation> {
+    let stderr = stderr.to_lowercase();
+    if stderr.contains("working copy is stale")
+        || stderr.contains("sibling of the working copy's operation")
+    {
+        Some(ConcurrentOperation::StaleWorkingCopy)
+    } else if stderr.contains("concurrent modification detected") {
+        Some(ConcurrentOperation::Merged)
+    } else {
+        None
+    }
+}
+
+/// Advice returned alongside a concurrent operation report
+const CONCURRENT_OPERATION_HINT: &str = "another process changed the repository at the same time; call the reconcile tool, then re-read status and log before continuing";
+
+thread_local! {
+    static CONCURRENT_OPERATIONS: std::cell::RefCell<Vec<String>> = const { std::cell::RefCell::new(Vec::new()) };
+}
+
+/// Remember a successful command whose stderr reports merged concurrent operations
+fn note_concurrent_operation(args: &[String], stderr: &[u8]) {
+    if concurrent_operation(&String::from_utf8_lossy(stderr)) == Some(ConcurrentOperation::Merged) {
+        CONCURRENT_OPERATIONS.with(|commands| commands.borrow_mut().push(jj_command_line(args)));
+    }
+}
+
+/// Append a structured report when commands in this call ran alongside another operation
+///
+/// The commands themselves succeeded, so the response isn't marked as an error, but
+/// anything read before them may be out of date.
+fn attach_concurrent_operations(mut response: CallToolResponse) -> CallToolResponse {
+    let commands = CONCURRENT_OPERATIONS.with(|commands| commands.take());
+    if !commands.is_empty() {
+        let report = serde_json::json!({
+            "error": "concurrent operation",
+            "kind": "merged",
+            "commands": commands,
+            "hint": CONCURRENT_OPERATION_HINT,
+        });
+        response.content.push(ToolResponseContent::Text {
+            text: report.to_string(),
+        });
+    }
+    response
+}
+
+/// Append the raw outputs recorded during a call, base64-encoded, as JSON text blocks
+fn attach_raw_outputs(mut response: CallToolResponse) -> CallToolResponse {
+    use base64::Engine as _;
+
+    for output in RAW_OUTPUTS.with(|outputs| outputs.take()) {
+        let encoded = serde_json::json!({
+            "command": output.argv.join(" "),
+            "encoding": "base64",
+            "data": base64::engine::general_purpose::STANDARD.encode(&output.bytes),
+        });
+        response.content.push(ToolResponseContent::Text {
+            text: encoded.to_string(),
+        });
+    }
+    response
+}
+
+/// Run a jj command synchronously, returning stdout unmodified (e.g. binary file content)
+pub fn run_jj_command_bytes(args: Vec<String>, cwd: Option<String>) -> Result<Vec<u8>> {
+    execute_jj(args, cwd).map(|output| output.stdout)
+}
+
+/// Counts of jj processes running and waiting in a [`ProcessPool`]
+#[derive(Debug, Clone, Copy, Serialize, Default, PartialEq)]
+pub struct PoolStats {
+    /// `None` when the number of processes is unlimited
+    pub limit: Option<usize>,
+    pub running: usize,
+    pub queued: usize,
+    #[serde(rename = "peakQueued")]
+    pub peak_queued: usize,
+}
+
+/// Limits how many jj processes run at once; callers over the limit wait in a queue
+#[derive(Debug, Default)]
+pub struct ProcessPool {
+    stats: std::sync::Mutex<PoolStats>,
+    available: std::sync::Condvar,
+}
+
+/// A slot in a [`ProcessPool`], released on drop
+pub struct PoolPermit<'a> {
+    pool: &'a ProcessPool,
+}
+
+impl ProcessPool {
+    pub fn new(limit: Option<usize>) -> Self {
+        ProcessPool {
+            stats: std::sync::Mutex::new(PoolStats {
+                limit,
+                ..PoolStats::default()
+            }),
+            available: std::sync::Condvar::new(),
+        }
+    }
+
+    /// Wait for a free slot
+    pub fn acquire(&self) -> PoolPermit<'_> {
+        let mut stats = self.stats.lock().unwrap();
+        if stats.limit.is_some_and(|limit| stats.running >= limit) {
+            stats.queued += 1;
+            stats.peak_queued = stats.peak_queued.max(stats.queued);
+            stats = self
+                .available
+                .wait_while(stats, |stats| {
+                    stats.limit.is_some_and(|limit| stats.running >= limit)
+                })
+                .unwrap();
+            stats.queued -= 1;
+        }
+        stats.running += 1;
+        PoolPermit { pool: self }
+    }
+
+    pub fn stats(&self) -> PoolStats {
+        *self.stats.lock().unwrap()
+    }
+}
+
+impl Drop for PoolPermit<'_> {
+    fn drop(&mut self) {
+        self.pool.stats.lock().unwrap().running -= 1;
+        self.pool.available.notify_one();
+    }
+}
+
+/// Shared by every jj process the server spawns
+static JJ_PROCESSES: std::sync::LazyLock<ProcessPool> =
+    std::sync::LazyLock::new(|| ProcessPool::new(server_config().concurrency_limit()));
+
+/// Current use of the jj process pool
+pub fn jj_process_stats() -> PoolStats {
+    JJ_PROCESSES.stats()
+}
+
+/// Locale every jj process runs with
+pub const JJ_LOCALE: &str = "C.UTF-8";
+
+/// Build the jj command for `args`
+///
+/// Returns `None` in explain mode when the command mutates the repo; it is
+/// recorded instead of run.
+fn prepare_jj(args: &[String], cwd: Option<String>) -> Option<std::process::Command> {
+    let options = current_call_options();
+    let fsmonitor = fsmonitor_setting(
+        options.fsmonitor,
+        std::env::var(FSMONITOR_ENV_VAR).ok().as_deref(),
+    );
+
+    let mut cmd = std::process::Command::new(jj_command());
+    // Pin the locale so messages and paths come out the same, as UTF-8, everywhere
+    cmd.env("LC_ALL", JJ_LOCALE);
+    cmd.envs(&options.env);
+    if let Some(timestamp) = &options.timestamp {
+        cmd.env("JJ_TIMESTAMP", timestamp);
+        cmd.env("JJ_OP_TIMESTAMP", timestamp);
+    }
+    cmd.args(fsmonitor_args(fsmonitor));
+    if options.explain {
+        let executed = is_read_only_jj_command(args);
+        EXPLAINED_COMMANDS.with(|commands| {
+            commands.borrow_mut().push(ExplainedCommand {
+                argv: std::iter::once(jj_command().to_string())
+                    .chain(args.iter().cloned())
+                    .collect(),
+                executed,
+            })
+        });
+        if !executed {
+            return None;
+        }
+        // Lookups must not snapshot the working copy, which would record an operation
+        cmd.arg("--ignore-working-copy");
+    }
+    cmd.args(args);
+    cmd.stdout(std::process::Stdio::piped());
+    cmd.stderr(std::process::Stdio::piped());
+
+    if let Some(cwd_path) = cwd {
+        cmd.current_dir(cwd_path);
+    }
+    Some(cmd)
+}
+
+thread_local! {
+    static JJ_STDIN: std::cell::RefCell<Option<String>> = const { std::cell::RefCell::new(None) };
+}
+
+/// Run `run` with `input` fed to the stdin of the jj processes it starts
+fn with_jj_stdin<T>(input: &str, run: impl FnOnce() -> T) -> T {
+    JJ_STDIN.with(|stdin| *stdin.borrow_mut() = Some(input.to_string()));
+    let result = run();
+    JJ_STDIN.with(|stdin| stdin.borrow_mut().take());
+    result
+}
+
+/// Start a prepared jj command, writing any input from [`with_jj_stdin`] on its own thread
+///
+/// Without input jj gets no stdin, so it never reads the protocol stream.
+fn spawn_jj(cmd: &mut std::process::Command) -> std::io::Result<std::process::Child> {
+    let input = JJ_STDIN.with(|stdin| stdin.borrow().clone());
+    cmd.stdin(match input {
+        Some(_) => std::process::Stdio::piped(),
+        None => std::process::Stdio::null(),
+    });
+    let mut child = cmd.spawn()?;
+    if let (Some(input), Some(mut stdin)) = (input, child.stdin.take()) {
+        std::thread::spawn(move || {
+            let _ = std::io::Write::write_all(&mut stdin, input.as_bytes());
+        });
+    }
+    Ok(child)
+}
+
+/// The successful, empty output of a command skipped in explain mode
+fn skipped_output() -> std::process::Output {
+    std::process::Output {
+        status: std::process::ExitStatus::default(),
+        stdout: Vec::new(),
+        stderr: Vec::new(),
+    }
+}
+
+/// Messages, lowercased, that jj or git print when another process holds a lock
+const LOCK_CONTENTION_MESSAGES: &[&str] = &[
+    "failed to lock",
+    "could not acquire lock",
+    "resource temporarily unavailable",
+    "index.lock",
+    "concurrent checkout",
+];
+
+/// Whether a jj failure was caused by another process holding a repository lock
+pub fn is_lock_contention(message: &str) -> bool {
+    let message = message.to_lowercase();
+    LOCK_CONTENTION_MESSAGES
+        .iter()
+        .any(|pattern| message.contains(pattern))
+}
+
+/// How long to wait before retry number `attempt` (from zero): 100ms, doubling up to 2s
+pub fn lock_retry_delay(attempt: u32) -> std::time::Duration {
+    std::time::Duration::from_millis((100u64 << attempt.min(5)).min(2000))
+}
+
+/// Run a jj invocation, retrying with backoff while the repository is locked
+///
+/// IDEs snapshot the working copy in the background, so a lock held for a moment
+/// is common and should not fail an automated caller.
+fn retry_on_lock<T>(mut run: impl FnMut() -> Result<T>) -> Result<T> {
+    let retries = server_config().lock_retries();
+    let mut attempt = 0;
+    loop {
+        match run() {
+            Err(e) if is_lock_contention(&e.to_string()) => {
+                if attempt == retries {
+                    return Err(if retries == 0 {
+                        e
+                    } else {
+                        anyhow::anyhow!("{} (still locked after {} retries)", e, retries)
+                    });
+                }
+                tracing::warn!(attempt = attempt + 1, retries, error = %e, "repository locked, retrying");
+                std::thread::sleep(lock_retry_delay(attempt));
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Spawn jj and wait for it, turning a non-zero exit into an error carrying its stderr
+///
+/// Failures caused by a locked repository are retried; see [`retry_on_lock`].
+fn execute_jj(args: Vec<String>, cwd: Option<String>) -> Result<std::process::Output> {
+    retry_on_lock(|| execute_jj_once(&args, cwd.clone()))
+}
+
+fn execute_jj_once(args: &[String], cwd: Option<String>) -> Result<std::process::Output> {
+    // Only a process whose output is read as it runs can be stopped at a deadline
+    if command_timeout().is_some() {
+        return execute_jj_streamed_once(args, cwd, usize::MAX).map(|(output, _)| {
+            note_concurrent_operation(args, &output.stderr);
+            output
+        });
+    }
+    let Some(mut cmd) = prepare_jj(args, cwd.clone()) else {
+        return Ok(skipped_output());
+    };
+
+    let permit = JJ_PROCESSES.acquire();
+    tracing::debug!(command = %jj_command_line(args), cwd, "spawning jj");
+    let started = std::time::Instant::now();
+    let output = spawn_jj(&mut cmd).and_then(std::process::Child::wait_with_output);
+    drop(permit);
+
+    match output {
+        Ok(output) => {
+            tracing::debug!(
+                command = %jj_command_line(args),
+                duration_ms = started.elapsed().as_millis() as u64,
+                status = output.status.code(),
+                "jj exited"
+            );
+            if output.status.success() {
+                note_concurrent_operation(args, &output.stderr);
+                Ok(output)
+            } else {
+                Err(jj_failure(args, &String::from_utf8_lossy(&output.stderr)))
+            }
+        }
+        Err(e) => {
+            tracing::error!(command = %jj_command_line(args), error = %e, "failed to spawn jj");
+            Err(anyhow::anyhow!("Error: {}", e))
+        }
+    }
+}
+
+/// A jj invocation as it would be typed, for logs and error reports
+fn jj_command_line(args: &[String]) -> String {
+    std::iter::once(jj_command().to_string())
+        .chain(args.iter().cloned())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// The subcommand or option clap rejected, from jj's stderr
+pub fn unrecognized_jj_argument(stderr: &str) -> Option<String> {
+    stderr.lines().find_map(|line| {
+        let rest = line
+            .trim()
+            .strip_prefix("error: unrecognized subcommand ")
+            .or_else(|| line.trim().strip_prefix("error: unexpected argument "))?;
+        let quoted = rest.strip_prefix('\'')?;
+        quoted
+            .split_once('\'')
+            .map(|(argument, _)| argument.to_string())
+    })
+}
+
+/// The error for a failed jj command
+///
+/// When jj doesn't recognize a subcommand or option the error is a JSON object
+/// naming the server feature that needs a newer jj, instead of clap's usage text.
+/// A stale working copy is likewise reported as JSON pointing at the reconcile tool.
+fn jj_failure(args: &[String], stderr: &str) -> anyhow::Error {
+    let command = jj_command_line(args);
+    if concurrent_operation(stderr) == Some(ConcurrentOperation::StaleWorkingCopy) {
+        let error = serde_json::json!({
+            "error": "concurrent operation",
+            "kind": "stale working copy",
+            "command": command,
+            "detail": stderr.trim(),
+            "hint": CONCURRENT_OPERATION_HINT,
+        });
+        return anyhow::anyhow!("Error: {}", error);
+    }
+    let Some(argument) = unrecognized_jj_argument(stderr) else {
+        return anyhow::anyhow!("Error: {}", stderr.trim());
+    };
+    let requirement = TOOL_JJ_REQUIREMENTS.iter().find(|(_, _, feature)| {
+        feature
+            .split_whitespace()
+            .skip(1)
+            .all(|word| args.iter().any(|arg| arg == word))
+    });
+    let installed = installed_jj_version().ok();
+
+    let error = serde_json::json!({
+        "error": "unsupported by the installed jj",
+        "command": command,
+        "unrecognized": argument,
+        "feature": requirement.map(|(_, _, feature)| feature),
+        "tool": requirement.map(|(tool, _, _)| tool),
+        "requiredVersion": requirement.map(|(_, version, _)| version.to_string()),
+        "installedVersion": installed.map(|version| version.to_string()),
+        "hint": "upgrade jj to use this feature",
+    });
+    anyhow::anyhow!("Error: {}", error)
+}
+
+/// The error for a jj process stopped at its deadline, carrying the stdout it had produced
+fn jj_timeout(args: &[String], timeout: std::time::Duration, stdout: &[u8]) -> anyhow::Error {
+    let error = serde_json::json!({
+        "error": "timed out",
+        "code": "timeout",
+        "command": jj_command_line(args),
+        "timeoutSeconds": timeout.as_secs_f64(),
+        "partial": true,
+        "output": String::from_utf8_lossy(stdout).trim_end(),
+    });
+    anyhow::anyhow!("Error: {}", error)
+}
+
+/// How long a jj process may run: the call's `timeout`, else the server's `--command-timeout`
+pub fn command_timeout() -> Option<std::time::Duration> {
+    match current_call_options().timeout {
+        Some(seconds) if seconds > 0.0 => Some(std::time::Duration::from_secs_f64(seconds)),
+        Some(_) => None,
+        None => server_config().command_timeout,
+    }
+}
+
+/// Read at most `budget` bytes, reporting whether more were available
+///
+/// A truncated result is cut back to its last complete line.
+pub fn read_with_budget(
+    reader: &mut impl std::io::Read,
+    budget: usize,
+) -> std::io::Result<(Vec<u8>, bool)> {
+    let mut content = Vec::new();
+    let mut chunk = [0u8; 64 * 1024];
+    loop {
+        let read = reader.read(&mut chunk)?;
+        if read == 0 {
+            return Ok((content, false));
+        }
+        if content.len() + read > budget {
+            content.extend_from_slice(&chunk[..budget - content.len()]);
+            if let Some(newline) = content.iter().rposition(|&byte| byte == b'\n') {
+                content.truncate(newline + 1);
+            }
+            return Ok((content, true));
+        }
+        content.extend_from_slice(&chunk[..read]);
+    }
+}
+
+/// A reader that also appends what it reads to a shared buffer, when given one
+struct TeeReader<R> {
+    inner: R,
+    copy: Option<std::sync::Arc<std::sync::Mutex<Vec<u8>>>>,
+}
+
+impl<R: std::io::Read> std::io::Read for TeeReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        if let Some(copy) = &self.copy {
+            copy.lock().unwrap().extend_from_slice(&buf[..read]);
+        }
+        Ok(read)
+    }
+}
+
+/// Whether a thread finishes within a short grace period
+fn finishes_soon<T>(thread: &std::thread::JoinHandle<T>) -> bool {
+    let started = std::time::Instant::now();
+    while !thread.is_finished() {
+        if started.elapsed() >= std::time::Duration::from_millis(200) {
+            return false;
+        }
+        std::thread::sleep(std::time::Duration::from_millis(10));
+    }
+    true
+}
+
+/// Spawn jj and read its stdout incrementally, stopping the process once the budget is spent
+///
+/// Returns the output and whether it was truncated.
+fn execute_jj_streamed(
+    args: Vec<String>,
+    cwd: Option<String>,
+    budget: usize,
+) -> Result<(std::process::Output, bool)> {
+    retry_on_lock(|| execute_jj_streamed_once(&args, cwd.clone(), budget))
+}
+
+fn execute_jj_streamed_once(
+    args: &[String],
+    cwd: Option<String>,
+    budget: usize,
+) -> Result<(std::process::Output, bool)> {
+    let Some(mut cmd) = prepare_jj(args, cwd.clone()) else {
+        return Ok((skipped_output(), false));
+    };
+
+    let permit = JJ_PROCESSES.acquire();
+    tracing::debug!(command = %jj_command_line(args), cwd, budget, "spawning jj");
+    let started = std::time::Instant::now();
+    let timeout = command_timeout();
+    let mut child = spawn_jj(&mut cmd).map_err(|e| {
+        tracing::error!(command = %jj_command_line(args), error = %e, "failed to spawn jj");
+        anyhow::anyhow!("Error: {}", e)
+    })?;
+    // Drain stderr on its own thread so a chatty process can't block on a full pipe
+    let stderr = child.stderr.take().map(|mut stderr| {
+        std::thread::spawn(move || {
+            let mut content = Vec::new();
+            let _ = std::io::Read::read_to_end(&mut stderr, &mut content);
+            content
+        })
+    });
+    // With a deadline, keep a copy of stdout as it is read: a process jj started can
+    // hold the pipe open after jj itself is stopped, so the reader may never finish
+    let copy = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+    let reader = child.stdout.take().map(|stdout| {
+        let mut stdout = TeeReader {
+            inner: stdout,
+            copy: timeout.map(|_| copy.clone()),
+        };
+        std::thread::spawn(move || read_with_budget(&mut stdout, budget))
+    });
+
+    // Stop the process at the deadline; whatever it printed by then is kept
+    let watch = |child: &mut std::process::Child, done: &dyn Fn() -> bool| -> Result<bool> {
+        let Some(timeout) = timeout else {
+            return Ok(false);
+        };
+        while !done() && child.try_wait()?.is_none() {
+            if started.elapsed() >= timeout {
+                let _ = child.kill();
+                return Ok(true);
+            }
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+        Ok(false)
+    };
+    let mut timed_out = watch(&mut child, &|| {
+        reader.as_ref().is_none_or(|reader| reader.is_finished())
+    })?;
+    let read = match reader {
+        Some(reader) if !timed_out || finishes_soon(&reader) => {
+            reader.join().unwrap_or_else(|_| Ok((Vec::new(), false)))
+        }
+        Some(_) => {
+            let mut stdout = std::mem::take(&mut *copy.lock().unwrap());
+            stdout.truncate(budget);
+            Ok((stdout, false))
+        }
+        None => Ok((Vec::new(), false)),
+    };
+    if !matches!(read, Ok((_, false))) {
+        let _ = child.kill();
+    }
+    timed_out |= watch(&mut child, &|| false)?;
+    let status = child.wait().map_err(|e| anyhow::anyhow!("Error: {}", e))?;
+    let stderr = stderr
+        .filter(|reader| !timed_out || finishes_soon(reader))
+        .and_then(|reader| reader.join().ok())
+        .unwrap_or_default();
+    drop(permit);
+    tracing::debug!(
+        command = %jj_command_line(args),
+        duration_ms = started.elapsed().as_millis() as u64,
+        status = status.code(),
+        truncated = matches!(read, Ok((_, true))),
+        "jj exited"
+    );
+
+    let (stdout, truncated) = read.map_err(|e| anyhow::anyhow!("Error: {}", e))?;
+    if let Some(timeout) = timeout.filter(|_| timed_out) {
+        tracing::warn!(command = %jj_command_line(args), ?timeout, "jj timed out");
+        return Err(jj_timeout(args, timeout, &stdout));
+    }
+    if !truncated && !status.success() {
+        return Err(jj_failure(args, &String::from_utf8_lossy(&stderr)));
+    }
+    Ok((
+        std::process::Output {
+            status,
+            stdout,
+            stderr,
+        },
+        truncated,
+    ))
+}
+
+/// Run a jj command, keeping at most the configured stream budget of its output in memory
+///
+/// Output past the budget is dropped and replaced with a note saying so.
+pub fn run_jj_command_streamed(args: Vec<String>, cwd: Option<String>) -> Result<String> {
+    let budget = server_config().stream_budget();
+    let (output, truncated) = execute_jj_streamed(args.clone(), cwd, budget)?;
+    let mut text = decode_output(&args, &output.stdout).trim().to_string();
+    if truncated {
+        text.push_str(&format!(
+            "\n... output truncated after {} bytes; narrow the revisions or paths to see the rest",
+            budget
+        ));
+    }
+    Ok(text)
+}
+
+/// Execute jj status command
+pub fn run_jj_status(params: StatusParams) -> CallToolResponse {
+    if let Some(true) = params.all_workspaces {
+        return json_response(
+            all_workspaces_status(params.repo_path, params.cwd)
+                .map(|workspaces| serde_json::json!({ "workspaces": workspaces })),
+        );
+    }
+
+    let mut args = vec!["status".to_string()];
+    add_repo_args(&mut args, params.repo_path);
+
+    match run_jj_command_sync(args, params.cwd) {
+        Ok(output) => CallToolResponse {
+            content: vec![ToolResponseContent::Text { text: output }],
+            is_error: Some(false),
+            meta: None,
+        },
+        Err(e) => CallToolResponse {
+            content: vec![ToolResponseContent::Text {
+                text: e.to_string(),
+            }],
+            is_error: Some(true),
+            meta: None,
+        },
+    }
+}
+
+/// A file changed in a commit, from a `jj diff --summary` line
+#[derive(Debug, Serialize, Deserialize, Default, Clone, PartialEq)]
+pub struct FileChange {
+    /// `modified`, `added`, `deleted`, `renamed` or `copied`
+    pub status: String,
+    /// The path, or `{old => new}` form jj prints for renames and copies
+    pub path: String,
+}
+
+/// Parse the output of `jj diff --summary`, skipping lines it doesn't recognize
+pub fn parse_diff_summary(output: &str) -> Vec<FileChange> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let (letter, path) = line.split_once(' ')?;
+            let status = match letter {
+                "M" => "modified",
+                "A" => "added",
+                "D" => "deleted",
+                "R" => "renamed",
+                "C" => "copied",
+                _ => return None,
+            };
+            Some(FileChange {
+                status: status.to_string(),
+                path: path.to_string(),
+            })
+        })
+        .collect()
+}
+
+/// One workspace's working-copy commit, as the status tool reports with `allWorkspaces`
+#[derive(Debug, Serialize, Deserialize, Default, Clone, PartialEq)]
+pub struct WorkspaceStatus {
+    pub name: String,
+    /// Whether this is the workspace the call ran in
+    pub current: bool,
+    #[serde(rename = "changeId")]
+    pub change_id: String,
+    #[serde(rename = "commitId")]
+    pub commit_id: String,
+    pub description: String,
+    pub conflict: bool,
+    pub changes: Vec<FileChange>,
+}
+
+/// Summarize the working-copy commit of every workspace in the repository
+///
+/// Only the current workspace is snapshotted; the others are reported as their
+/// last snapshot recorded them, since they may live anywhere on disk.
+fn all_workspaces_status(
+    repo_path: Option<String>,
+    cwd: Option<String>,
+) -> Result<Vec<WorkspaceStatus>> {
+    // Resolving `@` snapshots the current workspace first
+    let mut args = vec![
+        "log".to_string(),
+        "--no-graph".to_string(),
+        "-r".to_string(),
+        "@".to_string(),
+        "-T".to_string(),
+        "commit_id".to_string(),
+    ];
+    add_repo_args(&mut args, repo_path.clone());
+    let working_copy = run_jj_command_sync(args, cwd.clone())?;
+
+    let mut args = vec![
+        "workspace".to_string(),
+        "list".to_string(),
+        "--ignore-working-copy".to_string(),
+        "-T".to_string(),
+        log_record_template(&[
+            "name",
+            "target.commit_id()",
+            "target.change_id().short()",
+            "if(target.conflict(), \"true\", \"false\")",
+            "target.description().first_line()",
+        ]),
+    ];
+    add_repo_args(&mut args, repo_path.clone());
+    let output = run_jj_command_sync(args, cwd.clone())?;
+
+    let mut workspaces = Vec::new();
+    for line in output.lines() {
+        let fields: Vec<&str> = line.splitn(5, '\t').collect();
+        let [name, commit_id, change_id, conflict, description] = fields[..] else {
+            continue;
+        };
+        let mut args = vec![
+            "diff".to_string(),
+            "--ignore-working-copy".to_string(),
+            "--summary".to_string(),
+            "-r".to_string(),
+            commit_id.to_string(),
+        ];
+        add_repo_args(&mut args, repo_path.clone());
+        let changes = parse_diff_summary(&run_jj_command_sync(args, cwd.clone())?);
+        workspaces.push(WorkspaceStatus {
+            name: name.to_string(),
+            current: commit_id == working_copy,
+            change_id: change_id.to_string(),
+            commit_id: commit_id.to_string(),
+            description: description.to_string(),
+            conflict: conflict == "true",
+            changes,
+        });
+    }
+    Ok(workspaces)
+}
+
+/// Execute jj rebase command
+pub fn run_jj_rebase(params: RebaseParams) -> CallToolResponse {
+    let mut args = vec!["rebase".to_string()];
+
+    if let Some(source) = params.source {
+        args.push("-s".to_string());
+        args.push(source);
+    }
+
+    if let Some(destination) = params.destination {
+        args.push("-d".to_string());
+        args.push(destination);
+    }
+
+    add_repo_args(&mut args, params.repo_path);
+
+    match run_jj_command_output(args, params.cwd) {
+        Ok(output) => CallToolResponse {
+            meta: serde_json::to_value(parse_rebase_output(&output.stderr)).ok(),
+            // jj reports what a rebase did on stderr
+            content: vec![ToolResponseContent::Text {
+                text: if output.stdout.is_empty() {
+                    output.stderr
+                } else {
+                    output.stdout
+                },
+            }],
+            is_error: Some(false),
+        },
+        Err(e) => CallToolResponse {
+            content: vec![ToolResponseContent::Text {
+                text: e.to_string(),
+            }],
+            is_error: Some(true),
+            meta: None,
+        },
+    }
+}
+
+/// A commit as jj prints it in command summaries: change ID, commit ID, then description
+#[derive(Debug, Serialize, Deserialize, Default, Clone, PartialEq)]
+pub struct CommitSummaryLine {
+    #[serde(rename = "changeId")]
+    pub change_id: String,
+    #[serde(rename = "commitId")]
+    pub commit_id: String,
+    pub conflicted: bool,
+    pub description: String,
+}
+
+impl CommitSummaryLine {
+    /// Parse a line like `qpvuntsm 1a2b3c4d (conflict) Fix parser`
+    pub fn parse(line: &str) -> Option<Self> {
+        let mut fields = line.trim().splitn(3, ' ');
+        let change_id = fields.next().filter(|id| !id.is_empty())?;
+        let commit_id = fields.next()?;
+        let rest = fields.next().unwrap_or("").trim();
+        let (conflicted, description) = match rest.strip_prefix("(conflict)") {
+            Some(description) => (true, description.trim()),
+            None => (false, rest),
+        };
+        Some(CommitSummaryLine {
+            change_id: change_id.to_string(),
+            commit_id: commit_id.to_string(),
+            conflicted,
+            description: description.to_string(),
+        })
+    }
+}
+
+/// What a rebase did, parsed from jj's messages
+#[derive(Debug, Serialize, Deserialize, Default, Clone, PartialEq)]
+pub struct RebaseSummary {
+    /// Commits rebased, including descendants moved along with them
+    #[serde(rename = "rebasedCount")]
+    pub rebased_count: usize,
+    /// Commits that were conflicted by the rebase
+    #[serde(rename = "newConflicts")]
+    pub new_conflicts: Vec<CommitSummaryLine>,
+    /// The working-copy commit, when the rebase moved it
+    #[serde(rename = "workingCopy")]
+    pub working_copy: Option<CommitSummaryLine>,
+}
+
+/// Parse the messages `jj rebase` prints on stderr
+pub fn parse_rebase_output(stderr: &str) -> RebaseSummary {
+    let mut summary = RebaseSummary::default();
+    let mut in_conflicts = false;
+    for line in stderr.lines() {
+        if in_conflicts {
+            if line.starts_with(' ')
+                && let Some(commit) = CommitSummaryLine::parse(line)
+            {
+                summary.new_conflicts.push(commit);
+                continue;
+            }
+            in_conflicts = false;
+        }
+        if let Some(rest) = line.strip_prefix("Rebased ") {
+            summary.rebased_count += rest
+                .split_whitespace()
+                .next()
+                .and_then(|count| count.parse::<usize>().ok())
+                .unwrap_or(0);
+        } else if line.starts_with("Working copy")
+            && let Some((_, commit)) = line.split_once("now at:")
+        {
+            summary.working_copy = CommitSummaryLine::parse(commit);
+        } else if line.starts_with("New conflicts appeared in") {
+            in_conflicts = true;
+        }
+    }
+    summary
+}
+
+/// Execute jj commit command
+pub fn run_jj_commit(params: CommitParams) -> CallToolResponse {
+    if let Some(threshold) = params.max_file_size {
+        let large = resolve_workspace_root(params.repo_path.clone(), params.cwd.clone())
+            .and_then(|root| find_large_files(&root, "@", threshold));
+        let message = match large {
+            Ok(files) if files.is_empty() => None,
+            Ok(files) => Some(format!(
+                "Error: refusing to commit files larger than {} bytes: {}",
+                threshold,
+                files
+                    .iter()
+                    .map(|(path, size)| format!("{} ({} bytes)", path, size))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            )),
+            Err(e) => Some(e.to_string()),
+        };
+        if let Some(text) = message {
+            return CallToolResponse {
+                content: vec![ToolResponseContent::Text { text }],
+                is_error: Some(true),
+                meta: None,
+            };
+        }
+    }
+
+    let message = match CommitMessage::from_params(
+        params.message,
+        params.message_file,
+        params.cwd.as_deref(),
+    ) {
+        Ok(message) => message,
+        Err(e) => return json_response(Err(e)),
+    };
+    // jj commit can't read a message from stdin, so a long one is set with describe
+    // and the commit finished with new, which is what commit does
+    let long_message = message.as_ref().filter(|message| !message.is_inline());
+    let mut args = vec![
+        if long_message.is_some() {
+            "describe"
+        } else {
+            "commit"
+        }
+        .to_string(),
+    ];
+    if let Some(message) = &message {
+        args.extend(message.args());
+    }
+
+    match signing_config_args(
+        params.sign,
+        params.signing_backend.as_deref(),
+        params.signing_key.as_deref(),
+    ) {
+        Ok(signing) => args.extend(signing),
+        Err(e) => return json_response(Err(e)),
+    }
+
+    match author_flag_args(
+        params.author_name.as_deref(),
+        params.author_email.as_deref(),
+        params.repo_path.clone(),
+        params.cwd.clone(),
+    ) {
+        Ok(author) => args.extend(author),
+        Err(e) => return json_response(Err(e)),
+    }
+
+    add_repo_args(&mut args, params.repo_path.clone());
+
+    let result = match long_message {
+        None => run_jj_command_sync(args, params.cwd),
+        Some(message) => message.run(args, params.cwd.clone()).and_then(|described| {
+            let mut args = vec!["new".to_string()];
+            add_repo_args(&mut args, params.repo_path);
+            run_jj_command_sync(args, params.cwd).map(|created| described + &created)
+        }),
+    };
+
+    match result {
+        Ok(output) => CallToolResponse {
+            content: vec![ToolResponseContent::Text { text: output }],
+            is_error: Some(false),
+            meta: None,
+        },
+        Err(e) => CallToolResponse {
+            content: vec![ToolResponseContent::Text {
+                text: e.to_string(),
+            }],
+            is_error: Some(true),
+            meta: None,
+        },
+    }
+}
+
+/// Messages longer than this are fed to jj on stdin instead of with `--message`
+///
+/// Linux caps a single command-line argument at 128 KiB.
+pub const MAX_INLINE_MESSAGE_BYTES: usize = 64 * 1024;
+
+/// A commit message, checked for what jj's command line can carry
+#[derive(Debug)]
+pub struct CommitMessage {
+    pub text: String,
+}
+
+impl CommitMessage {
+    /// Prepare a message for jj
+    pub fn new(text: String) -> Result<Self> {
+        if text.contains('\0') {
+            return Err(anyhow::anyhow!("Error: messages cannot contain NUL bytes"));
+        }
+        Ok(CommitMessage { text })
+    }
+
+    /// The message from a tool's `message` or `messageFile` parameter
+    ///
+    /// `messageFile` is read relative to `cwd`.
+    pub fn from_params(
+        message: Option<String>,
+        message_file: Option<String>,
+        cwd: Option<&str>,
+    ) -> Result<Option<Self>> {
+        let text = match (message, message_file) {
+            (Some(_), Some(_)) => {
+                return Err(anyhow::anyhow!(
+                    "Error: give either message or messageFile, not both"
+                ));
+            }
+            (Some(message), None) => message,
+            (None, Some(file)) => {
+                let base = match cwd {
+                    Some(cwd) => std::path::PathBuf::from(cwd),
+                    None => std::env::current_dir()?,
+                };
+                std::fs::read_to_string(base.join(&file))
+                    .map_err(|e| anyhow::anyhow!("Error: cannot read {}: {}", file, e))?
+            }
+            (None, None) => return Ok(None),
+        };
+        Self::new(text).map(Some)
+    }
+
+    /// Whether the message fits on the command line
+    pub fn is_inline(&self) -> bool {
+        self.text.len() <= MAX_INLINE_MESSAGE_BYTES
+    }
+
+    /// Arguments that give jj the message
+    ///
+    /// An inline message uses the `--message=` form so one starting with `-` is not
+    /// taken for an option; newlines pass through the argument as they are. A longer
+    /// one is read from stdin, which only `jj describe` supports.
+    pub fn args(&self) -> Vec<String> {
+        if self.is_inline() {
+            vec![format!("--message={}", self.text)]
+        } else {
+            vec!["--stdin".to_string()]
+        }
+    }
+
+    /// Run a jj command built with [`CommitMessage::args`], feeding it the message if it reads stdin
+    pub fn run(&self, args: Vec<String>, cwd: Option<String>) -> Result<String> {
+        if self.is_inline() {
+            run_jj_command_sync(args, cwd)
+        } else {
+            with_jj_stdin(&self.text, || run_jj_command_sync(args, cwd))
+        }
+    }
+}
+
+/// Execute jj new command
+pub fn run_jj_new(params: NewParams) -> CallToolResponse {
+    let mut args = vec!["new".to_string()];
+
+    if let Some(parents) = params.parents {
+        args.push(parents);
+    }
+
+    match author_config_args(
+        params.author_name.as_deref(),
+        params.author_email.as_deref(),
+    ) {
+        Ok(author) => args.extend(author),
+        Err(e) => return json_response(Err(e)),
+    }
+
+    add_repo_args(&mut args, params.repo_path);
+
+    match run_jj_command_sync(args, params.cwd) {
+        Ok(output) => CallToolResponse {
+            content: vec![ToolResponseContent::Text { text: output }],
+            is_error: Some(false),
+            meta: None,
+        },
+        Err(e) => CallToolResponse {
+            content: vec![ToolResponseContent::Text {
+                text: e.to_string(),
+            }],
+            is_error: Some(true),
+            meta: None,
+        },
+    }
+}
+
+/// Bytes a token is taken to be when a client gives its budget in tokens
+pub const BYTES_PER_TOKEN: usize = 4;
+
+/// The response size a client asked for: the smaller of `maxBytes` and `maxTokens` in bytes
+pub fn output_budget(max_bytes: Option<usize>, max_tokens: Option<usize>) -> Option<usize> {
+    [
+        max_bytes,
+        max_tokens.map(|tokens| tokens.saturating_mul(BYTES_PER_TOKEN)),
+    ]
+    .into_iter()
+    .flatten()
+    .min()
+}
+
+/// Output picked to fit a client's budget, reported in the response's `_meta`
+#[derive(Debug, Serialize, Deserialize, Default, Clone, PartialEq)]
+pub struct BudgetedOutput {
+    #[serde(rename = "maxBytes")]
+    pub max_bytes: usize,
+    /// The level that was returned, such as `full`, `stat` or `summary`
+    pub level: String,
+    /// Whether even the coarsest level was too large and had to be cut
+    pub truncated: bool,
+    #[serde(skip)]
+    pub text: String,
+}
+
+/// Cut `text` to about `budget` bytes, ending on a whole line where possible
+pub fn truncate_to_budget(text: &str, budget: usize) -> String {
+    if text.len() <= budget {
+        return text.to_string();
+    }
+    let mut cut = budget;
+    while !text.is_char_boundary(cut) {
+        cut -= 1;
+    }
+    if let Some(newline) = text[..cut].rfind('\n') {
+        cut = newline + 1;
+    }
+    format!(
+        "{}[{} more bytes omitted to fit maxBytes/maxTokens]\n",
+        &text[..cut],
+        text.len() - cut
+    )
+}
+
+/// Render output at each level in turn, from most to least detailed, until one fits `budget`
+///
+/// Levels are only rendered when the previous one was too large. When none fits,
+/// the last is truncated.
+pub fn fit_to_budget<F>(
+    budget: usize,
+    levels: impl IntoIterator<Item = (&'static str, F)>,
+) -> Result<BudgetedOutput>
+where
+    F: FnOnce() -> Result<String>,
+{
+    let mut coarsest = None;
+    for (level, render) in levels {
+        let text = render()?;
+        if text.len() <= budget {
+            return Ok(BudgetedOutput {
+                max_bytes: budget,
+                level: level.to_string(),
+                truncated: false,
+                text,
+            });
+        }
+        coarsest = Some((level, text));
+    }
+    let (level, text) =
+        coarsest.ok_or_else(|| anyhow::anyhow!("Error: no output to fit the budget"))?;
+    Ok(BudgetedOutput {
+        max_bytes: budget,
+        level: level.to_string(),
+        truncated: true,
+        text: truncate_to_budget(&text, budget),
+    })
+}
+
+/// The revset the log tool shows, with its `author`, `since` and `until` filters applied
+///
+/// Without filters this is just `revisions`. Filters without `revisions` search
+/// all visible commits, since jj's default log revset leaves out most history.
+pub fn log_revset(
+    revisions: Option<&str>,
+    author: Option<&str>,
+    since: Option<&str>,
+    until: Option<&str>,
+) -> Option<String> {
+    let mut filters = Vec::new();
+    if let Some(author) = author {
+        filters.push(format!("author({})", quote_string_literal(author)));
+    }
+    if let Some(since) = since {
+        filters.push(format!(
+            "committer_date(after:{})",
+            quote_string_literal(since)
+        ));
+    }
+    if let Some(until) = until {
+        filters.push(format!(
+            "committer_date(before:{})",
+            quote_string_literal(until)
+        ));
+    }
+    if filters.is_empty() {
+        return revisions.map(str::to_string);
+    }
+    let base = revisions.unwrap_or("all()");
+    Some(format!("({}) & {}", base, filters.join(" & ")))
+}
+
+/// A `files()` revset matching any of `paths`, each quoted as a literal file path
+pub fn files_revset(paths: &[String]) -> String {
+    let patterns: Vec<String> = paths
+        .iter()
+        .map(|path| format!("file:{}", quote_string_literal(path)))
+        .collect();
+    format!("files({})", patterns.join(" | "))
+}
+
+/// Execute jj log command
+pub fn run_jj_log(params: LogParams) -> CallToolResponse {
+    let revisions = log_revset(
+        params.revisions.as_deref(),
+        params.author.as_deref(),
+        params.since.as_deref(),
+        params.until.as_deref(),
+    );
+    let paths = params.paths.unwrap_or_default();
+    if let Some(format) = params.format.as_deref()
+        && format != "text"
+    {
+        // The graph is built from a revset, so paths become a files() filter there
+        let revisions = if paths.is_empty() {
+            revisions
+        } else {
+            Some(format!(
+                "({}) & {}",
+                revisions.as_deref().unwrap_or(DEFAULT_LOG_REVSET),
+                files_revset(&paths)
+            ))
+        };
+        return graph_response(
+            format,
+            revisions,
+            params.limit,
+            params.repo_path,
+            params.cwd,
+        );
+    }
+
+    let mut args = vec!["log".to_string()];
+
+    if let Some(limit) = params.limit {
+        args.push("-n".to_string());
+        args.push(limit.to_string());
+    }
+
+    if let Some(revisions) = revisions {
+        args.push("-r".to_string());
+        args.push(revisions);
+    }
+
+    let run = |template: Option<&str>| {
+        let mut args = args.clone();
+        if let Some(template) = template {
+            args.push("-T".to_string());
+            args.push(template.to_string());
+        }
+        if !paths.is_empty() {
+            args.push("--".to_string());
+            args.extend(
+                paths
+                    .iter()
+                    .map(|path| format!("file:{}", quote_string_literal(path))),
+            );
+        }
+        run_jj_command_cached_streamed(args, params.repo_path.clone(), params.cwd.clone())
+    };
+
+    let result = match output_budget(params.max_bytes, params.max_tokens) {
+        None => run(params.template.as_deref()).map(|output| (output, None)),
+        Some(budget) => fit_to_budget(
+            budget,
+            [
+                ("full", params.template.as_deref()),
+                ("oneline", Some("builtin_log_oneline")),
+            ]
+            .map(|(level, template)| (level, move || run(template))),
+        )
+        .map(|fitted| {
+            let meta = serde_json::json!({ "budget": fitted });
+            (fitted.text, Some(meta))
+        }),
+    };
+
+    match result {
+        Ok((output, meta)) => CallToolResponse {
+            content: vec![ToolResponseContent::Text { text: output }],
+            is_error: Some(false),
+            meta,
+        },
+        Err(e) => CallToolResponse {
+            content: vec![ToolResponseContent::Text {
+                text: e.to_string(),
+            }],
+            is_error: Some(true),
+            meta: None,
+        },
+    }
+}
+
+/// The revset for the merge base of `to` and `base`, the revision a `base` diff starts from
+///
+/// `auto` stands for `trunk()`, so a stacked change shows everything it adds on
+/// top of the main line. `latest` picks one base when a criss-cross merge leaves several.
+pub fn diff_base_revset(base: &str, to: &str) -> String {
+    let base = if base == "auto" { "trunk()" } else { base };
+    format!("latest(heads(::({}) & ::({})))", base, to)
+}
+
+/// Execute jj diff command
+pub fn run_jj_diff(mut params: DiffParams) -> CallToolResponse {
+    if let Some(base) = params.base.take() {
+        if params.from.is_some() {
+            return json_response(Err(anyhow::anyhow!(
+                "Error: give either from or base, not both"
+            )));
+        }
+        let to = params.to.get_or_insert_with(|| "@".to_string());
+        params.from = Some(diff_base_revset(&base, to));
+    }
+    let mut resources = match params.embed_files {
+        Some(true) => match diff_file_resources(&params) {
+            Ok(resources) => resources,
+            Err(e) => return json_response(Err(e)),
+        },
+        _ => Vec::new(),
+    };
+    if let Some(true) = params.html {
+        match html_diff_resource(&params) {
+            Ok(html) => resources.extend(html),
+            Err(e) => return json_response(Err(e)),
+        }
+    }
+    let mut args = vec!["diff".to_string()];
+
+    if let Some(from) = params.from {
+        args.push("--from".to_string());
+        args.push(from);
+    }
+
+    if let Some(to) = params.to {
+        args.push("--to".to_string());
+        args.push(to);
+    }
+
+    if let Some(context) = params.context {
+        args.push("--context".to_string());
+        args.push(context.to_string());
+    }
+
+    let budget = output_budget(params.max_bytes, params.max_tokens);
+
+    if let Some(paths) = params.paths {
+        args.extend(paths);
+    }
+
+    // Word marking needs the whole git-format diff, so only the full level uses it
+    let word_diff_level = params.word_diff == Some(true);
+    let run_level = move |mut args: Vec<String>,
+                          flags: &[&str],
+                          repo_path: Option<String>,
+                          cwd: Option<String>| {
+        if flags.is_empty() && word_diff_level {
+            args.insert(1, "--git".to_string());
+            return run_jj_command_cached(args, repo_path, cwd).map(|output| word_diff(&output));
+        }
+        args.extend(flags.iter().map(|flag| flag.to_string()));
+        run_jj_command_cached_streamed(args, repo_path, cwd)
+    };
+
+    let result = match budget {
+        None => {
+            let mut flags = Vec::new();
+            if let Some(true) = params.summary {
+                flags.push("--summary");
+            }
+            if let Some(true) = params.stat {
+                flags.push("--stat");
+            }
+            run_level(args, &flags, params.repo_path, params.cwd).map(|output| (output, None))
+        }
+        Some(budget) => {
+            let levels: [(&str, &[&str]); 3] = [
+                ("full", &[]),
+                ("stat", &["--stat"]),
+                ("summary", &["--summary"]),
+            ];
+            let first = match (params.summary, params.stat) {
+                (Some(true), _) => 2,
+                (_, Some(true)) => 1,
+                _ => 0,
+            };
+            fit_to_budget(
+                budget,
+                levels[first..].iter().map(|&(level, flags)| {
+                    let args = args.clone();
+                    let (repo_path, cwd) = (params.repo_path.clone(), params.cwd.clone());
+                    (level, move || run_level(args, flags, repo_path, cwd))
+                }),
+            )
+            .map(|fitted| {
+                let meta = serde_json::json!({ "budget": fitted });
+                (fitted.text, Some(meta))
+            })
+        }
+    };
+
+    match result {
+        Ok((output, meta)) => CallToolResponse {
+            content: std::iter::once(ToolResponseContent::Text { text: output })
+                .chain(resources)
+                .collect(),
+            is_error: Some(false),
+            meta,
+        },
+        Err(e) => CallToolResponse {
+            content: vec![ToolResponseContent::Text {
+                text: e.to_string(),
+            }],
+            is_error: Some(true),
+            meta: None,
+        },
+    }
+}
+
+/// Most changed files embedded in a single diff response
+pub const MAX_EMBEDDED_FILES: usize = 50;
+
+/// Escape text for use in HTML element content and attribute values
+pub fn html_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+/// Starting line numbers from a unified diff hunk header like `@@ -3,7 +3,8 @@`
+fn hunk_start_lines(header: &str) -> Option<(usize, usize)> {
+    let mut ranges = header.strip_prefix("@@ ")?.split(' ');
+    let start = |range: &str, sign: char| -> Option<usize> {
+        range.strip_prefix(sign)?.split(',').next()?.parse().ok()
+    };
+    Some((start(ranges.next()?, '-')?, start(ranges.next()?, '+')?))
+}
+
+/// A row of a side-by-side diff: old line number and text, new line number and text
+type SideBySideRow = (Option<usize>, Option<String>, Option<usize>, Option<String>);
+
+/// Render a git-format diff as a self-contained side-by-side HTML page
+///
+/// Removed and added lines in the same run are paired row by row; the rest of
+/// the row is left blank. Lines are escaped and no syntax highlighting is applied.
+pub fn render_html_diff(git_diff: &str) -> String {
+    const STYLE: &str = "body{font-family:sans-serif;margin:1em}\
+        table{border-collapse:collapse;width:100%;font-family:monospace;font-size:13px;margin-bottom:2em}\
+        td{padding:0 .5em;white-space:pre-wrap;vertical-align:top;width:50%}\
+        td.num{width:1%;color:#888;text-align:right;user-select:none}\
+        td.del{background:#fdd}td.add{background:#dfd}\
+        tr.hunk td{background:#eef;color:#555}p.note{color:#555}";
+
+    let mut body = String::new();
+    let mut rows: Vec<SideBySideRow> = Vec::new();
+    let mut removed: Vec<(usize, String)> = Vec::new();
+    let mut added: Vec<(usize, String)> = Vec::new();
+    let (mut old_line, mut new_line) = (0, 0);
+    let mut in_table = false;
+
+    let flush = |rows: &mut Vec<SideBySideRow>,
+                 removed: &mut Vec<(usize, String)>,
+                 added: &mut Vec<(usize, String)>| {
+        let count = removed.len().max(added.len());
+        let mut removed = removed.drain(..);
+        let mut added = added.drain(..);
+        for _ in 0..count {
+            let (old_number, old_text) = removed.next().unzip();
+            let (new_number, new_text) = added.next().unzip();
+            rows.push((old_number, old_text, new_number, new_text));
+        }
+    };
+    let write_rows = |body: &mut String, rows: &mut Vec<SideBySideRow>| {
+        for (old_number, old_text, new_number, new_text) in rows.drain(..) {
+            let changed = old_text != new_text;
+            let cell = |number: Option<usize>, text: Option<String>, class: &str| {
+                let class = if changed && text.is_some() { class } else { "" };
+                format!(
+                    "<td class=\"num\">{}</td><td class=\"{}\">{}</td>",
+                    number.map(|n| n.to_string()).unwrap_or_default(),
+                    class,
+                    html_escape(&text.unwrap_or_default())
+                )
+            };
+            body.push_str(&format!(
+                "<tr>{}{}</tr>\n",
+                cell(old_number, old_text, "del"),
+                cell(new_number, new_text, "add")
+            ));
+        }
+    };
+
+    for line in git_diff.lines() {
+        if let Some(paths) = line.strip_prefix("diff --git ") {
+            flush(&mut rows, &mut removed, &mut added);
+            write_rows(&mut body, &mut rows);
+            if in_table {
+                body.push_str("</table>\n");
+            }
+            let path = paths
+                .rsplit_once(" b/")
+                .map(|(_, path)| path)
+                .unwrap_or(paths);
+            body.push_str(&format!("<h2>{}</h2>\n<table>\n", html_escape(path)));
+            in_table = true;
+        } else if line.starts_with("@@") {
+            flush(&mut rows, &mut removed, &mut added);
+            write_rows(&mut body, &mut rows);
+            if let Some((old_start, new_start)) = hunk_start_lines(line) {
+                old_line = old_start;
+                new_line = new_start;
+            }
+            body.push_str(&format!(
+                "<tr class=\"hunk\"><td colspan=\"4\">{}</td></tr>\n",
+                html_escape(line)
+            ));
+        } else if line.starts_with("Binary files ") {
+            body.push_str(&format!(
+                "<tr><td colspan=\"4\"><p class=\"note\">{}</p></td></tr>\n",
+                html_escape(line)
+            ));
+        } else if line.starts_with("--- ") || line.starts_with("+++ ") {
+            continue;
+        } else if let Some(text) = line.strip_prefix('-') {
+            removed.push((old_line, text.to_string()));
+            old_line += 1;
+        } else if let Some(text) = line.strip_prefix('+') {
+            added.push((new_line, text.to_string()));
+            new_line += 1;
+        } else if let Some(text) = line.strip_prefix(' ') {
+            flush(&mut rows, &mut removed, &mut added);
+            rows.push((
+                Some(old_line),
+                Some(text.to_string()),
+                Some(new_line),
+                Some(text.to_string()),
+            ));
+            old_line += 1;
+            new_line += 1;
+        }
+    }
+    flush(&mut rows, &mut removed, &mut added);
+    write_rows(&mut body, &mut rows);
+    if in_table {
+        body.push_str("</table>\n");
+    } else {
+        body.push_str("<p class=\"note\">No changes.</p>\n");
+    }
+
+    format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>Diff</title>\n<style>{}</style>\n</head>\n<body>\n{}</body>\n</html>\n",
+        STYLE, body
+    )
+}
+
+/// Split a line into words, runs of whitespace and single punctuation characters
+fn diff_tokens(line: &str) -> Vec<&str> {
+    let mut tokens = Vec::new();
+    let mut start = 0;
+    let mut chars = line.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        let same_kind = |next: char| {
+            (c.is_alphanumeric() || c == '_') && (next.is_alphanumeric() || next == '_')
+                || c.is_whitespace() && next.is_whitespace()
+        };
+        match chars.peek() {
+            Some(&(_, next)) if same_kind(next) => {}
+            _ => {
+                let end = i + c.len_utf8();
+                tokens.push(&line[start..end]);
+                start = end;
+            }
+        }
+    }
+    tokens
+}
+
+/// Most token pairs compared for a single line before falling back to a whole-line change
+const MAX_WORD_DIFF_CELLS: usize = 250_000;
+
+/// Mark the words that differ between two versions of a line, `[-removed-]{+added+}`
+pub fn word_diff_line(old: &str, new: &str) -> String {
+    let old_tokens = diff_tokens(old);
+    let new_tokens = diff_tokens(new);
+    let (n, m) = (old_tokens.len(), new_tokens.len());
+    if n * m > MAX_WORD_DIFF_CELLS {
+        return format!("[-{}-]{{+{}+}}", old, new);
+    }
+
+    // Longest common subsequence of tokens, filled from the end
+    let mut lengths = vec![vec![0usize; m + 1]; n + 1];
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            lengths[i][j] = if old_tokens[i] == new_tokens[j] {
+                lengths[i + 1][j + 1] + 1
+            } else {
+                lengths[i + 1][j].max(lengths[i][j + 1])
+            };
+        }
+    }
+
+    let mut result = String::new();
+    let (mut removed, mut added) = (String::new(), String::new());
+    let flush = |result: &mut String, removed: &mut String, added: &mut String| {
+        if !removed.is_empty() {
+            result.push_str(&format!("[-{}-]", removed));
+            removed.clear();
+        }
+        if !added.is_empty() {
+            result.push_str(&format!("{{+{}+}}", added));
+            added.clear();
+        }
+    };
+    let (mut i, mut j) = (0, 0);
+    while i < n || j < m {
+        if i < n && j < m && old_tokens[i] == new_tokens[j] {
+            flush(&mut result, &mut removed, &mut added);
+            result.push_str(old_tokens[i]);
+            i += 1;
+            j += 1;
+        } else if j == m || (i < n && lengths[i + 1][j] >= lengths[i][j + 1]) {
+            removed.push_str(old_tokens[i]);
+            i += 1;
+        } else {
+            added.push_str(new_tokens[j]);
+            j += 1;
+        }
+    }
+    flush(&mut result, &mut removed, &mut added);
+    result
+}
+
+/// Rewrite the hunks of a git-format diff with word-level change markers
+///
+/// Runs of removed lines are paired with the added lines that follow them and
+/// merged with [`word_diff_line`]; unpaired lines are marked as a whole. Context
+/// lines lose their leading space and file headers are kept as they are.
+pub fn word_diff(git_diff: &str) -> String {
+    let mut output = String::new();
+    let mut removed: Vec<&str> = Vec::new();
+    let mut added: Vec<&str> = Vec::new();
+
+    let flush = |output: &mut String, removed: &mut Vec<&str>, added: &mut Vec<&str>| {
+        let count = removed.len().max(added.len());
+        for k in 0..count {
+            let line = match (removed.get(k), added.get(k)) {
+                (Some(old), Some(new)) => word_diff_line(old, new),
+                (Some(old), None) => format!("[-{}-]", old),
+                (None, Some(new)) => format!("{{+{}+}}", new),
+                (None, None) => continue,
+            };
+            output.push_str(&line);
+            output.push('\n');
+        }
+        removed.clear();
+        added.clear();
+    };
+
+    let mut in_hunk = false;
+    for line in git_diff.lines() {
+        if line.starts_with("diff --git ") {
+            in_hunk = false;
+        } else if line.starts_with("@@") {
+            in_hunk = true;
+        }
+        if !in_hunk || line.starts_with("@@") {
+            flush(&mut output, &mut removed, &mut added);
+            output.push_str(line);
+            output.push('\n');
+        } else if let Some(text) = line.strip_prefix('-') {
+            if !added.is_empty() {
+                flush(&mut output, &mut removed, &mut added);
+            }
+            removed.push(text);
+        } else if let Some(text) = line.strip_prefix('+') {
+            added.push(text);
+        } else {
+            flush(&mut output, &mut removed, &mut added);
+            output.push_str(line.strip_prefix(' ').unwrap_or(line));
+            output.push('\n');
+        }
+    }
+    flush(&mut output, &mut removed, &mut added);
+    output
+}
+
+/// The diff described by `params` in git format, ignoring the summary and stat options
+fn git_diff_output(params: &DiffParams) -> Result<String> {
+    let mut args = vec!["diff".to_string(), "--git".to_string()];
+    if let Some(from) = &params.from {
+        args.extend(["--from".to_string(), from.clone()]);
+    }
+    if let Some(to) = &params.to {
+        args.extend(["--to".to_string(), to.clone()]);
+    }
+    if let Some(context) = params.context {
+        args.extend(["--context".to_string(), context.to_string()]);
+    }
+    if let Some(paths) = &params.paths {
+        args.extend(paths.iter().cloned());
+    }
+    add_repo_args(&mut args, params.repo_path.clone());
+    run_jj_command_sync(args, params.cwd.clone())
+}
+
+/// A base64 `data:` URI carrying `content`, so a resource holds its content itself
+pub fn data_uri(mime_type: &str, content: &[u8]) -> Result<url::Url> {
+    use base64::Engine as _;
+    let encoded = base64::engine::general_purpose::STANDARD.encode(content);
+    url::Url::parse(&format!("data:{};base64,{}", mime_type, encoded))
+        .map_err(|e| anyhow::anyhow!("Error: cannot build a data URI: {}", e))
+}
+
+/// Render the diff described by `params` as HTML, embedded in the returned resource
+fn html_diff_resource(params: &DiffParams) -> Result<Vec<ToolResponseContent>> {
+    let output = git_diff_output(params)?;
+    let html = render_html_diff(&output);
+    Ok(vec![
+        ToolResponseContent::Text {
+            text: format!(
+                "Side-by-side HTML diff ({} bytes) embedded in the following resource",
+                html.len()
+            ),
+        },
+        ToolResponseContent::Resource {
+            resource: ResourceContents {
+                uri: data_uri("text/html", html.as_bytes())?,
+                mime_type: Some("text/html".to_string()),
+            },
+        },
+    ])
+}
+
+/// Guess a text mime type from a file extension, falling back to `text/plain`
+pub fn mime_type_for_path(path: &str) -> &'static str {
+    let extension = std::path::Path::new(path)
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .unwrap_or_default()
+        .to_ascii_lowercase();
+    match extension.as_str() {
+        "rs" => "text/x-rust",
+        "md" | "markdown" => "text/markdown",
+        "json" => "application/json",
+        "toml" => "application/toml",
+        "yaml" | "yml" => "application/yaml",
+        "html" | "htm" => "text/html",
+        "css" => "text/css",
+        "js" | "mjs" => "text/javascript",
+        "ts" => "application/typescript",
+        "py" => "text/x-python",
+        "sh" => "application/x-sh",
+        "xml" => "application/xml",
+        "csv" => "text/csv",
+        _ => "text/plain",
+    }
+}
+
+/// How much of a file is checked for NUL bytes when deciding whether it is binary, as git does
+const BINARY_SNIFF_BYTES: usize = 8000;
+
+/// Whether file content is binary: it has a NUL byte near the start or isn't UTF-8
+pub fn is_binary_content(bytes: &[u8]) -> bool {
+    bytes[..bytes.len().min(BINARY_SNIFF_BYTES)].contains(&0) || std::str::from_utf8(bytes).is_err()
+}
+
+/// Guess the mime type of binary content from its leading bytes, then its extension
+pub fn binary_mime_type(path: &str, bytes: &[u8]) -> &'static str {
+    const SIGNATURES: &[(&[u8], &str)] = &[
+        (b"\x89PNG\r\n\x1a\n", "image/png"),
+        (b"\xff\xd8\xff", "image/jpeg"),
+        (b"GIF87a", "image/gif"),
+        (b"GIF89a", "image/gif"),
+        (b"%PDF-", "application/pdf"),
+        (b"PK\x03\x04", "application/zip"),
+        (b"\x1f\x8b", "application/gzip"),
+        (b"\0asm", "application/wasm"),
+        (b"\x7fELF", "application/x-elf"),
+    ];
+    if let Some((_, mime_type)) = SIGNATURES
+        .iter()
+        .find(|(signature, _)| bytes.starts_with(signature))
+    {
+        return mime_type;
+    }
+    if bytes.starts_with(b"RIFF") && bytes.get(8..12) == Some(b"WEBP") {
+        return "image/webp";
+    }
+
+    let extension = std::path::Path::new(path)
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .unwrap_or_default()
+        .to_ascii_lowercase();
+    match extension.as_str() {
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "ico" => "image/x-icon",
+        "pdf" => "application/pdf",
+        "zip" | "jar" => "application/zip",
+        "gz" | "tgz" => "application/gzip",
+        "wasm" => "application/wasm",
+        _ => "application/octet-stream",
+    }
+}
+
+/// Describe binary file content by size and guessed type, with the bytes as base64 when allowed
+pub fn binary_content_summary(
+    path: &str,
+    revision: &str,
+    bytes: &[u8],
+    allow_binary: bool,
+) -> Value {
+    use base64::Engine as _;
+
+    let mut summary = serde_json::json!({
+        "path": path,
+        "revision": revision,
+        "binary": true,
+        "size": bytes.len(),
+        "mimeType": binary_mime_type(path, bytes),
+    });
+    if allow_binary {
+        summary["base64"] =
+            serde_json::json!(base64::engine::general_purpose::STANDARD.encode(bytes));
+    }
+    summary
+}
+
+/// Changed files of a diff with their content at the target revision
+///
+/// When the target is the working copy (`@`), each file is followed by a
+/// `file://` resource link to it; for other revisions the file on disk may differ,
+/// so there is no link. Files deleted at the target are reported as deleted. Files
+/// past [`MAX_EMBEDDED_FILES`] are left out, and binary files are described rather
+/// than embedded as text.
+pub fn diff_file_resources(params: &DiffParams) -> Result<Vec<ToolResponseContent>> {
+    let mut args = vec!["diff".to_string(), "--name-only".to_string()];
+    if let Some(from) = &params.from {
+        args.push("--from".to_string());
+        args.push(from.clone());
+    }
+    if let Some(to) = &params.to {
+        args.push("--to".to_string());
+        args.push(to.clone());
+    }
+    if let Some(paths) = &params.paths {
+        args.extend(paths.iter().cloned());
+    }
+    let names = run_jj_command_cached(args, params.repo_path.clone(), params.cwd.clone())?;
+
+    // Paths are printed relative to the directory jj ran in
+    let base = match &params.cwd {
+        Some(cwd) => std::path::PathBuf::from(cwd),
+        None => std::env::current_dir()?,
+    };
+    let revision = params.to.clone().unwrap_or_else(|| "@".to_string());
+    let names: Vec<&str> = names
+        .lines()
+        .filter(|line| !line.is_empty())
+        .take(MAX_EMBEDDED_FILES)
+        .collect();
+    if names.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    // Changed files missing at the target revision were deleted there
+    let mut list = vec![
+        "file".to_string(),
+        "list".to_string(),
+        "-r".to_string(),
+        revision.clone(),
+        names
+            .iter()
+            .map(|path| format!("file:{}", quote_string_literal(path)))
+            .collect::<Vec<_>>()
+            .join(" | "),
+    ];
+    add_repo_args(&mut list, params.repo_path.clone());
+    let listing = run_jj_command_sync(list, params.cwd.clone())?;
+    let present: std::collections::HashSet<&str> = listing.lines().collect();
+
+    let mut content = Vec::new();
+    for path in names {
+        if !present.contains(path) {
+            content.push(ToolResponseContent::Text {
+                text: format!("{} deleted at {}", path, revision),
+            });
+            continue;
+        }
+        let mut show = vec![
+            "file".to_string(),
+            "show".to_string(),
+            "-r".to_string(),
+            revision.clone(),
+            format!("file:{}", quote_string_literal(path)),
+        ];
+        add_repo_args(&mut show, params.repo_path.clone());
+        let bytes = run_jj_command_bytes(show, params.cwd.clone())?;
+
+        let mime_type = if is_binary_content(&bytes) {
+            let summary = binary_content_summary(
+                path,
+                &revision,
+                &bytes,
+                params.allow_binary.unwrap_or(false),
+            );
+            content.push(ToolResponseContent::Text {
+                text: summary.to_string(),
+            });
+            binary_mime_type(path, &bytes)
+        } else {
+            content.push(ToolResponseContent::Text {
+                text: format!(
+                    "{} at {}:\n{}",
+                    path,
+                    revision,
+                    String::from_utf8_lossy(&bytes).trim_end()
+                ),
+            });
+            mime_type_for_path(path)
+        };
+        if revision == "@" {
+            let absolute = normalize_path(&base.join(path));
+            let uri = url::Url::from_file_path(&absolute).map_err(|_| {
+                anyhow::anyhow!("Error: cannot build a URI for {}", absolute.display())
+            })?;
+            content.push(ToolResponseContent::Resource {
+                resource: ResourceContents {
+                    uri,
+                    mime_type: Some(mime_type.to_string()),
+                },
+            });
+        }
+    }
+    Ok(content)
+}
+
+/// Execute jj git clone command
+pub fn run_jj_git_clone(params: GitCloneParams) -> CallToolResponse {
+    let mut args = vec!["git".to_string(), "clone".to_string()];
+
+    if let Some(source) = params.source {
+        args.push(source);
+    }
+
+    if let Some(destination) = params.destination {
+        args.push(destination);
+    }
+
+    if let Some(true) = params.colocate {
+        args.push("--colocate".to_string());
+    }
+
+    if let Some(remote) = params.remote {
+        args.push("--remote".to_string());
+        args.push(remote);
+    }
+
+    if let Some(depth) = params.depth {
+        args.push("--depth".to_string());
+        args.push(depth.to_string());
+    }
+
+    match run_jj_command_sync(args, None) {
+        Ok(output) => CallToolResponse {
+            content: vec![ToolResponseContent::Text { text: output }],
+            is_error: Some(false),
+            meta: None,
+        },
+        Err(e) => CallToolResponse {
+            content: vec![ToolResponseContent::Text {
+                text: e.to_string(),
+            }],
+            is_error: Some(true),
+            meta: None,
+        },
+    }
+}
+
+/// Quote a value as a string literal for use inside a revset or fileset expression
+pub fn quote_string_literal(value: &str) -> String {
+    let escaped = value.replace('\\', "\\\\").replace('"', "\\\"");
+    format!("\"{}\"", escaped)
+}
+
+/// Build a tool response from a JSON result, rendered as pretty-printed text
+fn json_response(result: Result<Value>) -> CallToolResponse {
+    match result {
+        Ok(value) => CallToolResponse {
+            content: vec![ToolResponseContent::Text {
+                text: serde_json::to_string_pretty(&value).unwrap_or_else(|_| value.to_string()),
+            }],
+            is_error: Some(false),
+            meta: None,
+        },
+        Err(e) => CallToolResponse {
+            content: vec![ToolResponseContent::Text {
+                text: e.to_string(),
+            }],
+            is_error: Some(true),
+            meta: None,
+        },
+    }
+}
+
+/// Which part of a conflict the parser is currently reading
+#[derive(Clone, Copy, PartialEq)]
+enum ConflictSection {
+    Pending,
+    Diff,
+    Side,
+    Base,
+}
+
+/// Return the marker character if `line` is a conflict marker of exactly `len` characters
+fn conflict_marker(line: &str, len: usize) -> Option<char> {
+    let first = line.chars().next()?;
+    if !matches!(first, '%' | '+' | '-' | '|' | '=' | '>' | '\\') {
+        return None;
+    }
+    let run = line.chars().take_while(|c| *c == first).count();
+    let rest = &line[run..];
+    if run == len && (rest.is_empty() || rest.starts_with(' ')) {
+        Some(first)
+    } else {
+        None
+    }
+}
+
+/// Parse jj (or git-style) conflict markers into structured hunks
+///
+/// Handles jj's "diff" style (`%%%%%%%` sections), its "snapshot" style
+/// (`-------`/`+++++++` sections) and git's `|||||||`/`=======` style.
+pub fn parse_conflict_markers(content: &str) -> Vec<ConflictHunk> {
+    let mut hunks = Vec::new();
+    let mut current: Option<(ConflictHunk, usize, ConflictSection)> = None;
+
+    for (index, line) in content.lines().enumerate() {
+        let line_number = index + 1;
+
+        let Some((hunk, marker_len, section)) = current.as_mut() else {
+            let run = line.chars().take_while(|c| *c == '<').count();
+            if run >= 7 && (line[run..].is_empty() || line[run..].starts_with(' ')) {
+                let hunk = ConflictHunk {
+                    start_line: line_number,
+                    ..Default::default()
+                };
+                current = Some((hunk, run, ConflictSection::Pending));
+            }
+            continue;
+        };
+
+        match conflict_marker(line, *marker_len) {
+            Some('>') => {
+                let (mut hunk, _, _) = current.take().unwrap();
+                hunk.end_line = line_number;
+                hunks.push(hunk);
+            }
+            Some('%') => {
+                hunk.bases.push(String::new());
+                hunk.sides.push(String::new());
+                *section = ConflictSection::Diff;
+            }
+            // Newer jj versions add a "to: side" line after the diff header
+            Some('\\') => {}
+            Some('+') | Some('=') => {
+                if *section == ConflictSection::Pending && line.starts_with('=') {
+                    hunk.sides.push(String::new());
+                }
+                hunk.sides.push(String::new());
+                *section = ConflictSection::Side;
+            }
+            Some('-') | Some('|') => {
+                if *section == ConflictSection::Pending && line.starts_with('|') {
+                    hunk.sides.push(String::new());
+                }
+                hunk.bases.push(String::new());
+                *section = ConflictSection::Base;
+            }
+            _ => {
+                let push_line = |target: &mut Vec<String>, text: &str| {
+                    if let Some(last) = target.last_mut() {
+                        last.push_str(text);
+                        last.push('\n');
+                    }
+                };
+                match *section {
+                    ConflictSection::Pending => {
+                        hunk.sides.push(String::new());
+                        push_line(&mut hunk.sides, line);
+                        *section = ConflictSection::Side;
+                    }
+                    ConflictSection::Side => push_line(&mut hunk.sides, line),
+                    ConflictSection::Base => push_line(&mut hunk.bases, line),
+                    ConflictSection::Diff => {
+                        if let Some(rest) = line.strip_prefix('+') {
+                            push_line(&mut hunk.sides, rest);
+                        } else if let Some(rest) = line.strip_prefix('-') {
+                            push_line(&mut hunk.bases, rest);
+                        } else {
+                            let rest = line.strip_prefix(' ').unwrap_or(line);
+                            push_line(&mut hunk.sides, rest);
+                            push_line(&mut hunk.bases, rest);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    hunks
+}
+
+/// Extract the conflicted regions of a file as structured JSON
+pub fn run_jj_conflict_content(params: ConflictContentParams) -> CallToolResponse {
+    let result = (|| {
+        let path = params
+            .path
+            .ok_or_else(|| anyhow::anyhow!("Error: path is required"))?;
+        let revision = params.revision.unwrap_or_else(|| "@".to_string());
+
+        let mut args = vec![
+            "file".to_string(),
+            "show".to_string(),
+            "-r".to_string(),
+            revision.clone(),
+            format!("root-file:{}", quote_string_literal(&path)),
+        ];
+        add_repo_args(&mut args, params.repo_path);
+
+        let content = run_jj_command_sync(args, params.cwd)?;
+        let conflicts = parse_conflict_markers(&content);
+
+        Ok(serde_json::json!({
+            "path": path,
+            "revision": revision,
+            "conflicted": !conflicts.is_empty(),
+            "conflicts": conflicts,
+        }))
+    })();
+
+    json_response(result)
+}
+
+/// Modification time of a path, if it can be read
+fn modified_time(path: &std::path::Path) -> Option<std::time::SystemTime> {
+    std::fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+/// Remembers workspace roots, keyed by the directory a lookup started from
+///
+/// An entry is dropped once the modification time of any directory it watches
+/// changes, e.g. when a workspace is created, moved or removed.
+#[derive(Debug, Default)]
+pub struct RootCache {
+    entries: std::collections::HashMap<(std::path::PathBuf, Option<String>), RootCacheEntry>,
+}
+
+#[derive(Debug)]
+struct RootCacheEntry {
+    root: std::path::PathBuf,
+    watched: Vec<(std::path::PathBuf, Option<std::time::SystemTime>)>,
+}
+
+impl RootCache {
+    pub fn get(
+        &mut self,
+        dir: &std::path::Path,
+        repo_path: Option<&str>,
+    ) -> Option<std::path::PathBuf> {
+        let key = (dir.to_path_buf(), repo_path.map(str::to_string));
+        let entry = self.entries.get(&key)?;
+        if entry
+            .watched
+            .iter()
+            .all(|(path, modified)| modified_time(path) == *modified)
+        {
+            return Some(entry.root.clone());
+        }
+        self.entries.remove(&key);
+        None
+    }
+
+    pub fn insert(
+        &mut self,
+        dir: &std::path::Path,
+        repo_path: Option<&str>,
+        root: std::path::PathBuf,
+        watched: &[std::path::PathBuf],
+    ) {
+        let watched = watched
+            .iter()
+            .map(|path| (path.clone(), modified_time(path)))
+            .collect();
+        self.entries.insert(
+            (dir.to_path_buf(), repo_path.map(str::to_string)),
+            RootCacheEntry { root, watched },
+        );
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+}
+
+static ROOT_CACHE: std::sync::LazyLock<std::sync::Mutex<RootCache>> =
+    std::sync::LazyLock::new(Default::default);
+
+/// Forget every resolved workspace root
+pub fn clear_root_cache() {
+    ROOT_CACHE.lock().unwrap().clear();
+}
+
+/// Resolve the root directory of the workspace targeted by `repo_path`/`cwd`
+///
+/// Roots are memoized per starting directory, so `jj root` only runs again
+/// after the starting directory, the root or its `.jj` directory changes.
+pub fn resolve_workspace_root(
+    repo_path: Option<String>,
+    cwd: Option<String>,
+) -> Result<std::path::PathBuf> {
+    let dir = match &cwd {
+        Some(cwd) => std::path::PathBuf::from(cwd),
+        None => std::env::current_dir()?,
+    };
+    let dir = normalize_path(&dir);
+    if let Some(root) = ROOT_CACHE.lock().unwrap().get(&dir, repo_path.as_deref()) {
+        return Ok(root);
+    }
+
+    let mut args = vec!["root".to_string()];
+    add_repo_args(&mut args, repo_path.clone());
+
+    let root = std::path::PathBuf::from(run_jj_command_sync(args, cwd)?);
+    let start = match &repo_path {
+        Some(repo_path) => normalize_path(&dir.join(repo_path)),
+        None => dir.clone(),
+    };
+    ROOT_CACHE.lock().unwrap().insert(
+        &dir,
+        repo_path.as_deref(),
+        root.clone(),
+        &[start, root.clone(), root.join(".jj")],
+    );
+    Ok(root)
+}
+
+/// Join a repo-relative path onto the workspace root, rejecting paths that escape it
+pub fn repo_file_path(root: &std::path::Path, path: &str) -> Result<std::path::PathBuf> {
+    let relative = std::path::Path::new(path);
+    let escapes = relative.components().any(|component| {
+        !matches!(
+            component,
+            std::path::Component::Normal(_) | std::path::Component::CurDir
+        )
+    });
+
+    if path.is_empty() || escapes {
+        return Err(anyhow::anyhow!(
+            "Error: path must be relative to the repo root and stay inside it: {}",
+            path
+        ));
+    }
+
+    Ok(root.join(relative))
+}
+
+/// List the conflicted paths of a revision, relative to the workspace root
+pub fn list_conflicted_paths(root: &std::path::Path, revision: &str) -> Result<Vec<String>> {
+    let args = vec![
+        "resolve".to_string(),
+        "--list".to_string(),
+        "-r".to_string(),
+        revision.to_string(),
+    ];
+
+    match run_jj_command_sync(args, Some(root.to_string_lossy().to_string())) {
+        Ok(output) => Ok(parse_conflict_list(&output)),
+        // jj reports a revision without conflicts as an error
+        Err(e) if e.to_string().contains("No conflicts") => Ok(Vec::new()),
+        Err(e) => Err(e),
+    }
+}
+
+/// The paths in `jj resolve --list` output
+///
+/// jj pads each path to a column and follows it with a description such as
+/// `2-sided conflict including 1 deletion`, so the path is everything before the
+/// padding that precedes the last description. Paths may contain spaces.
+pub fn parse_conflict_list(output: &str) -> Vec<String> {
+    static ENTRY: std::sync::LazyLock<regex::Regex> = std::sync::LazyLock::new(|| {
+        regex::Regex::new(r"^(.*\S)\s+\d+-sided conflict").expect("valid conflict list regex")
+    });
+    output
+        .lines()
+        .filter_map(|line| ENTRY.captures(line))
+        .map(|captures| captures[1].to_string())
+        .collect()
+}
+
+/// Write resolved content for a conflicted file and verify the conflict is gone
+///
+/// A revision other than `@` is resolved in a temporary workspace, so the user's
+/// checkout stays where it is; their workspace is then brought up to date with
+/// the rewritten commits.
+pub fn run_jj_conflict_resolve(params: ConflictResolveParams) -> CallToolResponse {
+    let result = (|| {
+        let path = params
+            .path
+            .ok_or_else(|| anyhow::anyhow!("Error: path is required"))?;
+        let content = params
+            .content
+            .ok_or_else(|| anyhow::anyhow!("Error: content is required"))?;
+
+        let root = resolve_workspace_root(params.repo_path, params.cwd)?;
+        // Listing conflicts snapshots the working copy, picking up the new content
+        let resolve_in = |workspace: &std::path::Path| -> Result<Vec<String>> {
+            let file_path = working_copy_write_path(workspace, &path)?;
+            std::fs::write(&file_path, &content)
+                .map_err(|e| anyhow::anyhow!("Error: failed to write {}: {}", path, e))?;
+            list_conflicted_paths(workspace, "@")
+        };
+
+        let remaining = match params.revision.as_ref().filter(|r| r.as_str() != "@") {
+            None => resolve_in(&root)?,
+            Some(revision) => {
+                let root_dir = Some(root.to_string_lossy().to_string());
+                // Record the user's own edits before anything is rewritten under them
+                run_jj_command_sync(
+                    vec!["debug".to_string(), "snapshot".to_string()],
+                    root_dir.clone(),
+                )?;
+                let remaining =
+                    in_temporary_workspace(&root, revision, "conflict-resolve", |workspace| {
+                        let workspace_dir = Some(workspace.to_string_lossy().to_string());
+                        run_jj_command_sync(
+                            vec!["edit".to_string(), revision.clone()],
+                            workspace_dir,
+                        )?;
+                        resolve_in(workspace)
+                    })?;
+                // Resolving may have rewritten the user's working-copy commit
+                run_jj_command_sync(
+                    vec!["workspace".to_string(), "update-stale".to_string()],
+                    root_dir,
+                )?;
+                remaining
+            }
+        };
+        let normalized = path.trim_start_matches("./").to_string();
+        let resolved = !remaining.iter().any(|p| p.replace('\\', "/") == normalized);
+
+        Ok(serde_json::json!({
+            "path": path,
+            "resolved": resolved,
+            "remainingConflicts": remaining,
+        }))
+    })();
+
+    json_response(result)
+}
+
+/// Render a template expression for a timestamp as a sortable UTC string
+pub fn utc_timestamp_template(timestamp: &str) -> String {
+    format!("{}.utc().format(\"%Y-%m-%dT%H:%M:%SZ\")", timestamp)
+}
+
+/// Build a `jj log` template that emits the given fields tab-separated, one record per line
+pub fn log_record_template(fields: &[&str]) -> String {
+    format!("{} ++ \"\\n\"", fields.join(" ++ \"\\t\" ++ "))
+}
+
+/// Run a graphless `jj log` over a revset and split its output into tab-separated records
+pub fn query_log_records(
+    revset: &str,
+    template: &str,
+    repo_path: Option<String>,
+    cwd: Option<String>,
+) -> Result<Vec<Vec<String>>> {
+    query_log_records_at_op(None, revset, template, repo_path, cwd)
+}
+
+/// Like [`query_log_records`], but evaluated as of an earlier operation when one is given
+pub fn query_log_records_at_op(
+    operation: Option<&str>,
+    revset: &str,
+    template: &str,
+    repo_path: Option<String>,
+    cwd: Option<String>,
+) -> Result<Vec<Vec<String>>> {
+    let mut args = Vec::new();
+    if let Some(operation) = operation {
+        args.push("--at-op".to_string());
+        args.push(operation.to_string());
+    }
+    args.extend([
+        "log".to_string(),
+        "--no-graph".to_string(),
+        "-r".to_string(),
+        revset.to_string(),
+        "-T".to_string(),
+        template.to_string(),
+    ]);
+    add_repo_args(&mut args, repo_path);
+
+    let output = run_jj_command_sync(args, cwd)?;
+    Ok(output
+        .lines()
+        .filter(|line| !line.is_empty())
+        .map(|line| line.split('\t').map(|field| field.to_string()).collect())
+        .collect())
+}
+
+/// A visible commit belonging to a divergent change
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct DivergentCommit {
+    #[serde(rename = "commitId")]
+    pub commit_id: String,
+    pub description: String,
+    #[serde(rename = "committedAt")]
+    pub committed_at: String,
+    #[serde(rename = "workingCopy")]
+    pub working_copy: bool,
+}
+
+/// Group divergent commits by change ID and suggest which commits to abandon
+///
+/// The working-copy commit is kept if present, otherwise the most recently
+/// committed one; every other commit of the change is a candidate to abandon.
+pub fn group_divergent_commits(records: &[Vec<String>]) -> Vec<Value> {
+    let mut groups: Vec<(String, Vec<DivergentCommit>)> = Vec::new();
+
+    for record in records {
+        let [
+            change_id,
+            commit_id,
+            committed_at,
+            working_copy,
+            description,
+        ] = record.as_slice()
+        else {
+            continue;
+        };
+        let commit = DivergentCommit {
+            commit_id: commit_id.clone(),
+            description: description.clone(),
+            committed_at: committed_at.clone(),
+            working_copy: working_copy == "true",
+        };
+        match groups.iter_mut().find(|(id, _)| id == change_id) {
+            Some((_, commits)) => commits.push(commit),
+            None => groups.push((change_id.clone(), vec![commit])),
+        }
+    }
+
+    groups
+        .into_iter()
+        .map(|(change_id, commits)| {
+            let keep = commits
+                .iter()
+                .find(|c| c.working_copy)
+                .or_else(|| {
+                    commits
+                        .iter()
+                        .max_by(|a, b| a.committed_at.cmp(&b.committed_at))
+                })
+                .map(|c| c.commit_id.clone())
+                .unwrap_or_default();
+            let abandon: Vec<&String> = commits
+                .iter()
+                .map(|c| &c.commit_id)
+                .filter(|id| **id != keep)
+                .collect();
+
+            serde_json::json!({
+                "changeId": change_id,
+                "commits": commits,
+                "hint": {
+                    "keep": keep,
+                    "abandon": abandon,
+                },
+            })
+        })
+        .collect()
+}
+
+/// List divergent changes with their visible commits
+pub fn run_jj_divergent_changes(params: DivergentChangesParams) -> CallToolResponse {
+    let result = (|| {
+        let revset = params.revisions.unwrap_or_else(|| "all()".to_string());
+        let committed_at = utc_timestamp_template("committer.timestamp()");
+        let record = log_record_template(&[
+            "change_id",
+            "commit_id.short()",
+            &committed_at,
+            "if(current_working_copy, \"true\", \"false\")",
+            "description.first_line()",
+        ]);
+        let template = format!("if(divergent, {})", record);
+
+        let records = query_log_records(&revset, &template, params.repo_path, params.cwd)?;
+        let changes = group_divergent_commits(&records);
+
+        Ok(serde_json::json!({
+            "divergent": !changes.is_empty(),
+            "changes": changes,
+        }))
+    })();
+
+    json_response(result)
+}
+
+/// Return the ID of the operation `count` steps before the current one
+///
+/// Falls back to the oldest operation when the log is shorter than requested.
+pub fn operation_before(
+    count: u32,
+    repo_path: Option<String>,
+    cwd: Option<String>,
+) -> Result<String> {
+    let mut args = vec![
+        "op".to_string(),
+        "log".to_string(),
+        "--no-graph".to_string(),
+        "-n".to_string(),
+        (count + 1).to_string(),
+        "-T".to_string(),
+        "id ++ \"\\n\"".to_string(),
+    ];
+    add_repo_args(&mut args, repo_path);
+
+    let output = run_jj_command_sync(args, cwd)?;
+    output
+        .lines()
+        .rfind(|line| !line.is_empty())
+        .map(|id| id.to_string())
+        .ok_or_else(|| anyhow::anyhow!("Error: operation log is empty"))
+}
+
+/// List commits that were visible at an earlier operation but are hidden now
+pub fn run_jj_hidden_commits(params: HiddenCommitsParams) -> CallToolResponse {
+    let result = (|| {
+        let revset = params.revisions.unwrap_or_else(|| "all()".to_string());
+        let operation = operation_before(
+            params.operations.unwrap_or(10),
+            params.repo_path.clone(),
+            params.cwd.clone(),
+        )?;
+
+        let committed_at = utc_timestamp_template("committer.timestamp()");
+        let template = log_record_template(&[
+            "commit_id",
+            "change_id",
+            &committed_at,
+            "description.first_line()",
+        ]);
+        let then = query_log_records_at_op(
+            Some(&operation),
+            &revset,
+            &template,
+            params.repo_path.clone(),
+            params.cwd.clone(),
+        )?;
+        let now = query_log_records(&revset, &template, params.repo_path, params.cwd)?;
+
+        let visible_commits: std::collections::HashSet<&str> =
+            now.iter().map(|record| record[0].as_str()).collect();
+        let visible_changes: std::collections::HashSet<&str> = now
+            .iter()
+            .filter_map(|record| record.get(1).map(|id| id.as_str()))
+            .collect();
+        let include_rewritten = params.include_rewritten.unwrap_or(false);
+
+        let hidden: Vec<Value> = then
+            .iter()
+            .filter(|record| record.len() >= 4 && !visible_commits.contains(record[0].as_str()))
+            .filter(|record| include_rewritten || !visible_changes.contains(record[1].as_str()))
+            .map(|record| {
+                serde_json::json!({
+                    "commitId": record[0],
+                    "changeId": record[1],
+                    "committedAt": record[2],
+                    "description": record[3..].join("\t"),
+                    "rewritten": visible_changes.contains(record[1].as_str()),
+                })
+            })
+            .collect();
+
+        Ok(serde_json::json!({
+            "sinceOperation": operation,
+            "hidden": hidden,
+        }))
+    })();
+
+    json_response(result)
+}
+
+/// Recover a hidden commit by duplicating it into a new visible commit
+pub fn run_jj_unabandon(params: UnabandonParams) -> CallToolResponse {
+    let mut args = vec!["duplicate".to_string()];
+
+    if let Some(commit) = params.commit {
+        args.push(commit);
+    }
+
+    if let Some(destination) = params.destination {
+        args.push("-d".to_string());
+        args.push(destination);
+    }
+
+    add_repo_args(&mut args, params.repo_path);
+
+    match run_jj_command_output(args, params.cwd) {
+        Ok(output) => CallToolResponse {
+            content: vec![ToolResponseContent::Text {
+                text: output.stderr,
+            }],
+            is_error: Some(false),
+            meta: None,
+        },
+        Err(e) => CallToolResponse {
+            content: vec![ToolResponseContent::Text {
+                text: e.to_string(),
+            }],
+            is_error: Some(true),
+            meta: None,
+        },
+    }
+}
+
+/// Build the revset selecting empty, description-less commits within `revisions`
+///
+/// The working-copy commit and merge commits are excluded: an empty `@` is the
+/// normal state after `jj new`, and an empty merge still records topology.
+pub fn empty_commits_revset(revisions: &str) -> String {
+    format!(
+        "({}) & empty() & description(exact:\"\") ~ @ ~ merges() ~ root()",
+        revisions
+    )
+}
+
+/// Find empty, description-less commits and abandon them unless in dry-run mode
+pub fn run_jj_cleanup_empty(params: CleanupEmptyParams) -> CallToolResponse {
+    let result = (|| {
+        let revisions = params.revisions.unwrap_or_else(|| "mutable()".to_string());
+        let revset = empty_commits_revset(&revisions);
+        let template = log_record_template(&["commit_id", "change_id.short()"]);
+
+        let records = query_log_records(
+            &revset,
+            &template,
+            params.repo_path.clone(),
+            params.cwd.clone(),
+        )?;
+        let commits: Vec<Value> = records
+            .iter()
+            .filter(|record| record.len() >= 2)
+            .map(|record| serde_json::json!({"commitId": record[0], "changeId": record[1]}))
+            .collect();
+
+        let dry_run = params.dry_run.unwrap_or(false);
+        if !dry_run && !records.is_empty() {
+            let mut args = vec!["abandon".to_string()];
+            args.extend(records.iter().map(|record| record[0].clone()));
+            add_repo_args(&mut args, params.repo_path);
+            run_jj_command_sync(args, params.cwd)?;
+        }
+
+        Ok(serde_json::json!({
+            "dryRun": dry_run,
+            "abandoned": if dry_run { 0 } else { commits.len() },
+            "commits": commits,
+        }))
+    })();
+
+    json_response(result)
+}
+
+/// Parse the "Duplicated <commit> as <change> <commit> ..." lines printed by `jj duplicate`
+pub fn parse_duplicated_commits(output: &str) -> Vec<DuplicatedCommit> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let mut words = line.split_whitespace();
+            if words.next()? != "Duplicated" {
+                return None;
+            }
+            let source = words.next()?.to_string();
+            if words.next()? != "as" {
+                return None;
+            }
+            Some(DuplicatedCommit {
+                source,
+                change_id: words.next()?.to_string(),
+                commit_id: words.next()?.to_string(),
+            })
+        })
+        .collect()
+}
+
+/// Copy revisions onto a destination by duplicating and then rebasing them
+pub fn run_jj_cherry_pick(params: CherryPickParams) -> CallToolResponse {
+    let result = (|| {
+        let revisions = params.revisions.unwrap_or_default();
+        if revisions.is_empty() {
+            return Err(anyhow::anyhow!("Error: at least one revision is required"));
+        }
+        let destination = params
+            .destination
+            .ok_or_else(|| anyhow::anyhow!("Error: destination is required"))?;
+
+        let mut args = vec!["duplicate".to_string()];
+        args.extend(revisions);
+        add_repo_args(&mut args, params.repo_path.clone());
+        let output = run_jj_command_output(args, params.cwd.clone())?;
+
+        let duplicated = parse_duplicated_commits(&output.stderr);
+        if duplicated.is_empty() {
+            return Err(anyhow::anyhow!(
+                "Error: could not determine duplicated commits from: {}",
+                output.stderr
+            ));
+        }
+
+        // Rebasing the duplicates together keeps their relative structure intact
+        let mut args = vec!["rebase".to_string()];
+        for commit in &duplicated {
+            args.push("-r".to_string());
+            args.push(commit.change_id.clone());
+        }
+        args.push("-d".to_string());
+        args.push(destination.clone());
+        add_repo_args(&mut args, params.repo_path);
+        let rebase = run_jj_command_output(args, params.cwd)?;
+
+        Ok(serde_json::json!({
+            "destination": destination,
+            "changeIds": duplicated.iter().map(|c| c.change_id.clone()).collect::<Vec<_>>(),
+            "duplicated": duplicated,
+            "output": rebase.stderr,
+        }))
+    })();
+
+    json_response(result)
+}
+
+/// Back out a range of revisions, newest first, stacking the reverts onto a destination
+pub fn run_jj_revert_range(params: RevertRangeParams) -> CallToolResponse {
+    let result = (|| {
+        let revisions = params
+            .revisions
+            .ok_or_else(|| anyhow::anyhow!("Error: revisions is required"))?;
+        let mut destination = resolve_commit_id(
+            params.destination.as_deref().unwrap_or("@"),
+            params.repo_path.clone(),
+            params.cwd.clone(),
+        )?;
+
+        // `jj log` lists newest commits first, which is the order to undo them in
+        let template = log_record_template(&["commit_id", "description.first_line()"]);
+        let commits = query_log_records(
+            &revisions,
+            &template,
+            params.repo_path.clone(),
+            params.cwd.clone(),
+        )?;
+
+        let mut reverts = Vec::new();
+        for commit in &commits {
+            let commit_id = &commit[0];
+            // The revert is the child of the destination that wasn't there before
+            let reverted = (|| {
+                let children = format!("children({})", destination);
+                let before =
+                    change_and_commit_ids(&children, params.repo_path.clone(), params.cwd.clone())?;
+                let mut args = vec![
+                    "revert".to_string(),
+                    "-r".to_string(),
+                    commit_id.clone(),
+                    "-d".to_string(),
+                    destination.clone(),
+                ];
+                add_repo_args(&mut args, params.repo_path.clone());
+                run_jj_command_sync(args, params.cwd.clone())?;
+                change_and_commit_ids(&children, params.repo_path.clone(), params.cwd.clone())?
+                    .into_iter()
+                    .find(|(_, new)| !before.iter().any(|(_, existing)| existing == new))
+                    .ok_or_else(|| anyhow::anyhow!("could not find the revert of {}", commit_id))
+            })();
+
+            let (change_id, new_commit) = match reverted {
+                Ok(ids) => ids,
+                // Report the reverts already made, which stay in the repository
+                Err(e) => {
+                    let error = serde_json::json!({
+                        "error": e.to_string().trim_start_matches("Error: "),
+                        "failedAt": commit_id,
+                        "partial": true,
+                        "reverts": reverts,
+                        "head": destination,
+                    });
+                    return Err(anyhow::anyhow!("Error: {}", error));
+                }
+            };
+            reverts.push(serde_json::json!({
+                "reverted": commit_id,
+                "description": commit.get(1).cloned().unwrap_or_default(),
+                "commitId": new_commit,
+                "changeId": change_id,
+            }));
+            destination = new_commit;
+        }
+
+        Ok(serde_json::json!({
+            "reverts": reverts,
+            "head": destination,
+        }))
+    })();
+
+    json_response(result)
+}
+
+/// Active bisection sessions, keyed by workspace root
+static BISECT_SESSIONS: std::sync::LazyLock<
+    std::sync::Mutex<std::collections::HashMap<std::path::PathBuf, BisectSession>>,
+> = std::sync::LazyLock::new(Default::default);
+
+/// Resolve a revision to exactly one full commit ID
+pub fn resolve_commit_id(
+    revision: &str,
+    repo_path: Option<String>,
+    cwd: Option<String>,
+) -> Result<String> {
+    #[cfg(feature = "jj-lib")]
+    if jj_lib_backend::supports_revset(revision) {
+        let base = cwd
+            .clone()
+            .map(std::path::PathBuf::from)
+            .or_else(|| std::env::current_dir().ok());
+        let start = match repo_path.as_deref() {
+            Some(repo_path) => base.map(|base| base.join(repo_path)),
+            None => base,
+        };
+        let commits = start
+            .and_then(|start| jj_lib_backend::find_workspace_root(&start))
+            .and_then(|root| jj_lib_backend::evaluate_revset(&root, revision).ok());
+        if let Some([commit]) = commits.as_deref() {
+            return Ok(commit.commit_id.clone());
+        }
+    }
+
+    let records = query_log_records(revision, "commit_id ++ \"\\n\"", repo_path, cwd)?;
+    match records.as_slice() {
+        [record] => Ok(record[0].clone()),
+        _ => Err(anyhow::anyhow!(
+            "Error: revision {} must resolve to exactly one commit, found {}",
+            revision,
+            records.len()
+        )),
+    }
+}
+
+impl BisectSession {
+    /// Revset of commits that may still be the first bad one, excluding the known bad commit
+    pub fn candidates_revset(&self) -> String {
+        let mut revset = format!("({})..{} ~ {}", self.good.join(" | "), self.bad, self.bad);
+        for skipped in &self.skipped {
+            revset.push_str(" ~ ");
+            revset.push_str(skipped);
+        }
+        revset
+    }
+}
+
+/// Pick the next candidate of a session and check it out with `jj edit`
+fn bisect_step(
+    session: &mut BisectSession,
+    repo_path: Option<String>,
+    cwd: Option<String>,
+) -> Result<Value> {
+    let candidates = query_log_records(
+        &session.candidates_revset(),
+        &log_record_template(&["commit_id", "description.first_line()"]),
+        repo_path.clone(),
+        cwd.clone(),
+    )?;
+
+    if candidates.is_empty() {
+        session.current = None;
+        session.first_bad = if session.skipped.is_empty() {
+            Some(session.bad.clone())
+        } else {
+            None
+        };
+        return Ok(serde_json::json!({
+            "done": true,
+            "firstBad": session.first_bad,
+            "remaining": 0,
+            "session": session,
+        }));
+    }
+
+    // Log order is topological, so the middle entry roughly halves the range
+    let next = candidates[candidates.len() / 2][0].clone();
+    let mut args = vec!["edit".to_string(), next.clone()];
+    add_repo_args(&mut args, repo_path);
+    run_jj_command_sync(args, cwd)?;
+    session.current = Some(next.clone());
+
+    Ok(serde_json::json!({
+        "done": false,
+        "current": next,
+        "remaining": candidates.len(),
+        "session": session,
+    }))
+}
+
+/// Start a bisection between known-good revisions and a bad revision
+pub fn run_jj_bisect_start(params: BisectStartParams) -> CallToolResponse {
+    let result = (|| {
+        let good = params.good.unwrap_or_default();
+        if good.is_empty() {
+            return Err(anyhow::anyhow!(
+                "Error: at least one good revision is required"
+            ));
+        }
+        let bad = params.bad.unwrap_or_else(|| "@".to_string());
+
+        let root = resolve_workspace_root(params.repo_path.clone(), params.cwd.clone())?;
+        let mut session = BisectSession {
+            bad: resolve_commit_id(&bad, params.repo_path.clone(), params.cwd.clone())?,
+            ..Default::default()
+        };
+        for revision in &good {
+            session.good.push(resolve_commit_id(
+                revision,
+                params.repo_path.clone(),
+                params.cwd.clone(),
+            )?);
+        }
+
+        let step = bisect_step(&mut session, params.repo_path, params.cwd)?;
+        BISECT_SESSIONS.lock().unwrap().insert(root, session);
+        Ok(step)
+    })();
+
+    json_response(result)
+}
+
+/// Mark a revision (the current candidate by default) as good, bad, or skipped
+pub fn run_jj_bisect_mark(params: BisectMarkParams) -> CallToolResponse {
+    let result = (|| {
+        let root = resolve_workspace_root(params.repo_path.clone(), params.cwd.clone())?;
+        let mut session = BISECT_SESSIONS
+            .lock()
+            .unwrap()
+            .get(&root)
+            .cloned()
+            .ok_or_else(|| anyhow::anyhow!("Error: no bisection in progress, use bisect-start"))?;
+
+        let commit = match params.revision {
+            Some(revision) => {
+                resolve_commit_id(&revision, params.repo_path.clone(), params.cwd.clone())?
+            }
+            None => session
+                .current
+                .clone()
+                .ok_or_else(|| anyhow::anyhow!("Error: bisection has already finished"))?,
+        };
+
+        match params.result.as_deref() {
+            Some("good") => session.good.push(commit),
+            Some("bad") => session.bad = commit,
+            Some("skip") => session.skipped.push(commit),
+            other => {
+                return Err(anyhow::anyhow!(
+                    "Error: result must be \"good\", \"bad\" or \"skip\", got {:?}",
+                    other
+                ));
+            }
+        }
+
+        let step = bisect_step(&mut session, params.repo_path, params.cwd)?;
+        BISECT_SESSIONS.lock().unwrap().insert(root, session);
+        Ok(step)
+    })();
+
+    json_response(result)
+}
+
+/// Report the bisection state of a repository
+pub fn run_jj_bisect_status(params: BisectStatusParams) -> CallToolResponse {
+    let result = (|| {
+        let root = resolve_workspace_root(params.repo_path, params.cwd)?;
+        let session = BISECT_SESSIONS.lock().unwrap().get(&root).cloned();
+
+        Ok(serde_json::json!({
+            "active": session.is_some(),
+            "session": session,
+        }))
+    })();
+
+    json_response(result)
+}
+
+/// Split a conventional-commit subject into its type, optional scope, and summary
+///
+/// Returns `None` for subjects that don't follow the `type(scope)!: summary` form.
+pub fn parse_conventional_commit(subject: &str) -> Option<(String, Option<String>, String)> {
+    let (prefix, summary) = subject.split_once(':')?;
+    let prefix = prefix.strip_suffix('!').unwrap_or(prefix);
+    let (kind, scope) = match prefix.split_once('(') {
+        Some((kind, rest)) => (kind, Some(rest.strip_suffix(')')?.to_string())),
+        None => (prefix, None),
+    };
+
+    if kind.is_empty() || !kind.chars().all(|c| c.is_ascii_alphabetic()) {
+        return None;
+    }
+
+    Some((kind.to_ascii_lowercase(), scope, summary.trim().to_string()))
+}
+
+/// Render a markdown changelog grouping `(change ID, subject)` entries by commit type
+pub fn render_changelog(title: &str, entries: &[(String, String)]) -> String {
+    let mut features = Vec::new();
+    let mut fixes = Vec::new();
+    let mut other = Vec::new();
+
+    for (change_id, subject) in entries {
+        let subject = if subject.is_empty() {
+            "(no description)"
+        } else {
+            subject.as_str()
+        };
+        match parse_conventional_commit(subject) {
+            Some((kind, scope, summary)) => {
+                let line = match scope {
+                    Some(scope) => format!("- **{}:** {} ({})", scope, summary, change_id),
+                    None => format!("- {} ({})", summary, change_id),
+                };
+                match kind.as_str() {
+                    "feat" => features.push(line),
+                    "fix" => fixes.push(line),
+                    _ => other.push(line),
+                }
+            }
+            None => other.push(format!("- {} ({})", subject, change_id)),
+        }
+    }
+
+    let mut markdown = format!("## {}\n", title);
+    for (heading, lines) in [("Features", features), ("Fixes", fixes), ("Other", other)] {
+        if !lines.is_empty() {
+            markdown.push_str(&format!("\n### {}\n\n{}\n", heading, lines.join("\n")));
+        }
+    }
+    markdown
+}
+
+/// Render a grouped markdown changelog for the commits between two revisions
+pub fn run_jj_changelog(params: ChangelogParams) -> CallToolResponse {
+    let result: Result<String> = (|| {
+        let from = params
+            .from
+            .ok_or_else(|| anyhow::anyhow!("Error: from is required"))?;
+        let to = params.to.unwrap_or_else(|| "@".to_string());
+
+        let records = query_log_records(
+            &format!("({})..({}) ~ empty()", from, to),
+            &log_record_template(&["change_id.short()", "description.first_line()"]),
+            params.repo_path,
+            params.cwd,
+        )?;
+        let entries: Vec<(String, String)> = records
+            .into_iter()
+            .map(|record| (record[0].clone(), record[1..].join("\t")))
+            .collect();
+
+        Ok(render_changelog(
+            &format!("Changes from {} to {}", from, to),
+            &entries,
+        ))
+    })();
+
+    match result {
+        Ok(output) => CallToolResponse {
+            content: vec![ToolResponseContent::Text { text: output }],
+            is_error: Some(false),
+            meta: None,
+        },
+        Err(e) => CallToolResponse {
+            content: vec![ToolResponseContent::Text {
+                text: e.to_string(),
+            }],
+            is_error: Some(true),
+            meta: None,
+        },
+    }
+}
+
+/// Aggregate `(name, email, timestamp)` records into per-author statistics
+///
+/// Authors are identified by email (case-insensitively) and sorted by commit count.
+pub fn aggregate_contributors(records: &[Vec<String>]) -> Vec<ContributorStats> {
+    let mut stats: Vec<ContributorStats> = Vec::new();
+
+    for record in records {
+        let [name, email, timestamp, ..] = record.as_slice() else {
+            continue;
+        };
+        match stats
+            .iter_mut()
+            .find(|s| s.email.eq_ignore_ascii_case(email))
+        {
+            Some(entry) => {
+                entry.commits += 1;
+                if *timestamp < entry.first_commit {
+                    entry.first_commit = timestamp.clone();
+                }
+                if *timestamp > entry.last_commit {
+                    entry.last_commit = timestamp.clone();
+                }
+            }
+            None => stats.push(ContributorStats {
+                name: name.clone(),
+                email: email.clone(),
+                commits: 1,
+                first_commit: timestamp.clone(),
+                last_commit: timestamp.clone(),
+            }),
+        }
+    }
+
+    stats.sort_by(|a, b| b.commits.cmp(&a.commits).then_with(|| a.name.cmp(&b.name)));
+    stats
+}
+
+/// Report per-author commit counts and date ranges over a revset
+pub fn run_jj_contributors(params: ContributorsParams) -> CallToolResponse {
+    let result = (|| {
+        let revisions = params.revisions.unwrap_or_else(|| "::@".to_string());
+        let authored_at = utc_timestamp_template("author.timestamp()");
+        let records = query_log_records(
+            &format!("({}) ~ root()", revisions),
+            &log_record_template(&["author.name()", "author.email()", &authored_at]),
+            params.repo_path,
+            params.cwd,
+        )?;
+
+        let contributors = aggregate_contributors(&records);
+        Ok(serde_json::json!({
+            "revisions": revisions,
+            "totalCommits": records.len(),
+            "contributors": contributors,
+        }))
+    })();
+
+    json_response(result)
+}
+
+/// Locate the jj repo directory of a workspace
+///
+/// Secondary workspaces store the path of the shared repo in `.jj/repo` as a file.
+pub fn repo_dir(workspace_root: &std::path::Path) -> std::path::PathBuf {
+    let repo = workspace_root.join(".jj").join("repo");
+    match std::fs::read_to_string(&repo) {
+        Ok(target) => {
+            let target = std::path::PathBuf::from(target.trim());
+            if target.is_absolute() {
+                target
+            } else {
+                workspace_root.join(".jj").join(target)
+            }
+        }
+        Err(_) => repo,
+    }
+}
+
+/// Detect whether a workspace is colocated with git and where its git directory lives
+pub fn detect_git_backing(workspace_root: &std::path::Path) -> GitBacking {
+    let store = repo_dir(workspace_root).join("store");
+    let git_dir = match std::fs::read_to_string(store.join("git_target")) {
+        Ok(target) => {
+            let target = std::path::PathBuf::from(target.trim());
+            let resolved = if target.is_absolute() {
+                target
+            } else {
+                store.join(target)
+            };
+            Some(std::fs::canonicalize(&resolved).unwrap_or(resolved))
+        }
+        Err(_) => None,
+    };
+
+    let workspace_git = std::fs::canonicalize(workspace_root.join(".git")).ok();
+    let colocated = workspace_git.is_some() && workspace_git == git_dir;
+
+    GitBacking { colocated, git_dir }
+}
+
+/// Describe how a workspace is colocated with git, for the doctor tool
+///
+/// In a colocated workspace jj imports git refs before and exports them after every
+/// command, so plain git commands and jj see each other's changes.
+pub fn colocation_report(workspace_root: &std::path::Path) -> Value {
+    let backing = detect_git_backing(workspace_root);
+    let dot_git = workspace_root.join(".git");
+    let dot_git_kind = if dot_git.is_dir() {
+        "directory"
+    } else if dot_git.is_file() {
+        "file"
+    } else {
+        "missing"
+    };
+    serde_json::json!({
+        "backend": if backing.git_dir.is_some() { "git" } else { "local" },
+        "colocated": backing.colocated,
+        "gitDir": backing.git_dir,
+        "workspaceGit": dot_git_kind,
+        "autoImportExport": backing.colocated,
+    })
+}
+
+/// Total size in bytes of the files in a working copy, skipping `.jj` and `.git`
+pub fn working_copy_size(root: &std::path::Path) -> u64 {
+    fn walk(dir: &std::path::Path, top: bool) -> u64 {
+        let Ok(entries) = std::fs::read_dir(dir) else {
+            return 0;
+        };
+        entries
+            .flatten()
+            .map(|entry| {
+                let name = entry.file_name();
+                if top && (name == ".jj" || name == ".git") {
+                    return 0;
+                }
+                match entry.file_type() {
+                    Ok(kind) if kind.is_dir() => walk(&entry.path(), false),
+                    Ok(kind) if kind.is_file() => entry.metadata().map(|m| m.len()).unwrap_or(0),
+                    _ => 0,
+                }
+            })
+            .sum()
+    }
+    walk(root, true)
+}
+
+/// Count the non-empty output lines of a jj command
+fn count_output_lines(args: Vec<String>, cwd: Option<String>) -> Result<usize> {
+    let output = run_jj_command_sync(args, cwd)?;
+    Ok(output
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .count())
+}
+
+/// Report repository-level statistics aggregated from several jj queries
+pub fn run_jj_repo_stats(params: RepoStatsParams) -> CallToolResponse {
+    let result = (|| {
+        let revisions = params.revisions.unwrap_or_else(|| "all()".to_string());
+        let count_revset = |revset: &str| {
+            query_log_records(
+                revset,
+                "commit_id ++ \"\\n\"",
+                params.repo_path.clone(),
+                params.cwd.clone(),
+            )
+            .map(|records| records.len())
+        };
+
+        let heads = count_revset("visible_heads()")?;
+        let commits = count_revset(&revisions)?;
+
+        let mut args = vec![
+            "bookmark".to_string(),
+            "list".to_string(),
+            "-T".to_string(),
+            "name ++ \"\\n\"".to_string(),
+        ];
+        add_repo_args(&mut args, params.repo_path.clone());
+        let bookmarks = count_output_lines(args, params.cwd.clone())?;
+
+        let mut args = vec!["workspace".to_string(), "list".to_string()];
+        add_repo_args(&mut args, params.repo_path.clone());
+        let workspaces = count_output_lines(args, params.cwd.clone())?;
+
+        let root = resolve_workspace_root(params.repo_path, params.cwd)?;
+        let backing = detect_git_backing(&root);
+
+        Ok(serde_json::json!({
+            "root": root,
+            "visibleHeads": heads,
+            "revisions": revisions,
+            "commitCount": commits,
+            "bookmarkCount": bookmarks,
+            "workspaceCount": workspaces,
+            "colocated": backing.colocated,
+            "workingCopyBytes": working_copy_size(&root),
+        }))
+    })();
+
+    json_response(result)
+}
+
+/// List commits added since a timestamp or since an earlier operation
+pub fn run_jj_changes_since(params: ChangesSinceParams) -> CallToolResponse {
+    let result = (|| {
+        let revisions = params.revisions.unwrap_or_else(|| "all()".to_string());
+        let committed_at = utc_timestamp_template("committer.timestamp()");
+        let template = log_record_template(&[
+            "commit_id",
+            "change_id.short()",
+            "author.name()",
+            &committed_at,
+            "description.first_line()",
+        ]);
+
+        let records = match (params.since, params.operation) {
+            (Some(since), None) => query_log_records(
+                &format!(
+                    "({}) & committer_date(after:{})",
+                    revisions,
+                    quote_string_literal(&since)
+                ),
+                &template,
+                params.repo_path,
+                params.cwd,
+            )?,
+            (None, Some(operation)) => {
+                let before = query_log_records_at_op(
+                    Some(&operation),
+                    &revisions,
+                    "commit_id ++ \"\\n\"",
+                    params.repo_path.clone(),
+                    params.cwd.clone(),
+                )?;
+                let before: std::collections::HashSet<&String> =
+                    before.iter().map(|record| &record[0]).collect();
+                query_log_records(&revisions, &template, params.repo_path, params.cwd)?
+                    .into_iter()
+                    .filter(|record| !before.contains(&record[0]))
+                    .collect()
+            }
+            _ => {
+                return Err(anyhow::anyhow!(
+                    "Error: exactly one of since or operation is required"
+                ));
+            }
+        };
+
+        let commits: Vec<Value> = records
+            .iter()
+            .filter(|record| record.len() >= 5)
+            .map(|record| {
+                serde_json::json!({
+                    "commitId": record[0],
+                    "changeId": record[1],
+                    "author": record[2],
+                    "committedAt": record[3],
+                    "description": record[4..].join("\t"),
+                })
+            })
+            .collect();
+
+        Ok(serde_json::json!({
+            "count": commits.len(),
+            "commits": commits,
+        }))
+    })();
+
+    json_response(result)
+}
+
+/// List visible heads that have no local or remote bookmark pointing at them
+pub fn run_jj_anonymous_heads(params: AnonymousHeadsParams) -> CallToolResponse {
+    let result = (|| {
+        let committed_at = utc_timestamp_template("committer.timestamp()");
+        let records = query_log_records(
+            "heads(all()) ~ bookmarks() ~ remote_bookmarks() ~ root()",
+            &log_record_template(&[
+                "commit_id.short()",
+                "change_id.short()",
+                &committed_at,
+                "committer.timestamp().ago()",
+                "if(empty, \"true\", \"false\")",
+                "if(current_working_copy, \"true\", \"false\")",
+                "description.first_line()",
+            ]),
+            params.repo_path,
+            params.cwd,
+        )?;
+
+        let heads: Vec<Value> = records
+            .iter()
+            .filter(|record| record.len() >= 7)
+            .map(|record| {
+                serde_json::json!({
+                    "commitId": record[0],
+                    "changeId": record[1],
+                    "committedAt": record[2],
+                    "age": record[3],
+                    "empty": record[4] == "true",
+                    "workingCopy": record[5] == "true",
+                    "description": record[6..].join("\t"),
+                })
+            })
+            .collect();
+
+        Ok(serde_json::json!({
+            "count": heads.len(),
+            "heads": heads,
+        }))
+    })();
+
+    json_response(result)
+}
+
+/// Report the commits only reachable from each of two revisions (ahead/behind)
+pub fn run_jj_compare(params: CompareParams) -> CallToolResponse {
+    let result = (|| {
+        let revision = params.revision.unwrap_or_else(|| "@".to_string());
+        let base = params
+            .base
+            .ok_or_else(|| anyhow::anyhow!("Error: base is required"))?;
+        let limit = params.limit.unwrap_or(20) as usize;
+        let template = log_record_template(&[
+            "change_id.short()",
+            "commit_id.short()",
+            "description.first_line()",
+        ]);
+
+        let side = |revset: String| -> Result<Value> {
+            let records = query_log_records(
+                &revset,
+                &template,
+                params.repo_path.clone(),
+                params.cwd.clone(),
+            )?;
+            let commits: Vec<Value> = records
+                .iter()
+                .take(limit)
+                .map(|record| {
+                    serde_json::json!({
+                        "changeId": record[0],
+                        "commitId": record.get(1),
+                        "description": record.get(2..).map(|d| d.join("\t")),
+                    })
+                })
+                .collect();
+            Ok(serde_json::json!({
+                "count": records.len(),
+                "commits": commits,
+            }))
+        };
+
+        Ok(serde_json::json!({
+            "revision": revision,
+            "base": base,
+            "ahead": side(format!("({})..({})", base, revision))?,
+            "behind": side(format!("({})..({})", revision, base))?,
+        }))
+    })();
+
+    json_response(result)
+}
+
+/// List the paths changed between two revisions, relative to the workspace root
+pub fn changed_paths(root: &std::path::Path, from: &str, to: &str) -> Result<Vec<String>> {
+    let args = vec![
+        "diff".to_string(),
+        "--name-only".to_string(),
+        "--from".to_string(),
+        from.to_string(),
+        "--to".to_string(),
+        to.to_string(),
+    ];
+
+    let output = run_jj_command_sync(args, Some(root.to_string_lossy().to_string()))?;
+    Ok(output
+        .lines()
+        .filter(|line| !line.is_empty())
+        .map(|line| line.replace('\\', "/"))
+        .collect())
+}
+
+/// Create a working-copy merge of several revisions and attribute conflicts to sources
+pub fn run_jj_megamerge(params: MegamergeParams) -> CallToolResponse {
+    let result = (|| {
+        let revisions = params.revisions.unwrap_or_default();
+        if revisions.len() < 2 {
+            return Err(anyhow::anyhow!(
+                "Error: at least two revisions are required"
+            ));
+        }
+
+        let root = resolve_workspace_root(params.repo_path, params.cwd)?;
+        let cwd = Some(root.to_string_lossy().to_string());
+
+        let mut args = vec!["new".to_string()];
+        args.extend(revisions.iter().cloned());
+        if let Some(message) = params.message {
+            args.push("-m".to_string());
+            args.push(message);
+        }
+        let output = run_jj_command_output(args, cwd.clone())?;
+
+        let conflicts = list_conflicted_paths(&root, "@")?;
+
+        // A source contributes to a conflict if it touched the path since the common base
+        let ancestors: Vec<String> = revisions.iter().map(|r| format!("::({})", r)).collect();
+        let base = format!("latest(heads({}))", ancestors.join(" & "));
+        let mut sources = Vec::new();
+        for revision in &revisions {
+            let touched = changed_paths(&root, &base, revision)?;
+            let source_conflicts: Vec<&String> = conflicts
+                .iter()
+                .filter(|path| touched.contains(&path.replace('\\', "/")))
+                .collect();
+            sources.push(serde_json::json!({
+                "revision": revision,
+                "changedFiles": touched.len(),
+                "conflicts": source_conflicts,
+            }));
+        }
+
+        Ok(serde_json::json!({
+            "workingCopy": resolve_commit_id("@", None, cwd)?,
+            "conflicted": !conflicts.is_empty(),
+            "conflicts": conflicts,
+            "sources": sources,
+            "output": output.stderr,
+        }))
+    })();
+
+    json_response(result)
+}
+
+/// List the paths a single revision changes, relative to the workspace root
+pub fn revision_changed_paths(root: &std::path::Path, revision: &str) -> Result<Vec<String>> {
+    let args = vec![
+        "diff".to_string(),
+        "--name-only".to_string(),
+        "-r".to_string(),
+        revision.to_string(),
+    ];
+
+    let output = run_jj_command_sync(args, Some(root.to_string_lossy().to_string()))?;
+    Ok(output
+        .lines()
+        .filter(|line| !line.is_empty())
+        .map(|line| line.replace('\\', "/"))
+        .collect())
+}
+
+/// Group repo-relative paths by their top-level directory (`.` for files at the root)
+pub fn group_paths_by_top_level_dir(
+    paths: &[String],
+) -> std::collections::BTreeMap<String, Vec<String>> {
+    let mut groups: std::collections::BTreeMap<String, Vec<String>> = Default::default();
+    for path in paths {
+        let group = match path.split_once('/') {
+            Some((dir, _)) => dir.to_string(),
+            None => ".".to_string(),
+        };
+        groups.entry(group).or_default().push(path.clone());
+    }
+    groups
+}
+
+/// Split a revision into one commit per top-level directory
+pub fn run_jj_split_by_directory(params: SplitByDirectoryParams) -> CallToolResponse {
+    let result = (|| {
+        let revision = params.revision.unwrap_or_else(|| "@".to_string());
+        let root = resolve_workspace_root(params.repo_path, params.cwd)?;
+        let cwd = Some(root.to_string_lossy().to_string());
+
+        let change_id = query_log_records(&revision, "change_id ++ \"\\n\"", None, cwd.clone())?
+            .first()
+            .map(|record| record[0].clone())
+            .ok_or_else(|| anyhow::anyhow!("Error: revision {} not found", revision))?;
+        let description = run_jj_command_sync(
+            vec![
+                "log".to_string(),
+                "--no-graph".to_string(),
+                "-r".to_string(),
+                change_id.clone(),
+                "-T".to_string(),
+                "description.first_line()".to_string(),
+            ],
+            cwd.clone(),
+        )?;
+
+        let groups = group_paths_by_top_level_dir(&revision_changed_paths(&root, &change_id)?);
+        let mut remaining: Vec<&String> = groups.values().flatten().collect();
+        let mut rest = resolve_commit_id(&change_id, None, cwd.clone())?;
+
+        // Peel each directory except the last off into its own commit
+        for (directory, paths) in groups.iter().take(groups.len().saturating_sub(1)) {
+            let mut args = vec![
+                "split".to_string(),
+                "-r".to_string(),
+                rest.clone(),
+                "-m".to_string(),
+                format!("{} ({})", description, directory),
+            ];
+            args.extend(
+                paths
+                    .iter()
+                    .map(|path| format!("root-file:{}", quote_string_literal(path))),
+            );
+            run_jj_command_sync(args, cwd.clone())?;
+            remaining.retain(|path| !paths.contains(path));
+
+            // Either half may keep the change ID, so find the one holding the remaining paths
+            let candidates = query_log_records(
+                &format!("{} | children({})", change_id, change_id),
+                "commit_id ++ \"\\n\"",
+                None,
+                cwd.clone(),
+            )?;
+            let mut next = None;
+            for candidate in &candidates {
+                let touched = revision_changed_paths(&root, &candidate[0])?;
+                if remaining.iter().any(|path| touched.contains(path)) {
+                    next = Some(candidate[0].clone());
+                    break;
+                }
+            }
+            rest = next.ok_or_else(|| {
+                anyhow::anyhow!("Error: lost track of the remaining changes after splitting")
+            })?;
+        }
+
+        let commits = query_log_records(
+            &format!("ancestors({}, {})", rest, groups.len().max(1)),
+            &log_record_template(&[
+                "change_id.short()",
+                "commit_id.short()",
+                "description.first_line()",
+            ]),
+            None,
+            cwd,
+        )?;
+
+        Ok(serde_json::json!({
+            "groups": groups,
+            "commits": commits
+                .iter()
+                .map(|record| serde_json::json!({
+                    "changeId": record[0],
+                    "commitId": record.get(1),
+                    "description": record.get(2..).map(|d| d.join("\t")),
+                }))
+                .collect::<Vec<_>>(),
+        }))
+    })();
+
+    json_response(result)
+}
+
+/// Check whether a revset contains conflicted commits
+pub fn run_jj_has_conflicts(params: HasConflictsParams) -> CallToolResponse {
+    let result = (|| {
+        let revisions = params.revisions.unwrap_or_else(|| "@".to_string());
+        let records = query_log_records(
+            &format!("({}) & conflicts()", revisions),
+            &log_record_template(&[
+                "change_id.short()",
+                "commit_id.short()",
+                "description.first_line()",
+            ]),
+            params.repo_path,
+            params.cwd,
+        )?;
+
+        let conflicted: Vec<Value> = records
+            .iter()
+            .map(|record| {
+                serde_json::json!({
+                    "changeId": record[0],
+                    "commitId": record.get(1),
+                    "description": record.get(2..).map(|d| d.join("\t")),
+                })
+            })
+            .collect();
+
+        Ok(serde_json::json!({
+            "revisions": revisions,
+            "hasConflicts": !conflicted.is_empty(),
+            "conflicted": conflicted,
+        }))
+    })();
+
+    json_response(result)
+}
+
+/// Default size above which changed files are reported by the large-file guard (1 MiB)
+pub const DEFAULT_LARGE_FILE_THRESHOLD: u64 = 1024 * 1024;
+
+/// Read a file's content at a revision; `path` is relative to the workspace root
+pub fn read_file_at(root: &std::path::Path, revision: &str, path: &str) -> Result<Vec<u8>> {
+    #[cfg(feature = "jj-lib")]
+    if jj_lib_backend::supports_revset(revision)
+        && let Ok(commits) = jj_lib_backend::evaluate_revset(root, revision)
+        && let [commit] = commits.as_slice()
+        && let Ok(content) = jj_lib_backend::read_file(root, &commit.commit_id, path)
+    {
+        return Ok(content);
+    }
+
+    run_jj_command_bytes(
+        vec![
+            "file".to_string(),
+            "show".to_string(),
+            "-r".to_string(),
+            revision.to_string(),
+            format!("root-file:{}", quote_string_literal(path)),
+        ],
+        Some(root.to_string_lossy().to_string()),
+    )
+}
+
+/// Find files changed in a revision whose size exceeds `threshold` bytes
+///
+/// Working-copy files are measured on disk; other revisions are measured from
+/// `jj file show` output.
+pub fn find_large_files(
+    root: &std::path::Path,
+    revision: &str,
+    threshold: u64,
+) -> Result<Vec<(String, u64)>> {
+    let mut large = Vec::new();
+
+    for path in revision_changed_paths(root, revision)? {
+        let size = if revision == "@" {
+            match std::fs::metadata(root.join(&path)) {
+                Ok(metadata) if metadata.is_file() => metadata.len(),
+                // Deleted files can't be too large to commit
+                _ => continue,
+            }
+        } else {
+            match read_file_at(root, revision, &path) {
+                Ok(content) => content.len() as u64,
+                Err(_) => continue,
+            }
+        };
+
+        if size > threshold {
+            large.push((path, size));
+        }
+    }
+
+    Ok(large)
+}
+
+/// Report changed files above a size threshold
+pub fn run_jj_large_files(params: LargeFilesParams) -> CallToolResponse {
+    let result = (|| {
+        let revision = params.revision.unwrap_or_else(|| "@".to_string());
+        let threshold = params
+            .threshold_bytes
+            .unwrap_or(DEFAULT_LARGE_FILE_THRESHOLD);
+        let root = resolve_workspace_root(params.repo_path, params.cwd)?;
+
+        let files: Vec<Value> = find_large_files(&root, &revision, threshold)?
+            .into_iter()
+            .map(|(path, size)| serde_json::json!({"path": path, "bytes": size}))
+            .collect();
+
+        Ok(serde_json::json!({
+            "revision": revision,
+            "thresholdBytes": threshold,
+            "ok": files.is_empty(),
+            "files": files,
+        }))
+    })();
+
+    json_response(result)
+}
+
+/// Report whether revisions fall within the configured `immutable_heads()` ancestry
+pub fn run_jj_check_immutable(params: CheckImmutableParams) -> CallToolResponse {
+    let result = (|| {
+        let revisions = params.revisions.unwrap_or_else(|| vec!["@".to_string()]);
+        let template = "commit_id.short() ++ \"\\n\"";
+
+        let mut results = Vec::new();
+        let mut any_immutable = false;
+        for revision in &revisions {
+            let commits = query_log_records(
+                revision,
+                template,
+                params.repo_path.clone(),
+                params.cwd.clone(),
+            )?;
+            let immutable = query_log_records(
+                &format!("({}) & ::immutable_heads()", revision),
+                template,
+                params.repo_path.clone(),
+                params.cwd.clone(),
+            )?;
+            any_immutable |= !immutable.is_empty();
+
+            results.push(serde_json::json!({
+                "revision": revision,
+                "immutable": !commits.is_empty() && commits.len() == immutable.len(),
+                "commitCount": commits.len(),
+                "immutableCommits": immutable.iter().map(|r| r[0].clone()).collect::<Vec<_>>(),
+            }));
+        }
+
+        Ok(serde_json::json!({
+            "anyImmutable": any_immutable,
+            "revisions": results,
+        }))
+    })();
+
+    json_response(result)
+}
+
+/// Build a revset matching commits in `revisions` whose description matches `pattern`
+pub fn description_search_revset(
+    pattern: &str,
+    regex: bool,
+    ignore_case: bool,
+    revisions: &str,
+) -> String {
+    let kind = if regex { "regex" } else { "substring" };
+    let suffix = if ignore_case { "-i" } else { "" };
+    format!(
+        "({}) & description({}{}:{})",
+        revisions,
+        kind,
+        suffix,
+        quote_string_literal(pattern)
+    )
+}
+
+/// Search commit descriptions over a revset
+pub fn run_jj_search_descriptions(params: SearchDescriptionsParams) -> CallToolResponse {
+    let result = (|| {
+        let pattern = params
+            .pattern
+            .ok_or_else(|| anyhow::anyhow!("Error: pattern is required"))?;
+        let revisions = params.revisions.unwrap_or_else(|| "all()".to_string());
+        let mut revset = description_search_revset(
+            &pattern,
+            params.regex.unwrap_or(false),
+            params.ignore_case.unwrap_or(false),
+            &revisions,
+        );
+        if let Some(limit) = params.limit {
+            revset = format!("latest({}, {})", revset, limit);
+        }
+
+        let committed_at = utc_timestamp_template("committer.timestamp()");
+        let records = query_log_records(
+            &revset,
+            &log_record_template(&[
+                "change_id.short()",
+                "commit_id.short()",
+                "author.name()",
+                &committed_at,
+                "description.first_line()",
+            ]),
+            params.repo_path,
+            params.cwd,
+        )?;
+
+        let matches: Vec<Value> = records
+            .iter()
+            .filter(|record| record.len() >= 5)
+            .map(|record| {
+                serde_json::json!({
+                    "changeId": record[0],
+                    "commitId": record[1],
+                    "author": record[2],
+                    "committedAt": record[3],
+                    "description": record[4..].join("\t"),
+                })
+            })
+            .collect();
+
+        Ok(serde_json::json!({
+            "pattern": pattern,
+            "count": matches.len(),
+            "matches": matches,
+        }))
+    })();
+
+    json_response(result)
+}
+
+/// Build a revset for the merge base(s) of all given revisions
+pub fn common_ancestor_revset(revisions: &[String]) -> String {
+    let ancestors: Vec<String> = revisions
+        .iter()
+        .map(|revision| format!("::({})", revision))
+        .collect();
+    format!("heads({})", ancestors.join(" & "))
+}
+
+/// Find the merge base of two or more revisions
+pub fn run_jj_common_ancestor(params: CommonAncestorParams) -> CallToolResponse {
+    let result = (|| {
+        let revisions = params.revisions.unwrap_or_default();
+        if revisions.len() < 2 {
+            return Err(anyhow::anyhow!(
+                "Error: at least two revisions are required"
+            ));
+        }
+
+        let records = query_log_records(
+            &common_ancestor_revset(&revisions),
+            &log_record_template(&[
+                "change_id.short()",
+                "commit_id.short()",
+                "description.first_line()",
+            ]),
+            params.repo_path,
+            params.cwd,
+        )?;
+
+        let ancestors: Vec<Value> = records
+            .iter()
+            .map(|record| {
+                serde_json::json!({
+                    "changeId": record[0],
+                    "commitId": record.get(1),
+                    "description": record.get(2..).map(|d| d.join("\t")),
+                })
+            })
+            .collect();
+
+        Ok(serde_json::json!({
+            "revisions": revisions,
+            "unique": ancestors.len() == 1,
+            "ancestors": ancestors,
+        }))
+    })();
+
+    json_response(result)
+}
+
+/// Check whether one revision is an ancestor of another
+pub fn run_jj_is_ancestor(params: IsAncestorParams) -> CallToolResponse {
+    let result = (|| {
+        let ancestor = params
+            .ancestor
+            .ok_or_else(|| anyhow::anyhow!("Error: ancestor is required"))?;
+        let descendant = params.descendant.unwrap_or_else(|| "@".to_string());
+        let ancestor_id =
+            resolve_commit_id(&ancestor, params.repo_path.clone(), params.cwd.clone())?;
+        let descendant_id =
+            resolve_commit_id(&descendant, params.repo_path.clone(), params.cwd.clone())?;
+
+        let template = "commit_id ++ \"\\n\"";
+        let is_ancestor = !query_log_records(
+            &format!("{} & ::{}", ancestor_id, descendant_id),
+            template,
+            params.repo_path.clone(),
+            params.cwd.clone(),
+        )?
+        .is_empty();
+
+        // The number of commits in ancestor..descendant equals the distance on a linear history
+        let distance = if is_ancestor {
+            Some(
+                query_log_records(
+                    &format!("{}..{}", ancestor_id, descendant_id),
+                    template,
+                    params.repo_path,
+                    params.cwd,
+                )?
+                .len(),
+            )
+        } else {
+            None
+        };
+
+        Ok(serde_json::json!({
+            "ancestor": ancestor,
+            "descendant": descendant,
+            "isAncestor": is_ancestor,
+            "distance": distance,
+        }))
+    })();
+
+    json_response(result)
+}
+
+/// An entry of the operation log
+#[derive(Debug, Clone, PartialEq)]
+pub struct OperationEntry {
+    pub id: String,
+    pub time: String,
+    pub user: String,
+    pub description: String,
+}
+
+/// Filters applied to operation log entries before paging
+#[derive(Debug, Default)]
+pub struct OperationFilter {
+    pub since: Option<String>,
+    pub until: Option<String>,
+    pub description_contains: Option<String>,
+}
+
+impl OperationFilter {
+    fn is_empty(&self) -> bool {
+        self.since.is_none() && self.until.is_none() && self.description_contains.is_none()
+    }
+
+    /// Timestamps are `%Y-%m-%dT%H:%M:%SZ`, so bounds compare lexicographically
+    fn matches(&self, entry: &OperationEntry) -> bool {
+        self.since
+            .as_ref()
+            .is_none_or(|since| entry.time.as_str() >= since.as_str())
+            && self
+                .until
+                .as_ref()
+                .is_none_or(|until| entry.time.as_str() < until.as_str())
+            && self
+                .description_contains
+                .as_ref()
+                .is_none_or(|needle| entry.description.contains(needle.as_str()))
+    }
+}
+
+/// Apply `filter`, then skip `offset` entries and take `limit`, reporting whether more remain
+pub fn page_operations(
+    entries: Vec<OperationEntry>,
+    filter: &OperationFilter,
+    offset: usize,
+    limit: usize,
+) -> (Vec<OperationEntry>, bool) {
+    let mut matching = entries
+        .into_iter()
+        .filter(|entry| filter.matches(entry))
+        .skip(offset);
+    let page: Vec<OperationEntry> = matching.by_ref().take(limit).collect();
+    let has_more = matching.next().is_some();
+    (page, has_more)
+}
+
+/// Show the operation log with paging and filtering
+pub fn run_jj_op_log(params: OpLogParams) -> CallToolResponse {
+    let result = (|| {
+        let offset = params.offset.unwrap_or(0) as usize;
+        let limit = params.limit.unwrap_or(50) as usize;
+        let filter = OperationFilter {
+            since: params.since,
+            until: params.until,
+            description_contains: params.description_contains,
+        };
+
+        let started_at = utc_timestamp_template("time.start()");
+        let mut args = vec![
+            "op".to_string(),
+            "log".to_string(),
+            "--no-graph".to_string(),
+            "-T".to_string(),
+            log_record_template(&[
+                "id.short()",
+                &started_at,
+                "user",
+                "description.first_line()",
+            ]),
+        ];
+        // Without filters only the requested page (plus one to detect more) is needed
+        if filter.is_empty() {
+            args.push("-n".to_string());
+            args.push((offset + limit + 1).to_string());
+        }
+        add_repo_args(&mut args, params.repo_path.clone());
+
+        let output = run_jj_command_sync(args, params.cwd.clone())?;
+        let entries: Vec<OperationEntry> = output
+            .lines()
+            .filter_map(|line| {
+                let fields: Vec<&str> = line.split('\t').collect();
+                (fields.len() >= 4).then(|| OperationEntry {
+                    id: fields[0].to_string(),
+                    time: fields[1].to_string(),
+                    user: fields[2].to_string(),
+                    description: fields[3..].join("\t"),
+                })
+            })
+            .collect();
+
+        let (page, has_more) = page_operations(entries, &filter, offset, limit);
+        let patch = params.patch.unwrap_or(false);
+        let op_diff = patch || params.op_diff.unwrap_or(false);
+        let mut operations = Vec::new();
+        for entry in &page {
+            let mut operation = serde_json::json!({
+                "id": entry.id,
+                "time": entry.time,
+                "user": entry.user,
+                "description": entry.description,
+            });
+            if op_diff {
+                let mut args = vec![
+                    "op".to_string(),
+                    "show".to_string(),
+                    entry.id.clone(),
+                    "--no-graph".to_string(),
+                ];
+                if patch {
+                    args.push("--patch".to_string());
+                }
+                add_repo_args(&mut args, params.repo_path.clone());
+                operation["diff"] =
+                    serde_json::json!(run_jj_command_sync(args, params.cwd.clone())?);
+            }
+            operations.push(operation);
+        }
+
+        Ok(serde_json::json!({
+            "offset": offset,
+            "count": operations.len(),
+            "hasMore": has_more,
+            "operations": operations,
+        }))
+    })();
+
+    json_response(result)
+}
+
+/// Undo the last operation, or a specific earlier one when an operation ID is given
+pub fn run_jj_undo(params: UndoParams) -> CallToolResponse {
+    let result = (|| {
+        let mut args = vec!["undo".to_string()];
+        if let Some(operation) = &params.operation {
+            args.push(operation.clone());
+        }
+        add_repo_args(&mut args, params.repo_path.clone());
+
+        let output = run_jj_command_output(args, params.cwd.clone())?;
+        let current = operation_before(0, params.repo_path, params.cwd)?;
+
+        Ok(serde_json::json!({
+            "undone": params.operation.unwrap_or_else(|| "@".to_string()),
+            "currentOperation": current,
+            "output": output.stderr,
+        }))
+    })();
+
+    json_response(result)
+}
+
+/// ID of the current operation, read without snapshotting the working copy
+fn current_operation_id(repo_path: Option<String>, cwd: Option<String>) -> Result<String> {
+    let mut args = vec![
+        "op".to_string(),
+        "log".to_string(),
+        "--ignore-working-copy".to_string(),
+        "--no-graph".to_string(),
+        "-n".to_string(),
+        "1".to_string(),
+        "-T".to_string(),
+        "id.short()".to_string(),
+    ];
+    add_repo_args(&mut args, repo_path);
+    run_jj_command_sync(args, cwd)
+}
+
+/// Force a working-copy snapshot and report the resulting tree state
+pub fn run_jj_snapshot(params: SnapshotParams) -> CallToolResponse {
+    let result = (|| {
+        let before = current_operation_id(params.repo_path.clone(), params.cwd.clone())?;
+
+        let mut args = vec!["debug".to_string(), "snapshot".to_string()];
+        add_repo_args(&mut args, params.repo_path.clone());
+        run_jj_command_sync(args, params.cwd.clone())?;
+
+        let after = current_operation_id(params.repo_path.clone(), params.cwd.clone())?;
+
+        let records = query_log_records(
+            "@",
+            &log_record_template(&["change_id.short()", "commit_id.short()", "empty"]),
+            params.repo_path.clone(),
+            params.cwd.clone(),
+        )?;
+        let record = records
+            .first()
+            .ok_or_else(|| anyhow::anyhow!("Error: no working-copy commit"))?;
+
+        let mut args = vec![
+            "diff".to_string(),
+            "--stat".to_string(),
+            "-r".to_string(),
+            "@".to_string(),
+        ];
+        add_repo_args(&mut args, params.repo_path);
+        let stat = run_jj_command_sync(args, params.cwd)?;
+
+        Ok(serde_json::json!({
+            "snapshotted": before != after,
+            "operation": after,
+            "changeId": record[0],
+            "commitId": record.get(1),
+            "empty": record.get(2).map(|empty| empty == "true"),
+            "stat": stat,
+        }))
+    })();
+
+    json_response(result)
+}
+
+/// Snapshot the working copy and return its `diff --stat`
+pub fn working_copy_diff_stat(root: &std::path::Path) -> Result<String> {
+    let args = vec![
+        "diff".to_string(),
+        "--stat".to_string(),
+        "-r".to_string(),
+        "@".to_string(),
+    ];
+    run_jj_command_sync(args, Some(root.to_string_lossy().to_string()))
+}
+
+/// Whether a repo-relative path is inside the `.jj` or `.git` directory
+fn is_repository_metadata(path: &str) -> bool {
+    let top_level = std::path::Path::new(path)
+        .components()
+        .find(|component| matches!(component, std::path::Component::Normal(_)));
+    matches!(
+        top_level,
+        Some(std::path::Component::Normal(name)) if name == ".jj" || name == ".git"
+    )
+}
+
+/// Resolve `dir` and require it to be inside `canonical_root` and outside repository metadata
+fn check_inside_workspace(
+    canonical_root: &std::path::Path,
+    dir: &std::path::Path,
+    path: &str,
+) -> Result<()> {
+    let resolved = dir
+        .canonicalize()
+        .map_err(|e| anyhow::anyhow!("Error: cannot resolve {}: {}", dir.display(), e))?;
+    let relative = resolved.strip_prefix(canonical_root).map_err(|_| {
+        anyhow::anyhow!(
+            "Error: {} leads outside the workspace through a symlink",
+            path
+        )
+    })?;
+    if is_repository_metadata(&relative.to_string_lossy()) {
+        return Err(anyhow::anyhow!(
+            "Error: refusing to write inside repository metadata: {}",
+            path
+        ));
+    }
+    Ok(())
+}
+
+/// The file `path` names in the workspace at `root`, checked so changing or removing it stays inside
+///
+/// Like [`working_copy_write_path`], but nothing is created, so a patch can check
+/// all its paths before touching any.
+pub fn working_copy_existing_path(
+    root: &std::path::Path,
+    path: &str,
+) -> Result<std::path::PathBuf> {
+    let file_path = repo_file_path(root, path)?;
+    if is_repository_metadata(path) {
+        return Err(anyhow::anyhow!(
+            "Error: refusing to write inside repository metadata: {}",
+            path
+        ));
+    }
+    let canonical_root = root
+        .canonicalize()
+        .map_err(|e| anyhow::anyhow!("Error: cannot resolve {}: {}", root.display(), e))?;
+    let parent = file_path.parent().unwrap_or(root);
+    let existing = parent.ancestors().find(|dir| dir.exists()).unwrap_or(root);
+    check_inside_workspace(&canonical_root, existing, path)?;
+    if std::fs::symlink_metadata(&file_path).is_ok_and(|metadata| metadata.file_type().is_symlink())
+    {
+        return Err(anyhow::anyhow!(
+            "Error: refusing to write through a symlink: {}",
+            path
+        ));
+    }
+    Ok(file_path)
+}
+
+/// The file `path` names in the workspace at `root`, checked so writing it stays inside
+///
+/// Besides the checks of [`repo_file_path`], repository metadata is refused, the
+/// parent directory must resolve inside the workspace so a symlinked directory
+/// can't redirect the write, and the file itself must not be a symlink. Missing
+/// parent directories are created.
+pub fn working_copy_write_path(root: &std::path::Path, path: &str) -> Result<std::path::PathBuf> {
+    let file_path = repo_file_path(root, path)?;
+    if is_repository_metadata(path) {
+        return Err(anyhow::anyhow!(
+            "Error: refusing to write inside repository metadata: {}",
+            path
+        ));
+    }
+
+    let canonical_root = root
+        .canonicalize()
+        .map_err(|e| anyhow::anyhow!("Error: cannot resolve {}: {}", root.display(), e))?;
+    let check_inside = |dir: &std::path::Path| check_inside_workspace(&canonical_root, dir, path);
+
+    let parent = file_path.parent().unwrap_or(root);
+    // Check before creating anything, so a symlinked directory can't get new directories
+    let existing = parent.ancestors().find(|dir| dir.exists()).unwrap_or(root);
+    check_inside(existing)?;
+    std::fs::create_dir_all(parent)
+        .map_err(|e| anyhow::anyhow!("Error: failed to create {}: {}", path, e))?;
+    check_inside(parent)?;
+
+    if std::fs::symlink_metadata(&file_path).is_ok_and(|metadata| metadata.file_type().is_symlink())
+    {
+        return Err(anyhow::anyhow!(
+            "Error: refusing to write through a symlink: {}",
+            path
+        ));
+    }
+    Ok(file_path)
+}
+
+/// Write content to a file in the working copy and snapshot the result
+pub fn run_jj_write_file(params: WriteFileParams) -> CallToolResponse {
+    let result = (|| {
+        let path = params
+            .path
+            .ok_or_else(|| anyhow::anyhow!("Error: path is required"))?;
+        let content = params
+            .content
+            .ok_or_else(|| anyhow::anyhow!("Error: content is required"))?;
+
+        let root = resolve_workspace_root(params.repo_path, params.cwd)?;
+        let file_path = working_copy_write_path(&root, &path)?;
+        let existed = file_path.exists();
+        if params.append.unwrap_or(false) {
+            use std::io::Write;
+            std::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&file_path)
+                .and_then(|mut file| file.write_all(content.as_bytes()))
+        } else {
+            std::fs::write(&file_path, &content)
+        }
+        .map_err(|e| anyhow::anyhow!("Error: failed to write {}: {}", path, e))?;
+
+        Ok(serde_json::json!({
+            "path": path,
+            "created": !existed,
+            "bytesWritten": content.len(),
+            "stat": working_copy_diff_stat(&root)?,
+        }))
+    })();
+
+    json_response(result)
+}
+
+/// A line matching a grep pattern
+#[derive(Debug, Clone, PartialEq)]
+pub struct GrepMatch {
+    pub path: String,
+    pub line: usize,
+    pub text: String,
+}
+
+/// Build the matcher for the grep tool, escaping the pattern unless it is a regex
+pub fn build_grep_regex(pattern: &str, regex: bool, ignore_case: bool) -> Result<regex::Regex> {
+    let source = if regex {
+        pattern.to_string()
+    } else {
+        regex::escape(pattern)
+    };
+    regex::RegexBuilder::new(&source)
+        .case_insensitive(ignore_case)
+        .build()
+        .map_err(|e| anyhow::anyhow!("Error: invalid pattern: {}", e))
+}
+
+/// Compile a list of globs, returning `None` when the list is empty
+fn build_glob_set(globs: &[String]) -> Result<Option<globset::GlobSet>> {
+    if globs.is_empty() {
+        return Ok(None);
+    }
+    let mut builder = globset::GlobSetBuilder::new();
+    for glob in globs {
+        builder.add(
+            globset::Glob::new(glob)
+                .map_err(|e| anyhow::anyhow!("Error: invalid glob {}: {}", glob, e))?,
+        );
+    }
+    builder
+        .build()
+        .map(Some)
+        .map_err(|e| anyhow::anyhow!("Error: invalid globs: {}", e))
+}
+
+/// Whether a path passes the include and exclude globs
+pub fn path_selected(
+    path: &str,
+    include: Option<&globset::GlobSet>,
+    exclude: Option<&globset::GlobSet>,
+) -> bool {
+    let path = path.replace('\\', "/");
+    include.is_none_or(|set| set.is_match(&path)) && !exclude.is_some_and(|set| set.is_match(&path))
+}
+
+/// Collect the lines of a file matching `regex`, numbered from 1
+pub fn grep_content(path: &str, content: &str, regex: &regex::Regex) -> Vec<GrepMatch> {
+    content
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| regex.is_match(line))
+        .map(|(index, line)| GrepMatch {
+            path: path.to_string(),
+            line: index + 1,
+            text: line.to_string(),
+        })
+        .collect()
+}
+
+/// Search file contents at a revision
+pub fn run_jj_grep(params: GrepParams) -> CallToolResponse {
+    let result = (|| {
+        let pattern = params
+            .pattern
+            .ok_or_else(|| anyhow::anyhow!("Error: pattern is required"))?;
+        let regex = build_grep_regex(
+            &pattern,
+            params.regex.unwrap_or(false),
+            params.ignore_case.unwrap_or(false),
+        )?;
+        let include = build_glob_set(&params.include.unwrap_or_default())?;
+        let exclude = build_glob_set(&params.exclude.unwrap_or_default())?;
+        let max_matches = params.max_matches.unwrap_or(200);
+        let revision = params.revision.unwrap_or_else(|| "@".to_string());
+
+        let root = resolve_workspace_root(params.repo_path, params.cwd)?;
+        let root_cwd = Some(root.to_string_lossy().to_string());
+        let listing = run_jj_command_sync(
+            vec![
+                "file".to_string(),
+                "list".to_string(),
+                "-r".to_string(),
+                revision.clone(),
+            ],
+            root_cwd.clone(),
+        )?;
+
+        let mut matches = Vec::new();
+        let mut files_searched = 0;
+        let mut truncated = false;
+        for path in listing.lines().filter(|line| !line.is_empty()) {
+            if !path_selected(path, include.as_ref(), exclude.as_ref()) {
+                continue;
+            }
+            let content =
+                String::from_utf8_lossy(&read_file_at(&root, &revision, path)?).to_string();
+            files_searched += 1;
+            // Skip binary files
+            if content.contains('\0') {
+                continue;
+            }
+            matches.extend(grep_content(path, &content, &regex));
+            if matches.len() > max_matches {
+                matches.truncate(max_matches);
+                truncated = true;
+                break;
+            }
+        }
+
+        let matches: Vec<Value> = matches
+            .iter()
+            .map(|m| serde_json::json!({"path": m.path, "line": m.line, "text": m.text}))
+            .collect();
+
+        Ok(serde_json::json!({
+            "revision": revision,
+            "filesSearched": files_searched,
+            "truncated": truncated,
+            "matches": matches,
+        }))
+    })();
+
+    json_response(result)
+}
+
+/// A hunk of a unified diff
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct PatchHunk {
+    pub old_start: usize,
+    /// Lines prefixed with ' ', '-' or '+'
+    pub lines: Vec<(char, String)>,
+    pub old_missing_newline: bool,
+    pub new_missing_newline: bool,
+}
+
+/// The changes a unified diff makes to one file; `None` paths stand for `/dev/null`
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct FilePatch {
+    pub old_path: Option<String>,
+    pub new_path: Option<String>,
+    pub hunks: Vec<PatchHunk>,
+}
+
+fn patch_header_path(header: &str) -> Option<String> {
+    let path = header.split('\t').next().unwrap_or(header).trim_end();
+    if path == "/dev/null" {
+        return None;
+    }
+    let path = path
+        .strip_prefix("a/")
+        .or_else(|| path.strip_prefix("b/"))
+        .unwrap_or(path);
+    Some(path.to_string())
+}
+
+/// Parse a unified diff (plain or git-style) into per-file patches
+pub fn parse_unified_diff(patch: &str) -> Result<Vec<FilePatch>> {
+    let mut files: Vec<FilePatch> = Vec::new();
+    let mut lines = patch.lines().peekable();
+
+    while let Some(line) = lines.next() {
+        if let Some(old) = line.strip_prefix("--- ") {
+            let new = lines
+                .next()
+                .and_then(|next| next.strip_prefix("+++ "))
+                .ok_or_else(|| anyhow::anyhow!("Error: expected +++ line after {}", line))?;
+            files.push(FilePatch {
+                old_path: patch_header_path(old),
+                new_path: patch_header_path(new),
+                hunks: Vec::new(),
+            });
+        } else if let Some(header) = line.strip_prefix("@@ ") {
+            let file = files
+                .last_mut()
+                .ok_or_else(|| anyhow::anyhow!("Error: hunk before file header: {}", line))?;
+            let (old_start, old_count, new_count) = parse_hunk_header(header)
+                .ok_or_else(|| anyhow::anyhow!("Error: malformed hunk header: {}", line))?;
+
+            let mut hunk = PatchHunk {
+                old_start,
+                ..Default::default()
+            };
+            let (mut old_seen, mut new_seen) = (0, 0);
+            while old_seen < old_count || new_seen < new_count {
+                let body = lines
+                    .next()
+                    .ok_or_else(|| anyhow::anyhow!("Error: truncated hunk: {}", line))?;
+                // Some tools drop the space prefix of empty context lines
+                let kind = body.chars().next().unwrap_or(' ');
+                let text = body.get(1..).unwrap_or("").to_string();
+                match kind {
+                    ' ' => {
+                        old_seen += 1;
+                        new_seen += 1;
+                    }
+                    '-' => old_seen += 1,
+                    '+' => new_seen += 1,
+                    '\\' => continue,
+                    _ => return Err(anyhow::anyhow!("Error: unexpected hunk line: {}", body)),
+                }
+                hunk.lines.push((kind, text));
+            }
+            while lines.peek().is_some_and(|next| next.starts_with('\\')) {
+                lines.next();
+                match hunk.lines.last().map(|(kind, _)| *kind) {
+                    Some('-') => hunk.old_missing_newline = true,
+                    Some('+') => hunk.new_missing_newline = true,
+                    _ => {
+                        hunk.old_missing_newline = true;
+                        hunk.new_missing_newline = true;
+                    }
+                }
+            }
+            file.hunks.push(hunk);
+        }
+    }
+
+    if files.is_empty() {
+        return Err(anyhow::anyhow!("Error: patch contains no file changes"));
+    }
+    Ok(files)
+}
+
+/// Parse `-a,b +c,d @@`, returning the old start and the old and new line counts
+fn parse_hunk_header(header: &str) -> Option<(usize, usize, usize)> {
+    let mut parts = header.split_whitespace();
+    let range = |part: &str| -> Option<(usize, usize)> {
+        match part.split_once(',') {
+            Some((start, count)) => Some((start.parse().ok()?, count.parse().ok()?)),
+            None => Some((part.parse().ok()?, 1)),
+        }
+    };
+    let (old_start, old_count) = range(parts.next()?.strip_prefix('-')?)?;
+    let (_, new_count) = range(parts.next()?.strip_prefix('+')?)?;
+    Some((old_start, old_count, new_count))
+}
+
+/// Apply hunks to file content, searching nearby lines when the stated position has drifted
+pub fn apply_hunks(original: &str, hunks: &[PatchHunk]) -> Result<String> {
+    let mut lines: Vec<String> = original.lines().map(|line| line.to_string()).collect();
+    let mut offset: isize = 0;
+    let mut trailing_newline = original.is_empty() || original.ends_with('\n');
+
+    for hunk in hunks {
+        let old: Vec<&str> = hunk
+            .lines
+            .iter()
+            .filter(|(kind, _)| *kind != '+')
+            .map(|(_, text)| text.as_str())
+            .collect();
+        let new: Vec<String> = hunk
+            .lines
+            .iter()
+            .filter(|(kind, _)| *kind != '-')
+            .map(|(_, text)| text.clone())
+            .collect();
+
+        let expected = (hunk.old_start.saturating_sub(1) as isize + offset).max(0) as usize;
+        let fits = |at: usize| {
+            at + old.len() <= lines.len()
+                && lines[at..at + old.len()]
+                    .iter()
+                    .zip(&old)
+                    .all(|(line, want)| line == want)
+        };
+        let position = (0..=lines.len())
+            .flat_map(|distance| {
+                [
+                    expected.checked_add(distance),
+                    expected.checked_sub(distance).filter(|_| distance > 0),
+                ]
+            })
+            .flatten()
+            .find(|&at| fits(at))
+            .ok_or_else(|| {
+                anyhow::anyhow!(
+                    "Error: hunk at line {} does not apply",
+                    hunk.old_start.max(1)
+                )
+            })?;
+
+        offset += new.len() as isize - old.len() as isize + position as isize - expected as isize;
+        lines.splice(position..position + old.len(), new);
+
+        if hunk.new_missing_newline {
+            trailing_newline = false;
+        } else if hunk.old_missing_newline {
+            trailing_newline = true;
+        }
+    }
+
+    let mut content = lines.join("\n");
+    if trailing_newline && !lines.is_empty() {
+        content.push('\n');
+    }
+    Ok(content)
+}
+
+/// Apply a unified diff to the working copy without git, returning the touched paths
+///
+/// Every path goes through the same checks as write-file, so a patch can't reach
+/// repository metadata or leave the workspace through a symlink.
+pub fn apply_unified_diff(root: &std::path::Path, patch: &str) -> Result<Vec<String>> {
+    // Compute every result before writing so a failing hunk leaves the tree untouched
+    let mut writes: Vec<(String, Option<String>)> = Vec::new();
+    let mut touched = Vec::new();
+    for file in parse_unified_diff(patch)? {
+        if let Some(new) = &file.new_path {
+            working_copy_existing_path(root, new)?;
+        }
+        let original = match &file.old_path {
+            Some(path) => {
+                let full = working_copy_existing_path(root, path)?;
+                std::fs::read_to_string(&full)
+                    .map_err(|e| anyhow::anyhow!("Error: failed to read {}: {}", path, e))?
+            }
+            None => String::new(),
+        };
+        let updated = apply_hunks(&original, &file.hunks)?;
+
+        match (&file.old_path, &file.new_path) {
+            (Some(old), new) if new.as_ref() != Some(old) => {
+                writes.push((old.clone(), None));
+                touched.push(old.clone());
+            }
+            _ => {}
+        }
+        if let Some(new) = &file.new_path {
+            writes.push((new.clone(), Some(updated)));
+            touched.push(new.clone());
+        }
+    }
+
+    for (path, content) in writes {
+        match content {
+            Some(content) => std::fs::write(working_copy_write_path(root, &path)?, content)?,
+            None => std::fs::remove_file(working_copy_existing_path(root, &path)?)?,
+        }
+    }
+    Ok(touched)
+}
+
+/// Every path a patch names, including git's rename and copy headers
+pub fn patch_paths(patch: &str) -> Vec<String> {
+    let mut paths: Vec<String> = parse_unified_diff(patch)
+        .unwrap_or_default()
+        .into_iter()
+        .flat_map(|file| [file.old_path, file.new_path])
+        .flatten()
+        .collect();
+    for line in patch.lines() {
+        for prefix in ["rename from ", "rename to ", "copy from ", "copy to "] {
+            if let Some(path) = line.strip_prefix(prefix) {
+                paths.push(path.to_string());
+            }
+        }
+    }
+    paths
+}
+
+/// Apply a patch with `git apply`, feeding it on stdin
+///
+/// git doesn't protect `.jj`, so every path is checked like a native apply first.
+fn git_apply(root: &std::path::Path, patch: &str) -> Result<()> {
+    use std::io::Write;
+
+    for path in patch_paths(patch) {
+        working_copy_existing_path(root, &path)?;
+    }
+
+    let mut child = std::process::Command::new("git")
+        .args(["apply", "--whitespace=nowarn", "-"])
+        .current_dir(root)
+        .stdin(std::process::Stdio::piped())
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped())
+        .spawn()
+        .map_err(|e| anyhow::anyhow!("Error: failed to execute git: {}", e))?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(patch.as_bytes())?;
+    }
+    let output = child.wait_with_output()?;
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(anyhow::anyhow!(
+            "Error: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ))
+    }
+}
+
+/// Apply a unified diff to the working copy and snapshot the result
+pub fn run_jj_apply_patch(params: ApplyPatchParams) -> CallToolResponse {
+    let result = (|| {
+        let patch = params
+            .patch
+            .ok_or_else(|| anyhow::anyhow!("Error: patch is required"))?;
+        let root = resolve_workspace_root(params.repo_path, params.cwd)?;
+
+        let method = match params.method.as_deref().unwrap_or("auto") {
+            "auto" if detect_git_backing(&root).colocated => "git",
+            "auto" => "native",
+            method @ ("git" | "native") => method,
+            other => {
+                return Err(anyhow::anyhow!(
+                    "Error: unknown method {} (expected auto, git or native)",
+                    other
+                ));
+            }
+        };
+
+        let files = if method == "git" {
+            git_apply(&root, &patch)?;
+            parse_unified_diff(&patch)?
+                .into_iter()
+                .filter_map(|file| file.new_path.or(file.old_path))
+                .collect()
+        } else {
+            apply_unified_diff(&root, &patch)?
+        };
+
+        Ok(serde_json::json!({
+            "method": method,
+            "files": files,
+            "stat": working_copy_diff_stat(&root)?,
+        }))
+    })();
+
+    json_response(result)
+}
+
+/// A commit to be exported as a patch
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct PatchCommit {
+    pub commit_id: String,
+    pub author_name: String,
+    pub author_email: String,
+    /// RFC 2822 date
+    pub date: String,
+    pub description: String,
+    pub diff: String,
+}
+
+/// Render a commit as a git-format patch, optionally with mbox/email headers
+pub fn format_patch(commit: &PatchCommit, index: usize, total: usize, email: bool) -> String {
+    let description = commit.description.trim();
+    let (subject, body) = match description.split_once('\n') {
+        Some((subject, body)) => (subject.trim(), body.trim()),
+        None => (description, ""),
+    };
+
+    let mut patch = String::new();
+    if email {
+        let counter = if total > 1 {
+            format!("[PATCH {}/{}]", index, total)
+        } else {
+            "[PATCH]".to_string()
+        };
+        patch.push_str(&format!(
+            "From {} Mon Sep 17 00:00:00 2001\nFrom: {} <{}>\nDate: {}\nSubject: {} {}\n\n",
+            commit.commit_id,
+            commit.author_name,
+            commit.author_email,
+            commit.date,
+            counter,
+            subject
+        ));
+    } else {
+        patch.push_str(subject);
+        patch.push_str("\n\n");
+    }
+    if !body.is_empty() {
+        patch.push_str(body);
+        patch.push_str("\n\n");
+    }
+    patch.push_str("---\n");
+    patch.push_str(&commit.diff);
+    if !commit.diff.ends_with('\n') {
+        patch.push('\n');
+    }
+    patch
+}
+
+/// Export revisions as git-format patches, oldest first
+pub fn run_jj_export_patch(params: ExportPatchParams) -> CallToolResponse {
+    let result: Result<String> = (|| {
+        let revisions = params.revisions.unwrap_or_else(|| "@".to_string());
+        let email = params.email_headers.unwrap_or(true);
+        let records = query_log_records(
+            &revisions,
+            &log_record_template(&[
+                "commit_id",
+                "author.name()",
+                "author.email()",
+                "author.timestamp().format(\"%a, %d %b %Y %H:%M:%S %z\")",
+            ]),
+            params.repo_path.clone(),
+            params.cwd.clone(),
+        )?;
+
+        let mut commits = Vec::new();
+        for record in records.iter().rev().filter(|record| record.len() >= 4) {
+            let show = |args: Vec<&str>| -> Result<String> {
+                let mut args: Vec<String> = args.into_iter().map(|a| a.to_string()).collect();
+                add_repo_args(&mut args, params.repo_path.clone());
+                run_jj_command_sync(args, params.cwd.clone())
+            };
+            commits.push(PatchCommit {
+                commit_id: record[0].clone(),
+                author_name: record[1].clone(),
+                author_email: record[2].clone(),
+                date: record[3].clone(),
+                description: show(vec![
+                    "log",
+                    "--no-graph",
+                    "-r",
+                    &record[0],
+                    "-T",
+                    "description",
+                ])?,
+                diff: show(vec!["diff", "--git", "-r", &record[0]])?,
+            });
+        }
+
+        if commits.is_empty() {
+            return Err(anyhow::anyhow!("Error: no commits in {}", revisions));
+        }
+        let total = commits.len();
+        Ok(commits
+            .iter()
+            .enumerate()
+            .map(|(i, commit)| format_patch(commit, i + 1, total, email))
+            .collect::<Vec<_>>()
+            .join("\n"))
+    })();
+
+    match result {
+        Ok(output) => CallToolResponse {
+            content: vec![ToolResponseContent::Text { text: output }],
+            is_error: Some(false),
+            meta: None,
+        },
+        Err(e) => CallToolResponse {
+            content: vec![ToolResponseContent::Text {
+                text: e.to_string(),
+            }],
+            is_error: Some(true),
+            meta: None,
+        },
+    }
+}
+
+/// Pick the archive format from an explicit choice or the destination's extension
+pub fn archive_format(format: Option<&str>, destination: &str) -> Result<&'static str> {
+    match format {
+        Some("directory") => Ok("directory"),
+        Some("tar.gz") => Ok("tar.gz"),
+        Some(other) => Err(anyhow::anyhow!(
+            "Error: unknown format {} (expected directory or tar.gz)",
+            other
+        )),
+        None if destination.ends_with(".tar.gz") || destination.ends_with(".tgz") => Ok("tar.gz"),
+        None => Ok("directory"),
+    }
+}
+
+/// Paths listed as symlinks (`L`) in the after column of `jj diff --types` output
+pub fn parse_symlink_types(output: &str) -> Vec<String> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let (types, path) = line.split_at_checked(3)?;
+            (types.as_bytes().get(1) == Some(&b'L')).then(|| path.to_string())
+        })
+        .collect()
+}
+
+/// The added lines of a single-file git diff, such as a new symlink's target
+pub fn parse_added_content(git_diff: &str) -> String {
+    git_diff
+        .lines()
+        .skip_while(|line| !line.starts_with("@@"))
+        .filter_map(|line| line.strip_prefix('+'))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[cfg(unix)]
+fn create_symlink(target: &str, link: &std::path::Path) -> std::io::Result<()> {
+    std::os::unix::fs::symlink(target, link)
+}
+
+#[cfg(not(unix))]
+fn create_symlink(target: &str, link: &std::path::Path) -> std::io::Result<()> {
+    std::fs::write(link, target)
+}
+
+/// Write every file of a revision's tree below `target`, returning the number of files
+///
+/// Symlinks are recreated as symlinks, after every regular file is written so no
+/// write can follow one.
+pub fn materialize_revision(
+    root: &std::path::Path,
+    revision: &str,
+    target: &std::path::Path,
+) -> Result<usize> {
+    let root_cwd = Some(root.to_string_lossy().to_string());
+    let listing = run_jj_command_sync(
+        vec![
+            "file".to_string(),
+            "list".to_string(),
+            "-r".to_string(),
+            revision.to_string(),
+        ],
+        root_cwd.clone(),
+    )?;
+    let types = run_jj_command_sync(
+        vec![
+            "diff".to_string(),
+            "--types".to_string(),
+            "--from".to_string(),
+            "root()".to_string(),
+            "--to".to_string(),
+            revision.to_string(),
+        ],
+        root_cwd.clone(),
+    )?;
+    let symlinks = parse_symlink_types(&types);
+
+    let mut count = 0;
+    for path in listing.lines().filter(|line| !line.is_empty()) {
+        if symlinks.iter().any(|symlink| symlink == path) {
+            continue;
+        }
+        let content = read_file_at(root, revision, path)?;
+        let file_path = repo_file_path(target, path)?;
+        if let Some(parent) = file_path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(&file_path, content)
+            .map_err(|e| anyhow::anyhow!("Error: failed to write {}: {}", path, e))?;
+        count += 1;
+    }
+
+    for path in &symlinks {
+        let diff = run_jj_command_sync(
+            vec![
+                "diff".to_string(),
+                "--git".to_string(),
+                "--from".to_string(),
+                "root()".to_string(),
+                "--to".to_string(),
+                revision.to_string(),
+                format!("root-file:{}", quote_string_literal(path)),
+            ],
+            root_cwd.clone(),
+        )?;
+        let link = repo_file_path(target, path)?;
+        if let Some(parent) = link.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        create_symlink(&parse_added_content(&diff), &link)
+            .map_err(|e| anyhow::anyhow!("Error: failed to create symlink {}: {}", path, e))?;
+        count += 1;
+    }
+    Ok(count)
+}
+
+/// Where an archive for the workspace at `root` should go, resolved and checked to be outside it
+///
+/// The nearest existing ancestor of `target` is canonicalized, so `..` and
+/// symlinks can't lead back into the workspace; the missing directories below it
+/// are appended and may not contain `..` or a dangling symlink.
+fn archive_destination(
+    root: &std::path::Path,
+    target: &std::path::Path,
+) -> Result<std::path::PathBuf> {
+    let absolute = std::path::absolute(target)?;
+    let existing = absolute
+        .ancestors()
+        .find(|dir| dir.exists())
+        .ok_or_else(|| anyhow::anyhow!("Error: cannot resolve {}", target.display()))?;
+    let rest = absolute.strip_prefix(existing)?;
+    let dangling = rest
+        .components()
+        .next()
+        .is_some_and(|first| std::fs::symlink_metadata(existing.join(first)).is_ok());
+    if dangling
+        || rest
+            .components()
+            .any(|component| !matches!(component, std::path::Component::Normal(_)))
+    {
+        return Err(anyhow::anyhow!(
+            "Error: cannot resolve {}",
+            target.display()
+        ));
+    }
+
+    let resolved = existing
+        .canonicalize()
+        .map_err(|e| anyhow::anyhow!("Error: cannot resolve {}: {}", existing.display(), e))?
+        .join(rest);
+    let canonical_root = root
+        .canonicalize()
+        .map_err(|e| anyhow::anyhow!("Error: cannot resolve {}: {}", root.display(), e))?;
+    if resolved.starts_with(&canonical_root) {
+        return Err(anyhow::anyhow!(
+            "Error: destination must be outside the workspace: {}",
+            target.display()
+        ));
+    }
+    Ok(resolved)
+}
+
+/// Export the tree of a revision to a directory or tarball without touching the working copy
+pub fn run_jj_archive(params: ArchiveParams) -> CallToolResponse {
+    let result = (|| {
+        let revision = params.revision.unwrap_or_else(|| "@".to_string());
+        let destination = params
+            .destination
+            .ok_or_else(|| anyhow::anyhow!("Error: destination is required"))?;
+        let format = archive_format(params.format.as_deref(), &destination)?;
+
+        let mut target = std::path::PathBuf::from(&destination);
+        if target.is_relative()
+            && let Some(cwd) = &params.cwd
+        {
+            target = std::path::Path::new(cwd).join(target);
+        }
+        let root = resolve_workspace_root(params.repo_path, params.cwd)?;
+        let commit_id =
+            resolve_commit_id(&revision, None, Some(root.to_string_lossy().to_string()))?;
+
+        if target.exists() && (format == "tar.gz" || std::fs::read_dir(&target)?.next().is_some()) {
+            return Err(anyhow::anyhow!(
+                "Error: destination already exists: {}",
+                destination
+            ));
+        }
+        let absolute_target = archive_destination(&root, &target)?;
+
+        let files = if format == "directory" {
+            materialize_revision(&root, &commit_id, &absolute_target)?
+        } else {
+            let staging = private_temp_dir("jj-mcp-archive-")?;
+            let files = materialize_revision(&root, &commit_id, staging.path())?;
+            let output = std::process::Command::new("tar")
+                .arg("-czf")
+                .arg(&absolute_target)
+                .arg("-C")
+                .arg(staging.path())
+                .arg(".")
+                .output()
+                .map_err(|e| anyhow::anyhow!("Error: failed to execute tar: {}", e))?;
+            if !output.status.success() {
+                return Err(anyhow::anyhow!(
+                    "Error: {}",
+                    String::from_utf8_lossy(&output.stderr).trim()
+                ));
+            }
+            files
+        };
+
+        Ok(serde_json::json!({
+            "revision": revision,
+            "commitId": commit_id,
+            "format": format,
+            "destination": absolute_target,
+            "files": files,
+        }))
+    })();
+
+    json_response(result)
+}
+
+/// Environment variable listing registered repositories, separated like `PATH`
+pub const REPOS_ENV_VAR: &str = "JJ_MCP_REPOS";
+
+/// Repositories to query: the explicit list if given, otherwise the registered ones
+pub fn registered_repos(explicit: Option<Vec<String>>, env_value: Option<&str>) -> Vec<String> {
+    match explicit {
+        Some(repos) if !repos.is_empty() => repos,
+        _ => env_value
+            .map(|value| {
+                std::env::split_paths(value)
+                    .filter(|path| !path.as_os_str().is_empty())
+                    .map(|path| path.to_string_lossy().to_string())
+                    .collect()
+            })
+            .unwrap_or_default(),
+    }
+}
+
+/// Run a read-only query across several repositories concurrently
+pub fn run_jj_multi_repo(params: MultiRepoParams) -> CallToolResponse {
+    let result = (|| {
+        let env_value = std::env::var(REPOS_ENV_VAR).ok();
+        let repos = registered_repos(params.repos, env_value.as_deref());
+        if repos.is_empty() {
+            return Err(anyhow::anyhow!(
+                "Error: no repos given and {} is not set",
+                REPOS_ENV_VAR
+            ));
+        }
+
+        let operation = params.operation.unwrap_or_else(|| "status".to_string());
+        let command: Vec<String> = match operation.as_str() {
+            "status" => vec!["status".to_string()],
+            "log" => vec![
+                "log".to_string(),
+                "-n".to_string(),
+                params.limit.unwrap_or(5).to_string(),
+            ],
+            "diff-stat" => vec!["diff".to_string(), "--stat".to_string()],
+            other => {
+                return Err(anyhow::anyhow!(
+                    "Error: unknown operation {} (expected status, log or diff-stat)",
+                    other
+                ));
+            }
+        };
+
+        let results: Vec<Value> = std::thread::scope(|scope| {
+            let handles: Vec<_> = repos
+                .iter()
+                .map(|repo| {
+                    let mut args = command.clone();
+                    let options = current_call_options();
+                    scope.spawn(move || {
+                        add_repo_args(&mut args, Some(repo.clone()));
+                        match with_call_options(options, || run_jj_command_sync(args, None)) {
+                            Ok(output) => serde_json::json!({
+                                "repo": repo,
+                                "ok": true,
+                                "output": output,
+                            }),
+                            Err(e) => serde_json::json!({
+                                "repo": repo,
+                                "ok": false,
+                                "error": e.to_string(),
+                            }),
+                        }
+                    })
+                })
+                .collect();
+            handles
+                .into_iter()
+                .map(|handle| handle.join().unwrap_or(Value::Null))
+                .collect()
+        });
+
+        Ok(serde_json::json!({
+            "operation": operation,
+            "results": results,
+        }))
+    })();
+
+    json_response(result)
+}
+
+/// Maximum number of cached read results kept across all repositories
+const READ_CACHE_CAPACITY: usize = 64;
+
+/// Results of read-only commands, valid only as long as the working copy state is unchanged
+#[derive(Debug, Default)]
+pub struct ReadCache {
+    entries: std::collections::HashMap<(std::path::PathBuf, Vec<String>), (String, String)>,
+    order: std::collections::VecDeque<(std::path::PathBuf, Vec<String>)>,
+}
+
+impl ReadCache {
+    /// Look up a result recorded at `operation`, a [`working_copy_state`]; entries from other states are dropped
+    pub fn get(
+        &mut self,
+        root: &std::path::Path,
+        args: &[String],
+        operation: &str,
+    ) -> Option<String> {
+        let key = (root.to_path_buf(), args.to_vec());
+        match self.entries.get(&key) {
+            Some((cached_at, output)) if cached_at == operation => Some(output.clone()),
+            Some(_) => {
+                self.entries.remove(&key);
+                self.order.retain(|k| k != &key);
+                None
+            }
+            None => None,
+        }
+    }
+
+    /// Record a result, evicting the oldest entry when full
+    pub fn insert(
+        &mut self,
+        root: &std::path::Path,
+        args: &[String],
+        operation: &str,
+        output: String,
+    ) {
+        let key = (root.to_path_buf(), args.to_vec());
+        if self
+            .entries
+            .insert(key.clone(), (operation.to_string(), output))
+            .is_none()
+        {
+            self.order.push_back(key);
+        }
+        while self.order.len() > READ_CACHE_CAPACITY {
+            if let Some(oldest) = self.order.pop_front() {
+                self.entries.remove(&oldest);
+            }
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.order.clear();
+    }
+}
+
+/// Shared cache for read tools
+static READ_CACHE: std::sync::LazyLock<std::sync::Mutex<ReadCache>> =
+    std::sync::LazyLock::new(Default::default);
+
+/// Drop every cached read result
+pub fn clear_read_cache() {
+    READ_CACHE.lock().unwrap().clear();
+}
+
+/// Current operation heads of a repo, read from its op store without spawning jj
+pub fn operation_heads(root: &std::path::Path) -> Result<String> {
+    let heads_dir = repo_dir(root).join("op_heads").join("heads");
+    let mut heads: Vec<String> = std::fs::read_dir(&heads_dir)
+        .map_err(|e| anyhow::anyhow!("Error: failed to read operation heads: {}", e))?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.file_name().to_string_lossy().to_string())
+        .collect();
+    heads.sort();
+    Ok(heads.join(","))
+}
+
+/// What a cached read depends on: the operation heads and when the working copy's
+/// tree state was last written
+///
+/// Read after a snapshot, this changes whenever the snapshot recorded something new.
+pub fn working_copy_state(root: &std::path::Path) -> Result<String> {
+    let tree_state = std::fs::metadata(root.join(".jj").join("working_copy").join("tree_state"))
+        .and_then(|metadata| metadata.modified())
+        .ok()
+        .and_then(|modified| {
+            modified
+                .duration_since(std::time::SystemTime::UNIX_EPOCH)
+                .ok()
+        })
+        .map(|modified| modified.as_nanos())
+        .unwrap_or_default();
+    Ok(format!("{};{}", operation_heads(root)?, tree_state))
+}
+
+/// Run a read-only jj command, reusing the previous result while the working copy state is unchanged
+///
+/// The working copy is snapshotted first so edits on disk show up as a new operation;
+/// only the command itself is skipped on a hit. On a miss it runs with
+/// `--ignore-working-copy` so its output matches the state it is cached under.
+pub fn run_jj_command_cached(
+    args: Vec<String>,
+    repo_path: Option<String>,
+    cwd: Option<String>,
+) -> Result<String> {
+    cached_jj_command(args, repo_path, cwd, false)
+}
+
+/// [`run_jj_command_cached`] for commands with potentially huge output, run with
+/// [`run_jj_command_streamed`]
+pub fn run_jj_command_cached_streamed(
+    args: Vec<String>,
+    repo_path: Option<String>,
+    cwd: Option<String>,
+) -> Result<String> {
+    cached_jj_command(args, repo_path, cwd, true)
+}
+
+fn cached_jj_command(
+    args: Vec<String>,
+    repo_path: Option<String>,
+    cwd: Option<String>,
+    streamed: bool,
+) -> Result<String> {
+    let root = resolve_workspace_root(repo_path.clone(), cwd.clone())?;
+    let mut args = args;
+    args.push("--ignore-working-copy".to_string());
+    add_repo_args(&mut args, repo_path.clone());
+    // Relative paths in the arguments depend on the directory the command runs in
+    let mut key = args.clone();
+    key.push(cwd.clone().unwrap_or_default());
+    // Streamed output may be truncated, so it must not be served to other callers
+    if streamed {
+        key.push("--streamed".to_string());
+    }
+
+    let mut snapshot = vec!["debug".to_string(), "snapshot".to_string()];
+    add_repo_args(&mut snapshot, repo_path);
+    run_jj_command_sync(snapshot, cwd.clone())?;
+
+    // A state that can't be read just means the result isn't cached
+    let state = working_copy_state(&root).ok();
+    if let Some(state) = &state
+        && let Some(output) = READ_CACHE.lock().unwrap().get(&root, &key, state)
+    {
+        return Ok(output);
+    }
+
+    let output = if streamed {
+        run_jj_command_streamed(args, cwd)?
+    } else {
+        run_jj_command_sync(args, cwd)?
+    };
+    if let Some(state) = state {
+        READ_CACHE
+            .lock()
+            .unwrap()
+            .insert(&root, &key, &state, output.clone());
+    }
+    Ok(output)
+}
+
+/// A jj release version
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+pub struct JjVersion {
+    pub major: u32,
+    pub minor: u32,
+    pub patch: u32,
+}
+
+impl JjVersion {
+    pub const fn new(major: u32, minor: u32, patch: u32) -> Self {
+        JjVersion {
+            major,
+            minor,
+            patch,
+        }
+    }
+}
+
+impl std::fmt::Display for JjVersion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+impl std::str::FromStr for JjVersion {
+    type Err = anyhow::Error;
+
+    /// Parse `0.29.0`, `jj 0.29.0` or `jj 0.29.0-<commit>`; a missing patch is 0
+    fn from_str(s: &str) -> Result<Self> {
+        let text = s.trim();
+        let text = text.strip_prefix("jj ").unwrap_or(text);
+        let mut numbers = text
+            .split(['-', '+', ' '])
+            .next()
+            .unwrap_or_default()
+            .split('.')
+            .map(|part| part.parse::<u32>());
+        match (numbers.next(), numbers.next(), numbers.next()) {
+            (Some(Ok(major)), Some(Ok(minor)), None) => Ok(JjVersion::new(major, minor, 0)),
+            (Some(Ok(major)), Some(Ok(minor)), Some(Ok(patch))) => {
+                Ok(JjVersion::new(major, minor, patch))
+            }
+            _ => Err(anyhow::anyhow!("Error: not a jj version: {}", s)),
+        }
+    }
+}
+
+/// Oldest jj the server supports, unless configured
+pub const DEFAULT_MIN_JJ_VERSION: JjVersion = JjVersion::new(0, 20, 0);
+
+/// Tools needing a newer jj than the server as a whole, with the feature they rely on
+pub const TOOL_JJ_REQUIREMENTS: &[(&str, JjVersion, &str)] = &[
+    (
+        "bookmark-list",
+        JjVersion::new(0, 22, 0),
+        "jj bookmark list",
+    ),
+    (
+        "git-push",
+        JjVersion::new(0, 22, 0),
+        "jj git push --bookmark",
+    ),
+    (
+        "abandon",
+        JjVersion::new(0, 22, 0),
+        "jj abandon --retain-bookmarks",
+    ),
+    ("complete", JjVersion::new(0, 22, 0), "jj bookmark list"),
+    ("annotate", JjVersion::new(0, 24, 0), "jj file annotate"),
+    ("sign", JjVersion::new(0, 26, 0), "jj sign"),
+    ("revert-range", JjVersion::new(0, 28, 0), "jj revert -d"),
+];
+
+/// The installed jj version, read from `jj --version`
+pub fn installed_jj_version() -> Result<JjVersion> {
+    run_jj_command_sync(vec!["--version".to_string()], None)?.parse()
+}
+
+/// Why a tool can't be offered with the installed jj, if it can't
+pub fn unsupported_tool_reason(
+    name: &str,
+    installed: JjVersion,
+    minimum: JjVersion,
+) -> Option<String> {
+    if installed < minimum {
+        return Some(format!(
+            "{} needs jj {} or newer (the server's minimum), but jj {} is installed",
+            name, minimum, installed
+        ));
+    }
+    TOOL_JJ_REQUIREMENTS
+        .iter()
+        .find(|(tool, required, _)| *tool == name && installed < *required)
+        .map(|(_, required, feature)| {
+            format!(
+                "{} needs jj {} or newer for {}, but jj {} is installed",
+                name, required, feature, installed
+            )
+        })
+}
+
+/// Check whether the watchman service answers, returning its version
+pub fn watchman_version() -> Result<String> {
+    let output = std::process::Command::new("watchman")
+        .arg("version")
+        .output()
+        .map_err(|e| anyhow::anyhow!("Error: failed to execute watchman: {}", e))?;
+    if !output.status.success() {
+        return Err(anyhow::anyhow!(
+            "Error: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    let response: Value = serde_json::from_slice(&output.stdout)?;
+    response
+        .get("version")
+        .and_then(Value::as_str)
+        .map(|version| version.to_string())
+        .ok_or_else(|| anyhow::anyhow!("Error: unexpected watchman response: {}", response))
+}
+
+/// Report the jj version, filesystem monitor configuration and watchman reachability
+pub fn run_jj_doctor(params: DoctorParams) -> CallToolResponse {
+    let jj_version = run_jj_command_sync(vec!["--version".to_string()], None);
+
+    let mut args = vec![
+        "config".to_string(),
+        "get".to_string(),
+        "core.fsmonitor".to_string(),
+    ];
+    add_repo_args(&mut args, params.repo_path.clone());
+    let configured = run_jj_command_sync(args, params.cwd.clone()).ok();
+    let requested = fsmonitor_setting(
+        current_call_options().fsmonitor,
+        std::env::var(FSMONITOR_ENV_VAR).ok().as_deref(),
+    );
+    let watchman = watchman_version();
+    let git = resolve_workspace_root(params.repo_path.clone(), params.cwd.clone())
+        .ok()
+        .map(|root| colocation_report(&root));
+    let minimum = server_config().min_jj_version();
+    let installed = jj_version
+        .as_ref()
+        .ok()
+        .and_then(|version| version.parse::<JjVersion>().ok());
+    let unsupported: Vec<String> = installed
+        .map(|installed| {
+            all_tools()
+                .iter()
+                .filter_map(|tool| unsupported_tool_reason(&tool.name, installed, minimum))
+                .collect()
+        })
+        .unwrap_or_default();
+
+    let effective = match requested {
+        Some(true) => "watchman".to_string(),
+        Some(false) => "none".to_string(),
+        None => configured.clone().unwrap_or_else(|| "none".to_string()),
+    };
+    let mut warnings = Vec::new();
+    if installed.is_some_and(|installed| installed < minimum) {
+        warnings.push(format!(
+            "jj is older than the minimum supported {}",
+            minimum
+        ));
+    }
+    if let Some(git) = &git
+        && git["workspaceGit"] != "missing"
+        && git["colocated"] == false
+    {
+        warnings.push(
+            "the workspace has a .git that jj doesn't use; git and jj won't see each other's changes"
+                .to_string(),
+        );
+    }
+    if effective == "watchman" && watchman.is_err() {
+        warnings.push("fsmonitor is set to watchman but watchman is not reachable".to_string());
+    }
+
+    let report = serde_json::json!({
+        "jj": {
+            "ok": jj_version.is_ok(),
+            "version": jj_version.as_ref().ok(),
+            "error": jj_version.as_ref().err().map(|e| e.to_string()),
+            "minimumVersion": minimum.to_string(),
+            "supported": installed.map(|installed| installed >= minimum),
+            "unsupportedTools": unsupported,
+        },
+        "fsmonitor": {
+            "configured": configured,
+            "requested": requested,
+            "effective": effective,
+        },
+        "watchman": {
+            "reachable": watchman.is_ok(),
+            "version": watchman.as_ref().ok(),
+            "error": watchman.as_ref().err().map(|e| e.to_string()),
+        },
+        "git": git,
+        "processes": jj_process_stats(),
+        "warnings": warnings,
+    });
+
+    json_response(Ok(report))
+}
+
+/// Read-only git subcommands the git-passthrough tool may run
+pub const GIT_PASSTHROUGH_ALLOWLIST: &[&str] = &[
+    "ls-remote",
+    "rev-parse",
+    "config",
+    "cat-file",
+    "ls-tree",
+    "show-ref",
+    "for-each-ref",
+    "describe",
+    "merge-base",
+    "rev-list",
+    "count-objects",
+    "check-ignore",
+];
+
+/// Check git arguments against the allowlist, returning the subcommand
+///
+/// `config` is limited to reading keys, and options that could run arbitrary
+/// programs or point git elsewhere are refused.
+pub fn check_git_passthrough_args(args: &[String]) -> Result<&str> {
+    let subcommand = args
+        .first()
+        .ok_or_else(|| anyhow::anyhow!("Error: args must start with a git subcommand"))?;
+    if !GIT_PASSTHROUGH_ALLOWLIST.contains(&subcommand.as_str()) {
+        return Err(anyhow::anyhow!(
+            "Error: git {} is not allowed (allowed: {})",
+            subcommand,
+            GIT_PASSTHROUGH_ALLOWLIST.join(", ")
+        ));
+    }
+
+    let options = &args[1..];
+    if subcommand == "config"
+        && !options.iter().any(|arg| {
+            matches!(
+                arg.as_str(),
+                "--get" | "--get-all" | "--get-regexp" | "--list" | "-l"
+            )
+        })
+    {
+        return Err(anyhow::anyhow!(
+            "Error: git config is only allowed with --get, --get-all, --get-regexp or --list"
+        ));
+    }
+    let forbidden = [
+        "--upload-pack",
+        "--exec",
+        "--receive-pack",
+        "--git-dir",
+        "--work-tree",
+    ];
+    if let Some(arg) = options.iter().find(|arg| {
+        forbidden
+            .iter()
+            .any(|option| arg.as_str() == *option || arg.starts_with(&format!("{}=", option)))
+            || (subcommand == "config"
+                && matches!(
+                    arg.as_str(),
+                    "--add" | "--unset" | "--unset-all" | "--replace-all" | "--edit" | "-e"
+                ))
+    }) {
+        return Err(anyhow::anyhow!("Error: git option {} is not allowed", arg));
+    }
+
+    Ok(subcommand)
+}
+
+/// Run an allowlisted git subcommand in a colocated repository
+pub fn run_git_passthrough(params: GitPassthroughParams) -> CallToolResponse {
+    let result = (|| {
+        let args = params.args.unwrap_or_default();
+        check_git_passthrough_args(&args)?;
+
+        let root = resolve_workspace_root(params.repo_path, params.cwd)?;
+        let backing = detect_git_backing(&root);
+        if !backing.colocated {
+            return Err(anyhow::anyhow!(
+                "Error: git-passthrough requires a colocated repository"
+            ));
+        }
+
+        let output = std::process::Command::new("git")
+            .args(&args)
+            .current_dir(&root)
+            .output()
+            .map_err(|e| anyhow::anyhow!("Error: failed to execute git: {}", e))?;
+        if !output.status.success() {
+            return Err(anyhow::anyhow!(
+                "Error: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+
+        Ok(serde_json::json!({
+            "args": args,
+            "stdout": String::from_utf8_lossy(&output.stdout).trim(),
+        }))
+    })();
+
+    json_response(result)
+}
+
+/// Categories tools are grouped into for enabling or disabling them together
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum ToolGroup {
+    /// Inspect the repository without changing it
+    Read,
+    /// Create, rewrite or abandon commits and edit the working copy
+    Write,
+    /// Talk to git remotes
+    Remote,
+    /// Operation log, undo, diagnostics and raw git access
+    Admin,
+}
+
+impl std::str::FromStr for ToolGroup {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "read" => Ok(ToolGroup::Read),
+            "write" => Ok(ToolGroup::Write),
+            "remote" => Ok(ToolGroup::Remote),
+            "admin" => Ok(ToolGroup::Admin),
+            other => Err(anyhow::anyhow!(
+                "Error: unknown tool group {} (expected read, write, remote or admin)",
+                other
+            )),
+        }
+    }
+}
+
+/// The group a tool belongs to
+pub fn tool_group(name: &str) -> ToolGroup {
+    match name {
+        "git-clone" | "git-fetch" | "git-push" | "remote-list" | "deepen" => ToolGroup::Remote,
+        "doctor" | "op-log" | "undo" | "snapshot" | "reconcile" | "git-passthrough"
+        | "sandbox-promote" | "sandbox-discard" => ToolGroup::Admin,
+        _ if default_tool_annotations(name).read_only_hint => ToolGroup::Read,
+        _ => ToolGroup::Write,
+    }
+}
+
+/// Server-wide settings, read once at startup from command-line flags and the environment
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct ServerConfig {
+    /// When non-empty, only tools in these groups are exposed
+    pub enabled_groups: Vec<ToolGroup>,
+    pub disabled_groups: Vec<ToolGroup>,
+    /// Tools exposed regardless of their group
+    pub enabled_tools: Vec<String>,
+    /// Tools hidden regardless of their group
+    pub disabled_tools: Vec<String>,
+    /// Prepended to every exposed tool name, e.g. `jj_` for `jj_status`
+    pub tool_prefix: Option<String>,
+    /// Directories tools may operate in, used until the client reports its roots
+    pub roots: Vec<std::path::PathBuf>,
+    /// Tools that must be confirmed by the user or with `"confirm": true`
+    pub confirm_tools: Vec<String>,
+    /// Larger text outputs are returned as resource links; `Some(0)` disables the limit
+    pub max_output_bytes: Option<usize>,
+    /// Most jj processes run at once; `Some(0)` removes the limit
+    pub max_concurrency: Option<usize>,
+    /// Most diff or log output kept in memory; `Some(0)` removes the limit
+    pub max_stream_bytes: Option<usize>,
+    /// Oldest jj tools are offered for, instead of [`DEFAULT_MIN_JJ_VERSION`]
+    pub min_jj_version: Option<JjVersion>,
+    /// Default `signing.behavior` for commit and describe (`drop`, `keep`, `own` or `force`)
+    pub signing_behavior: Option<String>,
+    /// Default `signing.backend`
+    pub signing_backend: Option<String>,
+    /// Default `signing.key`
+    pub signing_key: Option<String>,
+    /// Times a command is retried while the repository is locked by another process
+    pub lock_retries: Option<u32>,
+    /// How long a jj process may run before it is stopped; `None` for no limit
+    pub command_timeout: Option<std::time::Duration>,
+    /// Only expose tools that never modify the repository
+    pub read_only: bool,
+    /// The jj binary to run instead of `jj` from `PATH`
+    pub jj_bin: Option<String>,
+    /// Repository tools run in when a call gives neither `repoPath` nor `cwd`
+    pub repo: Option<std::path::PathBuf>,
+    /// Most verbose diagnostics logged, one of [`LOG_LEVELS`]
+    pub log_level: Option<String>,
+    /// `text` (default) or `json` log lines
+    pub log_format: Option<String>,
+    /// File logs are appended to instead of stderr
+    pub log_file: Option<std::path::PathBuf>,
+}
+
+/// Environment variables holding comma-separated defaults for the matching flags
+pub const ENABLE_GROUPS_ENV_VAR: &str = "JJ_MCP_ENABLE_GROUPS";
+pub const DISABLE_GROUPS_ENV_VAR: &str = "JJ_MCP_DISABLE_GROUPS";
+pub const ENABLE_TOOLS_ENV_VAR: &str = "JJ_MCP_ENABLE_TOOLS";
+pub const DISABLE_TOOLS_ENV_VAR: &str = "JJ_MCP_DISABLE_TOOLS";
+pub const TOOL_PREFIX_ENV_VAR: &str = "JJ_MCP_TOOL_PREFIX";
+/// Directories tools may operate in, separated like `PATH`
+pub const ROOTS_ENV_VAR: &str = "JJ_MCP_ROOTS";
+pub const REQUIRE_CONFIRMATION_ENV_VAR: &str = "JJ_MCP_REQUIRE_CONFIRMATION";
+pub const MAX_OUTPUT_BYTES_ENV_VAR: &str = "JJ_MCP_MAX_OUTPUT_BYTES";
+pub const MAX_CONCURRENCY_ENV_VAR: &str = "JJ_MCP_MAX_CONCURRENCY";
+pub const MAX_STREAM_BYTES_ENV_VAR: &str = "JJ_MCP_MAX_STREAM_BYTES";
+pub const MIN_JJ_VERSION_ENV_VAR: &str = "JJ_MCP_MIN_JJ_VERSION";
+pub const SIGNING_BEHAVIOR_ENV_VAR: &str = "JJ_MCP_SIGNING_BEHAVIOR";
+pub const SIGNING_BACKEND_ENV_VAR: &str = "JJ_MCP_SIGNING_BACKEND";
+pub const SIGNING_KEY_ENV_VAR: &str = "JJ_MCP_SIGNING_KEY";
+pub const LOCK_RETRIES_ENV_VAR: &str = "JJ_MCP_LOCK_RETRIES";
+pub const COMMAND_TIMEOUT_ENV_VAR: &str = "JJ_MCP_COMMAND_TIMEOUT";
+pub const READ_ONLY_ENV_VAR: &str = "JJ_MCP_READ_ONLY";
+pub const JJ_BIN_ENV_VAR: &str = "JJ_MCP_JJ_BIN";
+pub const REPO_ENV_VAR: &str = "JJ_MCP_REPO";
+pub const LOG_LEVEL_ENV_VAR: &str = "JJ_MCP_LOG_LEVEL";
+pub const LOG_FORMAT_ENV_VAR: &str = "JJ_MCP_LOG_FORMAT";
+pub const LOG_FILE_ENV_VAR: &str = "JJ_MCP_LOG_FILE";
+
+/// Accepted values of `--log-level`, least verbose first
+pub const LOG_LEVELS: &[&str] = &["error", "warn", "info", "debug", "trace"];
+
+/// Retries of a command that failed on a locked repository, unless configured
+pub const DEFAULT_LOCK_RETRIES: u32 = 3;
+
+/// Turn a JSON configuration file into flags for [`ServerConfig::from_sources`]
+///
+/// The file holds an object keyed by flag name without the dashes, e.g.
+/// `{"enable-groups": ["read", "remote"], "max-concurrency": 4, "read-only": true}`.
+/// Arrays become comma-separated lists.
+pub fn config_file_args(contents: &str) -> Result<Vec<String>> {
+    let settings: serde_json::Map<String, Value> = serde_json::from_str(contents)
+        .map_err(|e| anyhow::anyhow!("Error: invalid configuration file: {}", e))?;
+
+    let scalar = |key: &str, value: &Value| -> Result<String> {
+        match value {
+            Value::String(text) => Ok(text.clone()),
+            Value::Number(number) => Ok(number.to_string()),
+            Value::Bool(flag) => Ok(flag.to_string()),
+            _ => Err(anyhow::anyhow!(
+                "Error: configuration setting {} must be a string, number, boolean or array",
+                key
+            )),
+        }
+    };
+    settings
+        .iter()
+        .map(|(key, value)| {
+            let value = match value {
+                Value::Array(items) => items
+                    .iter()
+                    .map(|item| scalar(key, item))
+                    .collect::<Result<Vec<_>>>()?
+                    .join(","),
+                other => scalar(key, other)?,
+            };
+            Ok(format!("--{}={}", key, value))
+        })
+        .collect()
+}
+
+/// Diff or log output kept in memory, unless configured
+pub const DEFAULT_MAX_STREAM_BYTES: usize = 64 * 1024 * 1024;
+
+/// jj processes allowed to run at once, unless configured
+pub const DEFAULT_MAX_CONCURRENCY: usize = 8;
+
+/// Output size above which text is returned as a resource link, unless configured
+pub const DEFAULT_MAX_OUTPUT_BYTES: usize = 256 * 1024;
+
+fn split_list(value: &str) -> impl Iterator<Item = &str> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+}
+
+impl ServerConfig {
+    /// Build the configuration from environment values, then command-line flags
+    ///
+    /// Flags take the form `--enable-groups read,remote`, `--disable-groups write`,
+    /// `--enable-tools undo`, `--disable-tools git-clone`, `--tool-prefix jj_`,
+    /// `--roots ~/src:/work`, `--require-confirmation rebase,abandon` and
+    /// `--max-output-bytes 65536`, `--max-concurrency 4`,
+    /// `--max-stream-bytes 1048576`, `--min-jj-version 0.25`, `--signing-behavior own`,
+    /// `--signing-backend ssh`, `--signing-key ~/.ssh/id.pub`, `--lock-retries 5`,
+    /// `--command-timeout 30`,
+    /// `--read-only true`, `--jj-bin /opt/jj/bin/jj`, `--repo ~/src/project` and
+    /// `--log-level debug`, `--log-format json` and `--log-file /tmp/jj-mcp.log`
+    /// (or `--flag=value`).
+    /// List flags add to what the environment specified.
+    pub fn from_sources(
+        args: impl IntoIterator<Item = String>,
+        env: impl Fn(&str) -> Option<String>,
+    ) -> Result<Self> {
+        let mut config = ServerConfig::default();
+        for (var, flag) in [
+            (ENABLE_GROUPS_ENV_VAR, "--enable-groups"),
+            (DISABLE_GROUPS_ENV_VAR, "--disable-groups"),
+            (ENABLE_TOOLS_ENV_VAR, "--enable-tools"),
+            (DISABLE_TOOLS_ENV_VAR, "--disable-tools"),
+            (TOOL_PREFIX_ENV_VAR, "--tool-prefix"),
+            (ROOTS_ENV_VAR, "--roots"),
+            (REQUIRE_CONFIRMATION_ENV_VAR, "--require-confirmation"),
+            (MAX_OUTPUT_BYTES_ENV_VAR, "--max-output-bytes"),
+            (MAX_CONCURRENCY_ENV_VAR, "--max-concurrency"),
+            (MAX_STREAM_BYTES_ENV_VAR, "--max-stream-bytes"),
+            (MIN_JJ_VERSION_ENV_VAR, "--min-jj-version"),
+            (SIGNING_BEHAVIOR_ENV_VAR, "--signing-behavior"),
+            (SIGNING_BACKEND_ENV_VAR, "--signing-backend"),
+            (SIGNING_KEY_ENV_VAR, "--signing-key"),
+            (LOCK_RETRIES_ENV_VAR, "--lock-retries"),
+            (COMMAND_TIMEOUT_ENV_VAR, "--command-timeout"),
+            (READ_ONLY_ENV_VAR, "--read-only"),
+            (JJ_BIN_ENV_VAR, "--jj-bin"),
+            (REPO_ENV_VAR, "--repo"),
+            (LOG_LEVEL_ENV_VAR, "--log-level"),
+            (LOG_FORMAT_ENV_VAR, "--log-format"),
+            (LOG_FILE_ENV_VAR, "--log-file"),
+        ] {
+            if let Some(value) = env(var) {
+                config.apply(flag, &value)?;
+            }
+        }
+
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let (flag, value) = match arg.split_once('=') {
+                Some((flag, value)) => (flag.to_string(), value.to_string()),
+                None => {
+                    let value = args
+                        .next()
+                        .ok_or_else(|| anyhow::anyhow!("Error: {} needs a value", arg))?;
+                    (arg, value)
+                }
+            };
+            config.apply(&flag, &value)?;
+        }
+        Ok(config)
+    }
+
+    fn apply(&mut self, flag: &str, value: &str) -> Result<()> {
+        match flag {
+            "--enable-groups" => {
+                for group in split_list(value) {
+                    self.enabled_groups.push(group.parse()?);
+                }
+            }
+            "--disable-groups" => {
+                for group in split_list(value) {
+                    self.disabled_groups.push(group.parse()?);
+                }
+            }
+            "--enable-tools" => self
+                .enabled_tools
+                .extend(split_list(value).map(str::to_string)),
+            "--disable-tools" => self
+                .disabled_tools
+                .extend(split_list(value).map(str::to_string)),
+            "--tool-prefix" => {
+                self.tool_prefix = Some(value.to_string()).filter(|prefix| !prefix.is_empty())
+            }
+            "--max-output-bytes" => {
+                self.max_output_bytes = Some(value.trim().parse().map_err(|_| {
+                    anyhow::anyhow!("Error: --max-output-bytes needs a number, not {}", value)
+                })?)
+            }
+            "--max-concurrency" => {
+                self.max_concurrency = Some(value.trim().parse().map_err(|_| {
+                    anyhow::anyhow!("Error: --max-concurrency needs a number, not {}", value)
+                })?)
+            }
+            "--max-stream-bytes" => {
+                self.max_stream_bytes = Some(value.trim().parse().map_err(|_| {
+                    anyhow::anyhow!("Error: --max-stream-bytes needs a number, not {}", value)
+                })?)
+            }
+            "--min-jj-version" => self.min_jj_version = Some(value.parse()?),
+            "--read-only" => {
+                self.read_only = match value.trim() {
+                    "true" | "1" | "yes" => true,
+                    "false" | "0" | "no" => false,
+                    other => {
+                        return Err(anyhow::anyhow!(
+                            "Error: --read-only needs true or false, not {}",
+                            other
+                        ));
+                    }
+                }
+            }
+            "--jj-bin" => self.jj_bin = Some(value.to_string()).filter(|bin| !bin.is_empty()),
+            "--repo" => {
+                self.repo = Some(std::path::PathBuf::from(value)).filter(|_| !value.is_empty())
+            }
+            "--log-level" => {
+                let level = value.trim().to_lowercase();
+                if !LOG_LEVELS.contains(&level.as_str()) {
+                    return Err(anyhow::anyhow!(
+                        "Error: unknown log level {} (expected {})",
+                        value,
+                        LOG_LEVELS.join(", ")
+                    ));
+                }
+                self.log_level = Some(level)
+            }
+            "--log-format" => {
+                let format = value.trim();
+                if !["text", "json"].contains(&format) {
+                    return Err(anyhow::anyhow!(
+                        "Error: unknown log format {} (expected text or json)",
+                        format
+                    ));
+                }
+                self.log_format = Some(format.to_string())
+            }
+            "--log-file" => {
+                self.log_file = Some(std::path::PathBuf::from(value)).filter(|_| !value.is_empty())
+            }
+            "--lock-retries" => {
+                self.lock_retries = Some(value.trim().parse().map_err(|_| {
+                    anyhow::anyhow!("Error: --lock-retries needs a number, not {}", value)
+                })?)
+            }
+            "--command-timeout" => {
+                let seconds: f64 = value
+                    .trim()
+                    .parse()
+                    .ok()
+                    .filter(|seconds: &f64| seconds.is_finite() && *seconds >= 0.0)
+                    .ok_or_else(|| {
+                        anyhow::anyhow!(
+                            "Error: --command-timeout needs a number of seconds, not {}",
+                            value
+                        )
+                    })?;
+                self.command_timeout =
+                    Some(std::time::Duration::from_secs_f64(seconds)).filter(|_| seconds > 0.0)
+            }
+            "--signing-behavior" => {
+                let behavior = value.trim();
+                if !["drop", "keep", "own", "force"].contains(&behavior) {
+                    return Err(anyhow::anyhow!(
+                        "Error: unknown signing behavior {} (expected drop, keep, own or force)",
+                        behavior
+                    ));
+                }
+                self.signing_behavior = Some(behavior.to_string())
+            }
+            "--signing-backend" => {
+                let backend = value.trim();
+                if !SIGNING_BACKENDS.contains(&backend) {
+                    return Err(anyhow::anyhow!(
+                        "Error: unknown signing backend {} (expected {})",
+                        backend,
+                        SIGNING_BACKENDS.join(", ")
+                    ));
+                }
+                self.signing_backend = Some(backend.to_string())
+            }
+            "--signing-key" => {
+                self.signing_key = Some(value.to_string()).filter(|key| !key.is_empty())
+            }
+            "--require-confirmation" => self
+                .confirm_tools
+                .extend(split_list(value).map(str::to_string)),
+            "--roots" => self
+                .roots
+                .extend(std::env::split_paths(value).filter(|path| !path.as_os_str().is_empty())),
+            other => return Err(anyhow::anyhow!("Error: unknown option {}", other)),
+        }
+        Ok(())
+    }
+
+    /// Whether a tool should be exposed; per-tool settings win over group settings
+    ///
+    /// In read-only mode, tools that can modify the repository are never exposed.
+    pub fn is_tool_enabled(&self, name: &str) -> bool {
+        if self.read_only && !default_tool_annotations(name).read_only_hint {
+            return false;
+        }
+        if self.enabled_tools.iter().any(|tool| tool == name) {
+            return true;
+        }
+        if self.disabled_tools.iter().any(|tool| tool == name) {
+            return false;
+        }
+        let group = tool_group(name);
+        (self.enabled_groups.is_empty() || self.enabled_groups.contains(&group))
+            && !self.disabled_groups.contains(&group)
+    }
+
+    /// Why a tool isn't offered, if it isn't: turned off, or too new for the installed jj
+    ///
+    /// Tools that call other tools check this so they can't reach what a direct call couldn't.
+    pub fn unavailable_tool_reason(
+        &self,
+        name: &str,
+        installed: Option<JjVersion>,
+    ) -> Option<String> {
+        if !self.is_tool_enabled(name) {
+            return Some(format!("{} is disabled on this server", name));
+        }
+        installed
+            .and_then(|installed| unsupported_tool_reason(name, installed, self.min_jj_version()))
+    }
+
+    /// The output size above which text is returned as a resource link, if limited
+    pub fn output_limit(&self) -> Option<usize> {
+        match self.max_output_bytes {
+            Some(0) => None,
+            Some(limit) => Some(limit),
+            None => Some(DEFAULT_MAX_OUTPUT_BYTES),
+        }
+    }
+
+    /// The number of jj processes allowed to run at once, if limited
+    pub fn concurrency_limit(&self) -> Option<usize> {
+        match self.max_concurrency {
+            Some(0) => None,
+            Some(limit) => Some(limit),
+            None => Some(DEFAULT_MAX_CONCURRENCY),
+        }
+    }
+
+    /// The most diff or log output kept in memory
+    pub fn stream_budget(&self) -> usize {
+        match self.max_stream_bytes {
+            Some(0) => usize::MAX,
+            Some(limit) => limit,
+            None => DEFAULT_MAX_STREAM_BYTES,
+        }
+    }
+
+    /// Times a command is retried while the repository is locked
+    pub fn lock_retries(&self) -> u32 {
+        self.lock_retries.unwrap_or(DEFAULT_LOCK_RETRIES)
+    }
+
+    /// The oldest jj tools are offered for
+    pub fn min_jj_version(&self) -> JjVersion {
+        self.min_jj_version.unwrap_or(DEFAULT_MIN_JJ_VERSION)
+    }
+
+    /// Whether calls to a tool must be confirmed before they run
+    pub fn requires_confirmation(&self, name: &str) -> bool {
+        self.confirm_tools.iter().any(|tool| tool == name)
+    }
+
+    /// The name a tool is exposed under
+    pub fn exposed_name(&self, name: &str) -> String {
+        match &self.tool_prefix {
+            Some(prefix) => format!("{}{}", prefix, name),
+            None => name.to_string(),
+        }
+    }
+
+    /// The catalog name of an exposed tool, with the configured prefix removed
+    pub fn canonical_name<'a>(&self, exposed: &'a str) -> &'a str {
+        self.tool_prefix
+            .as_deref()
+            .and_then(|prefix| exposed.strip_prefix(prefix))
+            .unwrap_or(exposed)
+    }
+}
+
+static SERVER_CONFIG: std::sync::OnceLock<ServerConfig> = std::sync::OnceLock::new();
+
+/// Install the server configuration; returns false if one was already installed
+pub fn install_server_config(config: ServerConfig) -> bool {
+    SERVER_CONFIG.set(config).is_ok()
+}
+
+/// The installed server configuration, or the defaults if none was installed
+pub fn server_config() -> &'static ServerConfig {
+    SERVER_CONFIG.get_or_init(ServerConfig::default)
+}
+
+/// The catalog tools enabled by a configuration, named as they are exposed
+pub fn enabled_tools(config: &ServerConfig) -> Vec<JjTool> {
+    all_tools()
+        .into_iter()
+        .filter(|tool| config.is_tool_enabled(&tool.name))
+        .map(|mut tool| {
+            tool.name = config.exposed_name(&tool.name);
+            tool
+        })
+        .collect()
+}
+
+/// Sandboxes, keyed by the workspace root of the repository they copy
+static SANDBOXES: std::sync::LazyLock<
+    std::sync::Mutex<std::collections::HashMap<std::path::PathBuf, Sandbox>>,
+> = std::sync::LazyLock::new(Default::default);
+
+#[cfg(unix)]
+fn copy_symlink(from: &std::path::Path, to: &std::path::Path) -> std::io::Result<()> {
+    std::os::unix::fs::symlink(std::fs::read_link(from)?, to)
+}
+
+#[cfg(not(unix))]
+fn copy_symlink(from: &std::path::Path, to: &std::path::Path) -> std::io::Result<()> {
+    std::fs::copy(from, to).map(|_| ())
+}
+
+/// Recursively copy a directory, recreating symlinks instead of following them
+pub fn copy_dir_all(from: &std::path::Path, to: &std::path::Path) -> Result<()> {
+    std::fs::create_dir_all(to)?;
+    for entry in std::fs::read_dir(from)? {
+        let entry = entry?;
+        let file_type = entry.file_type()?;
+        let target = to.join(entry.file_name());
+        if file_type.is_dir() {
+            copy_dir_all(&entry.path(), &target)?;
+        } else if file_type.is_symlink() {
+            copy_symlink(&entry.path(), &target)?;
+        } else {
+            std::fs::copy(entry.path(), &target)?;
+        }
+    }
+    Ok(())
+}
+
+/// The sandbox copy of a workspace, created on first use
+pub fn sandbox_path(root: &std::path::Path) -> Result<std::path::PathBuf> {
+    let mut sandboxes = SANDBOXES.lock().unwrap();
+    if let Some(sandbox) = sandboxes.get(root) {
+        return Ok(sandbox.path().to_path_buf());
+    }
+    // A secondary workspace shares its repo with the main one, so copying it would
+    // still write to the real repository
+    if !root.join(".jj").join("repo").is_dir() {
+        return Err(anyhow::anyhow!(
+            "Error: sandbox mode needs the main workspace of the repository, not {}",
+            root.display()
+        ));
+    }
+
+    let dir = private_temp_dir("jj-mcp-sandbox-")?;
+    // Dropping `dir` on failure removes the partial copy
+    copy_dir_all(root, dir.path())
+        .map_err(|e| anyhow::anyhow!("Error: failed to copy {}: {}", root.display(), e))?;
+    let path = dir.path().to_path_buf();
+    sandboxes.insert(
+        root.to_path_buf(),
+        Sandbox {
+            dir,
+            calls: Vec::new(),
+        },
+    );
+    Ok(path)
+}
+
+/// Point tool arguments at the sandbox copy of `root` instead of the repository itself
+pub fn sandbox_arguments(args: &Value, root: &std::path::Path, sandbox: &std::path::Path) -> Value {
+    let mut sandboxed = match args {
+        Value::Object(_) => args.clone(),
+        _ => serde_json::json!({}),
+    };
+    if let Some(object) = sandboxed.as_object_mut() {
+        object.remove("sandbox");
+    }
+
+    let cwd = args
+        .get("cwd")
+        .and_then(Value::as_str)
+        .and_then(|cwd| std::path::Path::new(cwd).strip_prefix(root).ok())
+        .map(|relative| sandbox.join(relative))
+        .unwrap_or_else(|| sandbox.to_path_buf());
+    sandboxed["repoPath"] = serde_json::json!(sandbox.to_string_lossy());
+    sandboxed["cwd"] = serde_json::json!(cwd.to_string_lossy());
+    sandboxed
+}
+
+/// Record a mutating call made in the sandbox of `root`, returning the number pending
+fn record_sandbox_call(root: &std::path::Path, call: SandboxCall) -> usize {
+    let mut sandboxes = SANDBOXES.lock().unwrap();
+    match sandboxes.get_mut(root) {
+        Some(sandbox) => {
+            sandbox.calls.push(call);
+            sandbox.calls.len()
+        }
+        None => 0,
+    }
+}
+
+/// The text of a tool response, for reporting replayed calls
+fn response_text(response: &CallToolResponse) -> String {
+    response
+        .content
+        .iter()
+        .filter_map(|content| match content {
+            ToolResponseContent::Text { text } => Some(text.as_str()),
+            _ => None,
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Replay the calls recorded in a sandbox on the real repository
+///
+/// Replay stops at the first failing call; it and the calls after it stay pending.
+pub fn run_jj_sandbox_promote(params: SandboxPromoteParams) -> CallToolResponse {
+    let result = (|| {
+        let root = resolve_workspace_root(params.repo_path, params.cwd)?;
+        let sandbox = SANDBOXES
+            .lock()
+            .unwrap()
+            .remove(&root)
+            .ok_or_else(|| anyhow::anyhow!("Error: no sandbox for {}", root.display()))?;
+
+        if params.dry_run.unwrap_or(false) {
+            let report = serde_json::json!({
+                "sandbox": sandbox.path().to_string_lossy(),
+                "pending": sandbox.calls,
+            });
+            SANDBOXES.lock().unwrap().insert(root, sandbox);
+            return Ok(report);
+        }
+
+        let mut replayed = Vec::new();
+        let mut calls = sandbox.calls.into_iter();
+        while let Some(call) = calls.next() {
+            let tool = JjTool::new(&call.tool, "", serde_json::json!({}));
+            let error = match tool.call(Some(call.arguments.clone())) {
+                Ok(response) if response.is_error != Some(true) => {
+                    replayed.push(serde_json::json!({
+                        "tool": call.tool,
+                        "arguments": call.arguments,
+                        "output": response_text(&response),
+                    }));
+                    continue;
+                }
+                Ok(response) => response_text(&response),
+                Err(e) => e.to_string(),
+            };
+
+            let failed = serde_json::json!({
+                "tool": call.tool,
+                "arguments": call.arguments,
+                "error": error,
+            });
+            let pending: Vec<SandboxCall> = std::iter::once(call).chain(calls).collect();
+            let report = serde_json::json!({
+                "promoted": false,
+                "replayed": replayed,
+                "failed": failed,
+                "pending": pending.len(),
+            });
+            SANDBOXES.lock().unwrap().insert(
+                root,
+                Sandbox {
+                    dir: sandbox.dir,
+                    calls: pending,
+                },
+            );
+            return Ok(report);
+        }
+
+        drop(sandbox.dir);
+        Ok(serde_json::json!({
+            "promoted": true,
+            "replayed": replayed,
+        }))
+    })();
+
+    json_response(result)
+}
+
+/// Throw away a sandbox and the calls recorded in it
+pub fn run_jj_sandbox_discard(params: SandboxDiscardParams) -> CallToolResponse {
+    let result = (|| {
+        let root = resolve_workspace_root(params.repo_path, params.cwd)?;
+        let sandbox = SANDBOXES
+            .lock()
+            .unwrap()
+            .remove(&root)
+            .ok_or_else(|| anyhow::anyhow!("Error: no sandbox for {}", root.display()))?;
+        let discarded = sandbox.path().to_string_lossy().to_string();
+        let calls = sandbox.calls.len();
+        sandbox.dir.close()?;
+
+        Ok(serde_json::json!({
+            "discarded": discarded,
+            "calls": calls,
+        }))
+    })();
+
+    json_response(result)
+}
+
+/// Build a revset matching any of the given revisions
+pub fn union_revset(revisions: &[String]) -> String {
+    let revisions: Vec<String> = revisions
+        .iter()
+        .map(|revision| format!("({})", revision))
+        .collect();
+    revisions.join(" | ")
+}
+
+/// Pair `change_id`/`commit_id` records from before and after a rewrite, keeping the
+/// changes whose commit was replaced
+pub fn map_rewritten_commits(before: &[Vec<String>], after: &[Vec<String>]) -> Vec<Value> {
+    before
+        .iter()
+        .filter(|record| record.len() >= 2)
+        .filter_map(|old| {
+            let new = after
+                .iter()
+                .find(|record| record.len() >= 2 && record[0] == old[0])?;
+            (new[1] != old[1]).then(|| {
+                serde_json::json!({
+                    "changeId": old[0],
+                    "oldCommitId": old[1],
+                    "newCommitId": new[1],
+                })
+            })
+        })
+        .collect()
+}
+
+/// Abandon revisions and report where their descendants were rebased
+pub fn run_jj_abandon(params: AbandonParams) -> CallToolResponse {
+    let result = (|| {
+        let revisions = params.revisions.unwrap_or_else(|| vec!["@".to_string()]);
+        let revset = union_revset(&revisions);
+
+        let abandoned = query_log_records(
+            &revset,
+            &log_record_template(&[
+                "commit_id",
+                "change_id.short()",
+                "local_bookmarks.map(|b| b.name()).join(\",\")",
+            ]),
+            params.repo_path.clone(),
+            params.cwd.clone(),
+        )?;
+        if abandoned.is_empty() {
+            return Err(anyhow::anyhow!("Error: no revisions match {}", revset));
+        }
+
+        let id_template = log_record_template(&["change_id", "commit_id"]);
+        let descendants = query_log_records(
+            &format!("descendants({}) ~ ({})", revset, revset),
+            &id_template,
+            params.repo_path.clone(),
+            params.cwd.clone(),
+        )?;
+
+        let mut args = vec!["abandon".to_string()];
+        if params.retain_bookmarks.unwrap_or(false) {
+            args.push("--retain-bookmarks".to_string());
+        }
+        if params.restore_descendants.unwrap_or(false) {
+            args.push("--restore-descendants".to_string());
+        }
+        args.extend(abandoned.iter().map(|record| record[0].clone()));
+        add_repo_args(&mut args, params.repo_path.clone());
+        let output = run_jj_command_output(args, params.cwd.clone())?;
+
+        let rebased = if descendants.is_empty() {
+            Vec::new()
+        } else {
+            let change_ids: Vec<String> =
+                descendants.iter().map(|record| record[0].clone()).collect();
+            let after = query_log_records(
+                &union_revset(&change_ids),
+                &id_template,
+                params.repo_path,
+                params.cwd,
+            )?;
+            map_rewritten_commits(&descendants, &after)
+        };
+
+        let commits: Vec<Value> = abandoned
+            .iter()
+            .map(|record| {
+                let bookmarks: Vec<&str> = record
+                    .get(2)
+                    .map(|names| names.split(',').filter(|name| !name.is_empty()).collect())
+                    .unwrap_or_default();
+                serde_json::json!({
+                    "commitId": record[0],
+                    "changeId": record.get(1),
+                    "bookmarks": bookmarks,
+                })
+            })
+            .collect();
+
+        Ok(serde_json::json!({
+            "abandoned": commits,
+            "bookmarks": if params.retain_bookmarks.unwrap_or(false) { "moved to parents" } else { "deleted" },
+            "rebased": rebased,
+            "output": output.stderr,
+        }))
+    })();
+
+    json_response(result)
+}
+
+/// Build the `jj restore` arguments; paths come after `--` so none is read as an option
+pub fn restore_args(params: &RestoreParams) -> Vec<String> {
+    let mut args = vec!["restore".to_string()];
+
+    if let Some(from) = &params.from {
+        args.push("--from".to_string());
+        args.push(from.clone());
+    }
+    if let Some(into) = &params.into {
+        args.push("--into".to_string());
+        args.push(into.clone());
+    }
+    if params.restore_descendants.unwrap_or(false) {
+        args.push("--restore-descendants".to_string());
+    }
+    if let Some(paths) = params.paths.as_ref().filter(|paths| !paths.is_empty()) {
+        args.push("--".to_string());
+        args.extend(paths.iter().cloned());
+    }
+
+    args
+}
+
+/// Restore paths in a revision from another revision
+///
+/// With no options this discards the working-copy changes, restoring `@` from its parents.
+pub fn run_jj_restore(params: RestoreParams) -> CallToolResponse {
+    let mut args = restore_args(&params);
+    add_repo_args(&mut args, params.repo_path);
+
+    let result = run_jj_command_output(args, params.cwd).map(|output| {
+        serde_json::json!({
+            "from": params.from,
+            "into": params.into.unwrap_or_else(|| "@".to_string()),
+            "paths": params.paths.unwrap_or_default(),
+            "output": output.stderr,
+        })
+    });
+
+    json_response(result)
+}
+
+/// Build the `jj squash` arguments
+///
+/// Without a message jj would open an editor to combine the descriptions, so the
+/// editor is set to `true`, which accepts the combined description as is.
+pub fn squash_args(params: &SquashParams) -> Result<Vec<String>> {
+    let use_destination_message = params.use_destination_message.unwrap_or(false);
+    if params.message.is_some() && use_destination_message {
+        return Err(anyhow::anyhow!(
+            "Error: message and useDestinationMessage cannot be combined"
+        ));
+    }
+
+    let mut args = vec!["squash".to_string()];
+    if let Some(from) = &params.from {
+        args.push("--from".to_string());
+        args.push(from.clone());
+    }
+    if let Some(into) = &params.into {
+        args.push("--into".to_string());
+        args.push(into.clone());
+    }
+    match &params.message {
+        Some(message) => {
+            args.push("-m".to_string());
+            args.push(message.clone());
+        }
+        None if use_destination_message => args.push("--use-destination-message".to_string()),
+        None => {
+            args.push("--config".to_string());
+            args.push("ui.editor=true".to_string());
+        }
+    }
+    if params.keep_emptied.unwrap_or(false) {
+        args.push("--keep-emptied".to_string());
+    }
+    if let Some(paths) = params.paths.as_ref().filter(|paths| !paths.is_empty()) {
+        args.push("--".to_string());
+        args.extend(paths.iter().cloned());
+    }
+
+    Ok(args)
+}
+
+/// Move changes from one revision into another
+pub fn run_jj_squash(params: SquashParams) -> CallToolResponse {
+    let result = (|| {
+        let mut args = squash_args(&params)?;
+        add_repo_args(&mut args, params.repo_path);
+        let output = run_jj_command_output(args, params.cwd)?;
+
+        Ok(serde_json::json!({
+            "from": params.from.unwrap_or_else(|| "@".to_string()),
+            "into": params.into.unwrap_or_else(|| "@-".to_string()),
+            "output": output.stderr,
+        }))
+    })();
+
+    json_response(result)
+}
+
+/// Show the change that last modified each line of a file
+pub fn run_jj_annotate(params: AnnotateParams) -> CallToolResponse {
+    let result = (|| {
+        let path = params
+            .path
+            .ok_or_else(|| anyhow::anyhow!("Error: path is required"))?;
+        let range = match (params.start_line, params.end_line) {
+            (None, None) => None,
+            (start, end) => {
+                let start = start.unwrap_or(1);
+                let end = end.unwrap_or(usize::MAX);
+                if start == 0 || end < start {
+                    return Err(anyhow::anyhow!(
+                        "Error: startLine must be at least 1 and no greater than endLine"
+                    ));
+                }
+                Some(start..=end)
+            }
+        };
+
+        let mut args = vec!["file".to_string(), "annotate".to_string()];
+        if let Some(revision) = params.revision {
+            args.push("-r".to_string());
+            args.push(revision);
+        }
+        // A custom template can span any number of output lines, so each file line is
+        // marked with its number to find the range again
+        let marked = range.is_some() && params.template.is_some();
+        if let Some(template) = params.template {
+            args.push("-T".to_string());
+            args.push(if marked {
+                format!(
+                    "{} ++ line_number ++ \"\\t\" ++ ({})",
+                    quote_string_literal(ANNOTATE_LINE_MARKER),
+                    template
+                )
+            } else {
+                template
+            });
+        }
+        args.push(path);
+        add_repo_args(&mut args, params.repo_path);
+
+        let output = run_jj_command_sync(args, params.cwd)?;
+        Ok(match range {
+            Some(range) => annotated_line_range(&output, range, marked),
+            None => output,
+        })
+    })();
+
+    match result {
+        Ok(output) => CallToolResponse {
+            content: vec![ToolResponseContent::Text { text: output }],
+            is_error: Some(false),
+            meta: None,
+        },
+        Err(e) => CallToolResponse {
+            content: vec![ToolResponseContent::Text {
+                text: e.to_string(),
+            }],
+            is_error: Some(true),
+            meta: None,
+        },
+    }
+}
+
+/// Starts each line of annotate output rendered for a line range with a custom template
+const ANNOTATE_LINE_MARKER: &str = "::annotate-line::";
+
+/// Keep the annotations of the lines in `range`
+///
+/// Output from the default template has one line per file line. `marked` output
+/// instead starts each file line's annotation with [`ANNOTATE_LINE_MARKER`], its line
+/// number and a tab, and the marker and number are removed.
+pub fn annotated_line_range(
+    output: &str,
+    range: std::ops::RangeInclusive<usize>,
+    marked: bool,
+) -> String {
+    if !marked {
+        return output
+            .lines()
+            .enumerate()
+            .filter(|(index, _)| range.contains(&(index + 1)))
+            .map(|(_, line)| format!("{}\n", line))
+            .collect();
+    }
+    output
+        .split(ANNOTATE_LINE_MARKER)
+        .filter_map(|record| record.split_once('\t'))
+        .filter(|(number, _)| {
+            number
+                .parse()
+                .is_ok_and(|number: usize| range.contains(&number))
+        })
+        .map(|(_, annotation)| annotation)
+        .collect()
+}
+
+/// Template emitting one tab-separated record per local or remote bookmark
+const BOOKMARK_RECORD_TEMPLATE: &str = "name ++ \"\\t\" ++ remote ++ \"\\t\" ++ if(normal_target, normal_target.commit_id().short()) ++ \"\\t\" ++ conflict ++ \"\\t\" ++ tracked ++ \"\\n\"";
+
+/// Parse the output of `jj bookmark list` rendered with [`BOOKMARK_RECORD_TEMPLATE`]
+pub fn parse_bookmark_records(output: &str) -> Vec<Value> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split('\t').collect();
+            let [name, remote, commit_id, conflict, tracked] = fields[..] else {
+                return None;
+            };
+            Some(serde_json::json!({
+                "name": name,
+                "remote": (!remote.is_empty()).then_some(remote),
+                "commitId": (!commit_id.is_empty()).then_some(commit_id),
+                "conflicted": conflict == "true",
+                "tracked": tracked == "true",
+            }))
+        })
+        .collect()
+}
+
+/// List bookmarks, filtered by name pattern, target revisions, tracking and conflicts
+pub fn run_jj_bookmark_list(params: BookmarkListParams) -> CallToolResponse {
+    let result = (|| {
+        let mut args = vec![
+            "bookmark".to_string(),
+            "list".to_string(),
+            "-T".to_string(),
+            BOOKMARK_RECORD_TEMPLATE.to_string(),
+        ];
+        if params.all_remotes.unwrap_or(false) {
+            args.push("--all-remotes".to_string());
+        }
+        if let Some(remote) = params.remote {
+            args.push("--remote".to_string());
+            args.push(remote);
+        }
+        if params.tracked.unwrap_or(false) {
+            args.push("--tracked".to_string());
+        }
+        if params.conflicted.unwrap_or(false) {
+            args.push("--conflicted".to_string());
+        }
+        if let Some(revisions) = params.revisions {
+            args.push("-r".to_string());
+            args.push(revisions);
+        }
+        args.extend(params.names.unwrap_or_default());
+        add_repo_args(&mut args, params.repo_path);
+
+        let output = run_jj_command_sync(args, params.cwd)?;
+        let bookmarks = parse_bookmark_records(&output);
+        Ok(serde_json::json!({
+            "count": bookmarks.len(),
+            "bookmarks": bookmarks,
+        }))
+    })();
+
+    json_response(result)
+}
+
+/// A bookmark `jj git push --change` generated for a revision
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct CreatedBookmark {
+    pub bookmark: String,
+    pub revision: String,
+}
+
+/// Parse the `Creating bookmark push-... for revision ...` lines of `jj git push --change`
+pub fn parse_created_bookmarks(output: &str) -> Vec<CreatedBookmark> {
+    output
+        .lines()
+        .filter_map(
+            |line| match line.split_whitespace().collect::<Vec<_>>()[..] {
+                [
+                    "Creating",
+                    "bookmark",
+                    bookmark,
+                    "for",
+                    "revision",
+                    revision,
+                ] => Some(CreatedBookmark {
+                    bookmark: bookmark.to_string(),
+                    revision: revision.to_string(),
+                }),
+                _ => None,
+            },
+        )
+        .collect()
+}
+
+/// Parse the bookmark updates that `jj git push` lists, with or without `--dry-run`
+///
+/// ```text
+/// Changes to push to origin:
+///   Move forward bookmark main from 1234abcd to 5678ef01
+///   Add bookmark feature to abcdef12
+///   Delete bookmark old from 12345678
+/// ```
+pub fn parse_push_updates(output: &str) -> Vec<BookmarkUpdate> {
+    let mut remote = None;
+    let mut updates = Vec::new();
+    for line in output.lines().map(str::trim) {
+        if let Some(name) = line
+            .strip_prefix("Changes to push to ")
+            .and_then(|rest| rest.strip_suffix(':'))
+        {
+            remote = Some(name.to_string());
+            continue;
+        }
+
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let (action, bookmark, old_commit, new_commit) = match words[..] {
+            ["Add", "bookmark", bookmark, "to", new] => {
+                ("add".to_string(), bookmark, None, Some(new))
+            }
+            ["Delete", "bookmark", bookmark, "from", old] => {
+                ("delete".to_string(), bookmark, Some(old), None)
+            }
+            [
+                "Move",
+                direction,
+                "bookmark",
+                bookmark,
+                "from",
+                old,
+                "to",
+                new,
+            ] => (
+                format!("move-{}", direction),
+                bookmark,
+                Some(old),
+                Some(new),
+            ),
+            _ => continue,
+        };
+        updates.push(BookmarkUpdate {
+            remote: remote.clone(),
+            bookmark: bookmark.to_string(),
+            action,
+            old_commit: old_commit.map(str::to_string),
+            new_commit: new_commit.map(str::to_string),
+        });
+    }
+    updates
+}
+
+/// Push bookmarks to a git remote; with `dryRun` only report what would be pushed
+pub fn run_jj_git_push(params: GitPushParams) -> CallToolResponse {
+    let result = (|| {
+        let dry_run = params.dry_run.unwrap_or(false);
+        let mut args = vec!["git".to_string(), "push".to_string()];
+        if let Some(remote) = params.remote {
+            args.push("--remote".to_string());
+            args.push(remote);
+        }
+        for bookmark in params.bookmarks.unwrap_or_default() {
+            args.push("-b".to_string());
+            args.push(bookmark);
+        }
+        for change in params.changes.unwrap_or_default() {
+            args.push("-c".to_string());
+            args.push(change);
+        }
+        if params.all.unwrap_or(false) {
+            args.push("--all".to_string());
+        }
+        if params.tracked.unwrap_or(false) {
+            args.push("--tracked".to_string());
+        }
+        if params.deleted.unwrap_or(false) {
+            args.push("--deleted".to_string());
+        }
+        if params.allow_new.unwrap_or(false) {
+            args.push("--allow-new".to_string());
+        }
+        add_repo_args(&mut args, params.repo_path);
+
+        // Bookmark deletions only show up in a preview, so preview before confirming them
+        if !dry_run && server_config().requires_confirmation("git-push") {
+            let mut preview = args.clone();
+            preview.push("--dry-run".to_string());
+            let output = run_jj_command_output(preview, params.cwd.clone())?;
+            let deleted: Vec<String> = parse_push_updates(&output.stderr)
+                .into_iter()
+                .filter(|update| update.action == "delete")
+                .map(|update| update.bookmark)
+                .collect();
+            if !deleted.is_empty() {
+                confirm_operation(&format!(
+                    "git push deleting remote bookmarks: {}",
+                    deleted.join(", ")
+                ))?;
+            }
+        }
+
+        if dry_run {
+            args.push("--dry-run".to_string());
+        }
+        let output = run_jj_command_output(args, params.cwd)?;
+        let updates = parse_push_updates(&output.stderr);
+        Ok(serde_json::json!({
+            "dryRun": dry_run,
+            "pushed": !dry_run && !updates.is_empty(),
+            "createdBookmarks": parse_created_bookmarks(&output.stderr),
+            "updates": updates,
+            "output": output.stderr,
+        }))
+    })();
+
+    json_response(result)
+}
+
+/// Names of the git remotes configured for a repository
+pub fn list_git_remotes(repo_path: Option<String>, cwd: Option<String>) -> Result<Vec<String>> {
+    let mut args = vec!["git".to_string(), "remote".to_string(), "list".to_string()];
+    add_repo_args(&mut args, repo_path);
+
+    let output = run_jj_command_sync(args, cwd)?;
+    Ok(parse_git_remotes(&output)
+        .into_iter()
+        .map(|remote| remote.name)
+        .collect())
+}
+
+/// Fetch from git remotes, one remote at a time so each reports its own result
+///
+/// Without `remotes` or `allRemotes`, jj's default remote selection is used.
+pub fn run_jj_git_fetch(params: GitFetchParams) -> CallToolResponse {
+    let result = (|| {
+        let remotes: Vec<Option<String>> = if params.all_remotes.unwrap_or(false) {
+            list_git_remotes(params.repo_path.clone(), params.cwd.clone())?
+                .into_iter()
+                .map(Some)
+                .collect()
+        } else {
+            match params.remotes {
+                Some(remotes) if !remotes.is_empty() => remotes.into_iter().map(Some).collect(),
+                _ => vec![None],
+            }
+        };
+        let bookmarks = params.bookmarks.unwrap_or_default();
+
+        let mut results = Vec::new();
+        let mut failed = Vec::new();
+        for remote in remotes {
+            let mut args = vec!["git".to_string(), "fetch".to_string()];
+            if let Some(remote) = &remote {
+                args.push("--remote".to_string());
+                args.push(remote.clone());
+            }
+            for bookmark in &bookmarks {
+                args.push("-b".to_string());
+                args.push(bookmark.clone());
+            }
+            add_repo_args(&mut args, params.repo_path.clone());
+
+            match run_jj_command_output(args, params.cwd.clone()) {
+                Ok(output) => results.push(serde_json::json!({
+                    "remote": remote,
+                    "ok": true,
+                    "output": output.stderr,
+                })),
+                Err(e) => {
+                    failed.push(remote.clone().unwrap_or_default());
+                    results.push(serde_json::json!({
+                        "remote": remote,
+                        "ok": false,
+                        "error": e.to_string(),
+                    }));
+                }
+            }
+        }
+
+        if !results.is_empty() && failed.len() == results.len() {
+            return Err(anyhow::anyhow!(
+                "Error: fetch failed for every remote: {}",
+                serde_json::Value::Array(results)
+            ));
+        }
+        Ok(serde_json::json!({
+            "remotes": results,
+            "failed": failed,
+        }))
+    })();
+
+    json_response(result)
+}
+
+/// Template emitting one tab-separated record per configuration value, the value last
+const CONFIG_RECORD_TEMPLATE: &str = "name ++ \"\\t\" ++ source ++ \"\\t\" ++ path ++ \"\\t\" ++ overridden ++ \"\\t\" ++ value ++ \"\\n\"";
+
+/// Parse the output of `jj config list` rendered with [`CONFIG_RECORD_TEMPLATE`]
+pub fn parse_config_records(output: &str) -> Vec<ConfigEntry> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.splitn(5, '\t');
+            let name = fields.next()?;
+            let source = fields.next()?;
+            let path = fields.next()?;
+            let overridden = fields.next()?;
+            let value = fields.next()?;
+            Some(ConfigEntry {
+                name: name.to_string(),
+                value: value.to_string(),
+                source: source.to_string(),
+                path: (!path.is_empty()).then(|| path.to_string()),
+                overridden: overridden == "true",
+            })
+        })
+        .collect()
+}
+
+/// List configuration values with the source and file each one comes from
+pub fn run_jj_config_list(params: ConfigListParams) -> CallToolResponse {
+    let result = (|| {
+        let mut args = vec![
+            "config".to_string(),
+            "list".to_string(),
+            "-T".to_string(),
+            CONFIG_RECORD_TEMPLATE.to_string(),
+        ];
+        if params.include_defaults.unwrap_or(false) {
+            args.push("--include-defaults".to_string());
+        }
+        if params.include_overridden.unwrap_or(false) {
+            args.push("--include-overridden".to_string());
+        }
+        if let Some(name) = params.name {
+            args.push(name);
+        }
+        add_repo_args(&mut args, params.repo_path);
+
+        let output = run_jj_command_sync(args, params.cwd)?;
+        Ok(serde_json::json!({
+            "values": parse_config_records(&output),
+        }))
+    })();
+
+    json_response(result)
+}
+
+/// Report the path of the user or repository configuration file
+pub fn run_jj_config_path(params: ConfigPathParams) -> CallToolResponse {
+    let result = (|| {
+        let level = params.level.unwrap_or_else(|| "user".to_string());
+        if !matches!(level.as_str(), "user" | "repo") {
+            return Err(anyhow::anyhow!(
+                "Error: level must be user or repo, not {}",
+                level
+            ));
+        }
+
+        let mut args = vec![
+            "config".to_string(),
+            "path".to_string(),
+            format!("--{}", level),
+        ];
+        add_repo_args(&mut args, params.repo_path);
+
+        let path = run_jj_command_sync(args, params.cwd)?;
+        let exists = std::path::Path::new(&path).exists();
+        Ok(serde_json::json!({
+            "level": level,
+            "path": path,
+            "exists": exists,
+        }))
+    })();
+
+    json_response(result)
+}
+
+/// Whether a jj error reports that the working copy is stale
+pub fn is_stale_working_copy_error(message: &str) -> bool {
+    message.contains("working copy is stale")
+}
+
+/// Report which workspace a directory belongs to and whether it needs `update-stale`
+pub fn run_jj_workspace_info(params: WorkspaceInfoParams) -> CallToolResponse {
+    let result = (|| {
+        let root = resolve_workspace_root(params.repo_path.clone(), params.cwd.clone())?;
+        let repo = repo_dir(&root);
+        // `<root>/.jj/repo` belongs to the main workspace
+        let main_root = repo
+            .parent()
+            .filter(|dir| dir.file_name() == Some(std::ffi::OsStr::new(".jj")))
+            .and_then(std::path::Path::parent)
+            .map(std::path::Path::to_path_buf);
+
+        // Resolve `@` from the recorded view, which works even when the workspace is stale
+        let mut args = vec![
+            "log".to_string(),
+            "--ignore-working-copy".to_string(),
+            "--no-graph".to_string(),
+            "-r".to_string(),
+            "@".to_string(),
+            "-T".to_string(),
+            "commit_id".to_string(),
+        ];
+        add_repo_args(&mut args, params.repo_path.clone());
+        let working_copy = run_jj_command_sync(args, params.cwd.clone())?;
+
+        let mut args = vec![
+            "workspace".to_string(),
+            "list".to_string(),
+            "--ignore-working-copy".to_string(),
+            "-T".to_string(),
+            "name ++ \"\\t\" ++ target.commit_id() ++ \"\\n\"".to_string(),
+        ];
+        add_repo_args(&mut args, params.repo_path.clone());
+        let workspaces = run_jj_command_sync(args, params.cwd.clone())?;
+        let names: Vec<&str> = workspaces
+            .lines()
+            .filter_map(|line| line.split_once('\t'))
+            .filter(|(_, target)| *target == working_copy)
+            .map(|(name, _)| name)
+            .collect();
+
+        let mut args = vec![
+            "log".to_string(),
+            "--no-graph".to_string(),
+            "-r".to_string(),
+            "@".to_string(),
+            "-T".to_string(),
+            "\"\"".to_string(),
+        ];
+        add_repo_args(&mut args, params.repo_path);
+        let stale = match run_jj_command_sync(args, params.cwd) {
+            Ok(_) => false,
+            Err(e) if is_stale_working_copy_error(&e.to_string()) => true,
+            Err(e) => return Err(e),
+        };
+
+        Ok(serde_json::json!({
+            "workspace": if names.len() == 1 { Some(names[0]) } else { None },
+            "candidates": if names.len() > 1 { names.clone() } else { Vec::new() },
+            "workspaceRoot": root,
+            "repoDir": repo,
+            "mainWorkspaceRoot": main_root,
+            "workingCopyCommit": working_copy,
+            "stale": stale,
+        }))
+    })();
+
+    json_response(result)
+}
+
+/// Show how a change evolved, newest version first
+pub fn run_jj_evolog(params: EvologParams) -> CallToolResponse {
+    let mut args = vec!["evolog".to_string(), "--no-graph".to_string()];
+
+    if let Some(revision) = params.revision {
+        args.push("-r".to_string());
+        args.push(revision);
+    }
+    if let Some(limit) = params.limit {
+        args.push("-n".to_string());
+        args.push(limit.to_string());
+    }
+    if params.patch.unwrap_or(false) {
+        args.push("--patch".to_string());
+    }
+    if let Some(template) = params.template {
+        args.push("-T".to_string());
+        args.push(template);
+    }
+
+    add_repo_args(&mut args, params.repo_path);
+
+    match run_jj_command_sync(args, params.cwd) {
+        Ok(output) => CallToolResponse {
+            content: vec![ToolResponseContent::Text { text: output }],
+            is_error: Some(false),
+            meta: None,
+        },
+        Err(e) => CallToolResponse {
+            content: vec![ToolResponseContent::Text {
+                text: e.to_string(),
+            }],
+            is_error: Some(true),
+            meta: None,
+        },
+    }
+}
+
+/// Roots reported by the MCP client; they replace [`ServerConfig::roots`] once set
+static CLIENT_ROOTS: std::sync::RwLock<Option<Vec<std::path::PathBuf>>> =
+    std::sync::RwLock::new(None);
+
+/// Record the roots the client reported in a `roots/list` response
+pub fn set_client_roots(roots: Vec<std::path::PathBuf>) {
+    *CLIENT_ROOTS.write().unwrap() = Some(roots);
+}
+
+/// Extract the local directories from the result of a `roots/list` request
+///
+/// Roots that are not `file://` URIs are ignored.
+pub fn roots_from_list_result(result: &Value) -> Vec<std::path::PathBuf> {
+    result["roots"]
+        .as_array()
+        .map(|roots| {
+            roots
+                .iter()
+                .filter_map(|root| root["uri"].as_str())
+                .filter_map(|uri| url::Url::parse(uri).ok()?.to_file_path().ok())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// The directories tools may operate in; empty means unrestricted
+pub fn allowed_roots() -> Vec<std::path::PathBuf> {
+    CLIENT_ROOTS
+        .read()
+        .unwrap()
+        .clone()
+        .unwrap_or_else(|| server_config().roots.clone())
+}
+
+/// Resolve symlinks when the path exists, otherwise remove `.` and `..` lexically
+fn normalize_path(path: &std::path::Path) -> std::path::PathBuf {
+    if let Ok(path) = path.canonicalize() {
+        return path;
+    }
+    let mut normalized = std::path::PathBuf::new();
+    for component in path.components() {
+        match component {
+            std::path::Component::CurDir => {}
+            std::path::Component::ParentDir => {
+                normalized.pop();
+            }
+            component => normalized.push(component),
+        }
+    }
+    normalized
+}
+
+/// Whether a path lies inside one of the roots
+pub fn path_under_roots(path: &std::path::Path, roots: &[std::path::PathBuf]) -> bool {
+    let path = normalize_path(path);
+    roots
+        .iter()
+        .any(|root| path.starts_with(normalize_path(root)))
+}
+
+/// Arguments naming files or directories on disk, by tool, besides `repoPath` and `cwd`
+///
+/// Tools resolve relative values against `cwd`, so the roots check does too.
+const PATH_ARGUMENTS: &[(&str, &str)] = &[
+    ("archive", "destination"),
+    ("git-clone", "destination"),
+    ("init", "destination"),
+    ("init", "gitRepo"),
+    ("discover-repos", "path"),
+    ("commit", "messageFile"),
+    ("describe", "messageFile"),
+];
+
+/// Reject a call to `tool` whose arguments point outside the roots
+///
+/// When neither `repoPath` nor `cwd` is given and the server's own directory is
+/// outside the roots, `cwd` defaults to the first root that is a jj workspace.
+pub fn restrict_to_roots(tool: &str, args: &mut Value, roots: &[std::path::PathBuf]) -> Result<()> {
+    if roots.is_empty() {
+        return Ok(());
+    }
+    let current = std::env::current_dir()?;
+    let given = |args: &Value, key: &str| args.get(key).and_then(Value::as_str).map(str::to_string);
+
+    if given(args, "repoPath").is_none()
+        && given(args, "cwd").is_none()
+        && !path_under_roots(&current, roots)
+        && let Some(default) = roots.iter().find(|root| root.join(".jj").is_dir())
+    {
+        args["cwd"] = serde_json::json!(default.to_string_lossy());
+    }
+
+    let cwd = given(args, "cwd").map(|cwd| current.join(cwd));
+    let base = cwd.clone().unwrap_or(current);
+    let mut paths: Vec<std::path::PathBuf> = cwd.into_iter().collect();
+    paths.extend(given(args, "repoPath").map(|repo| base.join(repo)));
+    if let Some(repos) = args.get("repos").and_then(Value::as_array) {
+        paths.extend(
+            repos
+                .iter()
+                .filter_map(Value::as_str)
+                .map(|repo| base.join(repo)),
+        );
+    }
+    for (_, key) in PATH_ARGUMENTS.iter().filter(|(name, _)| *name == tool) {
+        paths.extend(given(args, key).map(|path| base.join(path)));
+    }
+    // Without explicit paths, tools run in the server's own directory
+    if paths.is_empty() {
+        paths.push(base);
+    }
+
+    match paths.iter().find(|path| !path_under_roots(path, roots)) {
+        Some(outside) => Err(anyhow::anyhow!(
+            "Error: {} is outside the allowed roots ({})",
+            outside.display(),
+            std::env::join_paths(roots)
+                .map(|joined| joined.to_string_lossy().to_string())
+                .unwrap_or_default()
+        )),
+        None => Ok(()),
+    }
+}
+
+/// Asks the connected client's model to complete a prompt, via MCP sampling
+pub trait Sampler: Send + Sync {
+    /// Send `sampling/createMessage` with these params and return the text of the reply
+    fn create_message(&self, params: &Value) -> Result<String>;
+}
+
+static SAMPLER: std::sync::RwLock<Option<std::sync::Arc<dyn Sampler>>> =
+    std::sync::RwLock::new(None);
+
+/// Install the sampler used by tools that need the client's model
+pub fn set_sampler(sampler: std::sync::Arc<dyn Sampler>) {
+    *SAMPLER.write().unwrap() = Some(sampler);
+}
+
+fn sampler() -> Option<std::sync::Arc<dyn Sampler>> {
+    SAMPLER.read().unwrap().clone()
+}
+
+/// Whether a sampler is installed; tools that need one are not offered without it
+pub fn sampler_installed() -> bool {
+    sampler().is_some()
+}
+
+/// Diffs longer than this are cut before being sent to the client's model
+pub const DESCRIBE_AUTO_MAX_DIFF_BYTES: usize = 32 * 1024;
+
+/// Build `sampling/createMessage` params asking for a commit message for a diff
+pub fn commit_message_sampling_params(diff: &str, instructions: Option<&str>) -> Value {
+    let mut cut = diff.len().min(DESCRIBE_AUTO_MAX_DIFF_BYTES);
+    while !diff.is_char_boundary(cut) {
+        cut -= 1;
+    }
+    let mut prompt = format!(
+        "Write a commit message for this diff: a short imperative subject line, then a blank line and a brief body if the change needs explaining. Reply with the message only.\n\n{}",
+        &diff[..cut]
+    );
+    if cut < diff.len() {
+        prompt.push_str("\n[diff truncated]");
+    }
+    if let Some(instructions) = instructions {
+        prompt.push_str("\n\nAdditional instructions: ");
+        prompt.push_str(instructions);
+    }
+
+    serde_json::json!({
+        "messages": [{
+            "role": "user",
+            "content": {"type": "text", "text": prompt},
+        }],
+        "systemPrompt": "You write concise, accurate commit messages.",
+        "includeContext": "none",
+        "maxTokens": 400,
+    })
+}
+
+/// Strip the code fence or whitespace a model may wrap a commit message in
+pub fn clean_commit_message(text: &str) -> String {
+    let text = text.trim();
+    let unfenced = text
+        .strip_prefix("```")
+        .and_then(|rest| rest.strip_suffix("```"))
+        .map(|inner| inner.split_once('\n').map_or(inner, |(_, body)| body))
+        .unwrap_or(text);
+    unfenced.trim().to_string()
+}
+
+/// Describe a revision with a message written by the client's model
+pub fn run_jj_describe_auto(params: DescribeAutoParams) -> CallToolResponse {
+    let result = (|| {
+        let sampler = sampler()
+            .ok_or_else(|| anyhow::anyhow!("Error: the client does not support sampling"))?;
+        let revision = params.revision.unwrap_or_else(|| "@".to_string());
+
+        let mut args = vec![
+            "diff".to_string(),
+            "--git".to_string(),
+            "-r".to_string(),
+            revision.clone(),
+        ];
+        add_repo_args(&mut args, params.repo_path.clone());
+        let diff = run_jj_command_sync(args, params.cwd.clone())?;
+        if diff.is_empty() {
+            return Err(anyhow::anyhow!(
+                "Error: {} has no changes to describe",
+                revision
+            ));
+        }
+
+        let request = commit_message_sampling_params(&diff, params.instructions.as_deref());
+        let message = clean_commit_message(&sampler.create_message(&request)?);
+        if message.is_empty() {
+            return Err(anyhow::anyhow!(
+                "Error: the client returned an empty message"
+            ));
+        }
+
+        let dry_run = params.dry_run.unwrap_or(false);
+        if !dry_run {
+            let mut args = vec![
+                "describe".to_string(),
+                "-r".to_string(),
+                revision.clone(),
+                "-m".to_string(),
+                message.clone(),
+            ];
+            add_repo_args(&mut args, params.repo_path);
+            run_jj_command_sync(args, params.cwd)?;
+        }
+
+        Ok(serde_json::json!({
+            "revision": revision,
+            "message": message,
+            "applied": !dry_run,
+        }))
+    })();
+
+    json_response(result)
+}
+
+/// Confirm an operation: the call must pass `"confirm": true`
+pub fn confirm_operation(summary: &str) -> Result<()> {
+    confirm_with(current_call_options().confirm, summary)
+}
+
+/// [`confirm_operation`] with the call's `confirm` value given explicitly
+pub fn confirm_with(confirmed: bool, summary: &str) -> Result<()> {
+    if confirmed {
+        Ok(())
+    } else {
+        Err(anyhow::anyhow!(
+            "Error: confirmation required for {}; call again with \"confirm\": true to proceed",
+            summary
+        ))
+    }
+}
+
+/// MCP caps completion results at this many values
+pub const MAX_COMPLETION_VALUES: usize = 100;
+
+/// What kind of value an argument takes, for completion
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompletionKind {
+    Revision,
+    Bookmark,
+    Remote,
+}
+
+/// The completion kind for an argument name, if it is completable
+pub fn completion_kind(argument: &str) -> Option<CompletionKind> {
+    match argument {
+        "revision" | "revisions" | "from" | "to" | "into" | "source" | "destination"
+        | "parents" | "base" | "ancestor" | "descendant" | "good" | "bad" => {
+            Some(CompletionKind::Revision)
+        }
+        "bookmark" | "bookmarks" => Some(CompletionKind::Bookmark),
+        "remote" | "remotes" => Some(CompletionKind::Remote),
+        _ => None,
+    }
+}
+
+/// Candidate values for an argument, filtered by prefix, in MCP `completion/complete` form
+pub fn complete_argument(
+    argument: &str,
+    prefix: &str,
+    repo_path: Option<String>,
+    cwd: Option<String>,
+) -> Result<Value> {
+    let local_bookmarks = |repo_path: Option<String>, cwd: Option<String>| {
+        let mut args = vec![
+            "bookmark".to_string(),
+            "list".to_string(),
+            "-T".to_string(),
+            "if(!remote, name ++ \"\\n\")".to_string(),
+        ];
+        add_repo_args(&mut args, repo_path);
+        run_jj_command_sync(args, cwd)
+            .map(|output| output.lines().map(str::to_string).collect::<Vec<_>>())
+    };
+
+    let candidates = match completion_kind(argument) {
+        Some(CompletionKind::Bookmark) => local_bookmarks(repo_path, cwd)?,
+        Some(CompletionKind::Remote) => list_git_remotes(repo_path, cwd)?,
+        Some(CompletionKind::Revision) => {
+            let mut candidates = local_bookmarks(repo_path.clone(), cwd.clone())?;
+            let records = query_log_records(
+                "latest(::@ | bookmarks(), 100)",
+                &log_record_template(&["change_id.short()"]),
+                repo_path,
+                cwd,
+            )?;
+            candidates.extend(
+                records
+                    .into_iter()
+                    .filter_map(|record| record.into_iter().next()),
+            );
+            candidates
+        }
+        None => Vec::new(),
+    };
+
+    let mut values: Vec<String> = candidates
+        .into_iter()
+        .filter(|candidate| candidate.starts_with(prefix))
+        .collect();
+    let mut seen = std::collections::HashSet::new();
+    values.retain(|value| seen.insert(value.clone()));
+    let total = values.len();
+    values.truncate(MAX_COMPLETION_VALUES);
+    Ok(serde_json::json!({
+        "completion": {
+            "values": values,
+            "total": total,
+            "hasMore": total > MAX_COMPLETION_VALUES,
+        }
+    }))
+}
+
+/// Answer an MCP `completion/complete` request
+///
+/// The repository is taken from `repoPath` or `cwd` in `context.arguments`, the
+/// arguments the user has already filled in, and checked against the allowed
+/// roots like a tool call's.
+pub fn complete_request(request: &Value) -> Result<Value> {
+    let name = request["argument"]["name"]
+        .as_str()
+        .ok_or_else(|| anyhow::anyhow!("Error: argument.name is required"))?;
+    let mut args = serde_json::json!({});
+    for key in ["repoPath", "cwd"] {
+        if let Some(value) = request["context"]["arguments"][key].as_str() {
+            args[key] = serde_json::json!(value);
+        }
+    }
+    restrict_to_roots("complete", &mut args, &allowed_roots())?;
+
+    let given = |key: &str| args[key].as_str().map(str::to_string);
+    complete_argument(
+        name,
+        request["argument"]["value"].as_str().unwrap_or(""),
+        given("repoPath"),
+        given("cwd"),
+    )
+}
+
+/// Complete a tool argument, for clients without the MCP completion capability
+pub fn run_complete(params: CompleteParams) -> CallToolResponse {
+    let result = (|| {
+        let argument = params
+            .argument
+            .ok_or_else(|| anyhow::anyhow!("Error: argument is required"))?;
+        complete_argument(
+            &argument,
+            params.value.as_deref().unwrap_or(""),
+            params.repo_path,
+            params.cwd,
+        )
+    })();
+
+    json_response(result)
+}
+
+/// Most outputs kept in the store; the oldest are deleted as new ones arrive
+pub const MAX_STORED_OUTPUTS: usize = 32;
+
+/// Most bytes kept in the store, beyond which the oldest outputs are deleted (256 MiB)
+pub const MAX_STORED_OUTPUT_BYTES: u64 = 256 * 1024 * 1024;
+
+/// A new directory under the system temp directory that only this user can open
+///
+/// The name is random and creation fails rather than reuse an existing directory.
+pub fn private_temp_dir(prefix: &str) -> std::io::Result<tempfile::TempDir> {
+    let mut builder = tempfile::Builder::new();
+    builder.prefix(prefix);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        builder.permissions(std::fs::Permissions::from_mode(0o700));
+    }
+    builder.tempdir()
+}
+
+/// Private directory for the store, created on first use
+static OUTPUT_STORE: std::sync::LazyLock<std::io::Result<tempfile::TempDir>> =
+    std::sync::LazyLock::new(|| private_temp_dir("jj-mcp-output-"));
+
+/// Stored outputs with their sizes, oldest first
+static STORED_OUTPUTS: std::sync::LazyLock<
+    std::sync::Mutex<std::collections::VecDeque<(std::path::PathBuf, u64)>>,
+> = std::sync::LazyLock::new(Default::default);
+
+/// Directory holding outputs too large to return inline, one per server process
+pub fn output_store_dir() -> Result<std::path::PathBuf> {
+    match &*OUTPUT_STORE {
+        Ok(dir) => Ok(dir.path().to_path_buf()),
+        Err(e) => Err(anyhow::anyhow!(
+            "Error: failed to create the output store: {}",
+            e
+        )),
+    }
+}
+
+/// Delete the output store; statics are never dropped, so the server calls this on exit
+pub fn remove_output_store() {
+    if let Ok(dir) = &*OUTPUT_STORE {
+        let _ = std::fs::remove_dir_all(dir.path());
+    }
+}
+
+/// Write an output to the store, returning its `file://` URI
+///
+/// Once the store holds more than [`MAX_STORED_OUTPUTS`] outputs or
+/// [`MAX_STORED_OUTPUT_BYTES`] bytes, the oldest are deleted.
+pub fn store_output(text: &str) -> Result<url::Url> {
+    use std::io::Write;
+    static NEXT_OUTPUT: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+
+    let dir = output_store_dir()?;
+    let id = NEXT_OUTPUT.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+    let path = dir.join(format!("{}.txt", id));
+    std::fs::File::options()
+        .write(true)
+        .create_new(true)
+        .open(&path)?
+        .write_all(text.as_bytes())?;
+
+    let mut stored = STORED_OUTPUTS.lock().unwrap();
+    stored.push_back((path.clone(), text.len() as u64));
+    let mut total: u64 = stored.iter().map(|(_, size)| size).sum();
+    while stored.len() > 1
+        && (stored.len() > MAX_STORED_OUTPUTS || total > MAX_STORED_OUTPUT_BYTES)
+        && let Some((oldest, size)) = stored.pop_front()
+    {
+        let _ = std::fs::remove_file(oldest);
+        total -= size;
+    }
+    drop(stored);
+
+    url::Url::from_file_path(&path)
+        .map_err(|_| anyhow::anyhow!("Error: cannot build a URI for {}", path.display()))
+}
+
+/// Lines shown inline when an output is returned as a resource link
+const OUTPUT_PREVIEW_LINES: usize = 20;
+
+/// Replace text outputs above `limit` bytes with a short summary and a resource link
+pub fn offload_output(response: CallToolResponse, limit: usize) -> CallToolResponse {
+    let mut content = Vec::with_capacity(response.content.len());
+    for item in response.content {
+        let text = match &item {
+            ToolResponseContent::Text { text } if text.len() > limit => text,
+            _ => {
+                content.push(item);
+                continue;
+            }
+        };
+        let Ok(uri) = store_output(text) else {
+            content.push(item);
+            continue;
+        };
+
+        let preview: Vec<&str> = text.lines().take(OUTPUT_PREVIEW_LINES).collect();
+        content.push(ToolResponseContent::Text {
+            text: format!(
+                "Output is {} bytes in {} lines, stored at {}. Read it with the read-output tool. First lines:\n{}",
+                text.len(),
+                text.lines().count(),
+                uri,
+                preview.join("\n")
+            ),
+        });
+        content.push(ToolResponseContent::Resource {
+            resource: ResourceContents {
+                uri,
+                mime_type: Some("text/plain".to_string()),
+            },
+        });
+    }
+    CallToolResponse {
+        content,
+        ..response
+    }
+}
+
+/// [`offload_output`] with the configured limit
+fn offload_large_output(response: CallToolResponse) -> CallToolResponse {
+    match server_config().output_limit() {
+        Some(limit) => offload_output(response, limit),
+        None => response,
+    }
+}
+
+/// Read part of a stored output; only files in [`output_store_dir`] can be read
+pub fn read_stored_output(uri: &str, offset: usize, length: usize) -> Result<Value> {
+    let path = url::Url::parse(uri)
+        .ok()
+        .and_then(|uri| uri.to_file_path().ok())
+        .ok_or_else(|| anyhow::anyhow!("Error: not a file URI: {}", uri))?;
+    let store = output_store_dir()?.canonicalize()?;
+    let path = path
+        .canonicalize()
+        .ok()
+        .filter(|path| path.starts_with(&store))
+        .ok_or_else(|| {
+            anyhow::anyhow!(
+                "Error: {} is not a stored output (or was deleted to make room for newer ones)",
+                uri
+            )
+        })?;
+
+    let content = std::fs::read(&path)?;
+    let start = offset.min(content.len());
+    let end = start.saturating_add(length).min(content.len());
+    Ok(serde_json::json!({
+        "uri": uri,
+        "offset": start,
+        "totalBytes": content.len(),
+        "hasMore": end < content.len(),
+        "text": String::from_utf8_lossy(&content[start..end]),
+    }))
+}
+
+/// Page through an output that was returned as a resource link
+pub fn run_read_output(params: ReadOutputParams) -> CallToolResponse {
+    let result = (|| {
+        let uri = params
+            .uri
+            .ok_or_else(|| anyhow::anyhow!("Error: uri is required"))?;
+        let length = params
+            .length
+            .or(server_config().output_limit())
+            .unwrap_or(DEFAULT_MAX_OUTPUT_BYTES);
+        read_stored_output(&uri, params.offset.unwrap_or(0), length)
+    })();
+
+    json_response(result)
+}
+
+/// Look up a dotted path such as `create.changeId` or `info.json.bookmarks.0.name`
+fn lookup_reference<'a>(results: &'a Value, reference: &str) -> Option<&'a Value> {
+    reference
+        .split('.')
+        .try_fold(results, |value, key| match value {
+            Value::Object(map) => map.get(key),
+            Value::Array(items) => key.parse::<usize>().ok().and_then(|index| items.get(index)),
+            _ => None,
+        })
+}
+
+/// Substitute `{{step.field}}` references in a step's arguments with earlier results
+///
+/// A string that is exactly one reference takes the referenced value as is, so
+/// numbers, booleans and arrays keep their type; references inside longer strings
+/// are interpolated as text.
+pub fn resolve_step_references(value: &Value, results: &Value) -> Result<Value> {
+    static REFERENCE: std::sync::LazyLock<regex::Regex> =
+        std::sync::LazyLock::new(|| regex::Regex::new(r"\{\{\s*([A-Za-z0-9_.-]+)\s*\}\}").unwrap());
+
+    match value {
+        Value::String(text) => {
+            let resolve = |reference: &str| {
+                lookup_reference(results, reference).ok_or_else(|| {
+                    anyhow::anyhow!("Error: unknown reference {{{{{}}}}}", reference)
+                })
+            };
+            if let Some(captures) = REFERENCE.captures(text)
+                && captures[0].len() == text.len()
+            {
+                return resolve(&captures[1]).cloned();
+            }
+
+            let mut resolved = String::new();
+            let mut last = 0;
+            for captures in REFERENCE.captures_iter(text) {
+                let whole = captures.get(0).unwrap();
+                resolved.push_str(&text[last..whole.start()]);
+                match resolve(&captures[1])? {
+                    Value::String(inner) => resolved.push_str(inner),
+                    other => resolved.push_str(&other.to_string()),
+                }
+                last = whole.end();
+            }
+            resolved.push_str(&text[last..]);
+            Ok(Value::String(resolved))
+        }
+        Value::Array(items) => items
+            .iter()
+            .map(|item| resolve_step_references(item, results))
+            .collect::<Result<Vec<_>>>()
+            .map(Value::Array),
+        Value::Object(map) => map
+            .iter()
+            .map(|(key, item)| Ok((key.clone(), resolve_step_references(item, results)?)))
+            .collect::<Result<serde_json::Map<_, _>>>()
+            .map(Value::Object),
+        other => Ok(other.clone()),
+    }
+}
+
+/// What later pipeline steps can reference from a step's output
+///
+/// `output` is the text, `json` the parsed output when it is JSON, `changeId` and
+/// `commitId` identify the working-copy commit after the step, and each capture
+/// pattern adds a field of its own.
+fn step_result(
+    output: &str,
+    capture: &std::collections::HashMap<String, String>,
+    repo_path: Option<String>,
+    cwd: Option<String>,
+) -> Result<Value> {
+    let mut result = serde_json::json!({ "output": output });
+    if let Ok(json) = serde_json::from_str::<Value>(output) {
+        result["json"] = json;
+    }
+
+    let mut args = vec![
+        "log".to_string(),
+        "-r".to_string(),
+        "@".to_string(),
+        "--no-graph".to_string(),
+        "-T".to_string(),
+        r#"change_id ++ " " ++ commit_id"#.to_string(),
+    ];
+    add_repo_args(&mut args, repo_path);
+    if let Ok(ids) = run_jj_command_sync(args, cwd)
+        && let Some((change_id, commit_id)) = ids.split_once(' ')
+    {
+        result["changeId"] = serde_json::json!(change_id);
+        result["commitId"] = serde_json::json!(commit_id);
+    }
+
+    for (name, pattern) in capture {
+        let regex = regex::Regex::new(pattern)
+            .map_err(|e| anyhow::anyhow!("Error: invalid capture pattern {}: {}", name, e))?;
+        let captured = regex
+            .captures(output)
+            .and_then(|captures| captures.get(1).or_else(|| captures.get(0)))
+            .ok_or_else(|| anyhow::anyhow!("Error: capture {} matched nothing", name))?;
+        result[name.as_str()] = serde_json::json!(captured.as_str());
+    }
+    Ok(result)
+}
+
+/// Run tool calls in order, letting each step reference the results of earlier ones
+pub fn run_pipeline(params: PipelineParams) -> CallToolResponse {
+    let result = (|| {
+        let steps = params
+            .steps
+            .filter(|steps| !steps.is_empty())
+            .ok_or_else(|| anyhow::anyhow!("Error: at least one step is required"))?;
+        let continue_on_error = params.continue_on_error.unwrap_or(false);
+
+        // Every step is checked before any runs, so a rejected one leaves nothing half done
+        let installed = installed_jj_version().ok();
+        for (index, step) in steps.iter().enumerate() {
+            let Some(tool) = &step.tool else { continue };
+            let name = server_config().canonical_name(tool);
+            if let Some(reason) = server_config().unavailable_tool_reason(name, installed) {
+                let id = step.id.clone().unwrap_or_else(|| index.to_string());
+                return Err(anyhow::anyhow!("Error: step {} can't run: {}", id, reason));
+            }
+        }
+
+        let mut results = serde_json::Map::new();
+        let mut report = Vec::new();
+        let mut completed = true;
+        for (index, step) in steps.into_iter().enumerate() {
+            let id = step.id.clone().unwrap_or_else(|| index.to_string());
+            let tool = step
+                .tool
+                .clone()
+                .ok_or_else(|| anyhow::anyhow!("Error: step {} has no tool", id))?;
+            if tool == "pipeline" {
+                return Err(anyhow::anyhow!("Error: pipelines can't be nested"));
+            }
+
+            // Steps are checked against the tool's own schema, like direct calls
+            let schema = all_tools()
+                .into_iter()
+                .find(|candidate| candidate.name == server_config().canonical_name(&tool))
+                .map(|candidate| candidate.input_schema)
+                .unwrap_or_else(|| serde_json::json!({}));
+            let outcome = (|| {
+                let mut arguments = resolve_step_references(
+                    &step
+                        .arguments
+                        .clone()
+                        .unwrap_or_else(|| serde_json::json!({})),
+                    &Value::Object(results.clone()),
+                )?;
+                let map = arguments
+                    .as_object_mut()
+                    .ok_or_else(|| anyhow::anyhow!("Error: arguments must be an object"))?;
+                for (key, value) in [("repoPath", &params.repo_path), ("cwd", &params.cwd)] {
+                    if let Some(value) = value
+                        && !map.contains_key(key)
+                        && schema["properties"].get(key).is_some()
+                    {
+                        map.insert(key.to_string(), serde_json::json!(value));
+                    }
+                }
+                // Steps are separate calls, so they inherit the pipeline's own options
+                let options = current_call_options();
+                for (key, value) in [
+                    ("fsmonitor", options.fsmonitor.map(Value::Bool)),
+                    (
+                        "explain",
+                        Some(Value::Bool(true)).filter(|_| options.explain),
+                    ),
+                    (
+                        "confirm",
+                        Some(Value::Bool(true)).filter(|_| options.confirm),
+                    ),
+                    ("base64", Some(Value::Bool(true)).filter(|_| options.base64)),
+                    ("timestamp", options.timestamp.clone().map(Value::String)),
+                    (
+                        "timeout",
+                        options.timeout.map(|timeout| serde_json::json!(timeout)),
+                    ),
+                    (
+                        "env",
+                        Some(serde_json::json!(options.env)).filter(|_| !options.env.is_empty()),
+                    ),
+                ] {
+                    if let Some(value) = value {
+                        map.entry(key).or_insert(value);
+                    }
+                }
+
+                let response =
+                    JjTool::new(&tool, "", schema.clone()).call(Some(arguments.clone()))?;
+                let output = response_text(&response);
+                if response.is_error == Some(true) {
+                    return Err(anyhow::anyhow!("{}", output));
+                }
+                let string = |key: &str| arguments[key].as_str().map(str::to_string);
+                let result = step_result(
+                    &output,
+                    &step.capture.clone().unwrap_or_default(),
+                    string("repoPath"),
+                    string("cwd"),
+                )?;
+                Ok((arguments, result))
+            })();
+
+            match outcome {
+                Ok((arguments, result)) => {
+                    report.push(serde_json::json!({
+                        "id": id,
+                        "tool": tool,
+                        "arguments": arguments,
+                        "ok": true,
+                        "result": result,
+                    }));
+                    results.insert(id, result);
+                }
+                Err(e) => {
+                    report.push(serde_json::json!({
+                        "id": id,
+                        "tool": tool,
+                        "ok": false,
+                        "error": e.to_string(),
+                    }));
+                    completed = false;
+                    if !continue_on_error {
+                        break;
+                    }
+                }
+            }
+        }
+
+        Ok(serde_json::json!({
+            "completed": completed,
+            "steps": report,
+        }))
+    })();
+
+    json_response(result)
+}
+
+/// Signing backends jj understands
+pub const SIGNING_BACKENDS: &[&str] = &["gpg", "gpgsm", "ssh", "none"];
+
+/// `--config signing.*` overrides for a call, falling back to the server's configuration
+///
+/// `sign` picks `signing.behavior`: `own` signs the commits the call rewrites or
+/// creates, `drop` leaves them unsigned. Without it jj's own configuration decides,
+/// unless the server has a default behavior.
+pub fn signing_config_args(
+    sign: Option<bool>,
+    backend: Option<&str>,
+    key: Option<&str>,
+) -> Result<Vec<String>> {
+    let config = server_config();
+    let behavior = match sign {
+        Some(true) => Some("own"),
+        Some(false) => Some("drop"),
+        None => config.signing_behavior.as_deref(),
+    };
+    let backend = backend.or(config.signing_backend.as_deref());
+    let key = key.or(config.signing_key.as_deref());
+
+    if let Some(backend) = backend
+        && !SIGNING_BACKENDS.contains(&backend)
+    {
+        return Err(anyhow::anyhow!(
+            "Error: unknown signing backend {} (expected {})",
+            backend,
+            SIGNING_BACKENDS.join(", ")
+        ));
+    }
+
+    let mut args = Vec::new();
+    for (name, value) in [
+        ("signing.behavior", behavior),
+        ("signing.backend", backend),
+        ("signing.key", key),
+    ] {
+        if let Some(value) = value {
+            args.push("--config".to_string());
+            // A JSON string is also a valid TOML basic string
+            args.push(format!("{}={}", name, serde_json::json!(value)));
+        }
+    }
+    Ok(args)
+}
+
+/// Check an author name or email can be written into a commit signature
+fn check_author_part(kind: &str, value: &str) -> Result<()> {
+    if value.trim().is_empty() || value.contains(['<', '>', '\n']) {
+        return Err(anyhow::anyhow!(
+            "Error: author {} must be non-empty and contain no '<', '>' or newlines: {:?}",
+            kind,
+            value
+        ));
+    }
+    Ok(())
+}
+
+/// `--config user.*` overrides, so commits a call creates are authored by someone else
+pub fn author_config_args(name: Option<&str>, email: Option<&str>) -> Result<Vec<String>> {
+    let mut args = Vec::new();
+    for (kind, key, value) in [("name", "user.name", name), ("email", "user.email", email)] {
+        if let Some(value) = value {
+            check_author_part(kind, value)?;
+            args.push("--config".to_string());
+            args.push(format!("{}={}", key, serde_json::json!(value)));
+        }
+    }
+    Ok(args)
+}
+
+/// `--author` for commands that set an existing commit's author
+///
+/// A missing name or email is taken from jj's `user.*` configuration.
+pub fn author_flag_args(
+    name: Option<&str>,
+    email: Option<&str>,
+    repo_path: Option<String>,
+    cwd: Option<String>,
+) -> Result<Vec<String>> {
+    if name.is_none() && email.is_none() {
+        return Ok(Vec::new());
+    }
+    let configured = |key: &str| {
+        let mut args = vec!["config".to_string(), "get".to_string(), key.to_string()];
+        add_repo_args(&mut args, repo_path.clone());
+        run_jj_command_sync(args, cwd.clone())
+    };
+    let name = match name {
+        Some(name) => name.to_string(),
+        None => configured("user.name")?,
+    };
+    let email = match email {
+        Some(email) => email.to_string(),
+        None => configured("user.email")?,
+    };
+    check_author_part("name", &name)?;
+    check_author_part("email", &email)?;
+    Ok(vec![
+        "--author".to_string(),
+        format!("{} <{}>", name, email),
+    ])
+}
+
+/// Set a revision's description, optionally signing the rewritten commit
+pub fn run_jj_describe(params: DescribeParams) -> CallToolResponse {
+    let result = (|| {
+        let message =
+            CommitMessage::from_params(params.message, params.message_file, params.cwd.as_deref())?
+                .ok_or_else(|| anyhow::anyhow!("Error: message or messageFile is required"))?;
+        let mut args = vec![
+            "describe".to_string(),
+            params.revision.unwrap_or_else(|| "@".to_string()),
+        ];
+        args.extend(message.args());
+        args.extend(signing_config_args(
+            params.sign,
+            params.signing_backend.as_deref(),
+            params.signing_key.as_deref(),
+        )?);
+        args.extend(author_flag_args(
+            params.author_name.as_deref(),
+            params.author_email.as_deref(),
+            params.repo_path.clone(),
+            params.cwd.clone(),
+        )?);
+        add_repo_args(&mut args, params.repo_path);
+        message.run(args, params.cwd)
+    })();
+
+    match result {
+        Ok(output) => CallToolResponse {
+            content: vec![ToolResponseContent::Text { text: output }],
+            is_error: Some(false),
+            meta: None,
+        },
+        Err(e) => CallToolResponse {
+            content: vec![ToolResponseContent::Text {
+                text: e.to_string(),
+            }],
+            is_error: Some(true),
+            meta: None,
+        },
+    }
+}
+
+/// Sign existing commits with the configured or given key
+pub fn run_jj_sign(params: SignParams) -> CallToolResponse {
+    let result = (|| {
+        let revisions = params.revisions.unwrap_or_else(|| vec!["@".to_string()]);
+        let mut args = vec![
+            "sign".to_string(),
+            "-r".to_string(),
+            union_revset(&revisions),
+        ];
+        if let Some(key) = &params.signing_key {
+            args.push("--key".to_string());
+            args.push(key.clone());
+        }
+        args.extend(signing_config_args(
+            None,
+            params.signing_backend.as_deref(),
+            None,
+        )?);
+        add_repo_args(&mut args, params.repo_path);
+        run_jj_command_sync(args, params.cwd)
+    })();
+
+    match result {
+        Ok(output) => CallToolResponse {
             content: vec![ToolResponseContent::Text { text: output }],
             is_error: Some(false),
             meta: None,
-        },
-        Err(e) => CallToolResponse {
-            content: vec![ToolResponseContent::Text {
-                text: e.to_string(),
-            }],
-            is_error: Some(true),
+        },
+        Err(e) => CallToolResponse {
+            content: vec![ToolResponseContent::Text {
+                text: e.to_string(),
+            }],
+            is_error: Some(true),
+            meta: None,
+        },
+    }
+}
+
+/// Directories discovery never descends into
+const DISCOVERY_SKIPPED_DIRS: &[&str] = &["node_modules", "target"];
+
+/// Find jj workspace roots under `dir`, at most `max_depth` directories down
+///
+/// Hidden and build directories are skipped, and the search doesn't descend into
+/// a workspace once one is found.
+pub fn find_workspaces(dir: &std::path::Path, max_depth: usize) -> Vec<std::path::PathBuf> {
+    if dir.join(".jj").is_dir() {
+        return vec![dir.to_path_buf()];
+    }
+    if max_depth == 0 {
+        return Vec::new();
+    }
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut subdirs: Vec<std::path::PathBuf> = entries
+        .flatten()
+        .filter(|entry| entry.file_type().is_ok_and(|kind| kind.is_dir()))
+        .filter(|entry| {
+            let name = entry.file_name();
+            let name = name.to_string_lossy();
+            !name.starts_with('.') && !DISCOVERY_SKIPPED_DIRS.contains(&name.as_ref())
+        })
+        .map(|entry| entry.path())
+        .collect();
+    subdirs.sort();
+    subdirs
+        .iter()
+        .flat_map(|subdir| find_workspaces(subdir, max_depth - 1))
+        .collect()
+}
+
+/// Bookmarks on the closest ancestor of `@` that has any, without snapshotting
+fn current_bookmarks(root: &std::path::Path) -> Result<Vec<String>> {
+    let args = vec![
+        "log".to_string(),
+        "--no-graph".to_string(),
+        "--ignore-working-copy".to_string(),
+        "-r".to_string(),
+        "heads(::@ & bookmarks())".to_string(),
+        "-T".to_string(),
+        r#"local_bookmarks.map(|b| b.name()).join("\n") ++ "\n""#.to_string(),
+    ];
+    let output = run_jj_command_sync(args, Some(root.to_string_lossy().to_string()))?;
+    Ok(output
+        .lines()
+        .filter(|line| !line.is_empty())
+        .map(str::to_string)
+        .collect())
+}
+
+/// Scan a directory tree for jj workspaces
+pub fn run_discover_repos(params: DiscoverReposParams) -> CallToolResponse {
+    let result = (|| {
+        let current = std::env::current_dir()?;
+        let dir = match params.path {
+            Some(path) => current.join(path),
+            None => current,
+        };
+
+        let workspaces: Vec<Value> = find_workspaces(&dir, params.max_depth.unwrap_or(3))
+            .into_iter()
+            .map(|root| {
+                let backing = detect_git_backing(&root);
+                let bookmarks = current_bookmarks(&root);
+                serde_json::json!({
+                    "root": root.to_string_lossy(),
+                    "colocated": backing.colocated,
+                    "gitDir": backing.git_dir,
+                    "bookmarks": bookmarks.as_ref().ok(),
+                    "error": bookmarks.as_ref().err().map(|e| e.to_string()),
+                })
+            })
+            .collect();
+
+        let registry = std::env::join_paths(
+            workspaces
+                .iter()
+                .filter_map(|workspace| workspace["root"].as_str()),
+        )
+        .map(|joined| joined.to_string_lossy().to_string())
+        .unwrap_or_default();
+        Ok(serde_json::json!({
+            "path": dir.to_string_lossy(),
+            "count": workspaces.len(),
+            "workspaces": workspaces,
+            "registry": registry,
+        }))
+    })();
+
+    json_response(result)
+}
+
+/// Parse the `name url` lines of `jj git remote list`
+pub fn parse_git_remotes(output: &str) -> Vec<GitRemote> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let (name, url) = line.trim().split_once(char::is_whitespace)?;
+            Some(GitRemote {
+                name: name.to_string(),
+                url: url.trim().to_string(),
+            })
+        })
+        .collect()
+}
+
+/// Parse `git ls-remote --symref <url> HEAD` into the default branch and its commit
+pub fn parse_ls_remote_head(output: &str) -> (Option<String>, Option<String>) {
+    let mut branch = None;
+    let mut commit = None;
+    for line in output.lines() {
+        match line.split('\t').collect::<Vec<_>>()[..] {
+            [target, "HEAD"] if target.starts_with("ref: ") => {
+                let target = target.trim_start_matches("ref: ");
+                branch = Some(
+                    target
+                        .strip_prefix("refs/heads/")
+                        .unwrap_or(target)
+                        .to_string(),
+                );
+            }
+            [id, "HEAD"] => commit = Some(id.to_string()),
+            _ => {}
+        }
+    }
+    (branch, commit)
+}
+
+/// Ask a remote for its default branch, without prompting for credentials
+fn check_remote(url: &str) -> Value {
+    let output = std::process::Command::new("git")
+        .args(["ls-remote", "--symref", url, "HEAD"])
+        .env("GIT_TERMINAL_PROMPT", "0")
+        .stdin(std::process::Stdio::null())
+        .output();
+    match output {
+        Ok(output) if output.status.success() => {
+            let (branch, commit) = parse_ls_remote_head(&String::from_utf8_lossy(&output.stdout));
+            serde_json::json!({
+                "reachable": true,
+                "defaultBranch": branch,
+                "head": commit,
+            })
+        }
+        Ok(output) => serde_json::json!({
+            "reachable": false,
+            "error": String::from_utf8_lossy(&output.stderr).trim(),
+        }),
+        Err(e) => serde_json::json!({
+            "reachable": false,
+            "error": format!("failed to execute git: {}", e),
+        }),
+    }
+}
+
+/// List git remotes with their URLs, optionally checking each one answers
+pub fn run_jj_remote_list(params: RemoteListParams) -> CallToolResponse {
+    let result = (|| {
+        let mut args = vec!["git".to_string(), "remote".to_string(), "list".to_string()];
+        add_repo_args(&mut args, params.repo_path);
+        let remotes = parse_git_remotes(&run_jj_command_sync(args, params.cwd)?);
+
+        let check = params.check.unwrap_or(false);
+        let results: Vec<Value> = std::thread::scope(|scope| {
+            let handles: Vec<_> = remotes
+                .iter()
+                .map(|remote| {
+                    scope.spawn(move || {
+                        let mut result = serde_json::json!({
+                            "name": remote.name,
+                            "url": remote.url,
+                        });
+                        if check {
+                            result["check"] = check_remote(&remote.url);
+                        }
+                        result
+                    })
+                })
+                .collect();
+            handles
+                .into_iter()
+                .map(|handle| handle.join().unwrap_or(Value::Null))
+                .collect()
+        });
+
+        Ok(serde_json::json!({
+            "count": results.len(),
+            "remotes": results,
+        }))
+    })();
+
+    json_response(result)
+}
+
+/// Bookmarks created by the stash tool start with this
+pub const STASH_BOOKMARK_PREFIX: &str = "stash/";
+
+/// The bookmark a stash is kept under
+pub fn stash_bookmark(name: &str) -> String {
+    if name.starts_with(STASH_BOOKMARK_PREFIX) {
+        name.to_string()
+    } else {
+        format!("{}{}", STASH_BOOKMARK_PREFIX, name)
+    }
+}
+
+/// Change and commit ID of each commit in a revset, newest first
+fn change_and_commit_ids(
+    revset: &str,
+    repo_path: Option<String>,
+    cwd: Option<String>,
+) -> Result<Vec<(String, String)>> {
+    Ok(query_log_records(
+        revset,
+        r#"change_id ++ "\t" ++ commit_id ++ "\n""#,
+        repo_path,
+        cwd,
+    )?
+    .into_iter()
+    .filter_map(|fields| match &fields[..] {
+        [change_id, commit_id] => Some((change_id.clone(), commit_id.clone())),
+        _ => None,
+    })
+    .collect())
+}
+
+/// Move the working-copy changes onto a new bookmarked child of `base`, leaving `@` clean
+///
+/// The original working-copy commit is edited again if it survives (it has a
+/// description); otherwise a new working copy is started on its parents.
+pub fn run_jj_stash(params: StashParams) -> CallToolResponse {
+    let result = (|| {
+        let repo_path = params.repo_path;
+        let cwd = params.cwd;
+        let run = |args: Vec<&str>| {
+            let mut args: Vec<String> = args.into_iter().map(str::to_string).collect();
+            add_repo_args(&mut args, repo_path.clone());
+            run_jj_command_sync(args, cwd.clone())
+        };
+
+        let (original, _) = change_and_commit_ids("@", repo_path.clone(), cwd.clone())?
+            .into_iter()
+            .next()
+            .ok_or_else(|| anyhow::anyhow!("Error: no working-copy commit"))?;
+        if !change_and_commit_ids("@ & empty()", repo_path.clone(), cwd.clone())?.is_empty() {
+            return Err(anyhow::anyhow!("Error: no working-copy changes to stash"));
+        }
+        let parents: Vec<String> = change_and_commit_ids("@-", repo_path.clone(), cwd.clone())?
+            .into_iter()
+            .map(|(_, commit_id)| commit_id)
+            .collect();
+
+        let base = params.base.unwrap_or_else(|| "trunk()".to_string());
+        let message = format!(
+            "stash: {}",
+            params
+                .message
+                .unwrap_or_else(|| format!("changes from {}", original))
+        );
+        run(vec!["new", &base, "-m", &message])?;
+        let (stash, _) = change_and_commit_ids("@", repo_path.clone(), cwd.clone())?
+            .into_iter()
+            .next()
+            .ok_or_else(|| anyhow::anyhow!("Error: no working-copy commit"))?;
+        // The stash keeps its own message rather than opening an editor to combine them
+        let mut args = squash_args(&SquashParams {
+            from: Some(original.clone()),
+            into: Some(stash.clone()),
+            use_destination_message: Some(true),
+            ..Default::default()
+        })?;
+        add_repo_args(&mut args, repo_path.clone());
+        run_jj_command_sync(args, cwd.clone())?;
+        let bookmark = stash_bookmark(params.name.as_deref().unwrap_or(&stash));
+        run(vec!["bookmark", "create", &bookmark, "-r", &stash])?;
+
+        let original_kept = run(vec![
+            "log",
+            "--no-graph",
+            "-r",
+            &original,
+            "-T",
+            "change_id",
+        ])
+        .is_ok();
+        if original_kept {
+            run(vec!["edit", &original])?;
+        } else {
+            let mut args = vec!["new"];
+            args.extend(parents.iter().map(String::as_str));
+            run(args)?;
+        }
+        let (working_copy, _) = change_and_commit_ids("@", repo_path.clone(), cwd.clone())?
+            .into_iter()
+            .next()
+            .unwrap_or_default();
+
+        Ok(serde_json::json!({
+            "bookmark": bookmark,
+            "changeId": stash,
+            "base": base,
+            "workingCopy": working_copy,
+        }))
+    })();
+
+    json_response(result)
+}
+
+/// Apply a stash to the working copy and drop it
+///
+/// Without a name, the most recently stashed changes are applied.
+pub fn run_jj_unstash(params: UnstashParams) -> CallToolResponse {
+    let result = (|| {
+        let repo_path = params.repo_path;
+        let cwd = params.cwd;
+        let revset = match &params.name {
+            Some(name) => format!(
+                "bookmarks(exact:{})",
+                quote_string_literal(&stash_bookmark(name))
+            ),
+            None => format!(
+                "latest(bookmarks(glob:{}))",
+                quote_string_literal(&format!("{}*", STASH_BOOKMARK_PREFIX))
+            ),
+        };
+        let records = query_log_records(
+            &revset,
+            r#"change_id ++ "\t" ++ local_bookmarks.map(|b| b.name()).join(",") ++ "\n""#,
+            repo_path.clone(),
+            cwd.clone(),
+        )?;
+        let (stash, bookmarks) = match &records[..] {
+            [fields] if fields.len() == 2 => (fields[0].clone(), fields[1].clone()),
+            [] => return Err(anyhow::anyhow!("Error: no stash found")),
+            _ => return Err(anyhow::anyhow!("Error: the stash bookmark is ambiguous")),
+        };
+
+        // The working copy keeps its description; the stash's is dropped with the stash
+        let mut args = squash_args(&SquashParams {
+            from: Some(stash.clone()),
+            into: Some("@".to_string()),
+            use_destination_message: Some(true),
+            ..Default::default()
+        })?;
+        add_repo_args(&mut args, repo_path.clone());
+        let output = run_jj_command_sync(args, cwd.clone())?;
+
+        // The stash commit is empty now; abandoning it also deletes its bookmark
+        let mut args = vec!["abandon".to_string(), stash.clone()];
+        add_repo_args(&mut args, repo_path.clone());
+        run_jj_command_sync(args, cwd.clone())?;
+
+        let conflicted = !change_and_commit_ids("@ & conflicts()", repo_path, cwd)?.is_empty();
+        Ok(serde_json::json!({
+            "bookmarks": bookmarks
+                .split(',')
+                .filter(|bookmark| bookmark.starts_with(STASH_BOOKMARK_PREFIX))
+                .collect::<Vec<_>>(),
+            "changeId": stash,
+            "conflicted": conflicted,
+            "output": output,
+        }))
+    })();
+
+    json_response(result)
+}
+
+/// The revset `jj log` shows by default
+pub const DEFAULT_LOG_REVSET: &str =
+    "present(@) | ancestors(immutable_heads().., 2) | present(trunk())";
+
+/// Load the commits of a revset as graph nodes, newest first
+pub fn graph_nodes(
+    revset: &str,
+    limit: u32,
+    repo_path: Option<String>,
+    cwd: Option<String>,
+) -> Result<Vec<GraphNode>> {
+    let records = query_log_records(
+        &format!("latest({}, {})", revset, limit),
+        &log_record_template(&[
+            "commit_id",
+            "change_id.short()",
+            "parents.map(|c| c.commit_id()).join(\",\")",
+            "local_bookmarks.map(|b| b.name()).join(\",\")",
+            "if(current_working_copy, \"true\", \"false\")",
+            "if(immutable, \"true\", \"false\")",
+            "description.first_line()",
+        ]),
+        repo_path,
+        cwd,
+    )?;
+
+    let split = |field: &str| -> Vec<String> {
+        field
+            .split(',')
+            .filter(|part| !part.is_empty())
+            .map(str::to_string)
+            .collect()
+    };
+    Ok(records
+        .iter()
+        .filter(|record| record.len() >= 7)
+        .map(|record| GraphNode {
+            commit_id: record[0].clone(),
+            change_id: record[1].clone(),
+            parents: split(&record[2]),
+            bookmarks: split(&record[3]),
+            working_copy: record[4] == "true",
+            immutable: record[5] == "true",
+            description: record[6..].join("\t"),
+        })
+        .collect())
+}
+
+/// Two-line label for a commit: change ID and bookmarks, then the description
+fn graph_node_label(node: &GraphNode) -> String {
+    let mut label = node.change_id.clone();
+    if !node.bookmarks.is_empty() {
+        label.push_str(&format!(" ({})", node.bookmarks.join(", ")));
+    }
+    label.push('\n');
+    label.push_str(if node.description.is_empty() {
+        "(no description set)"
+    } else {
+        &node.description
+    });
+    label
+}
+
+/// Render commits as a Graphviz digraph with an edge from each commit to its parents
+///
+/// Parents outside `nodes` are left out, so the graph only covers the exported revset.
+pub fn render_dot(nodes: &[GraphNode]) -> String {
+    let known: std::collections::HashSet<&str> =
+        nodes.iter().map(|node| node.commit_id.as_str()).collect();
+    let mut dot =
+        String::from("digraph commits {\n    node [shape=box, fontname=\"monospace\"];\n");
+
+    for node in nodes {
+        let mut attributes = vec![format!(
+            "label={}",
+            quote_string_literal(&graph_node_label(node)).replace('\n', "\\n")
+        )];
+        if node.working_copy {
+            attributes.push("penwidth=2".to_string());
+        }
+        if node.immutable {
+            attributes.push("style=filled, fillcolor=lightgray".to_string());
+        }
+        dot.push_str(&format!(
+            "    {} [{}];\n",
+            quote_string_literal(&node.commit_id),
+            attributes.join(", ")
+        ));
+    }
+
+    for node in nodes {
+        for parent in node.parents.iter().filter(|p| known.contains(p.as_str())) {
+            dot.push_str(&format!(
+                "    {} -> {};\n",
+                quote_string_literal(&node.commit_id),
+                quote_string_literal(parent)
+            ));
+        }
+    }
+
+    dot.push_str("}\n");
+    dot
+}
+
+/// Quote a label for a mermaid node, escaping characters mermaid treats specially
+fn mermaid_label(label: &str) -> String {
+    let escaped = label
+        .replace('#', "#35;")
+        .replace('"', "#quot;")
+        .replace('<', "#lt;")
+        .replace('>', "#gt;")
+        .replace('\n', "<br/>");
+    format!("\"{}\"", escaped)
+}
+
+/// Render commits as a mermaid flowchart with an edge from each commit to its parents
+///
+/// Mermaid's `gitGraph` can't draw an arbitrary DAG, so a flowchart is used instead.
+/// Like [`render_dot`], parents outside `nodes` are left out.
+pub fn render_mermaid(nodes: &[GraphNode]) -> String {
+    let known: std::collections::HashSet<&str> =
+        nodes.iter().map(|node| node.commit_id.as_str()).collect();
+    let id = |commit_id: &str| format!("c{}", commit_id);
+    let mut mermaid = String::from("flowchart TB\n");
+
+    for node in nodes {
+        mermaid.push_str(&format!(
+            "    {}[{}]\n",
+            id(&node.commit_id),
+            mermaid_label(&graph_node_label(node))
+        ));
+    }
+    for node in nodes {
+        for parent in node.parents.iter().filter(|p| known.contains(p.as_str())) {
+            mermaid.push_str(&format!("    {} --> {}\n", id(&node.commit_id), id(parent)));
+        }
+    }
+
+    let working_copy: Vec<String> = nodes
+        .iter()
+        .filter(|node| node.working_copy)
+        .map(|node| id(&node.commit_id))
+        .collect();
+    let immutable: Vec<String> = nodes
+        .iter()
+        .filter(|node| node.immutable)
+        .map(|node| id(&node.commit_id))
+        .collect();
+    if !working_copy.is_empty() {
+        mermaid.push_str("    classDef workingCopy stroke-width:3px\n");
+        mermaid.push_str(&format!(
+            "    class {} workingCopy\n",
+            working_copy.join(",")
+        ));
+    }
+    if !immutable.is_empty() {
+        mermaid.push_str("    classDef immutable fill:#ddd\n");
+        mermaid.push_str(&format!("    class {} immutable\n", immutable.join(",")));
+    }
+    mermaid
+}
+
+/// Render a revset's commit graph as DOT, or as a mermaid block clients can show inline
+fn graph_response(
+    format: &str,
+    revisions: Option<String>,
+    limit: Option<u32>,
+    repo_path: Option<String>,
+    cwd: Option<String>,
+) -> CallToolResponse {
+    let result = (|| {
+        let revset = revisions.unwrap_or_else(|| DEFAULT_LOG_REVSET.to_string());
+        let render: fn(&[GraphNode]) -> String = match format {
+            "dot" => render_dot,
+            "mermaid" => |nodes| format!("```mermaid\n{}```\n", render_mermaid(nodes)),
+            other => {
+                return Err(anyhow::anyhow!(
+                    "Error: unknown graph format '{}' (expected dot or mermaid)",
+                    other
+                ));
+            }
+        };
+        let nodes = graph_nodes(&revset, limit.unwrap_or(100), repo_path, cwd)?;
+        Ok(render(&nodes))
+    })();
+
+    match result {
+        Ok(text) => CallToolResponse {
+            content: vec![ToolResponseContent::Text { text }],
+            is_error: Some(false),
+            meta: None,
+        },
+        Err(e) => CallToolResponse {
+            content: vec![ToolResponseContent::Text {
+                text: e.to_string(),
+            }],
+            is_error: Some(true),
+            meta: None,
+        },
+    }
+}
+
+/// Export a revset's commit graph in Graphviz DOT or mermaid format
+pub fn run_jj_graph_export(params: GraphExportParams) -> CallToolResponse {
+    graph_response(
+        params.format.as_deref().unwrap_or("dot"),
+        params.revisions,
+        params.limit,
+        params.repo_path,
+        params.cwd,
+    )
+}
+
+/// Starts the templated header line of each commit in author-stats log output
+const AUTHOR_STATS_MARKER: &str = "::author-stats::";
+
+/// Parse a `--stat` summary line like `3 files changed, 10 insertions(+), 2 deletions(-)`
+///
+/// Returns the number of files changed, insertions and deletions.
+pub fn parse_stat_summary(line: &str) -> Option<(usize, usize, usize)> {
+    let (mut files, mut insertions, mut deletions) = (None, 0, 0);
+    for part in line.trim().split(", ") {
+        let (count, what) = part.split_once(' ')?;
+        let count: usize = count.parse().ok()?;
+        if what.starts_with("file") {
+            files = Some(count);
+        } else if what.starts_with("insertion") {
+            insertions = count;
+        } else if what.starts_with("deletion") {
+            deletions = count;
+        } else {
+            return None;
+        }
+    }
+    Some((files?, insertions, deletions))
+}
+
+/// Aggregate `jj log --stat` output, with one marker line per commit, into per-author totals
+///
+/// Authors are identified by email (case-insensitively) and sorted by lines changed.
+pub fn aggregate_author_stats(output: &str) -> Vec<AuthorDiffStats> {
+    let mut stats: Vec<AuthorDiffStats> = Vec::new();
+    let mut current: Option<usize> = None;
+
+    for line in output.lines() {
+        if let Some(header) = line.strip_prefix(AUTHOR_STATS_MARKER) {
+            let (name, email) = header.split_once('\t').unwrap_or((header, ""));
+            let index = match stats
+                .iter()
+                .position(|s| s.email.eq_ignore_ascii_case(email))
+            {
+                Some(index) => index,
+                None => {
+                    stats.push(AuthorDiffStats {
+                        name: name.to_string(),
+                        email: email.to_string(),
+                        commits: 0,
+                        files_changed: 0,
+                        insertions: 0,
+                        deletions: 0,
+                    });
+                    stats.len() - 1
+                }
+            };
+            stats[index].commits += 1;
+            current = Some(index);
+        } else if let Some(index) = current
+            && let Some((files, insertions, deletions)) = parse_stat_summary(line)
+        {
+            let entry = &mut stats[index];
+            entry.files_changed += files;
+            entry.insertions += insertions;
+            entry.deletions += deletions;
+        }
+    }
+
+    stats.sort_by(|a, b| {
+        (b.insertions + b.deletions)
+            .cmp(&(a.insertions + a.deletions))
+            .then_with(|| a.name.cmp(&b.name))
+    });
+    stats
+}
+
+/// Report insertions and deletions per author over a revset
+pub fn run_jj_author_stats(params: AuthorStatsParams) -> CallToolResponse {
+    let result = (|| {
+        let revisions = params.revisions.unwrap_or_else(|| "::@".to_string());
+        let mut args = vec![
+            "log".to_string(),
+            "--no-graph".to_string(),
+            "--stat".to_string(),
+            "-r".to_string(),
+            format!("({}) ~ root()", revisions),
+            "-T".to_string(),
+            format!(
+                "{} ++ author.name() ++ \"\\t\" ++ author.email() ++ \"\\n\"",
+                quote_string_literal(AUTHOR_STATS_MARKER)
+            ),
+        ];
+        add_repo_args(&mut args, params.repo_path);
+        let output = run_jj_command_sync(args, params.cwd)?;
+
+        let authors = aggregate_author_stats(&output);
+        Ok(serde_json::json!({
+            "revisions": revisions,
+            "totalCommits": authors.iter().map(|a| a.commits).sum::<usize>(),
+            "insertions": authors.iter().map(|a| a.insertions).sum::<usize>(),
+            "deletions": authors.iter().map(|a| a.deletions).sum::<usize>(),
+            "authors": authors,
+        }))
+    })();
+
+    json_response(result)
+}
+
+/// Bring a workspace up to date after concurrent operations
+///
+/// Updates a stale working copy, then loads the repository so jj merges any
+/// divergent operation heads, and reports the operation the workspace is now at.
+pub fn run_jj_reconcile(params: ReconcileParams) -> CallToolResponse {
+    let result = (|| {
+        let root = resolve_workspace_root(params.repo_path.clone(), params.cwd.clone())?;
+        let heads_before = operation_heads(&root)?;
+
+        let mut args = vec!["workspace".to_string(), "update-stale".to_string()];
+        add_repo_args(&mut args, params.repo_path.clone());
+        let update = run_jj_command_output(args, params.cwd.clone())?;
+
+        let mut args = vec![
+            "op".to_string(),
+            "log".to_string(),
+            "--no-graph".to_string(),
+            "-n".to_string(),
+            "1".to_string(),
+            "-T".to_string(),
+            r#"self.id().short() ++ "\t" ++ description.first_line() ++ "\n""#.to_string(),
+        ];
+        add_repo_args(&mut args, params.repo_path);
+        let operation = run_jj_command_sync(args, params.cwd)?;
+        let (id, description) = operation
+            .trim()
+            .split_once('\t')
+            .unwrap_or((operation.trim(), ""));
+
+        let working_copy = [update.stdout, update.stderr].join("\n").trim().to_string();
+        let heads_after = operation_heads(&root)?;
+        let split = |heads: &str| -> Vec<String> {
+            heads
+                .split(',')
+                .filter(|head| !head.is_empty())
+                .map(str::to_string)
+                .collect()
+        };
+        Ok(serde_json::json!({
+            "divergentOperationHeads": split(&heads_before).len() > 1,
+            "operationHeadsBefore": split(&heads_before),
+            "operationHeads": split(&heads_after),
+            "workingCopy": working_copy,
+            "operation": {
+                "id": id,
+                "description": description,
+            },
+        }))
+    })();
+
+    json_response(result)
+}
+
+/// Create a new repository backed by git or by jj's native backend
+///
+/// jj only initializes native repositories through `jj debug init-native`, or on
+/// older releases through `jj init` with `ui.allow-init-native` set, so both are tried.
+pub fn run_jj_init(params: InitParams) -> CallToolResponse {
+    let result = (|| {
+        let base = match &params.cwd {
+            Some(cwd) => std::path::PathBuf::from(cwd),
+            None => std::env::current_dir()?,
+        };
+        let destination = params.destination.unwrap_or_else(|| ".".to_string());
+        let path = normalize_path(&base.join(&destination));
+
+        let backend = params.backend.unwrap_or_else(|| "git".to_string());
+        let output = match backend.as_str() {
+            "git" => {
+                let mut args = vec!["git".to_string(), "init".to_string()];
+                if let Some(true) = params.colocate {
+                    args.push("--colocate".to_string());
+                }
+                if let Some(git_repo) = params.git_repo {
+                    args.push("--git-repo".to_string());
+                    args.push(git_repo);
+                }
+                args.push(destination);
+                run_jj_command_sync(args, params.cwd)?
+            }
+            "native" => {
+                if params.colocate.is_some() || params.git_repo.is_some() {
+                    return Err(anyhow::anyhow!(
+                        "Error: colocate and gitRepo only apply to the git backend"
+                    ));
+                }
+                let args = vec![
+                    "debug".to_string(),
+                    "init-native".to_string(),
+                    destination.clone(),
+                ];
+                match run_jj_command_sync(args, params.cwd.clone()) {
+                    Err(e) if e.to_string().contains("unsupported by the installed jj") => {
+                        let args = vec![
+                            "--config".to_string(),
+                            "ui.allow-init-native=true".to_string(),
+                            "init".to_string(),
+                            destination,
+                        ];
+                        run_jj_command_sync(args, params.cwd)?
+                    }
+                    other => other?,
+                }
+            }
+            other => {
+                return Err(anyhow::anyhow!(
+                    "Error: unknown backend {} (expected git or native)",
+                    other
+                ));
+            }
+        };
+
+        let colocated = backend == "git" && detect_git_backing(&path).colocated;
+        Ok(serde_json::json!({
+            "path": path.to_string_lossy(),
+            "backend": backend,
+            "colocated": colocated,
+            "output": output,
+        }))
+    })();
+
+    json_response(result)
+}
+
+/// The `git fetch` arguments that deepen a shallow clone by `depth` commits, or
+/// fetch the rest of its history when `depth` is `None`
+pub fn deepen_git_args(remote: &str, depth: Option<u32>) -> Vec<String> {
+    let mut args = vec!["fetch".to_string()];
+    match depth {
+        Some(depth) => args.push(format!("--deepen={}", depth)),
+        None => args.push("--unshallow".to_string()),
+    }
+    args.push(remote.to_string());
+    args
+}
+
+/// Fetch more history into a repository cloned with `depth`
+///
+/// jj cannot deepen a shallow clone itself, so git fetches into the backing git
+/// repository and `jj git import` then picks up the new history.
+pub fn run_jj_deepen(params: DeepenParams) -> CallToolResponse {
+    let result = (|| {
+        let root = resolve_workspace_root(params.repo_path.clone(), params.cwd.clone())?;
+        let git_dir = detect_git_backing(&root)
+            .git_dir
+            .ok_or_else(|| anyhow::anyhow!("Error: the repository is not backed by git"))?;
+        if !git_dir.join("shallow").exists() {
+            return Ok(serde_json::json!({
+                "shallow": false,
+                "message": "The repository already has its full history",
+            }));
+        }
+
+        let remote = params.remote.unwrap_or_else(|| "origin".to_string());
+        let output = std::process::Command::new("git")
+            .arg("--git-dir")
+            .arg(&git_dir)
+            .args(deepen_git_args(&remote, params.depth))
+            .output()
+            .map_err(|e| anyhow::anyhow!("Error: failed to execute git: {}", e))?;
+        if !output.status.success() {
+            return Err(anyhow::anyhow!(
+                "Error: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+
+        let mut args = vec!["git".to_string(), "import".to_string()];
+        add_repo_args(&mut args, params.repo_path);
+        let imported = run_jj_command_sync(args, params.cwd)?;
+
+        Ok(serde_json::json!({
+            "remote": remote,
+            "depth": params.depth,
+            "shallow": git_dir.join("shallow").exists(),
+            "output": String::from_utf8_lossy(&output.stderr).trim(),
+            "imported": imported,
+        }))
+    })();
+
+    json_response(result)
+}
+
+/// Parse the submodule sections of a `.gitmodules` file
+///
+/// Sections without a `path` are skipped, as git itself ignores them.
+pub fn parse_gitmodules(contents: &str) -> Vec<Submodule> {
+    let mut submodules: Vec<Submodule> = Vec::new();
+    let mut current: Option<Submodule> = None;
+    for line in contents.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+            continue;
+        }
+        if let Some(section) = line.strip_prefix('[') {
+            submodules.extend(
+                current
+                    .take()
+                    .filter(|submodule| !submodule.path.is_empty()),
+            );
+            current = section
+                .strip_prefix("submodule")
+                .map(|name| name.trim().trim_end_matches(']').trim().trim_matches('"'))
+                .map(|name| Submodule {
+                    name: name.to_string(),
+                    ..Default::default()
+                });
+            continue;
+        }
+        if let Some(submodule) = current.as_mut()
+            && let Some((key, value)) = line.split_once('=')
+        {
+            let value = value.trim().trim_matches('"').to_string();
+            match key.trim() {
+                "path" => submodule.path = value,
+                "url" => submodule.url = Some(value),
+                "branch" => submodule.branch = Some(value),
+                _ => {}
+            }
+        }
+    }
+    submodules.extend(current.filter(|submodule| !submodule.path.is_empty()));
+    submodules
+}
+
+/// Run git against a repository's git directory and return its stdout
+fn git_dir_output(git_dir: &std::path::Path, args: &[&str]) -> Result<String> {
+    let output = std::process::Command::new("git")
+        .arg("--git-dir")
+        .arg(git_dir)
+        .args(args)
+        .output()
+        .map_err(|e| anyhow::anyhow!("Error: failed to execute git: {}", e))?;
+    if !output.status.success() {
+        return Err(anyhow::anyhow!(
+            "Error: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// List the git submodules of a revision and the commits it pins them to
+///
+/// jj does not manage submodules; it leaves their directories alone and keeps
+/// whatever commit the tree pins. This reads `.gitmodules` and the gitlink
+/// entries of the revision's tree from the backing git repository.
+pub fn run_submodules(params: SubmodulesParams) -> CallToolResponse {
+    let result = (|| {
+        let root = resolve_workspace_root(params.repo_path.clone(), params.cwd.clone())?;
+        let git_dir = detect_git_backing(&root)
+            .git_dir
+            .ok_or_else(|| anyhow::anyhow!("Error: the repository is not backed by git"))?;
+        let revision = params.revision.unwrap_or_else(|| "@".to_string());
+        let commit = resolve_commit_id(&revision, params.repo_path, params.cwd)?;
+
+        let gitmodules = git_dir_output(
+            &git_dir,
+            &["cat-file", "-p", &format!("{}:.gitmodules", commit)],
+        )
+        .unwrap_or_default();
+        let mut submodules = parse_gitmodules(&gitmodules);
+        let tree = git_dir_output(&git_dir, &["ls-tree", "-r", "-z", &commit])?;
+        for entry in tree.split('\0') {
+            // <mode> SP <type> SP <object> TAB <path>
+            let Some((meta, path)) = entry.split_once('\t') else {
+                continue;
+            };
+            let fields: Vec<&str> = meta.split(' ').collect();
+            if fields.first() != Some(&"160000") {
+                continue;
+            }
+            let pinned = fields.get(2).map(|object| object.to_string());
+            match submodules
+                .iter_mut()
+                .find(|submodule| submodule.path == path)
+            {
+                Some(submodule) => submodule.commit = pinned,
+                None => submodules.push(Submodule {
+                    name: path.to_string(),
+                    path: path.to_string(),
+                    commit: pinned,
+                    ..Default::default()
+                }),
+            }
+        }
+        for submodule in &mut submodules {
+            submodule.initialized = root.join(&submodule.path).join(".git").exists();
+        }
+
+        Ok(serde_json::json!({
+            "revision": revision,
+            "commit": commit,
+            "submodules": submodules,
+        }))
+    })();
+
+    json_response(result)
+}
+
+/// Run `work` in a temporary workspace whose working copy starts on top of `revision`
+///
+/// The workspace lives in a private temporary directory and is forgotten and
+/// deleted afterwards, so the user's own working copy is never touched.
+fn in_temporary_workspace<T>(
+    root: &std::path::Path,
+    revision: &str,
+    purpose: &str,
+    work: impl FnOnce(&std::path::Path) -> Result<T>,
+) -> Result<T> {
+    static NEXT_WORKSPACE: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+
+    let id = NEXT_WORKSPACE.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+    let name = format!("{}-{}-{}", purpose, std::process::id(), id);
+    let temp_dir = tempfile::TempDir::new()
+        .map_err(|e| anyhow::anyhow!("Error: failed to create a temporary directory: {}", e))?;
+    let path = temp_dir.path().join("workspace");
+    let root_dir = Some(root.to_string_lossy().to_string());
+    run_jj_command_sync(
+        vec![
+            "workspace".to_string(),
+            "add".to_string(),
+            "--name".to_string(),
+            name.clone(),
+            "-r".to_string(),
+            revision.to_string(),
+            path.to_string_lossy().to_string(),
+        ],
+        root_dir.clone(),
+    )?;
+
+    let result = work(&path);
+    let forgotten = run_jj_command_sync(
+        vec!["workspace".to_string(), "forget".to_string(), name],
+        root_dir,
+    );
+    drop(temp_dir);
+    let value = result?;
+    forgotten?;
+    Ok(value)
+}
+
+/// Commit files on top of a revision in a throwaway workspace, returning the new commit ID
+fn commit_in_temporary_workspace(
+    root: &std::path::Path,
+    parent: &str,
+    message: &str,
+    files: &std::collections::BTreeMap<String, String>,
+) -> Result<String> {
+    in_temporary_workspace(root, parent, "append-commit", |path| {
+        let workspace_dir = Some(path.to_string_lossy().to_string());
+        for (file, content) in files {
+            let file_path = working_copy_write_path(path, file)?;
+            std::fs::write(&file_path, content)
+                .map_err(|e| anyhow::anyhow!("Error: failed to write {}: {}", file, e))?;
+        }
+        run_jj_command_sync(
+            vec![
+                "describe".to_string(),
+                "-m".to_string(),
+                message.to_string(),
+            ],
+            workspace_dir.clone(),
+        )?;
+        resolve_commit_id("@", None, workspace_dir)
+    })
+}
+
+/// Add a commit on top of a bookmark and move the bookmark to it, leaving the
+/// working copy where it is
+///
+/// Without files the commit is made with `jj new --no-edit`; with files it is made
+/// in a temporary workspace so the user's checkout is never rewritten.
+pub fn run_jj_append_commit(params: AppendCommitParams) -> CallToolResponse {
+    let result = (|| {
+        let bookmark = params
+            .bookmark
+            .ok_or_else(|| anyhow::anyhow!("Error: bookmark is required"))?;
+        let message = params
+            .message
+            .ok_or_else(|| anyhow::anyhow!("Error: message is required"))?;
+        let files = params.files.unwrap_or_default();
+
+        let target = format!("bookmarks(exact:{})", quote_string_literal(&bookmark));
+        let parent = resolve_commit_id(&target, params.repo_path.clone(), params.cwd.clone())?;
+
+        let commit = if files.is_empty() {
+            let children = format!("children({})", parent);
+            let before =
+                change_and_commit_ids(&children, params.repo_path.clone(), params.cwd.clone())?;
+            let mut args = vec![
+                "new".to_string(),
+                parent.clone(),
+                "--no-edit".to_string(),
+                "-m".to_string(),
+                message.clone(),
+            ];
+            add_repo_args(&mut args, params.repo_path.clone());
+            run_jj_command_sync(args, params.cwd.clone())?;
+            change_and_commit_ids(&children, params.repo_path.clone(), params.cwd.clone())?
+                .into_iter()
+                .map(|(_, commit_id)| commit_id)
+                .find(|commit_id| !before.iter().any(|(_, existing)| existing == commit_id))
+                .ok_or_else(|| anyhow::anyhow!("Error: could not find the new commit"))?
+        } else {
+            let root = resolve_workspace_root(params.repo_path.clone(), params.cwd.clone())?;
+            commit_in_temporary_workspace(&root, &parent, &message, &files)?
+        };
+
+        let move_bookmark = params.move_bookmark.unwrap_or(true);
+        if move_bookmark {
+            let mut args = vec![
+                "bookmark".to_string(),
+                "set".to_string(),
+                bookmark.clone(),
+                "-r".to_string(),
+                commit.clone(),
+            ];
+            add_repo_args(&mut args, params.repo_path.clone());
+            run_jj_command_sync(args, params.cwd.clone())?;
+        }
+
+        let (change_id, commit_id) = change_and_commit_ids(&commit, params.repo_path, params.cwd)?
+            .into_iter()
+            .next()
+            .ok_or_else(|| anyhow::anyhow!("Error: could not find the new commit"))?;
+        Ok(serde_json::json!({
+            "bookmark": bookmark,
+            "bookmarkMoved": move_bookmark,
+            "parent": parent,
+            "changeId": change_id,
+            "commitId": commit_id,
+            "files": files.keys().collect::<Vec<_>>(),
+        }))
+    })();
+
+    json_response(result)
+}
+
+/// Show a file's content at a revision, describing binary files instead of printing them
+pub fn run_jj_file_show(params: FileShowParams) -> CallToolResponse {
+    let result = (|| -> Result<Vec<ToolResponseContent>> {
+        let path = params
+            .path
+            .ok_or_else(|| anyhow::anyhow!("Error: path is required"))?;
+        let revision = params.revision.unwrap_or_else(|| "@".to_string());
+        let mut args = vec![
+            "file".to_string(),
+            "show".to_string(),
+            "-r".to_string(),
+            revision.clone(),
+            format!("file:{}", quote_string_literal(&path)),
+        ];
+        add_repo_args(&mut args, params.repo_path);
+        let bytes = run_jj_command_bytes(args, params.cwd)?;
+        if !is_binary_content(&bytes) {
+            return Ok(vec![ToolResponseContent::Text {
+                text: String::from_utf8_lossy(&bytes).into_owned(),
+            }]);
+        }
+
+        let allow_binary = params.allow_binary.unwrap_or(false);
+        let summary = binary_content_summary(&path, &revision, &bytes, allow_binary);
+        let mime_type = summary["mimeType"].as_str().unwrap_or_default().to_string();
+        let mut content = vec![ToolResponseContent::Text {
+            text: serde_json::to_string_pretty(&summary)?,
+        }];
+        if allow_binary && mime_type.starts_with("image/") {
+            use base64::Engine as _;
+            content.push(ToolResponseContent::Image {
+                data: base64::engine::general_purpose::STANDARD.encode(&bytes),
+                mime_type,
+            });
+        }
+        Ok(content)
+    })();
+
+    match result {
+        Ok(content) => CallToolResponse {
+            content,
+            is_error: Some(false),
+            meta: None,
+        },
+        Err(e) => CallToolResponse {
+            content: vec![ToolResponseContent::Text {
+                text: e.to_string(),
+            }],
+            is_error: Some(true),
+            meta: None,
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_jj_tool_creation() {
+        let tool = JjTool {
+            name: "test-tool".to_string(),
+            description: "Test tool description".to_string(),
+            input_schema: json!({"type": "object"}),
+            annotations: ToolAnnotations::default(),
+        };
+
+        assert_eq!(tool.name(), "test-tool");
+        assert_eq!(tool.description(), "Test tool description");
+        assert_eq!(tool.input_schema(), json!({"type": "object"}));
+    }
+
+    #[test]
+    fn test_add_repo_args() {
+        let mut args = vec!["status".to_string()];
+        add_repo_args(&mut args, Some("/path/to/repo".to_string()));
+
+        assert_eq!(args, vec!["status", "-R", "/path/to/repo"]);
+    }
+
+    #[test]
+    fn test_add_repo_args_none() {
+        let mut args = vec!["status".to_string()];
+        add_repo_args(&mut args, None);
+
+        assert_eq!(args, vec!["status"]);
+    }
+
+    #[test]
+    fn test_status_params_default() {
+        let params = StatusParams::default();
+        assert!(params.repo_path.is_none());
+        assert!(params.cwd.is_none());
+    }
+
+    #[test]
+    fn test_status_params_deserialization() {
+        let json_val = json!({
+            "repoPath": "/test/repo",
+            "cwd": "/test/dir"
+        });
+
+        let params: StatusParams = serde_json::from_value(json_val).unwrap();
+        assert_eq!(params.repo_path, Some("/test/repo".to_string()));
+        assert_eq!(params.cwd, Some("/test/dir".to_string()));
+    }
+
+    #[test]
+    fn test_rebase_params_deserialization() {
+        let json_val = json!({
+            "source": "@",
+            "destination": "main",
+            "repoPath": "/test/repo"
+        });
+
+        let params: RebaseParams = serde_json::from_value(json_val).unwrap();
+        assert_eq!(params.source, Some("@".to_string()));
+        assert_eq!(params.destination, Some("main".to_string()));
+        assert_eq!(params.repo_path, Some("/test/repo".to_string()));
+    }
+
+    #[test]
+    fn test_tool_call_unknown_tool() {
+        let tool = JjTool {
+            name: "unknown".to_string(),
+            description: "Unknown tool".to_string(),
+            input_schema: json!({}),
+            annotations: ToolAnnotations::default(),
+        };
+
+        let result = tool.call(None).unwrap();
+        assert_eq!(result.is_error, Some(true));
+
+        if let ToolResponseContent::Text { text } = &result.content[0] {
+            assert!(text.contains("Unknown tool: unknown"));
+        } else {
+            panic!("Expected text content");
+        }
+    }
+
+    #[test]
+    fn test_run_jj_command_sync_invalid_command() {
+        // Test with an invalid jj command
+        let result = run_jj_command_sync(vec!["invalid-command".to_string()], None);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_error_response_format() {
+        let params = StatusParams {
+            repo_path: Some("/nonexistent/path".to_string()),
+            cwd: None,
+            all_workspaces: None,
+        };
+
+        let result = run_jj_status(params);
+        assert_eq!(result.is_error, Some(true));
+        assert_eq!(result.content.len(), 1);
+
+        if let ToolResponseContent::Text { text } = &result.content[0] {
+            assert!(text.contains("Error:"));
+        } else {
+            panic!("Expected text content");
+        }
+    }
+
+    #[test]
+    fn test_quote_string_literal() {
+        assert_eq!(quote_string_literal("src/main.rs"), "\"src/main.rs\"");
+        assert_eq!(quote_string_literal("a \"b\" \\c"), "\"a \\\"b\\\" \\\\c\"");
+    }
+
+    #[test]
+    fn test_parse_conflict_markers_diff_style() {
+        let content = "line 1\n\
+<<<<<<< Conflict 1 of 1\n\
+%%%%%%% Changes from base to side #1\n\
+-old\n\
++left\n\
+ shared\n\
++++++++ Contents of side #2\n\
+right\n\
+shared\n\
+>>>>>>> Conflict 1 of 1 ends\n\
+line 2\n";
+
+        let hunks = parse_conflict_markers(content);
+        assert_eq!(
+            hunks,
+            vec![ConflictHunk {
+                start_line: 2,
+                end_line: 10,
+                bases: vec!["old\nshared\n".to_string()],
+                sides: vec!["left\nshared\n".to_string(), "right\nshared\n".to_string()],
+            }]
+        );
+    }
+
+    #[test]
+    fn test_parse_conflict_markers_git_style() {
+        let content = "<<<<<<< side #1\nleft\n||||||| base\nold\n=======\nright\n>>>>>>> side #2\n";
+
+        let hunks = parse_conflict_markers(content);
+        assert_eq!(hunks.len(), 1);
+        assert_eq!(hunks[0].bases, vec!["old\n"]);
+        assert_eq!(hunks[0].sides, vec!["left\n", "right\n"]);
+    }
+
+    #[test]
+    fn test_parse_conflict_markers_ignores_longer_content_runs() {
+        let content = "<<<<<<<<<<< Conflict 1 of 1\n\
++++++++++++ Contents of side #1\n\
++++++++ not a marker\n\
+----------- Contents of base\n\
+base\n\
++++++++++++ Contents of side #2\n\
+right\n\
+>>>>>>>>>>> Conflict 1 of 1 ends\n";
+
+        let hunks = parse_conflict_markers(content);
+        assert_eq!(hunks.len(), 1);
+        assert_eq!(hunks[0].sides, vec!["+++++++ not a marker\n", "right\n"]);
+        assert_eq!(hunks[0].bases, vec!["base\n"]);
+    }
+
+    #[test]
+    fn test_repo_file_path_rejects_escapes() {
+        let root = std::path::Path::new("/repo");
+
+        assert_eq!(
+            repo_file_path(root, "src/lib.rs").unwrap(),
+            std::path::PathBuf::from("/repo/src/lib.rs")
+        );
+        assert!(repo_file_path(root, "../outside").is_err());
+        assert!(repo_file_path(root, "/etc/passwd").is_err());
+        assert!(repo_file_path(root, "").is_err());
+    }
+
+    #[test]
+    fn test_log_record_template() {
+        assert_eq!(
+            log_record_template(&["change_id", "commit_id"]),
+            "change_id ++ \"\\t\" ++ commit_id ++ \"\\n\""
+        );
+    }
+
+    #[test]
+    fn test_group_divergent_commits_prefers_working_copy() {
+        let record = |change: &str, commit: &str, at: &str, wc: &str| {
+            vec![
+                change.to_string(),
+                commit.to_string(),
+                at.to_string(),
+                wc.to_string(),
+                "desc".to_string(),
+            ]
+        };
+        let records = vec![
+            record("kkmp", "aaa", "2024-01-02T00:00:00Z", "false"),
+            record("kkmp", "bbb", "2024-01-01T00:00:00Z", "true"),
+            record("zzyx", "ccc", "2024-01-01T00:00:00Z", "false"),
+            record("zzyx", "ddd", "2024-01-03T00:00:00Z", "false"),
+        ];
+
+        let groups = group_divergent_commits(&records);
+        assert_eq!(groups.len(), 2);
+        assert_eq!(groups[0]["hint"]["keep"], "bbb");
+        assert_eq!(groups[0]["hint"]["abandon"], serde_json::json!(["aaa"]));
+        assert_eq!(groups[1]["hint"]["keep"], "ddd");
+        assert_eq!(groups[1]["hint"]["abandon"], serde_json::json!(["ccc"]));
+    }
+
+    #[test]
+    fn test_hidden_commits_params_deserialization() {
+        let json_val = json!({
+            "operations": 25,
+            "includeRewritten": true,
+            "repoPath": "/test/repo"
+        });
+
+        let params: HiddenCommitsParams = serde_json::from_value(json_val).unwrap();
+        assert_eq!(params.operations, Some(25));
+        assert_eq!(params.include_rewritten, Some(true));
+        assert!(params.revisions.is_none());
+    }
+
+    #[test]
+    fn test_empty_commits_revset() {
+        assert_eq!(
+            empty_commits_revset("main..@"),
+            "(main..@) & empty() & description(exact:\"\") ~ @ ~ merges() ~ root()"
+        );
+    }
+
+    #[test]
+    fn test_parse_duplicated_commits() {
+        let output = "Duplicated 1a2b3c4d5e6f as kkmpptxz 9f8e7d6c5b4a Add feature\n\
+Duplicated 0f0f0f0f0f0f as yqosqzyt 123412341234 (empty) Fix bug\n\
+Rebased 2 commits";
+
+        assert_eq!(
+            parse_duplicated_commits(output),
+            vec![
+                DuplicatedCommit {
+                    source: "1a2b3c4d5e6f".to_string(),
+                    change_id: "kkmpptxz".to_string(),
+                    commit_id: "9f8e7d6c5b4a".to_string(),
+                },
+                DuplicatedCommit {
+                    source: "0f0f0f0f0f0f".to_string(),
+                    change_id: "yqosqzyt".to_string(),
+                    commit_id: "123412341234".to_string(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_bisect_candidates_revset() {
+        let session = BisectSession {
+            good: vec!["aaa".to_string(), "bbb".to_string()],
+            bad: "ccc".to_string(),
+            skipped: vec!["ddd".to_string()],
+            ..Default::default()
+        };
+
+        assert_eq!(session.candidates_revset(), "(aaa | bbb)..ccc ~ ccc ~ ddd");
+    }
+
+    #[test]
+    fn test_parse_conventional_commit() {
+        assert_eq!(
+            parse_conventional_commit("feat(cli)!: add flag"),
+            Some((
+                "feat".to_string(),
+                Some("cli".to_string()),
+                "add flag".to_string()
+            ))
+        );
+        assert_eq!(
+            parse_conventional_commit("fix: typo"),
+            Some(("fix".to_string(), None, "typo".to_string()))
+        );
+        assert_eq!(parse_conventional_commit("Update README"), None);
+        assert_eq!(parse_conventional_commit("see http://example.com"), None);
+    }
+
+    #[test]
+    fn test_render_changelog_groups_entries() {
+        let entries = vec![
+            ("aaa".to_string(), "feat: new tool".to_string()),
+            ("bbb".to_string(), "fix(log): limit".to_string()),
+            ("ccc".to_string(), "Bump version".to_string()),
+        ];
+
+        assert_eq!(
+            render_changelog("v1..v2", &entries),
+            "## v1..v2\n\n### Features\n\n- new tool (aaa)\n\n### Fixes\n\n\
+- **log:** limit (bbb)\n\n### Other\n\n- Bump version (ccc)\n"
+        );
+    }
+
+    #[test]
+    fn test_aggregate_contributors() {
+        let record = |name: &str, email: &str, at: &str| {
+            vec![name.to_string(), email.to_string(), at.to_string()]
+        };
+        let records = vec![
+            record("Ann", "ann@example.com", "2024-03-01T00:00:00Z"),
+            record("Bob", "bob@example.com", "2024-02-01T00:00:00Z"),
+            record("Ann", "Ann@Example.com", "2024-01-01T00:00:00Z"),
+        ];
+
+        let stats = aggregate_contributors(&records);
+        assert_eq!(stats.len(), 2);
+        assert_eq!(stats[0].name, "Ann");
+        assert_eq!(stats[0].commits, 2);
+        assert_eq!(stats[0].first_commit, "2024-01-01T00:00:00Z");
+        assert_eq!(stats[0].last_commit, "2024-03-01T00:00:00Z");
+        assert_eq!(stats[1].email, "bob@example.com");
+    }
+
+    #[test]
+    fn test_colocation_report() {
+        let temp = tempfile::TempDir::new().unwrap();
+        let root = temp.path();
+        std::fs::create_dir_all(root.join(".jj/repo/store")).unwrap();
+        let report = colocation_report(root);
+        assert_eq!(report["backend"], json!("local"));
+        assert_eq!(report["colocated"], json!(false));
+        assert_eq!(report["workspaceGit"], json!("missing"));
+
+        std::fs::create_dir_all(root.join(".git")).unwrap();
+        std::fs::write(root.join(".jj/repo/store/git_target"), "../../../.git").unwrap();
+        let report = colocation_report(root);
+        assert_eq!(report["backend"], json!("git"));
+        assert_eq!(report["colocated"], json!(true));
+        assert_eq!(report["workspaceGit"], json!("directory"));
+        assert_eq!(report["autoImportExport"], json!(true));
+    }
+
+    #[test]
+    fn test_detect_git_backing_and_size() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let dir = temp_dir.path();
+        let store = dir.join(".jj").join("repo").join("store");
+        std::fs::create_dir_all(&store).unwrap();
+        std::fs::create_dir_all(dir.join(".git")).unwrap();
+        std::fs::write(store.join("git_target"), "../../../.git").unwrap();
+        std::fs::write(dir.join("file.txt"), "12345").unwrap();
+
+        let backing = detect_git_backing(dir);
+        assert!(backing.colocated);
+        assert_eq!(
+            backing.git_dir,
+            Some(std::fs::canonicalize(dir.join(".git")).unwrap())
+        );
+        assert_eq!(working_copy_size(dir), 5);
+    }
+
+    #[test]
+    fn test_changes_since_params_deserialization() {
+        let json_val = json!({
+            "operation": "abc123",
+            "revisions": "mutable()"
+        });
+
+        let params: ChangesSinceParams = serde_json::from_value(json_val).unwrap();
+        assert_eq!(params.operation, Some("abc123".to_string()));
+        assert_eq!(params.revisions, Some("mutable()".to_string()));
+        assert!(params.since.is_none());
+    }
+
+    #[test]
+    fn test_compare_params_deserialization() {
+        let json_val = json!({
+            "revision": "my-feature",
+            "base": "main@origin",
+            "limit": 5
+        });
+
+        let params: CompareParams = serde_json::from_value(json_val).unwrap();
+        assert_eq!(params.revision, Some("my-feature".to_string()));
+        assert_eq!(params.base, Some("main@origin".to_string()));
+        assert_eq!(params.limit, Some(5));
+    }
+
+    #[test]
+    fn test_group_paths_by_top_level_dir() {
+        let paths = vec![
+            "src/lib.rs".to_string(),
+            "README.md".to_string(),
+            "src/main.rs".to_string(),
+            "tests/it.rs".to_string(),
+        ];
+
+        let groups = group_paths_by_top_level_dir(&paths);
+        assert_eq!(groups.keys().collect::<Vec<_>>(), vec![".", "src", "tests"]);
+        assert_eq!(groups["src"], vec!["src/lib.rs", "src/main.rs"]);
+        assert_eq!(groups["."], vec!["README.md"]);
+    }
+
+    #[test]
+    fn test_commit_params_max_file_size() {
+        let json_val = json!({
+            "message": "Add data",
+            "maxFileSize": 1048576
+        });
+
+        let params: CommitParams = serde_json::from_value(json_val).unwrap();
+        assert_eq!(params.message, Some("Add data".to_string()));
+        assert_eq!(params.max_file_size, Some(1048576));
+    }
+
+    #[test]
+    fn test_description_search_revset() {
+        assert_eq!(
+            description_search_revset("migration", false, false, "all()"),
+            "(all()) & description(substring:\"migration\")"
+        );
+        assert_eq!(
+            description_search_revset("^fix", true, true, "main..@"),
+            "(main..@) & description(regex-i:\"^fix\")"
+        );
+    }
+
+    #[test]
+    fn test_common_ancestor_revset() {
+        let revisions = vec!["main".to_string(), "feature | other".to_string()];
+        assert_eq!(
+            common_ancestor_revset(&revisions),
+            "heads(::(main) & ::(feature | other))"
+        );
+    }
+
+    #[test]
+    fn test_page_operations() {
+        let entry = |id: &str, time: &str, description: &str| OperationEntry {
+            id: id.to_string(),
+            time: time.to_string(),
+            user: "me@host".to_string(),
+            description: description.to_string(),
+        };
+        let entries = vec![
+            entry("d", "2024-03-04T10:00:00Z", "snapshot working copy"),
+            entry("c", "2024-03-03T10:00:00Z", "describe commit abc"),
+            entry("b", "2024-03-02T10:00:00Z", "snapshot working copy"),
+            entry("a", "2024-03-01T10:00:00Z", "new empty commit"),
+        ];
+
+        let (page, has_more) = page_operations(entries.clone(), &OperationFilter::default(), 1, 2);
+        assert_eq!(
+            page.iter().map(|e| e.id.as_str()).collect::<Vec<_>>(),
+            ["c", "b"]
+        );
+        assert!(has_more);
+
+        let filter = OperationFilter {
+            since: Some("2024-03-02".to_string()),
+            until: Some("2024-03-04".to_string()),
+            description_contains: Some("snapshot".to_string()),
+        };
+        let (page, has_more) = page_operations(entries, &filter, 0, 10);
+        assert_eq!(
+            page.iter().map(|e| e.id.as_str()).collect::<Vec<_>>(),
+            ["b"]
+        );
+        assert!(!has_more);
+    }
+
+    #[test]
+    fn test_grep_content_and_globs() {
+        let regex = build_grep_regex("fn main(", false, false).unwrap();
+        let content = "use std::io;\n\nfn main() {\n}\n";
+        assert_eq!(
+            grep_content("src/main.rs", content, &regex),
+            vec![GrepMatch {
+                path: "src/main.rs".to_string(),
+                line: 3,
+                text: "fn main() {".to_string(),
+            }]
+        );
+
+        let regex = build_grep_regex("^USE", true, true).unwrap();
+        assert_eq!(grep_content("a.rs", content, &regex).len(), 1);
+
+        let include = build_glob_set(&["src/**/*.rs".to_string()]).unwrap();
+        let exclude = build_glob_set(&["**/generated/**".to_string()]).unwrap();
+        assert!(path_selected(
+            "src/lib.rs",
+            include.as_ref(),
+            exclude.as_ref()
+        ));
+        assert!(!path_selected(
+            "README.md",
+            include.as_ref(),
+            exclude.as_ref()
+        ));
+        assert!(!path_selected(
+            "src/generated/api.rs",
+            include.as_ref(),
+            exclude.as_ref()
+        ));
+        assert!(path_selected("README.md", None, None));
+    }
+
+    #[test]
+    fn test_parse_and_apply_unified_diff() {
+        let patch = "\
+diff --git a/src/lib.rs b/src/lib.rs
+--- a/src/lib.rs
++++ b/src/lib.rs
+@@ -1,3 +1,4 @@
+ fn one() {}
+-fn two() {}
++fn two() -> u32 { 2 }
++fn two_and_a_half() {}
+ fn three() {}
+--- /dev/null
++++ b/NOTES.md
+@@ -0,0 +1 @@
++notes
+\\ No newline at end of file
+";
+        let files = parse_unified_diff(patch).unwrap();
+        assert_eq!(files.len(), 2);
+        assert_eq!(files[0].old_path.as_deref(), Some("src/lib.rs"));
+        assert_eq!(files[1].old_path, None);
+        assert_eq!(files[1].new_path.as_deref(), Some("NOTES.md"));
+        assert!(files[1].hunks[0].new_missing_newline);
+
+        // Two lines were inserted above the hunk since the patch was made
+        let original = "// header\n// more\nfn one() {}\nfn two() {}\nfn three() {}\n";
+        assert_eq!(
+            apply_hunks(original, &files[0].hunks).unwrap(),
+            "// header\n// more\nfn one() {}\nfn two() -> u32 { 2 }\nfn two_and_a_half() {}\nfn three() {}\n"
+        );
+        assert_eq!(apply_hunks("", &files[1].hunks).unwrap(), "notes");
+        assert!(apply_hunks("fn other() {}\n", &files[0].hunks).is_err());
+    }
+
+    #[test]
+    fn test_format_patch() {
+        let commit = PatchCommit {
+            commit_id: "abc123".to_string(),
+            author_name: "Ada".to_string(),
+            author_email: "ada@example.com".to_string(),
+            date: "Mon, 04 Mar 2024 10:00:00 +0000".to_string(),
+            description: "Fix parser\n\nHandles empty input.\n".to_string(),
+            diff: "diff --git a/x b/x\n".to_string(),
+        };
+        assert_eq!(
+            format_patch(&commit, 2, 3, true),
+            "From abc123 Mon Sep 17 00:00:00 2001\nFrom: Ada <ada@example.com>\n\
+             Date: Mon, 04 Mar 2024 10:00:00 +0000\nSubject: [PATCH 2/3] Fix parser\n\n\
+             Handles empty input.\n\n---\ndiff --git a/x b/x\n"
+        );
+        assert_eq!(
+            format_patch(&commit, 1, 1, false),
+            "Fix parser\n\nHandles empty input.\n\n---\ndiff --git a/x b/x\n"
+        );
+    }
+
+    #[test]
+    fn test_archive_format() {
+        assert_eq!(archive_format(None, "/tmp/out").unwrap(), "directory");
+        assert_eq!(archive_format(None, "/tmp/v1.2.tar.gz").unwrap(), "tar.gz");
+        assert_eq!(archive_format(None, "release.tgz").unwrap(), "tar.gz");
+        assert_eq!(
+            archive_format(Some("directory"), "odd.tar.gz").unwrap(),
+            "directory"
+        );
+        assert!(archive_format(Some("zip"), "out.zip").is_err());
+    }
+
+    #[test]
+    fn test_registered_repos() {
+        let explicit = Some(vec!["/work/a".to_string()]);
+        assert_eq!(
+            registered_repos(explicit, Some("/work/b")),
+            vec!["/work/a".to_string()]
+        );
+
+        let joined = std::env::join_paths(["/work/b", "/work/c"]).unwrap();
+        assert_eq!(
+            registered_repos(None, joined.to_str()),
+            vec!["/work/b".to_string(), "/work/c".to_string()]
+        );
+        assert!(registered_repos(Some(Vec::new()), None).is_empty());
+    }
+
+    #[test]
+    fn test_read_cache_invalidates_on_new_operation() {
+        let root = std::path::Path::new("/repo");
+        let args = vec!["log".to_string(), "-n".to_string(), "5".to_string()];
+        let mut cache = ReadCache::default();
+
+        cache.insert(root, &args, "op1", "log output".to_string());
+        assert_eq!(cache.get(root, &args, "op1").as_deref(), Some("log output"));
+        assert_eq!(cache.get(root, &args, "op2"), None);
+        assert!(cache.is_empty());
+
+        for i in 0..READ_CACHE_CAPACITY + 5 {
+            cache.insert(root, &[i.to_string()], "op1", String::new());
+        }
+        assert_eq!(cache.len(), READ_CACHE_CAPACITY);
+        assert_eq!(cache.get(root, &["0".to_string()], "op1"), None);
+    }
+
+    #[test]
+    fn test_fsmonitor_setting() {
+        assert_eq!(fsmonitor_setting(None, None), None);
+        assert_eq!(fsmonitor_setting(None, Some("watchman")), Some(true));
+        assert_eq!(fsmonitor_setting(None, Some("0")), Some(false));
+        assert_eq!(
+            fsmonitor_setting(Some(false), Some("watchman")),
+            Some(false)
+        );
+        assert_eq!(
+            fsmonitor_args(Some(true)),
+            vec![
+                "--config".to_string(),
+                "core.fsmonitor=watchman".to_string()
+            ]
+        );
+        assert!(fsmonitor_args(None).is_empty());
+
+        let options = CallOptions::from_args(&serde_json::json!({"fsmonitor": true}));
+        assert_eq!(options.fsmonitor, Some(true));
+        with_call_options(options, || {
+            assert_eq!(current_call_options().fsmonitor, Some(true));
+        });
+        assert_eq!(current_call_options(), CallOptions::default());
+    }
+
+    #[test]
+    fn test_check_git_passthrough_args() {
+        let args = |list: &[&str]| list.iter().map(|a| a.to_string()).collect::<Vec<_>>();
+
+        assert_eq!(
+            check_git_passthrough_args(&args(&["rev-parse", "HEAD"])).unwrap(),
+            "rev-parse"
+        );
+        assert!(check_git_passthrough_args(&args(&["config", "--get", "user.email"])).is_ok());
+        assert!(check_git_passthrough_args(&args(&["push", "origin"])).is_err());
+        assert!(check_git_passthrough_args(&args(&["config", "user.email", "x@y"])).is_err());
+        assert!(check_git_passthrough_args(&args(&["config", "--get", "a", "--unset"])).is_err());
+        assert!(
+            check_git_passthrough_args(&args(&["ls-remote", "--upload-pack=touch /tmp/x", "o"]))
+                .is_err()
+        );
+        assert!(check_git_passthrough_args(&[]).is_err());
+    }
+
+    #[test]
+    fn test_catalog_tools_are_annotated() {
+        let tools = all_tools();
+        let mut names: Vec<&str> = tools.iter().map(|tool| tool.name.as_str()).collect();
+        names.sort();
+        names.dedup();
+        assert_eq!(names.len(), tools.len(), "tool names must be unique");
+
+        for tool in &tools {
+            assert_ne!(
+                tool.annotations,
+                ToolAnnotations::default(),
+                "{} has no explicit annotations",
+                tool.name
+            );
+        }
+        assert!(default_tool_annotations("log").read_only_hint);
+        assert!(default_tool_annotations("rebase").destructive_hint);
+        assert!(default_tool_annotations("git-clone").open_world_hint);
+    }
+
+    #[test]
+    fn test_server_config_tool_groups() {
+        let args = ["--enable-groups", "read,remote", "--enable-tools=undo"]
+            .iter()
+            .map(|arg| arg.to_string());
+        let env = |var: &str| (var == DISABLE_TOOLS_ENV_VAR).then(|| "grep".to_string());
+        let config = ServerConfig::from_sources(args, env).unwrap();
+
+        assert_eq!(
+            config.enabled_groups,
+            vec![ToolGroup::Read, ToolGroup::Remote]
+        );
+        assert!(config.is_tool_enabled("log"));
+        assert!(config.is_tool_enabled("git-clone"));
+        assert!(config.is_tool_enabled("undo"));
+        assert!(!config.is_tool_enabled("grep"));
+        assert!(!config.is_tool_enabled("rebase"));
+        assert!(!config.is_tool_enabled("doctor"));
+
+        let none = |_: &str| None;
+        let config =
+            ServerConfig::from_sources(["--disable-groups=write".to_string()], none).unwrap();
+        assert!(config.is_tool_enabled("status"));
+        assert!(!config.is_tool_enabled("commit"));
+
+        assert!(ServerConfig::from_sources(["--enable-groups=all".to_string()], none).is_err());
+        assert!(ServerConfig::from_sources(["--enable-tools".to_string()], none).is_err());
+    }
+
+    #[test]
+    fn test_server_config_tool_prefix() {
+        let env = |var: &str| (var == TOOL_PREFIX_ENV_VAR).then(|| "jj_".to_string());
+        let config = ServerConfig::from_sources(std::iter::empty(), env).unwrap();
+
+        assert_eq!(config.exposed_name("status"), "jj_status");
+        assert_eq!(config.canonical_name("jj_status"), "status");
+        assert_eq!(config.canonical_name("status"), "status");
+
+        let tools = enabled_tools(&config);
+        assert!(tools.iter().all(|tool| tool.name.starts_with("jj_")));
+        assert_eq!(
+            ServerConfig::default().canonical_name("jj_status"),
+            "jj_status"
+        );
+    }
+
+    #[test]
+    fn test_is_read_only_jj_command() {
+        let argv = |line: &str| line.split(' ').map(str::to_string).collect::<Vec<_>>();
+
+        assert!(is_read_only_jj_command(&argv("log -r @ -R /repo")));
+        assert!(is_read_only_jj_command(&argv("-R /repo file show -r @ x")));
+        assert!(is_read_only_jj_command(&argv("--at-op abc op log")));
+        assert!(is_read_only_jj_command(&argv("resolve --list -r @")));
+        assert!(!is_read_only_jj_command(&argv("resolve -r @")));
+        assert!(!is_read_only_jj_command(&argv("rebase -s @ -d main")));
+        assert!(!is_read_only_jj_command(&argv("file chmod x y")));
+        assert!(!is_read_only_jj_command(&argv("config set --user a b")));
+        assert!(!is_read_only_jj_command(&argv("debug snapshot")));
+    }
+
+    #[test]
+    fn test_explain_records_mutations_without_running() {
+        let tool = JjTool::new("new", "Create new commit", json!({"type": "object"}));
+        let result = tool
+            .call(Some(json!({"parents": "main", "explain": true})))
+            .unwrap();
+        assert_eq!(result.is_error, Some(false));
+
+        if let ToolResponseContent::Text { text } = &result.content[0] {
+            let value: Value = serde_json::from_str(text).unwrap();
+            assert_eq!(value["tool"], json!("new"));
+            assert_eq!(value["parameters"], json!({"parents": "main"}));
+            assert_eq!(value["commands"], json!([["jj", "new", "main"]]));
+            assert_eq!(value["destructive"], json!(false));
+        } else {
+            panic!("Expected text content");
+        }
+    }
+
+    #[test]
+    fn test_explain_does_not_run_direct_write_tools() {
+        for name in ["sandbox-promote", "sandbox-discard", "deepen"] {
+            let tool = JjTool::new(name, "Sandbox", json!({"type": "object"}));
+            let result = tool
+                .call(Some(
+                    json!({"repoPath": "/nonexistent/repo", "explain": true}),
+                ))
+                .unwrap();
+            assert_eq!(result.is_error, Some(false));
+
+            if let ToolResponseContent::Text { text } = &result.content[0] {
+                let value: Value = serde_json::from_str(text).unwrap();
+                assert_eq!(value["writesFilesDirectly"], json!(true));
+                assert_eq!(value["commands"], json!([]));
+                assert_eq!(value["error"], Value::Null);
+            } else {
+                panic!("Expected text content");
+            }
+        }
+    }
+
+    #[test]
+    fn test_sandbox_arguments() {
+        let root = std::path::Path::new("/work/repo");
+        let sandbox = std::path::Path::new("/tmp/jj-mcp-sandbox-1-0");
+
+        let args = json!({"cwd": "/work/repo/src", "message": "wip", "sandbox": true});
+        assert_eq!(
+            sandbox_arguments(&args, root, sandbox),
+            json!({
+                "cwd": "/tmp/jj-mcp-sandbox-1-0/src",
+                "message": "wip",
+                "repoPath": "/tmp/jj-mcp-sandbox-1-0"
+            })
+        );
+
+        assert_eq!(
+            sandbox_arguments(&Value::Null, root, sandbox),
+            json!({
+                "cwd": "/tmp/jj-mcp-sandbox-1-0",
+                "repoPath": "/tmp/jj-mcp-sandbox-1-0"
+            })
+        );
+    }
+
+    #[test]
+    fn test_copy_dir_all() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let from = temp_dir.path().join("from");
+        std::fs::create_dir_all(from.join(".jj").join("repo")).unwrap();
+        std::fs::write(from.join("a.txt"), "a").unwrap();
+        std::fs::write(from.join(".jj").join("repo").join("b"), "b").unwrap();
+
+        let to = temp_dir.path().join("to");
+        copy_dir_all(&from, &to).unwrap();
+        assert_eq!(std::fs::read_to_string(to.join("a.txt")).unwrap(), "a");
+        assert_eq!(
+            std::fs::read_to_string(to.join(".jj").join("repo").join("b")).unwrap(),
+            "b"
+        );
+    }
+
+    #[test]
+    fn test_union_revset() {
+        assert_eq!(
+            union_revset(&["@".to_string(), "main..feature".to_string()]),
+            "(@) | (main..feature)"
+        );
+    }
+
+    #[test]
+    fn test_map_rewritten_commits() {
+        let record = |fields: &[&str]| fields.iter().map(|f| f.to_string()).collect::<Vec<_>>();
+        let before = vec![record(&["kkmp", "aaa"]), record(&["yqos", "bbb"])];
+        let after = vec![record(&["kkmp", "ccc"]), record(&["yqos", "bbb"])];
+
+        assert_eq!(
+            map_rewritten_commits(&before, &after),
+            vec![json!({"changeId": "kkmp", "oldCommitId": "aaa", "newCommitId": "ccc"})]
+        );
+    }
+
+    #[test]
+    fn test_restore_args() {
+        let params: RestoreParams = serde_json::from_value(json!({
+            "from": "main",
+            "to": "@-",
+            "restoreDescendants": true,
+            "paths": ["src/lib.rs", "-weird"]
+        }))
+        .unwrap();
+        assert_eq!(params.into.as_deref(), Some("@-"));
+        assert_eq!(
+            restore_args(&params),
+            vec![
+                "restore",
+                "--from",
+                "main",
+                "--into",
+                "@-",
+                "--restore-descendants",
+                "--",
+                "src/lib.rs",
+                "-weird"
+            ]
+        );
+
+        assert_eq!(restore_args(&RestoreParams::default()), vec!["restore"]);
+    }
+
+    #[test]
+    fn test_squash_args() {
+        let params: SquashParams = serde_json::from_value(json!({
+            "from": "kkmp",
+            "into": "yqos",
+            "useDestinationMessage": true,
+            "keepEmptied": true,
+            "paths": ["src/"]
+        }))
+        .unwrap();
+        assert_eq!(
+            squash_args(&params).unwrap(),
+            vec![
+                "squash",
+                "--from",
+                "kkmp",
+                "--into",
+                "yqos",
+                "--use-destination-message",
+                "--keep-emptied",
+                "--",
+                "src/"
+            ]
+        );
+
+        assert_eq!(
+            squash_args(&SquashParams::default()).unwrap(),
+            vec!["squash", "--config", "ui.editor=true"]
+        );
+
+        let conflicting = SquashParams {
+            message: Some("msg".to_string()),
+            use_destination_message: Some(true),
+            ..Default::default()
+        };
+        assert!(squash_args(&conflicting).is_err());
+    }
+
+    #[test]
+    fn test_parse_bookmark_records() {
+        let output = "main\t\tabc123\tfalse\tfalse\nmain\torigin\tabc123\tfalse\ttrue\nfeat\t\t\ttrue\tfalse";
+        assert_eq!(
+            parse_bookmark_records(output),
+            vec![
+                json!({"name": "main", "remote": null, "commitId": "abc123", "conflicted": false, "tracked": false}),
+                json!({"name": "main", "remote": "origin", "commitId": "abc123", "conflicted": false, "tracked": true}),
+                json!({"name": "feat", "remote": null, "commitId": null, "conflicted": true, "tracked": false}),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_created_bookmarks() {
+        let output = "Creating bookmark push-kxyzqwer for revision kxyzqwer\nChanges to push to origin:\n  Add bookmark push-kxyzqwer to 5678ef01";
+        assert_eq!(
+            parse_created_bookmarks(output),
+            vec![CreatedBookmark {
+                bookmark: "push-kxyzqwer".to_string(),
+                revision: "kxyzqwer".to_string(),
+            }]
+        );
+        assert!(parse_created_bookmarks("Nothing changed.").is_empty());
+    }
+
+    #[test]
+    fn test_parse_push_updates() {
+        let output = "Changes to push to origin:\n  Move forward bookmark main from 1234abcd to 5678ef01\n  Add bookmark feature to abcdef12\n  Delete bookmark old from 12345678\nDry-run requested, not pushing.";
+        let updates = parse_push_updates(output);
+
+        assert_eq!(updates.len(), 3);
+        assert_eq!(
+            updates[0],
+            BookmarkUpdate {
+                remote: Some("origin".to_string()),
+                bookmark: "main".to_string(),
+                action: "move-forward".to_string(),
+                old_commit: Some("1234abcd".to_string()),
+                new_commit: Some("5678ef01".to_string()),
+            }
+        );
+        assert_eq!(updates[1].action, "add");
+        assert_eq!(updates[1].old_commit, None);
+        assert_eq!(updates[2].action, "delete");
+        assert_eq!(updates[2].new_commit, None);
+    }
+
+    #[test]
+    fn test_parse_config_records() {
+        let output = "user.name\tuser\t/home/me/.config/jj/config.toml\tfalse\t\"Me\"\nui.color\tdefault\t\ttrue\t\"auto\"";
+        let entries = parse_config_records(output);
+
+        assert_eq!(
+            entries[0],
+            ConfigEntry {
+                name: "user.name".to_string(),
+                value: "\"Me\"".to_string(),
+                source: "user".to_string(),
+                path: Some("/home/me/.config/jj/config.toml".to_string()),
+                overridden: false,
+            }
+        );
+        assert_eq!(entries[1].source, "default");
+        assert_eq!(entries[1].path, None);
+        assert!(entries[1].overridden);
+    }
+
+    #[test]
+    fn test_roots_from_list_result() {
+        let result = json!({
+            "roots": [
+                {"uri": "file:///home/me/project", "name": "project"},
+                {"uri": "file:///work/with%20space"},
+                {"uri": "https://example.com/repo"}
+            ]
+        });
+        assert_eq!(
+            roots_from_list_result(&result),
+            vec![
+                std::path::PathBuf::from("/home/me/project"),
+                std::path::PathBuf::from("/work/with space")
+            ]
+        );
+    }
+
+    #[test]
+    fn test_server_config_roots() {
+        let env = |var: &str| (var == ROOTS_ENV_VAR).then(|| "/work/a:/work/b".to_string());
+        let args = ["--roots=/home/me/src".to_string()];
+        let config = ServerConfig::from_sources(args, env).unwrap();
+
+        assert_eq!(
+            config.roots,
+            vec![
+                std::path::PathBuf::from("/work/a"),
+                std::path::PathBuf::from("/work/b"),
+                std::path::PathBuf::from("/home/me/src")
+            ]
+        );
+    }
+
+    #[test]
+    fn test_restrict_to_roots() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let root = temp_dir.path().join("root");
+        std::fs::create_dir_all(root.join(".jj")).unwrap();
+        let roots = vec![root.clone()];
+
+        let mut args = json!({"repoPath": root.join("sub").to_string_lossy()});
+        assert!(restrict_to_roots("status", &mut args, &roots).is_ok());
+
+        let mut args = json!({"cwd": root.to_string_lossy(), "repoPath": "../elsewhere"});
+        assert!(restrict_to_roots("status", &mut args, &roots).is_err());
+
+        let mut args = json!({"repos": [root.to_string_lossy(), "/"]});
+        assert!(restrict_to_roots("status", &mut args, &roots).is_err());
+
+        // The test process runs from the crate directory, outside the temp root
+        let mut args = Value::Null;
+        restrict_to_roots("status", &mut args, &roots).unwrap();
+        assert_eq!(args["cwd"], json!(root.to_string_lossy()));
+
+        let mut args = json!({"cwd": "/"});
+        assert!(restrict_to_roots("status", &mut args, &[]).is_ok());
+
+        // Paths a tool writes to or reads from are checked too, relative to cwd
+        let cwd = root.to_string_lossy();
+        let mut args = json!({"cwd": cwd, "destination": "/tmp/out.tar.gz"});
+        assert!(restrict_to_roots("archive", &mut args, &roots).is_err());
+        let mut args = json!({"cwd": cwd, "destination": "out"});
+        assert!(restrict_to_roots("archive", &mut args, &roots).is_ok());
+        let mut args = json!({"cwd": cwd, "messageFile": "../message.txt"});
+        assert!(restrict_to_roots("commit", &mut args, &roots).is_err());
+        // Elsewhere destination is a revision
+        let mut args = json!({"cwd": cwd, "destination": "/tmp/out.tar.gz"});
+        assert!(restrict_to_roots("rebase", &mut args, &roots).is_ok());
+    }
+
+    #[test]
+    fn test_commit_message_sampling_params() {
+        let params = commit_message_sampling_params("+fn main() {}\n", Some("mention JJ-12"));
+        let prompt = params["messages"][0]["content"]["text"].as_str().unwrap();
+        assert!(prompt.contains("+fn main() {}"));
+        assert!(prompt.ends_with("Additional instructions: mention JJ-12"));
+        assert_eq!(params["includeContext"], json!("none"));
+
+        let long_diff = "é".repeat(DESCRIBE_AUTO_MAX_DIFF_BYTES);
+        let params = commit_message_sampling_params(&long_diff, None);
+        let prompt = params["messages"][0]["content"]["text"].as_str().unwrap();
+        assert!(prompt.ends_with("[diff truncated]"));
+    }
+
+    #[test]
+    fn test_clean_commit_message() {
+        assert_eq!(
+            clean_commit_message("```text\nFix parser\n\nHandle EOF.\n```\n"),
+            "Fix parser\n\nHandle EOF."
+        );
+        assert_eq!(clean_commit_message("  Fix parser \n"), "Fix parser");
+    }
+
+    #[test]
+    fn test_confirm_with() {
+        assert!(confirm_with(true, "rebase").is_ok());
+        let err = confirm_with(false, "rebase").unwrap_err();
+        assert!(err.to_string().contains("rebase"));
+        assert!(err.to_string().contains("\"confirm\": true"));
+
+        let env =
+            |var: &str| (var == REQUIRE_CONFIRMATION_ENV_VAR).then(|| "rebase,abandon".to_string());
+        let config = ServerConfig::from_sources(std::iter::empty(), env).unwrap();
+        assert!(config.requires_confirmation("abandon"));
+        assert!(!config.requires_confirmation("log"));
+    }
+
+    #[test]
+    fn test_completion_kind() {
+        assert_eq!(
+            completion_kind("destination"),
+            Some(CompletionKind::Revision)
+        );
+        assert_eq!(completion_kind("bookmarks"), Some(CompletionKind::Bookmark));
+        assert_eq!(completion_kind("remote"), Some(CompletionKind::Remote));
+        assert_eq!(completion_kind("message"), None);
+
+        let completion = complete_argument("message", "", None, None).unwrap();
+        assert_eq!(completion["completion"]["values"], json!([]));
+    }
+
+    #[test]
+    fn test_check_call_timestamp() {
+        assert!(check_call_timestamp(&json!({})).is_ok());
+        assert!(check_call_timestamp(&json!({"timestamp": "2024-01-01T00:00:00Z"})).is_ok());
+        assert!(check_call_timestamp(&json!({"timestamp": "2024-01-01T12:30:00.5+02:00"})).is_ok());
+        assert!(check_call_timestamp(&json!({"timestamp": "yesterday"})).is_err());
+        assert!(check_call_timestamp(&json!({"timestamp": 1704067200})).is_err());
+
+        let options = CallOptions::from_args(&json!({"timestamp": "2024-01-01T00:00:00Z"}));
+        assert_eq!(options.timestamp.as_deref(), Some("2024-01-01T00:00:00Z"));
+    }
+
+    #[test]
+    fn test_call_env() {
+        let args = json!({
+            "env": {"JJ_USER": "Release Bot", "JJ_TIMESTAMP": "2024-01-01T00:00:00Z"}
+        });
+        assert!(check_call_env(&args).is_ok());
+        let options = CallOptions::from_args(&args);
+        assert_eq!(options.env.len(), 2);
+        assert_eq!(options.env["JJ_USER"], "Release Bot");
+
+        assert!(check_call_env(&json!({})).is_ok());
+        assert!(check_call_env(&json!({"env": {"PATH": "/tmp"}})).is_err());
+        assert!(check_call_env(&json!({"env": {"JJ_USER": 1}})).is_err());
+        assert!(check_call_env(&json!({"env": ["JJ_USER"]})).is_err());
+    }
+
+    #[test]
+    fn test_raw_outputs_attached_with_base64_option() {
+        let args = vec!["file".to_string(), "show".to_string()];
+        let options = CallOptions {
+            base64: true,
+            ..CallOptions::default()
+        };
+        let response = with_call_options(options, || {
+            assert_eq!(decode_output(&args, b"caf\xe9"), "caf\u{fffd}");
+            assert_eq!(decode_output(&args, "café".as_bytes()), "café");
+            attach_raw_outputs(CallToolResponse {
+                content: Vec::new(),
+                is_error: Some(false),
+                meta: None,
+            })
+        });
+        assert_eq!(response.content.len(), 1);
+        if let ToolResponseContent::Text { text } = &response.content[0] {
+            let value: Value = serde_json::from_str(text).unwrap();
+            assert_eq!(value["command"], json!("jj file show"));
+            assert_eq!(value["data"], json!("Y2Fm6Q=="));
+        } else {
+            panic!("Expected text content");
+        }
+
+        decode_output(&args, b"caf\xe9");
+        let response = attach_raw_outputs(CallToolResponse {
+            content: Vec::new(),
+            is_error: Some(false),
+            meta: None,
+        });
+        assert!(response.content.is_empty());
+    }
+
+    #[test]
+    fn test_unrecognized_jj_argument() {
+        let stderr = "error: unrecognized subcommand 'annotate'\n\nUsage: jj file [OPTIONS] <COMMAND>\n\nFor more information, try '--help'.";
+        assert_eq!(
+            unrecognized_jj_argument(stderr),
+            Some("annotate".to_string())
+        );
+
+        let stderr = "error: unexpected argument '--retain-bookmarks' found\n\n  tip: to pass '--retain-bookmarks' as a value, use '-- --retain-bookmarks'";
+        assert_eq!(
+            unrecognized_jj_argument(stderr),
+            Some("--retain-bookmarks".to_string())
+        );
+
+        assert_eq!(
+            unrecognized_jj_argument("Error: Revision `nope` doesn't exist"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_parse_jj_version() {
+        assert_eq!(
+            "jj 0.29.0-1d8a9c2b".parse::<JjVersion>().unwrap(),
+            JjVersion::new(0, 29, 0)
+        );
+        assert_eq!(
+            "0.25".parse::<JjVersion>().unwrap(),
+            JjVersion::new(0, 25, 0)
+        );
+        assert!("jj unknown".parse::<JjVersion>().is_err());
+        assert!(JjVersion::new(0, 9, 0) < JjVersion::new(0, 10, 0));
+    }
+
+    #[test]
+    fn test_unsupported_tool_reason() {
+        let minimum = JjVersion::new(0, 20, 0);
+        assert_eq!(
+            unsupported_tool_reason("status", JjVersion::new(0, 23, 0), minimum),
+            None
+        );
+        let reason =
+            unsupported_tool_reason("annotate", JjVersion::new(0, 23, 0), minimum).unwrap();
+        assert!(reason.contains("0.24.0"));
+        assert!(reason.contains("jj file annotate"));
+        let reason = unsupported_tool_reason("status", JjVersion::new(0, 19, 1), minimum).unwrap();
+        assert!(reason.contains("minimum"));
+    }
+
+    #[test]
+    fn test_unavailable_tool_reason() {
+        let config = ServerConfig {
+            disabled_tools: vec!["git-push".to_string()],
+            disabled_groups: vec![ToolGroup::Admin],
+            ..Default::default()
+        };
+        assert_eq!(config.unavailable_tool_reason("status", None), None);
+        assert!(
+            config
+                .unavailable_tool_reason("git-push", None)
+                .unwrap()
+                .contains("disabled")
+        );
+        assert!(
+            config
+                .unavailable_tool_reason("git-passthrough", None)
+                .is_some()
+        );
+        let reason = config
+            .unavailable_tool_reason("annotate", Some(JjVersion::new(0, 23, 0)))
+            .unwrap();
+        assert!(reason.contains("0.24.0"));
+    }
+
+    #[test]
+    fn test_read_with_budget() {
+        let mut reader = std::io::Cursor::new(b"one\ntwo\nthree\n".to_vec());
+        let (content, truncated) = read_with_budget(&mut reader, 100).unwrap();
+        assert_eq!(content, b"one\ntwo\nthree\n");
+        assert!(!truncated);
+
+        let mut reader = std::io::Cursor::new(b"one\ntwo\nthree\n".to_vec());
+        let (content, truncated) = read_with_budget(&mut reader, 10).unwrap();
+        assert_eq!(content, b"one\ntwo\n");
+        assert!(truncated);
+
+        let mut reader = std::io::Cursor::new(b"a very long line".to_vec());
+        let (content, truncated) = read_with_budget(&mut reader, 6).unwrap();
+        assert_eq!(content, b"a very");
+        assert!(truncated);
+    }
+
+    #[test]
+    fn test_root_cache_invalidated_on_mtime_change() {
+        let temp = tempfile::TempDir::new().unwrap();
+        let root = temp.path().to_path_buf();
+        let mut cache = RootCache::default();
+
+        cache.insert(&root, None, root.clone(), std::slice::from_ref(&root));
+        assert_eq!(cache.get(&root, None), Some(root.clone()));
+        assert_eq!(cache.get(&root, Some("sub")), None);
+
+        let watched = std::fs::File::open(&root).unwrap();
+        watched
+            .set_modified(std::time::SystemTime::UNIX_EPOCH)
+            .unwrap();
+        assert_eq!(cache.get(&root, None), None);
+    }
+
+    #[test]
+    fn test_process_pool_queues_over_limit() {
+        let pool = std::sync::Arc::new(ProcessPool::new(Some(1)));
+        let permit = pool.acquire();
+
+        let waiter = {
+            let pool = pool.clone();
+            std::thread::spawn(move || {
+                let _permit = pool.acquire();
+            })
+        };
+        while pool.stats().queued == 0 {
+            std::thread::yield_now();
+        }
+        assert_eq!(pool.stats().running, 1);
+
+        drop(permit);
+        waiter.join().unwrap();
+        let stats = pool.stats();
+        assert_eq!(stats.running, 0);
+        assert_eq!(stats.queued, 0);
+        assert_eq!(stats.peak_queued, 1);
+
+        let unlimited = ProcessPool::new(None);
+        let _first = unlimited.acquire();
+        let _second = unlimited.acquire();
+        assert_eq!(unlimited.stats().running, 2);
+    }
+
+    #[test]
+    fn test_mime_type_for_path() {
+        assert_eq!(mime_type_for_path("src/lib.rs"), "text/x-rust");
+        assert_eq!(mime_type_for_path("README.MD"), "text/markdown");
+        assert_eq!(mime_type_for_path("Makefile"), "text/plain");
+        assert_eq!(mime_type_for_path("docs/notes.txt"), "text/plain");
+    }
+
+    #[test]
+    fn test_offload_output() {
+        let long = "line\n".repeat(100);
+        let response = CallToolResponse {
+            content: vec![
+                ToolResponseContent::Text {
+                    text: "short".to_string(),
+                },
+                ToolResponseContent::Text { text: long.clone() },
+            ],
+            is_error: Some(false),
             meta: None,
-        },
+        };
+
+        let response = offload_output(response, 64);
+        assert_eq!(response.content.len(), 3);
+        let uri = match &response.content[2] {
+            ToolResponseContent::Resource { resource } => resource.uri.to_string(),
+            _ => panic!("Expected resource content"),
+        };
+        if let ToolResponseContent::Text { text } = &response.content[1] {
+            assert!(text.contains(&uri));
+        } else {
+            panic!("Expected text content");
+        }
+
+        let page = read_stored_output(&uri, 5, 10).unwrap();
+        assert_eq!(page["text"], json!("line\nline\n"));
+        assert_eq!(page["totalBytes"], json!(long.len()));
+        assert_eq!(page["hasMore"], json!(true));
+
+        assert!(read_stored_output("file:///etc/hostname", 0, 10).is_err());
     }
-}
 
-/// Execute jj log command
-pub fn run_jj_log(params: LogParams) -> CallToolResponse {
-    let mut args = vec!["log".to_string()];
+    #[test]
+    fn test_resolve_step_references() {
+        let results = json!({
+            "create": {"output": "Working copy now at: kxyz", "changeId": "kxyzabcd"},
+            "stats": {"json": {"count": 3, "names": ["main", "dev"]}}
+        });
 
-    if let Some(limit) = params.limit {
-        args.push("-n".to_string());
-        args.push(limit.to_string());
+        let arguments = json!({
+            "source": "{{create.changeId}}",
+            "destination": "{{ stats.json.names.1 }}",
+            "limit": "{{stats.json.count}}",
+            "message": "Rebased {{create.changeId}} onto {{stats.json.names.0}}",
+            "paths": ["{{stats.json.names.0}}/src"],
+            "revision": "@-"
+        });
+        let resolved = resolve_step_references(&arguments, &results).unwrap();
+        assert_eq!(
+            resolved,
+            json!({
+                "source": "kxyzabcd",
+                "destination": "dev",
+                "limit": 3,
+                "message": "Rebased kxyzabcd onto main",
+                "paths": ["main/src"],
+                "revision": "@-"
+            })
+        );
+
+        assert!(resolve_step_references(&json!("{{missing.changeId}}"), &results).is_err());
     }
 
-    if let Some(template) = params.template {
-        args.push("-T".to_string());
-        args.push(template);
+    #[test]
+    fn test_author_args() {
+        assert_eq!(
+            author_config_args(Some("Release Bot"), Some("bot@example.com")).unwrap(),
+            vec![
+                "--config",
+                r#"user.name="Release Bot""#,
+                "--config",
+                r#"user.email="bot@example.com""#,
+            ]
+        );
+        assert!(author_config_args(None, None).unwrap().is_empty());
+        assert!(author_config_args(Some("Bot <bot@example.com>"), None).is_err());
+
+        assert_eq!(
+            author_flag_args(Some("Release Bot"), Some("bot@example.com"), None, None).unwrap(),
+            vec!["--author", "Release Bot <bot@example.com>"]
+        );
+        assert!(author_flag_args(None, None, None, None).unwrap().is_empty());
+        assert!(author_flag_args(Some("Bot"), Some("bot\n@example.com"), None, None).is_err());
     }
 
-    if let Some(revisions) = params.revisions {
-        args.push(revisions);
+    #[test]
+    fn test_signing_config_args() {
+        assert!(signing_config_args(None, None, None).unwrap().is_empty());
+        assert_eq!(
+            signing_config_args(Some(true), Some("ssh"), Some("~/.ssh/id_ed25519.pub")).unwrap(),
+            vec![
+                "--config",
+                r#"signing.behavior="own""#,
+                "--config",
+                r#"signing.backend="ssh""#,
+                "--config",
+                r#"signing.key="~/.ssh/id_ed25519.pub""#,
+            ]
+        );
+        assert_eq!(
+            signing_config_args(Some(false), None, None).unwrap(),
+            vec!["--config", r#"signing.behavior="drop""#]
+        );
+        assert!(signing_config_args(Some(true), Some("pgp"), None).is_err());
     }
 
-    add_repo_args(&mut args, params.repo_path);
+    #[test]
+    fn test_find_workspaces() {
+        let temp = tempfile::TempDir::new().unwrap();
+        for dir in [
+            "app/.jj",
+            "app/nested/.jj",
+            "libs/core/.jj",
+            "libs/deep/er/still/.jj",
+            ".hidden/repo/.jj",
+            "node_modules/dep/.jj",
+        ] {
+            std::fs::create_dir_all(temp.path().join(dir)).unwrap();
+        }
 
-    match run_jj_command_sync(args, params.cwd) {
-        Ok(output) => CallToolResponse {
-            content: vec![ToolResponseContent::Text { text: output }],
-            is_error: Some(false),
-            meta: None,
-        },
-        Err(e) => CallToolResponse {
-            content: vec![ToolResponseContent::Text {
-                text: e.to_string(),
-            }],
-            is_error: Some(true),
-            meta: None,
-        },
+        let found = find_workspaces(temp.path(), 3);
+        assert_eq!(
+            found,
+            vec![temp.path().join("app"), temp.path().join("libs/core")]
+        );
+        assert_eq!(
+            find_workspaces(temp.path(), 1),
+            vec![temp.path().join("app")]
+        );
+        assert_eq!(
+            find_workspaces(&temp.path().join("app"), 0),
+            vec![temp.path().join("app")]
+        );
     }
-}
 
-/// Execute jj diff command
-pub fn run_jj_diff(params: DiffParams) -> CallToolResponse {
-    let mut args = vec!["diff".to_string()];
+    #[test]
+    fn test_parse_git_remotes() {
+        let output =
+            "origin https://github.com/user/repo.git\nupstream git@github.com:org/repo.git";
+        assert_eq!(
+            parse_git_remotes(output),
+            vec![
+                GitRemote {
+                    name: "origin".to_string(),
+                    url: "https://github.com/user/repo.git".to_string(),
+                },
+                GitRemote {
+                    name: "upstream".to_string(),
+                    url: "git@github.com:org/repo.git".to_string(),
+                },
+            ]
+        );
 
-    if let Some(from) = params.from {
-        args.push("--from".to_string());
-        args.push(from);
+        let output = "ref: refs/heads/main\tHEAD\n1234abcd5678ef01\tHEAD";
+        assert_eq!(
+            parse_ls_remote_head(output),
+            (
+                Some("main".to_string()),
+                Some("1234abcd5678ef01".to_string())
+            )
+        );
     }
 
-    if let Some(to) = params.to {
-        args.push("--to".to_string());
-        args.push(to);
+    #[test]
+    fn test_stash_bookmark() {
+        assert_eq!(stash_bookmark("wip"), "stash/wip");
+        assert_eq!(stash_bookmark("stash/wip"), "stash/wip");
     }
 
-    if let Some(context) = params.context {
-        args.push("--context".to_string());
-        args.push(context.to_string());
+    #[test]
+    fn test_render_dot() {
+        let nodes = vec![
+            GraphNode {
+                commit_id: "bbb".to_string(),
+                change_id: "kx".to_string(),
+                parents: vec!["aaa".to_string()],
+                bookmarks: vec!["feature".to_string()],
+                working_copy: true,
+                immutable: false,
+                description: "Add \"quotes\"".to_string(),
+            },
+            GraphNode {
+                commit_id: "aaa".to_string(),
+                change_id: "qp".to_string(),
+                parents: vec!["zzz".to_string()],
+                bookmarks: Vec::new(),
+                working_copy: false,
+                immutable: true,
+                description: String::new(),
+            },
+        ];
+
+        let dot = render_dot(&nodes);
+        assert!(dot.starts_with("digraph commits {\n"));
+        assert!(dot.contains(r#""bbb" [label="kx (feature)\nAdd \"quotes\"", penwidth=2];"#));
+        assert!(dot.contains(
+            r#""aaa" [label="qp\n(no description set)", style=filled, fillcolor=lightgray];"#
+        ));
+        assert!(dot.contains(r#""bbb" -> "aaa";"#));
+        assert!(!dot.contains("zzz"));
     }
 
-    if let Some(true) = params.summary {
-        args.push("--summary".to_string());
+    #[test]
+    fn test_render_mermaid() {
+        let nodes = vec![
+            GraphNode {
+                commit_id: "bbb".to_string(),
+                change_id: "kx".to_string(),
+                parents: vec!["aaa".to_string(), "zzz".to_string()],
+                bookmarks: Vec::new(),
+                working_copy: true,
+                immutable: false,
+                description: "Use <T> in \"#1\"".to_string(),
+            },
+            GraphNode {
+                commit_id: "aaa".to_string(),
+                change_id: "qp".to_string(),
+                parents: Vec::new(),
+                bookmarks: vec!["main".to_string()],
+                working_copy: false,
+                immutable: true,
+                description: "Initial".to_string(),
+            },
+        ];
+
+        let mermaid = render_mermaid(&nodes);
+        assert!(mermaid.starts_with("flowchart TB\n"));
+        assert!(mermaid.contains(r#"cbbb["kx<br/>Use #lt;T#gt; in #quot;#35;1#quot;"]"#));
+        assert!(mermaid.contains(r#"caaa["qp (main)<br/>Initial"]"#));
+        assert!(mermaid.contains("cbbb --> caaa\n"));
+        assert!(!mermaid.contains("zzz"));
+        assert!(mermaid.contains("class cbbb workingCopy\n"));
+        assert!(mermaid.contains("class caaa immutable\n"));
     }
 
-    if let Some(true) = params.stat {
-        args.push("--stat".to_string());
+    #[test]
+    fn test_render_html_diff() {
+        let diff = "diff --git a/src/a.rs b/src/a.rs
+index 1111111..2222222 100644
+--- a/src/a.rs
++++ b/src/a.rs
+@@ -1,3 +1,4 @@
+ fn main() {
+-    let x = 1;
++    let x = 2;
++    let y = x < 3;
+ }
+";
+        let html = render_html_diff(diff);
+        assert!(html.starts_with("<!DOCTYPE html>"));
+        assert!(html.contains("<h2>src/a.rs</h2>"));
+        assert!(html.contains(
+            "<tr><td class=\"num\">2</td><td class=\"del\">    let x = 1;</td><td class=\"num\">2</td><td class=\"add\">    let x = 2;</td></tr>"
+        ));
+        assert!(html.contains(
+            "<tr><td class=\"num\"></td><td class=\"\"></td><td class=\"num\">3</td><td class=\"add\">    let y = x &lt; 3;</td></tr>"
+        ));
+        assert!(html.contains(
+            "<tr><td class=\"num\">3</td><td class=\"\">}</td><td class=\"num\">4</td><td class=\"\">}</td></tr>"
+        ));
+        assert!(render_html_diff("").contains("No changes."));
     }
 
-    if let Some(paths) = params.paths {
-        args.extend(paths);
+    #[test]
+    fn test_word_diff_line() {
+        assert_eq!(
+            word_diff_line("let x = foo(1);", "let x = bar(1, 2);"),
+            "let x = [-foo-]{+bar+}(1{+, 2+});"
+        );
+        assert_eq!(word_diff_line("same", "same"), "same");
     }
 
-    add_repo_args(&mut args, params.repo_path);
+    #[test]
+    fn test_word_diff() {
+        let diff = "diff --git a/a.txt b/a.txt
+--- a/a.txt
++++ b/a.txt
+@@ -1,3 +1,3 @@
+ first line
+-the quick fox
++the slow fox
+-gone
+";
+        assert_eq!(
+            word_diff(diff),
+            "diff --git a/a.txt b/a.txt
+--- a/a.txt
++++ b/a.txt
+@@ -1,3 +1,3 @@
+first line
+the [-quick-]{+slow+} fox
+[-gone-]
+"
+        );
+    }
 
-    match run_jj_command_sync(args, params.cwd) {
-        Ok(output) => CallToolResponse {
-            content: vec![ToolResponseContent::Text { text: output }],
-            is_error: Some(false),
-            meta: None,
-        },
-        Err(e) => CallToolResponse {
-            content: vec![ToolResponseContent::Text {
-                text: e.to_string(),
-            }],
-            is_error: Some(true),
-            meta: None,
-        },
+    #[test]
+    fn test_parse_stat_summary() {
+        assert_eq!(
+            parse_stat_summary("3 files changed, 10 insertions(+), 2 deletions(-)"),
+            Some((3, 10, 2))
+        );
+        assert_eq!(
+            parse_stat_summary("1 file changed, 1 deletion(-)"),
+            Some((1, 0, 1))
+        );
+        assert_eq!(
+            parse_stat_summary("0 files changed, 0 insertions(+), 0 deletions(-)"),
+            Some((0, 0, 0))
+        );
+        assert_eq!(parse_stat_summary("src/lib.rs | 3 ++-"), None);
     }
-}
 
-/// Execute jj git clone command
-pub fn run_jj_git_clone(params: GitCloneParams) -> CallToolResponse {
-    let mut args = vec!["git".to_string(), "clone".to_string()];
+    #[test]
+    fn test_aggregate_author_stats() {
+        let output = "::author-stats::Alice\talice@example.com
+src/a.rs | 3 ++-
+1 file changed, 2 insertions(+), 1 deletion(-)
+::author-stats::Bob\tbob@example.com
+0 files changed, 0 insertions(+), 0 deletions(-)
+::author-stats::Alice\tALICE@example.com
+a | 1 +
+b | 1 +
+2 files changed, 2 insertions(+)
+";
+        let stats = aggregate_author_stats(output);
+        assert_eq!(stats.len(), 2);
+        assert_eq!(stats[0].name, "Alice");
+        assert_eq!(stats[0].commits, 2);
+        assert_eq!(stats[0].files_changed, 3);
+        assert_eq!(stats[0].insertions, 4);
+        assert_eq!(stats[0].deletions, 1);
+        assert_eq!(stats[1].name, "Bob");
+        assert_eq!(stats[1].commits, 1);
+        assert_eq!(stats[1].insertions, 0);
+    }
 
-    if let Some(source) = params.source {
-        args.push(source);
+    #[test]
+    fn test_lock_contention() {
+        assert!(is_lock_contention(
+            "Error: Failed to lock working copy: Resource temporarily unavailable"
+        ));
+        assert!(is_lock_contention(
+            "fatal: Unable to create '/repo/.git/index.lock': File exists."
+        ));
+        assert!(!is_lock_contention("Error: Revision `nope` doesn't exist"));
+
+        assert_eq!(lock_retry_delay(0), std::time::Duration::from_millis(100));
+        assert_eq!(lock_retry_delay(2), std::time::Duration::from_millis(400));
+        assert_eq!(lock_retry_delay(10), std::time::Duration::from_secs(2));
+
+        let none = |_: &str| None;
+        let config = ServerConfig::from_sources(["--lock-retries=0".to_string()], none).unwrap();
+        assert_eq!(config.lock_retries(), 0);
+        assert_eq!(ServerConfig::default().lock_retries(), DEFAULT_LOCK_RETRIES);
     }
 
-    if let Some(destination) = params.destination {
-        args.push(destination);
+    #[test]
+    fn test_concurrent_operation() {
+        assert_eq!(
+            concurrent_operation("Concurrent modification detected, resolving automatically.\n"),
+            Some(ConcurrentOperation::Merged)
+        );
+        assert_eq!(
+            concurrent_operation(
+                "Error: The working copy is stale (not updated since operation 1a2b3c).\nHint: Run `jj workspace update-stale` to update it."
+            ),
+            Some(ConcurrentOperation::StaleWorkingCopy)
+        );
+        assert_eq!(concurrent_operation("Working copy now at: kx 12ab"), None);
+
+        let error = jj_failure(
+            &["status".to_string()],
+            "Error: The working copy is stale (not updated since operation 1a2b3c).",
+        )
+        .to_string();
+        let report: Value = serde_json::from_str(error.strip_prefix("Error: ").unwrap()).unwrap();
+        assert_eq!(report["kind"], "stale working copy");
+        assert!(report["hint"].as_str().unwrap().contains("reconcile"));
     }
 
-    if let Some(true) = params.colocate {
-        args.push("--colocate".to_string());
+    #[test]
+    fn test_server_config_launch_options() {
+        let args = config_file_args(
+            r#"{"enable-groups": ["read", "write"], "max-concurrency": 2, "read-only": true, "jj-bin": "/opt/jj"}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            args,
+            vec![
+                "--enable-groups=read,write",
+                "--jj-bin=/opt/jj",
+                "--max-concurrency=2",
+                "--read-only=true",
+            ]
+        );
+        assert!(config_file_args(r#"{"roots": {"a": 1}}"#).is_err());
+        assert!(config_file_args("[]").is_err());
+
+        let env = |var: &str| (var == LOG_LEVEL_ENV_VAR).then(|| "DEBUG".to_string());
+        let config = ServerConfig::from_sources(args, env).unwrap();
+        assert!(config.read_only);
+        assert_eq!(config.jj_bin.as_deref(), Some("/opt/jj"));
+        assert_eq!(config.log_level.as_deref(), Some("debug"));
+        assert!(config.is_tool_enabled("log"));
+        assert!(!config.is_tool_enabled("rebase"));
+
+        let none = |_: &str| None;
+        assert!(ServerConfig::from_sources(["--log-level=loud".to_string()], none).is_err());
+        assert!(ServerConfig::from_sources(["--log-format=xml".to_string()], none).is_err());
+        assert!(ServerConfig::from_sources(["--read-only=maybe".to_string()], none).is_err());
     }
 
-    if let Some(remote) = params.remote {
-        args.push("--remote".to_string());
-        args.push(remote);
+    #[test]
+    fn test_export_tool_catalog() {
+        let catalog = export_tool_catalog();
+        let tools = catalog["tools"].as_array().unwrap();
+        assert_eq!(tools.len(), all_tools().len());
+
+        let names: std::collections::HashSet<&str> = tools
+            .iter()
+            .map(|tool| tool["name"].as_str().unwrap())
+            .collect();
+        assert_eq!(names.len(), tools.len(), "tool names must be unique");
+
+        let status = tools.iter().find(|tool| tool["name"] == "status").unwrap();
+        assert_eq!(status["group"], "read");
+        assert_eq!(status["annotations"]["readOnlyHint"], true);
+        assert!(status["inputSchema"]["properties"]["repoPath"].is_object());
+        assert!(status["inputSchema"]["properties"]["explain"].is_object());
+        assert!(status["inputSchema"]["properties"]["timestamp"].is_null());
+
+        let commit = tools.iter().find(|tool| tool["name"] == "commit").unwrap();
+        assert!(commit["inputSchema"]["properties"]["timestamp"].is_object());
     }
 
-    if let Some(depth) = params.depth {
-        args.push("--depth".to_string());
-        args.push(depth.to_string());
+    #[test]
+    fn test_deepen_git_args() {
+        assert_eq!(
+            deepen_git_args("origin", Some(50)),
+            vec!["fetch", "--deepen=50", "origin"]
+        );
+        assert_eq!(
+            deepen_git_args("upstream", None),
+            vec!["fetch", "--unshallow", "upstream"]
+        );
     }
 
-    match run_jj_command_sync(args, None) {
-        Ok(output) => CallToolResponse {
-            content: vec![ToolResponseContent::Text { text: output }],
-            is_error: Some(false),
-            meta: None,
-        },
-        Err(e) => CallToolResponse {
-            content: vec![ToolResponseContent::Text {
-                text: e.to_string(),
-            }],
-            is_error: Some(true),
-            meta: None,
-        },
+    #[test]
+    fn test_parse_gitmodules() {
+        let contents = "[submodule \"vendor/lib\"]\n\tpath = vendor/lib\n\turl = https://example.com/lib.git\n\tbranch = main\n[core]\n\tpath = ignored\n[submodule \"docs\"]\n\turl = https://example.com/docs.git\n[submodule \"tools\"]\n\tpath = \"tools\"\n";
+        let submodules = parse_gitmodules(contents);
+        assert_eq!(submodules.len(), 2);
+        assert_eq!(submodules[0].name, "vendor/lib");
+        assert_eq!(submodules[0].path, "vendor/lib");
+        assert_eq!(
+            submodules[0].url.as_deref(),
+            Some("https://example.com/lib.git")
+        );
+        assert_eq!(submodules[0].branch.as_deref(), Some("main"));
+        assert_eq!(submodules[1].path, "tools");
+        assert_eq!(submodules[1].url, None);
     }
-}
 
+    #[test]
+    #[cfg(unix)]
+    fn test_working_copy_write_path() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let root = temp_dir.path().join("repo");
+        let outside = temp_dir.path().join("outside");
+        std::fs::create_dir_all(root.join(".jj")).unwrap();
+        std::fs::create_dir_all(&outside).unwrap();
+        std::os::unix::fs::symlink(&outside, root.join("link")).unwrap();
+        std::os::unix::fs::symlink(root.join(".jj"), root.join("meta")).unwrap();
+        std::os::unix::fs::symlink(outside.join("file"), root.join("file-link")).unwrap();
+
+        let path = working_copy_write_path(&root, "src/new/lib.rs").unwrap();
+        assert_eq!(path, root.join("src/new/lib.rs"));
+        assert!(root.join("src/new").is_dir());
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use serde_json::json;
+        assert!(working_copy_write_path(&root, "link/passwd").is_err());
+        assert!(working_copy_write_path(&root, "link/sub/passwd").is_err());
+        assert!(!outside.join("sub").exists());
+        assert!(working_copy_write_path(&root, "meta/repo/store").is_err());
+        assert!(working_copy_write_path(&root, ".jj/working_copy").is_err());
+        assert!(working_copy_write_path(&root, "file-link").is_err());
+    }
 
+    #[cfg(unix)]
     #[test]
-    fn test_jj_tool_creation() {
-        let tool = JjTool {
-            name: "test-tool".to_string(),
-            description: "Test tool description".to_string(),
-            input_schema: json!({"type": "object"}),
-        };
+    fn test_apply_unified_diff_stays_in_workspace() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let root = temp_dir.path().join("repo");
+        let outside = temp_dir.path().join("outside");
+        std::fs::create_dir_all(root.join(".jj/repo")).unwrap();
+        std::fs::create_dir_all(&outside).unwrap();
+        std::fs::write(outside.join("victim.txt"), "old\n").unwrap();
+        std::os::unix::fs::symlink(&outside, root.join("link")).unwrap();
 
-        assert_eq!(tool.name(), "test-tool");
-        assert_eq!(tool.description(), "Test tool description");
-        assert_eq!(tool.input_schema(), json!({"type": "object"}));
+        let add = |path: &str| format!("--- /dev/null\n+++ b/{}\n@@ -0,0 +1,1 @@\n+[ui]\n", path);
+        assert!(apply_unified_diff(&root, &add(".jj/repo/config.toml")).is_err());
+        assert!(!root.join(".jj/repo/config.toml").exists());
+        assert!(apply_unified_diff(&root, &add("link/new.txt")).is_err());
+        assert!(!outside.join("new.txt").exists());
+
+        let edit = "--- a/link/victim.txt\n+++ b/link/victim.txt\n@@ -1,1 +1,1 @@\n-old\n+new\n";
+        assert!(apply_unified_diff(&root, edit).is_err());
+        let delete = "--- a/link/victim.txt\n+++ /dev/null\n@@ -1,1 +0,0 @@\n-old\n";
+        assert!(apply_unified_diff(&root, delete).is_err());
+        assert_eq!(
+            std::fs::read_to_string(outside.join("victim.txt")).unwrap(),
+            "old\n"
+        );
+
+        assert_eq!(
+            apply_unified_diff(&root, &add("src/new.txt")).unwrap(),
+            vec!["src/new.txt"]
+        );
+        assert!(root.join("src/new.txt").is_file());
+
+        let rename = "diff --git a/a.txt b/.jj/a.txt\nrename from a.txt\nrename to .jj/a.txt\n";
+        assert!(patch_paths(rename).contains(&".jj/a.txt".to_string()));
     }
 
     #[test]
-    fn test_add_repo_args() {
-        let mut args = vec!["status".to_string()];
-        add_repo_args(&mut args, Some("/path/to/repo".to_string()));
+    fn test_parse_conflict_list() {
+        let output = "\
+src/lib.rs                     2-sided conflict
+docs/release notes.md          2-sided conflict including 1 deletion
+a very long path with spaces/and/more/directories.txt 3-sided conflict including an executable
+";
+        assert_eq!(
+            parse_conflict_list(output),
+            vec![
+                "src/lib.rs",
+                "docs/release notes.md",
+                "a very long path with spaces/and/more/directories.txt",
+            ]
+        );
+        assert!(parse_conflict_list("").is_empty());
+    }
 
-        assert_eq!(args, vec!["status", "-R", "/path/to/repo"]);
+    #[test]
+    fn test_is_repository_metadata() {
+        assert!(is_repository_metadata(".jj/repo/store"));
+        assert!(is_repository_metadata("./.git/config"));
+        assert!(!is_repository_metadata("src/.git-blame-ignore"));
+        assert!(!is_repository_metadata("docs/.jj"));
     }
 
     #[test]
-    fn test_add_repo_args_none() {
-        let mut args = vec!["status".to_string()];
-        add_repo_args(&mut args, None);
+    fn test_annotated_line_range() {
+        let output = "abc one\nabc two\ndef three\nabc four\n";
+        assert_eq!(
+            annotated_line_range(output, 2..=3, false),
+            "abc two\ndef three\n"
+        );
+        assert_eq!(
+            annotated_line_range(output, 4..=usize::MAX, false),
+            "abc four\n"
+        );
 
-        assert_eq!(args, vec!["status"]);
+        let marked = format!(
+            "{m}1\tabc\none\n{m}2\tdef\ntwo\n{m}3\tabc\nthree\n",
+            m = ANNOTATE_LINE_MARKER
+        );
+        assert_eq!(annotated_line_range(&marked, 2..=2, true), "def\ntwo\n");
     }
 
     #[test]
-    fn test_status_params_default() {
-        let params = StatusParams::default();
-        assert!(params.repo_path.is_none());
-        assert!(params.cwd.is_none());
+    fn test_parse_rebase_output() {
+        let stderr = "Rebased 1 commits onto destination\nRebased 2 descendant commits\nWorking copy  (@) now at: kkmpptxz 4f5e6d7c (conflict) Add parser\nParent commit (@-)      : rlvkpnrz 9a8b7c6d main | Base\nAdded 0 files, modified 1 files, removed 0 files\nWarning: There are unresolved conflicts at these paths:\nsrc/lib.rs    2-sided conflict\nNew conflicts appeared in these commits:\n  zsuskuln 3c4d5e6f (conflict) Fix lexer\n  kkmpptxz 4f5e6d7c (conflict) Add parser\nHint: To resolve the conflicts, start by creating a commit on top of\n";
+        let summary = parse_rebase_output(stderr);
+        assert_eq!(summary.rebased_count, 3);
+        let working_copy = summary.working_copy.unwrap();
+        assert_eq!(working_copy.change_id, "kkmpptxz");
+        assert_eq!(working_copy.commit_id, "4f5e6d7c");
+        assert!(working_copy.conflicted);
+        assert_eq!(working_copy.description, "Add parser");
+        assert_eq!(summary.new_conflicts.len(), 2);
+        assert_eq!(summary.new_conflicts[0].change_id, "zsuskuln");
+        assert_eq!(summary.new_conflicts[0].description, "Fix lexer");
+
+        let summary = parse_rebase_output("Nothing changed.\n");
+        assert_eq!(summary, RebaseSummary::default());
     }
 
     #[test]
-    fn test_status_params_deserialization() {
-        let json_val = json!({
-            "repoPath": "/test/repo",
-            "cwd": "/test/dir"
-        });
+    fn test_commit_message_args() {
+        let message = CommitMessage::new("-v flag removed".to_string()).unwrap();
+        assert_eq!(message.args(), vec!["--message=-v flag removed"]);
 
-        let params: StatusParams = serde_json::from_value(json_val).unwrap();
-        assert_eq!(params.repo_path, Some("/test/repo".to_string()));
-        assert_eq!(params.cwd, Some("/test/dir".to_string()));
+        let text = "Subject\n\nBody with \"quotes\" and $vars\nJJ: kept\n";
+        let message = CommitMessage::new(text.to_string()).unwrap();
+        assert_eq!(message.args(), vec![format!("--message={}", text)]);
+
+        let message = CommitMessage::new("x".repeat(MAX_INLINE_MESSAGE_BYTES + 1)).unwrap();
+        assert!(!message.is_inline());
+        assert_eq!(message.args(), vec!["--stdin"]);
+
+        assert!(CommitMessage::new("a\0b".to_string()).is_err());
+        assert!(
+            CommitMessage::from_params(Some("a".to_string()), Some("b".to_string()), None).is_err()
+        );
+        assert!(
+            CommitMessage::from_params(None, None, None)
+                .unwrap()
+                .is_none()
+        );
     }
 
     #[test]
-    fn test_rebase_params_deserialization() {
-        let json_val = json!({
-            "source": "@",
-            "destination": "main",
-            "repoPath": "/test/repo"
+    fn test_validate_arguments() {
+        let schema = json!({
+            "type": "object",
+            "properties": {
+                "revisions": {"type": "string"},
+                "limit": {"type": "number"},
+                "repoPath": {"type": "string"}
+            }
         });
+        assert!(validate_arguments(&schema, &json!({"revisions": "@", "limit": 5})).is_ok());
+        assert!(validate_arguments(&schema, &Value::Null).is_ok());
+        // Global parameters are accepted even when the schema doesn't list them
+        assert!(validate_arguments(&schema, &json!({"explain": true})).is_ok());
 
-        let params: RebaseParams = serde_json::from_value(json_val).unwrap();
-        assert_eq!(params.source, Some("@".to_string()));
-        assert_eq!(params.destination, Some("main".to_string()));
-        assert_eq!(params.repo_path, Some("/test/repo".to_string()));
+        let error = validate_arguments(&schema, &json!({"revsions": "@"})).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Error: unknown parameter revsions (did you mean revisions?)"
+        );
+        let error = validate_arguments(&schema, &json!({"frobnicate": 1})).unwrap_err();
+        assert_eq!(error.to_string(), "Error: unknown parameter frobnicate");
+
+        let error = validate_arguments(&schema, &json!({"limit": "ten"})).unwrap_err();
+        assert!(
+            error
+                .to_string()
+                .starts_with("Error: invalid arguments: limit: ")
+        );
+
+        // A schema without properties only checks the type
+        assert!(validate_arguments(&json!({"type": "object"}), &json!({"anything": 1})).is_ok());
+
+        for tool in all_tools() {
+            assert!(
+                jsonschema::validator_for(&tool.input_schema).is_ok(),
+                "{} has an invalid input schema",
+                tool.name
+            );
+        }
     }
 
     #[test]
-    fn test_tool_call_unknown_tool() {
-        let tool = JjTool {
-            name: "unknown".to_string(),
-            description: "Unknown tool".to_string(),
-            input_schema: json!({}),
-        };
+    fn test_binary_content() {
+        assert!(!is_binary_content(b"fn main() {}\n"));
+        assert!(!is_binary_content("caf\u{e9}\n".as_bytes()));
+        assert!(is_binary_content(b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR"));
+        assert!(is_binary_content(&[0xff, 0xfe, 0x41]));
 
-        let result = tool.call(None).unwrap();
-        assert_eq!(result.is_error, Some(true));
+        assert_eq!(
+            binary_mime_type("logo", b"\x89PNG\r\n\x1a\n\0\0"),
+            "image/png"
+        );
+        assert_eq!(binary_mime_type("doc.pdf", b"%PDF-1.7"), "application/pdf");
+        assert_eq!(binary_mime_type("photo.jpeg", &[0, 1, 2]), "image/jpeg");
+        assert_eq!(
+            binary_mime_type("data.bin", &[0, 1, 2]),
+            "application/octet-stream"
+        );
 
-        if let ToolResponseContent::Text { text } = &result.content[0] {
-            assert!(text.contains("Unknown tool: unknown"));
-        } else {
-            panic!("Expected text content");
-        }
+        let summary = binary_content_summary("logo.png", "@", b"\x89PNG\r\n\x1a\n\0", false);
+        assert_eq!(summary["binary"], json!(true));
+        assert_eq!(summary["size"], json!(9));
+        assert_eq!(summary["mimeType"], json!("image/png"));
+        assert!(summary.get("base64").is_none());
+        let summary = binary_content_summary("a.bin", "@", &[0, 1], true);
+        assert_eq!(summary["base64"], json!("AAE="));
     }
 
     #[test]
-    fn test_run_jj_command_sync_invalid_command() {
-        // Test with an invalid jj command
-        let result = run_jj_command_sync(vec!["invalid-command".to_string()], None);
-        assert!(result.is_err());
+    fn test_command_timeout() {
+        let none = |_: &str| None;
+        let config =
+            ServerConfig::from_sources(["--command-timeout=2.5".to_string()], none).unwrap();
+        assert_eq!(
+            config.command_timeout,
+            Some(std::time::Duration::from_millis(2500))
+        );
+        let config = ServerConfig::from_sources(["--command-timeout=0".to_string()], none).unwrap();
+        assert_eq!(config.command_timeout, None);
+        assert!(ServerConfig::from_sources(["--command-timeout=soon".to_string()], none).is_err());
+
+        let options = CallOptions::from_args(&json!({"timeout": 10}));
+        let timeout = with_call_options(options, command_timeout);
+        assert_eq!(timeout, Some(std::time::Duration::from_secs(10)));
+        let options = CallOptions::from_args(&json!({"timeout": 0}));
+        assert_eq!(with_call_options(options, command_timeout), None);
+
+        let args = vec!["log".to_string()];
+        let error = jj_timeout(&args, std::time::Duration::from_secs(5), b"@  abc\n");
+        let report: Value =
+            serde_json::from_str(error.to_string().strip_prefix("Error: ").unwrap()).unwrap();
+        assert_eq!(report["code"], json!("timeout"));
+        assert_eq!(report["partial"], json!(true));
+        assert_eq!(report["output"], json!("@  abc"));
+        assert_eq!(report["timeoutSeconds"], json!(5.0));
     }
 
     #[test]
-    fn test_error_response_format() {
-        let params = StatusParams {
-            repo_path: Some("/nonexistent/path".to_string()),
-            cwd: None,
+    fn test_log_revset() {
+        assert_eq!(log_revset(None, None, None, None), None);
+        assert_eq!(
+            log_revset(Some("main"), None, None, None).as_deref(),
+            Some("main")
+        );
+        assert_eq!(
+            log_revset(None, Some("alice"), None, None).as_deref(),
+            Some("(all()) & author(\"alice\")")
+        );
+        assert_eq!(
+            log_revset(
+                Some("main..@"),
+                Some("Bob \"B\""),
+                Some("2024-01-01"),
+                Some("1 week ago")
+            )
+            .as_deref(),
+            Some(
+                "(main..@) & author(\"Bob \\\"B\\\"\") \
+                 & committer_date(after:\"2024-01-01\") \
+                 & committer_date(before:\"1 week ago\")"
+            )
+        );
+    }
+
+    #[test]
+    fn test_diff_base_revset() {
+        assert_eq!(
+            diff_base_revset("auto", "@"),
+            "latest(heads(::(trunk()) & ::(@)))"
+        );
+        assert_eq!(
+            diff_base_revset("main@origin", "feature-a | feature-b"),
+            "latest(heads(::(main@origin) & ::(feature-a | feature-b)))"
+        );
+    }
+
+    #[test]
+    fn test_parse_diff_summary() {
+        let output =
+            "M src/lib.rs\nA docs/new file.md\nD old.txt\nR {a.txt => b.txt}\nWarning: ignored\n";
+        assert_eq!(
+            parse_diff_summary(output),
+            vec![
+                FileChange {
+                    status: "modified".to_string(),
+                    path: "src/lib.rs".to_string(),
+                },
+                FileChange {
+                    status: "added".to_string(),
+                    path: "docs/new file.md".to_string(),
+                },
+                FileChange {
+                    status: "deleted".to_string(),
+                    path: "old.txt".to_string(),
+                },
+                FileChange {
+                    status: "renamed".to_string(),
+                    path: "{a.txt => b.txt}".to_string(),
+                },
+            ]
+        );
+        assert!(parse_diff_summary("").is_empty());
+    }
+
+    #[test]
+    fn test_fit_to_budget() {
+        assert_eq!(output_budget(None, None), None);
+        assert_eq!(output_budget(Some(1000), Some(100)), Some(400));
+        assert_eq!(output_budget(Some(300), Some(100)), Some(300));
+
+        let levels = |texts: [&'static str; 2]| {
+            ["full", "stat"]
+                .into_iter()
+                .zip(texts)
+                .map(|(level, text)| (level, move || -> Result<String> { Ok(text.to_string()) }))
         };
+        let fitted = fit_to_budget(10, levels(["short", "unused"])).unwrap();
+        assert_eq!((fitted.level.as_str(), fitted.truncated), ("full", false));
+        let fitted = fit_to_budget(10, levels(["far too long", "short"])).unwrap();
+        assert_eq!(
+            (fitted.level.as_str(), fitted.text.as_str()),
+            ("stat", "short")
+        );
+        let fitted = fit_to_budget(10, levels(["far too long", "line one\nline two\n"])).unwrap();
+        assert_eq!(fitted.level, "stat");
+        assert!(fitted.truncated);
+        assert_eq!(
+            fitted.text,
+            "line one\n[9 more bytes omitted to fit maxBytes/maxTokens]\n"
+        );
 
-        let result = run_jj_status(params);
-        assert_eq!(result.is_error, Some(true));
-        assert_eq!(result.content.len(), 1);
+        assert_eq!(truncate_to_budget("fits", 10), "fits");
+        assert_eq!(
+            truncate_to_budget("héllo", 2),
+            "h[5 more bytes omitted to fit maxBytes/maxTokens]\n"
+        );
+    }
 
-        if let ToolResponseContent::Text { text } = &result.content[0] {
-            assert!(text.contains("Error:"));
-        } else {
-            panic!("Expected text content");
+    #[cfg(feature = "jj-lib")]
+    #[test]
+    fn test_jj_lib_supports_revset() {
+        assert!(jj_lib_backend::supports_revset("trunk()..main"));
+        assert!(!jj_lib_backend::supports_revset("@-"));
+        assert!(!jj_lib_backend::supports_revset("mine() & ~empty()"));
+        assert!(!jj_lib_backend::supports_revset("author(x) | mine ()"));
+        assert!(jj_lib_backend::supports_revset("bookmarks(mine-feature)"));
+    }
+
+    #[test]
+    fn test_files_revset() {
+        assert_eq!(
+            files_revset(&["a.txt".to_string()]),
+            r#"files(file:"a.txt")"#
+        );
+        assert_eq!(
+            files_revset(&["my file.txt".to_string(), "x|y(\"z\")".to_string()]),
+            r#"files(file:"my file.txt" | file:"x|y(\"z\")")"#
+        );
+    }
+
+    #[test]
+    fn test_data_uri() {
+        let uri = data_uri("text/html", b"<p>a & b</p>").unwrap();
+        assert_eq!(uri.scheme(), "data");
+        assert_eq!(uri.as_str(), "data:text/html;base64,PHA+YSAmIGI8L3A+");
+    }
+
+    #[test]
+    fn test_parse_symlink_types() {
+        let types = "-F src/main.rs\n-L docs/latest\n-X build.sh\n-L my link\n";
+        assert_eq!(parse_symlink_types(types), vec!["docs/latest", "my link"]);
+
+        let diff = "diff --git a/docs/latest b/docs/latest
+new file mode 120000
+index 0000000000..1111111111
+--- /dev/null
++++ b/docs/latest
+@@ -0,0 +1,1 @@
++v1.2/index.md
+\\ No newline at end of file
+";
+        assert_eq!(parse_added_content(diff), "v1.2/index.md");
+    }
+
+    #[test]
+    fn test_store_output_prunes_oldest() {
+        let first = store_output("first").unwrap();
+        let path = first.to_file_path().unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "first");
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(output_store_dir().unwrap())
+                .unwrap()
+                .permissions()
+                .mode();
+            assert_eq!(mode & 0o077, 0);
         }
+
+        for i in 0..MAX_STORED_OUTPUTS {
+            store_output(&i.to_string()).unwrap();
+        }
+        assert!(!path.exists());
+        assert!(read_stored_output(first.as_str(), 0, 10).is_err());
+    }
+
+    #[test]
+    fn test_working_copy_state() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let root = dir.path();
+        let heads = root.join(".jj/repo/op_heads/heads");
+        std::fs::create_dir_all(&heads).unwrap();
+        std::fs::create_dir_all(root.join(".jj/working_copy")).unwrap();
+        std::fs::write(heads.join("aaa"), "").unwrap();
+        std::fs::write(root.join(".jj/working_copy/tree_state"), "").unwrap();
+        let before = working_copy_state(root).unwrap();
+        assert!(before.starts_with("aaa;"));
+        assert_eq!(working_copy_state(root).unwrap(), before);
+
+        let later = std::time::SystemTime::now() + std::time::Duration::from_secs(60);
+        std::fs::File::options()
+            .write(true)
+            .open(root.join(".jj/working_copy/tree_state"))
+            .unwrap()
+            .set_modified(later)
+            .unwrap();
+        let snapshotted = working_copy_state(root).unwrap();
+        assert_ne!(snapshotted, before);
+
+        std::fs::rename(heads.join("aaa"), heads.join("bbb")).unwrap();
+        assert!(working_copy_state(root).unwrap().starts_with("bbb;"));
+    }
+
+    #[test]
+    fn test_complete_request() {
+        let request = json!({
+            "ref": {"type": "ref/prompt", "name": "log"},
+            "argument": {"name": "message", "value": "fi"},
+        });
+        let completion = complete_request(&request).unwrap();
+        assert_eq!(completion["completion"]["values"], json!([]));
+        assert_eq!(completion["completion"]["hasMore"], json!(false));
+
+        assert!(complete_request(&json!({"argument": {"value": "fi"}})).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_archive_destination() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let base = temp_dir.path().canonicalize().unwrap();
+        let root = base.join("repo");
+        let outside = base.join("out");
+        std::fs::create_dir_all(root.join("src")).unwrap();
+        std::fs::create_dir_all(&outside).unwrap();
+        std::os::unix::fs::symlink(root.join("src"), base.join("link")).unwrap();
+        std::os::unix::fs::symlink(root.join("missing"), base.join("dangling")).unwrap();
+
+        assert_eq!(
+            archive_destination(&root, &outside.join("build/v1")).unwrap(),
+            outside.join("build/v1")
+        );
+        assert!(archive_destination(&root, &root.join("dist")).is_err());
+        assert!(archive_destination(&root, &outside.join("../repo/dist")).is_err());
+        assert!(archive_destination(&root, &outside.join("new/../../repo/dist")).is_err());
+        assert!(archive_destination(&root, &base.join("link/dist")).is_err());
+        assert!(archive_destination(&root, &base.join("dangling")).is_err());
+        assert!(!root.join("dist").exists());
     }
 }
@@ -1,234 +1,348 @@
 use anyhow::Result;
+use clap::{Parser, ValueEnum};
 use jj_mcp_server::*;
-use mcp_sdk::server::Server;
+use mcp_sdk::protocol::{Protocol, RequestOptions};
 use mcp_sdk::tools::Tools;
-use mcp_sdk::transport::ServerStdioTransport;
-use mcp_sdk::types::ServerCapabilities;
-use serde_json::json;
+use mcp_sdk::transport::{JsonRpcResponse, ServerStdioTransport};
+use mcp_sdk::types::{
+    CallToolRequest, Implementation, InitializeRequest, InitializeResponse,
+    LATEST_PROTOCOL_VERSION, ListRequest, ServerCapabilities, ToolsListResponse,
+};
+use serde_json::{Value, json};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, OnceLock};
 
-fn create_tools() -> Tools {
-    let mut tools = Tools::default();
+/// Transports the server can listen on
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Transport {
+    /// JSON-RPC over stdin and stdout
+    Stdio,
+}
 
-    // Status tool
-    tools.add_tool(JjTool {
-        name: "status".to_string(),
-        description: "Show the status of the working directory".to_string(),
-        input_schema: json!({
-            "type": "object",
-            "properties": {
-                "repoPath": {
-                    "type": "string",
-                    "description": "Optional path to repo root"
-                },
-                "cwd": {
-                    "type": "string",
-                    "description": "Optional working directory"
-                }
-            }
-        }),
-    });
+/// MCP server for the Jujutsu (jj) version control system
+///
+/// Every option can also be set with a `JJ_MCP_*` environment variable or in the
+/// `--config` file; command-line values win.
+#[derive(Debug, Parser)]
+#[command(version, about)]
+struct Cli {
+    /// Transport to serve the MCP protocol over
+    #[arg(long, value_enum, default_value = "stdio")]
+    transport: Transport,
+    /// JSON file of settings keyed by option name, e.g. {"read-only": true}
+    #[arg(long, env = "JJ_MCP_CONFIG", value_name = "FILE")]
+    config: Option<std::path::PathBuf>,
+    /// Repository tools run in when a call gives neither repoPath nor cwd
+    #[arg(long, value_name = "PATH")]
+    repo: Option<String>,
+    /// Only expose tools that never modify the repository
+    #[arg(long)]
+    read_only: bool,
+    /// Most verbose diagnostics logged (default: info)
+    #[arg(long, value_parser = clap::builder::PossibleValuesParser::new(LOG_LEVELS))]
+    log_level: Option<String>,
+    /// Write logs as plain text or as one JSON object per line
+    #[arg(long, value_parser = ["text", "json"])]
+    log_format: Option<String>,
+    /// Append logs to this file instead of writing them to stderr
+    #[arg(long, value_name = "FILE")]
+    log_file: Option<String>,
+    /// jj binary to run instead of jj from PATH
+    #[arg(long, value_name = "PATH")]
+    jj_bin: Option<String>,
+    /// Print the registered tools with their schemas as JSON and exit
+    #[arg(long)]
+    list_tools: bool,
 
-    // Rebase tool
-    tools.add_tool(JjTool {
-        name: "rebase".to_string(),
-        description: "Rebase a revision onto another".to_string(),
-        input_schema: json!({
-            "type": "object",
-            "properties": {
-                "source": {
-                    "type": "string",
-                    "description": "Source revision to rebase"
-                },
-                "destination": {
-                    "type": "string",
-                    "description": "Destination revision to rebase onto"
-                },
-                "repoPath": {
-                    "type": "string",
-                    "description": "Optional path to repo root"
-                },
-                "cwd": {
-                    "type": "string",
-                    "description": "Optional working directory"
-                }
-            }
-        }),
-    });
+    /// Only expose tools in these groups (read, write, remote, admin)
+    #[arg(long, value_name = "GROUPS")]
+    enable_groups: Vec<String>,
+    /// Hide tools in these groups
+    #[arg(long, value_name = "GROUPS")]
+    disable_groups: Vec<String>,
+    /// Expose these tools regardless of their group
+    #[arg(long, value_name = "TOOLS")]
+    enable_tools: Vec<String>,
+    /// Hide these tools regardless of their group
+    #[arg(long, value_name = "TOOLS")]
+    disable_tools: Vec<String>,
+    /// Prefix for every exposed tool name, e.g. jj_
+    #[arg(long, value_name = "PREFIX")]
+    tool_prefix: Option<String>,
+    /// Directories tools may operate in, separated like PATH
+    #[arg(long, value_name = "DIRS")]
+    roots: Vec<String>,
+    /// Tools whose calls must be confirmed
+    #[arg(long, value_name = "TOOLS")]
+    require_confirmation: Vec<String>,
+    /// Larger text outputs are returned as resource links (0 for no limit)
+    #[arg(long, value_name = "BYTES")]
+    max_output_bytes: Option<String>,
+    /// Most jj processes run at once (0 for no limit)
+    #[arg(long, value_name = "N")]
+    max_concurrency: Option<String>,
+    /// Most diff or log output kept in memory (0 for no limit)
+    #[arg(long, value_name = "BYTES")]
+    max_stream_bytes: Option<String>,
+    /// Oldest jj version tools are offered for
+    #[arg(long, value_name = "VERSION")]
+    min_jj_version: Option<String>,
+    /// Default signing.behavior for commit and describe
+    #[arg(long, value_name = "BEHAVIOR")]
+    signing_behavior: Option<String>,
+    /// Default signing.backend
+    #[arg(long, value_name = "BACKEND")]
+    signing_backend: Option<String>,
+    /// Default signing.key
+    #[arg(long, value_name = "KEY")]
+    signing_key: Option<String>,
+    /// Times a command is retried while the repository is locked
+    #[arg(long, value_name = "N")]
+    lock_retries: Option<String>,
+    /// Seconds a jj command may run before it is stopped (0 for no limit)
+    #[arg(long, value_name = "SECONDS")]
+    command_timeout: Option<String>,
+}
 
-    // Commit tool
-    tools.add_tool(JjTool {
-        name: "commit".to_string(),
-        description: "Create a new commit".to_string(),
-        input_schema: json!({
-            "type": "object",
-            "properties": {
-                "message": {
-                    "type": "string",
-                    "description": "Commit message"
-                },
-                "repoPath": {
-                    "type": "string",
-                    "description": "Optional path to repo root"
-                },
-                "cwd": {
-                    "type": "string",
-                    "description": "Optional working directory"
-                }
+impl Cli {
+    /// The server settings given on the command line, as flags for [`ServerConfig::from_sources`]
+    fn server_args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+        let mut push = |flag: &str, value: &str| args.push(format!("--{}={}", flag, value));
+        for (flag, values) in [
+            ("enable-groups", &self.enable_groups),
+            ("disable-groups", &self.disable_groups),
+            ("enable-tools", &self.enable_tools),
+            ("disable-tools", &self.disable_tools),
+            ("roots", &self.roots),
+            ("require-confirmation", &self.require_confirmation),
+        ] {
+            for value in values {
+                push(flag, value);
             }
-        }),
-    });
-
-    // New tool
-    tools.add_tool(JjTool {
-        name: "new".to_string(),
-        description: "Create a new empty commit".to_string(),
-        input_schema: json!({
-            "type": "object",
-            "properties": {
-                "parents": {
-                    "type": "string",
-                    "description": "Parent revisions for the new commit"
-                },
-                "repoPath": {
-                    "type": "string",
-                    "description": "Optional path to repo root"
-                },
-                "cwd": {
-                    "type": "string",
-                    "description": "Optional working directory"
-                }
+        }
+        for (flag, value) in [
+            ("repo", &self.repo),
+            ("log-level", &self.log_level),
+            ("log-format", &self.log_format),
+            ("log-file", &self.log_file),
+            ("jj-bin", &self.jj_bin),
+            ("tool-prefix", &self.tool_prefix),
+            ("max-output-bytes", &self.max_output_bytes),
+            ("max-concurrency", &self.max_concurrency),
+            ("max-stream-bytes", &self.max_stream_bytes),
+            ("min-jj-version", &self.min_jj_version),
+            ("signing-behavior", &self.signing_behavior),
+            ("signing-backend", &self.signing_backend),
+            ("signing-key", &self.signing_key),
+            ("lock-retries", &self.lock_retries),
+            ("command-timeout", &self.command_timeout),
+        ] {
+            if let Some(value) = value {
+                push(flag, value);
             }
-        }),
-    });
+        }
+        if self.read_only {
+            push("read-only", "true");
+        }
+        args
+    }
+}
 
-    // Log tool
-    tools.add_tool(JjTool {
-        name: "log".to_string(),
-        description: "Show commit history".to_string(),
-        input_schema: json!({
-            "type": "object",
-            "properties": {
-                "limit": {
-                    "type": "number",
-                    "description": "Maximum number of commits to show"
-                },
-                "template": {
-                    "type": "string",
-                    "description": "Template for formatting output"
-                },
-                "revisions": {
-                    "type": "string",
-                    "description": "Revisions to show"
-                },
-                "repoPath": {
-                    "type": "string",
-                    "description": "Optional path to repo root"
-                },
-                "cwd": {
-                    "type": "string",
-                    "description": "Optional working directory"
-                }
-            }
-        }),
-    });
+/// Send `tracing` events to stderr or the configured log file
+///
+/// stdout carries the protocol, so logs never go there.
+fn init_logging(config: &ServerConfig) -> Result<()> {
+    use tracing_subscriber::fmt::writer::BoxMakeWriter;
 
-    // Diff tool
-    tools.add_tool(JjTool {
-        name: "diff".to_string(),
-        description: "Show differences between revisions".to_string(),
-        input_schema: json!({
-            "type": "object",
-            "properties": {
-                "from": {
-                    "type": "string",
-                    "description": "Source revision"
-                },
-                "to": {
-                    "type": "string",
-                    "description": "Target revision"
-                },
-                "paths": {
-                    "type": "array",
-                    "items": {"type": "string"},
-                    "description": "Specific paths to diff"
-                },
-                "context": {
-                    "type": "number",
-                    "description": "Number of context lines"
-                },
-                "summary": {
-                    "type": "boolean",
-                    "description": "Show summary only"
-                },
-                "stat": {
-                    "type": "boolean",
-                    "description": "Show file statistics"
-                },
-                "repoPath": {
-                    "type": "string",
-                    "description": "Optional path to repo root"
-                },
-                "cwd": {
-                    "type": "string",
-                    "description": "Optional working directory"
-                }
-            }
-        }),
-    });
+    let level: tracing::Level = config.log_level.as_deref().unwrap_or("info").parse()?;
+    let writer = match &config.log_file {
+        Some(path) => {
+            let file = std::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .map_err(|e| anyhow::anyhow!("Error: cannot open {}: {}", path.display(), e))?;
+            BoxMakeWriter::new(std::sync::Mutex::new(file))
+        }
+        None => BoxMakeWriter::new(std::io::stderr),
+    };
 
-    // Git clone tool
-    tools.add_tool(JjTool {
-        name: "git-clone".to_string(),
-        description: "Clone a Git repository using jj".to_string(),
-        input_schema: json!({
-            "type": "object",
-            "properties": {
-                "source": {
-                    "type": "string",
-                    "description": "Git repository URL to clone"
-                },
-                "destination": {
-                    "type": "string",
-                    "description": "Destination directory"
-                },
-                "colocate": {
-                    "type": "boolean",
-                    "description": "Create a colocated jj/git repository"
-                },
-                "remote": {
-                    "type": "string",
-                    "description": "Name for the remote"
-                },
-                "depth": {
-                    "type": "number",
-                    "description": "Depth for shallow clone"
+    let subscriber = tracing_subscriber::fmt()
+        .with_max_level(level)
+        .with_ansi(false)
+        .with_writer(writer);
+    match config.log_format.as_deref() {
+        Some("json") => subscriber.json().init(),
+        _ => subscriber.init(),
+    }
+    Ok(())
+}
+
+/// The protocol the server listens on, kept for sending requests to the client
+static PROTOCOL: OnceLock<Protocol<ServerStdioTransport>> = OnceLock::new();
+
+/// Whether the client said in `initialize` that it can list its roots
+static CLIENT_LISTS_ROOTS: AtomicBool = AtomicBool::new(false);
+
+/// Ask the client for its roots and use them in place of the configured ones
+///
+/// The request runs on its own task: the listen loop has to keep reading to
+/// receive the reply. A client that reports no local roots leaves the configured
+/// roots in place.
+fn refresh_client_roots() {
+    if !CLIENT_LISTS_ROOTS.load(Ordering::Relaxed) {
+        return;
+    }
+    let Some(protocol) = PROTOCOL.get().cloned() else {
+        return;
+    };
+    tokio::spawn(async move {
+        match protocol
+            .request("roots/list", None, RequestOptions::default())
+            .await
+        {
+            Ok(JsonRpcResponse {
+                result: Some(result),
+                ..
+            }) => {
+                let roots = roots_from_list_result(&result);
+                if roots.is_empty() {
+                    tracing::info!(
+                        "the client reported no local roots, keeping the configured roots"
+                    );
+                } else {
+                    tracing::info!(roots = roots.len(), "using the client's roots");
+                    set_client_roots(roots);
                 }
             }
-        }),
+            Ok(response) => {
+                tracing::warn!(error = ?response.error, "the client did not list its roots")
+            }
+            Err(e) => tracing::warn!(error = %e, "could not list the client's roots"),
+        }
     });
+}
 
+/// The tools to register, leaving out those the installed jj is too old for
+fn create_tools(config: &ServerConfig) -> Vec<JjTool> {
+    let installed = match installed_jj_version() {
+        Ok(version) => Some(version),
+        Err(e) => {
+            tracing::warn!(error = %e, "could not determine the jj version, offering every tool");
+            None
+        }
+    };
+
+    let mut tools = Vec::new();
+    for tool in enabled_tools(config) {
+        let name = config.canonical_name(&tool.name);
+        if name == "describe-auto" && !sampler_installed() {
+            tracing::info!(tool = %tool.name, reason = "no sampler is installed", "not registering tool");
+            continue;
+        }
+        if let Some(reason) = installed
+            .and_then(|installed| unsupported_tool_reason(name, installed, config.min_jj_version()))
+        {
+            tracing::info!(tool = %tool.name, reason, "not registering tool");
+            continue;
+        }
+        tools.push(advertised_tool(tool));
+    }
     tools
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    let transport = ServerStdioTransport::default();
-    let tools = create_tools();
-
-    let server = Server::builder(transport)
-        .name("jj-mcp-server")
-        .version("1.0.0")
-        .capabilities(ServerCapabilities {
-            tools: Some(json!({})),
-            prompts: None,
-            resources: None,
-            logging: None,
-            experimental: None,
+    let cli = Cli::parse();
+    // Settings from the configuration file come first so the command line overrides them
+    let mut args = match &cli.config {
+        Some(path) => config_file_args(
+            &std::fs::read_to_string(path)
+                .map_err(|e| anyhow::anyhow!("Error: cannot read {}: {}", path.display(), e))?,
+        )?,
+        None => Vec::new(),
+    };
+    args.extend(cli.server_args());
+    let config = ServerConfig::from_sources(args, |var| std::env::var(var).ok())?;
+    if let Some(repo) = &config.repo {
+        std::env::set_current_dir(repo)
+            .map_err(|e| anyhow::anyhow!("Error: cannot use {}: {}", repo.display(), e))?;
+    }
+    init_logging(&config)?;
+    install_server_config(config);
+
+    let prepared = create_tools(server_config());
+    if cli.list_tools {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&tool_catalog(&prepared))?
+        );
+        return Ok(());
+    }
+
+    let transport = match cli.transport {
+        Transport::Stdio => ServerStdioTransport::default(),
+    };
+    let tool_count = prepared.len();
+    let mut tools = Tools::default();
+    for tool in prepared {
+        tools.add_tool(tool);
+    }
+
+    // Built on the protocol rather than `Server` so requests can be sent to the client
+    let tools = Arc::new(tools);
+    let listed = tools.clone();
+    let protocol = Protocol::builder(transport)
+        .request_handler("initialize", |req: InitializeRequest| {
+            CLIENT_LISTS_ROOTS.store(req.capabilities.roots.is_some(), Ordering::Relaxed);
+            let mut response = serde_json::to_value(InitializeResponse {
+                protocol_version: LATEST_PROTOCOL_VERSION.to_string(),
+                capabilities: ServerCapabilities {
+                    tools: Some(json!({})),
+                    prompts: None,
+                    resources: None,
+                    logging: None,
+                    experimental: None,
+                },
+                server_info: Implementation {
+                    name: "jj-mcp-server".to_string(),
+                    version: "1.0.0".to_string(),
+                },
+            })?;
+            // The SDK's ServerCapabilities predates the `completions` capability
+            response["capabilities"]["completions"] = json!({});
+            Ok(response)
+        })
+        .notification_handler("notifications/initialized", |_: Value| {
+            refresh_client_roots();
+            Ok(())
+        })
+        .notification_handler("notifications/roots/list_changed", |_: Value| {
+            refresh_client_roots();
+            Ok(())
+        })
+        .request_handler("completion/complete", |req: Value| complete_request(&req))
+        .request_handler("tools/list", move |_req: ListRequest| {
+            Ok(ToolsListResponse {
+                tools: listed.list_tools(),
+                next_cursor: None,
+                meta: None,
+            })
+        })
+        .request_handler("tools/call", move |req: CallToolRequest| {
+            Ok(tools.call_tool(req))
         })
-        .tools(tools)
         .build();
+    let _ = PROTOCOL.set(protocol.clone());
 
-    eprintln!("jj MCP Server starting...");
-    server.listen().await?;
+    tracing::info!(
+        version = env!("CARGO_PKG_VERSION"),
+        tools = tool_count,
+        "jj MCP server starting"
+    );
+    let result = protocol.listen().await;
+    remove_output_store();
+    result?;
     Ok(())
 }
@@ -0,0 +1,110 @@
+//! Helpers for testing tools against real, temporary jj repositories.
+//!
+//! Only compiled with the `testing` feature. Everything here runs the jj CLI, so
+//! tests using it need jj installed; [`create_test_repo`] fails with a message
+//! saying so when it isn't.
+
+use std::path::Path;
+
+use anyhow::Result;
+use mcp_sdk::tools::Tool;
+use serde_json::Value;
+use tempfile::TempDir;
+
+use crate::{CallToolResponse, JjTool, all_tools};
+
+/// Run jj in `repo` and return its stdout
+pub fn run_jj(repo: &Path, args: &[&str]) -> Result<String> {
+    let output = std::process::Command::new(crate::jj_command())
+        .args(args)
+        .current_dir(repo)
+        .output()
+        .map_err(|e| anyhow::anyhow!("jj command not found: {}", e))?;
+    if !output.status.success() {
+        return Err(anyhow::anyhow!(
+            "jj {} failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Create a git-backed jj repository in a new temporary directory
+///
+/// The repository is deleted when the returned directory is dropped. A test
+/// user name and email are configured so commits can be made.
+pub fn create_test_repo() -> Result<TempDir> {
+    let temp_dir = TempDir::new()?;
+    run_jj(temp_dir.path(), &["git", "init"])
+        .map_err(|e| anyhow::anyhow!("Failed to init jj repo: {}", e))?;
+    for (key, value) in [
+        ("user.name", "Test User"),
+        ("user.email", "test@example.com"),
+    ] {
+        run_jj(temp_dir.path(), &["config", "set", "--repo", key, value])?;
+    }
+    Ok(temp_dir)
+}
+
+/// Write a file relative to the repository root, creating parent directories
+pub fn create_test_file(repo: &Path, filename: &str, content: &str) -> Result<()> {
+    let path = repo.join(filename);
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::write(path, content)?;
+    Ok(())
+}
+
+/// Commit the working copy with a message, returning the commit's change ID
+pub fn commit(repo: &Path, message: &str) -> Result<String> {
+    run_jj(repo, &["commit", "-m", message])?;
+    Ok(
+        run_jj(repo, &["log", "--no-graph", "-r", "@-", "-T", "change_id"])?
+            .trim()
+            .to_string(),
+    )
+}
+
+/// Write a file and commit it in one step, returning the commit's change ID
+pub fn commit_file(repo: &Path, filename: &str, content: &str, message: &str) -> Result<String> {
+    create_test_file(repo, filename, content)?;
+    commit(repo, message)
+}
+
+/// The catalog tool with this name
+///
+/// # Panics
+///
+/// If no tool has the name.
+pub fn tool(name: &str) -> JjTool {
+    all_tools()
+        .into_iter()
+        .find(|tool| tool.name == name)
+        .unwrap_or_else(|| panic!("no tool named {}", name))
+}
+
+/// Call a tool with `repoPath` set to `repo`
+///
+/// Tools without a `repoPath` parameter get `cwd` set instead, when they take one.
+pub fn call_tool(name: &str, repo: &Path, mut arguments: Value) -> Result<CallToolResponse> {
+    let tool = tool(name);
+    let key = ["repoPath", "cwd"]
+        .into_iter()
+        .find(|key| tool.input_schema["properties"].get(*key).is_some());
+    if let Some(object) = arguments.as_object_mut()
+        && let Some(key) = key
+    {
+        object.insert(
+            key.to_string(),
+            Value::String(repo.to_string_lossy().into_owned()),
+        );
+    }
+    tool.call(Some(arguments))
+}
+
+/// The text blocks of a response, joined by newlines
+pub fn response_text(response: &CallToolResponse) -> String {
+    crate::response_text(response)
+}